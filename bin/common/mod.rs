@@ -0,0 +1,96 @@
+//! Terminal and process setup shared by the command-line binaries: logging,
+//! Ctrl+C, and the prompts and report printed around a run.
+//!
+//! Included by path (`#[path = "../common/mod.rs"] mod common;`) rather than
+//! living in the library, which should not install global handlers or talk
+//! to the terminal on its users' behalf.
+
+use std::io::{self, Write};
+
+use env_logger::Env;
+use log::{LevelFilter, error, info};
+
+use rusty_tip::routine::RunReport;
+use rusty_tip::shutdown::ShutdownFlag;
+use rusty_tip::signal_profile::ProfileChange;
+use rusty_tip::spm_error::SpmError;
+
+/// A shutdown flag that Ctrl+C requests. Fails if a Ctrl+C handler is
+/// already installed.
+pub fn shutdown_on_ctrl_c() -> Result<ShutdownFlag, ctrlc::Error> {
+    let shutdown = ShutdownFlag::new();
+    let flag = shutdown.clone();
+
+    ctrlc::set_handler(move || {
+        info!("Ctrl+C received - initiating graceful shutdown...");
+        flag.request();
+    })?;
+
+    Ok(shutdown)
+}
+
+/// Log to stderr at `log_level` (trace, debug, info, warn, error; anything
+/// else is info), with millisecond timestamps. `RUST_LOG` still applies. A
+/// logger installed earlier stays in place.
+pub fn init_logging(log_level: &str) {
+    let level = match log_level.to_lowercase().as_str() {
+        "trace" => LevelFilter::Trace,
+        "debug" => LevelFilter::Debug,
+        "info" => LevelFilter::Info,
+        "warn" => LevelFilter::Warn,
+        "error" => LevelFilter::Error,
+        _ => {
+            eprintln!("Warning: Invalid log level '{}', using 'info'", log_level);
+            LevelFilter::Info
+        }
+    };
+
+    if env_logger::Builder::from_env(Env::default())
+        .filter_level(level)
+        .format_timestamp_millis()
+        .try_init()
+        .is_err()
+    {
+        eprintln!("Warning: a logger is already installed; keeping it");
+    }
+}
+
+/// Wait for Enter on the terminal before starting `what`.
+pub fn wait_for_start(what: &str) -> Result<(), SpmError> {
+    println!();
+    println!("Press Enter to start {what} (or Ctrl+C to cancel)...");
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(|source| SpmError::Io {
+            source,
+            context: "waiting for Enter".into(),
+        })?;
+    Ok(())
+}
+
+/// Ask on the terminal whether to accept a signal layout that differs from
+/// the saved profile; anything but "y" refuses.
+pub fn confirm_signal_layout(changes: &[ProfileChange]) -> bool {
+    println!();
+    println!("The controller's signal layout differs from the saved profile:");
+    for change in changes {
+        println!("  - {change}");
+    }
+    print!("Accept the new layout and update the profile? [y/N] ");
+    let _ = io::stdout().flush();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).is_ok() && input.trim().eq_ignore_ascii_case("y")
+}
+
+/// Print the run report as pretty JSON.
+pub fn print_run_report(report: &RunReport) {
+    match serde_json::to_string_pretty(report) {
+        Ok(json) => {
+            println!();
+            println!("Run report:");
+            println!("{json}");
+        }
+        Err(e) => error!("Failed to serialize run report: {}", e),
+    }
+}
@@ -0,0 +1,301 @@
+use clap::{Parser, Subcommand};
+use log::{error, info};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use config::ConfigError;
+use rusty_tip::config::{load_config, load_config_document};
+use rusty_tip::routine::operator::StdinOperator;
+use rusty_tip::routine::registry::{RoutineContext, RoutineRegistry};
+use rusty_tip::routine::script::ScriptRoutine;
+use rusty_tip::routine::{Outcome, Routine, run_routine_reported};
+use rusty_tip::session::{Backend, event_bus};
+use rusty_tip::spm_error::SpmError;
+
+#[path = "../common/mod.rs"]
+mod common;
+
+use common::{
+    confirm_signal_layout, init_logging, print_run_report, shutdown_on_ctrl_c, wait_for_start,
+};
+
+/// Run any registered routine by name
+#[derive(Parser, Debug)]
+#[command(name = "rusty-tip")]
+#[command(about = "Run STM/AFM automation routines", long_about = None)]
+struct Cli {
+    /// Override log level (trace, debug, info, warn, error)
+    #[arg(short, long, value_name = "LEVEL", global = true)]
+    log_level: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the registered routines and the config section each reads
+    List,
+    /// Check a config file without connecting to the instrument
+    Validate {
+        /// Path to configuration file
+        #[arg(short, long, value_name = "FILE")]
+        config: PathBuf,
+
+        /// Routine whose section to check (default: all routines)
+        routine: Option<String>,
+    },
+    /// Run a routine
+    Run {
+        /// Routine to run (see `rusty-tip list`)
+        routine: String,
+
+        /// Path to configuration file
+        #[arg(short, long, value_name = "FILE")]
+        config: PathBuf,
+
+        /// Run against the in-memory mock controller instead of Nanonis
+        #[arg(long)]
+        mock: bool,
+
+        /// Start without waiting for Enter
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Run a Rhai script as a routine
+    RunScript {
+        /// Script file (see the `routine::script` docs for the bindings)
+        script: PathBuf,
+
+        /// Path to configuration file
+        #[arg(short, long, value_name = "FILE")]
+        config: PathBuf,
+
+        /// Run against the in-memory mock controller instead of Nanonis
+        #[arg(long)]
+        mock: bool,
+
+        /// Start without waiting for Enter
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+/// Options shared by `run` and `run-script`.
+struct RunOptions<'a> {
+    config: &'a Path,
+    log_level: Option<&'a str>,
+    mock: bool,
+    yes: bool,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        // Same convention as tip-prep: 2 = the routine ended without reaching
+        // its goal, 1 = hard error.
+        Err(RunError::Incomplete) => ExitCode::from(2),
+        Err(RunError::Fatal(e)) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+enum RunError {
+    /// Outcome that isn't success but isn't a crash either (cycle limit,
+    /// timeout, user-requested stop).
+    Incomplete,
+    Fatal(Box<dyn std::error::Error>),
+}
+
+impl<E: Into<Box<dyn std::error::Error>>> From<E> for RunError {
+    fn from(e: E) -> Self {
+        RunError::Fatal(e.into())
+    }
+}
+
+fn run() -> Result<(), RunError> {
+    let cli = Cli::parse();
+    let registry = RoutineRegistry::with_builtins();
+
+    match cli.command {
+        Command::List => {
+            init_logging(cli.log_level.as_deref().unwrap_or("info"));
+            list_routines(&registry);
+            Ok(())
+        }
+        Command::Validate { config, routine } => {
+            init_logging(cli.log_level.as_deref().unwrap_or("info"));
+            validate_config(&registry, &config, routine.as_deref())
+        }
+        Command::Run {
+            routine,
+            config,
+            mock,
+            yes,
+        } => {
+            let options = RunOptions {
+                config: &config,
+                log_level: cli.log_level.as_deref(),
+                mock,
+                yes,
+            };
+            run_with(
+                &options,
+                |document| registry.validate(&routine, document),
+                |document, ctx| registry.build(&routine, document, ctx),
+            )
+        }
+        Command::RunScript {
+            script,
+            config,
+            mock,
+            yes,
+        } => {
+            let options = RunOptions {
+                config: &config,
+                log_level: cli.log_level.as_deref(),
+                mock,
+                yes,
+            };
+            run_with(
+                &options,
+                |_| Ok(()),
+                |_, ctx| {
+                    ScriptRoutine::from_file(&script, ctx)
+                        .map(|r| Box::new(r) as Box<dyn Routine + '_>)
+                },
+            )
+        }
+    }
+}
+
+fn list_routines(registry: &RoutineRegistry) {
+    for entry in registry.entries() {
+        println!(
+            "{:<16} [{}]  {}",
+            entry.name(),
+            entry.section(),
+            entry.description()
+        );
+    }
+}
+
+fn validate_config(
+    registry: &RoutineRegistry,
+    path: &Path,
+    routine: Option<&str>,
+) -> Result<(), RunError> {
+    load_config(path)?;
+    let document = load_config_document(path)?;
+
+    let names: Vec<&str> = match routine {
+        Some(name) => vec![name],
+        None => registry.entries().map(|e| e.name()).collect(),
+    };
+
+    let mut invalid = 0;
+    for name in names {
+        match registry.validate(name, &document) {
+            Ok(()) => println!("{name}: ok"),
+            Err(e) => {
+                println!("{name}: {e}");
+                invalid += 1;
+            }
+        }
+    }
+
+    if invalid > 0 {
+        return Err(format!("{invalid} routine config(s) invalid").into());
+    }
+    Ok(())
+}
+
+/// Shared setup around one routine: config, logging, backend, event log,
+/// Ctrl+C, then the run and its report. `check` vets the raw config before
+/// anything connects; `build` constructs the routine once it has.
+fn run_with(
+    options: &RunOptions,
+    check: impl FnOnce(&serde_json::Value) -> Result<(), ConfigError>,
+    build: impl for<'c> FnOnce(
+        &serde_json::Value,
+        &RoutineContext<'c>,
+    ) -> Result<Box<dyn Routine + 'c>, SpmError>,
+) -> Result<(), RunError> {
+    let config = load_config(options.config)?;
+    let document = load_config_document(options.config)?;
+
+    init_logging(options.log_level.unwrap_or(&config.console.verbosity));
+
+    // Reject a bad routine section before touching the instrument.
+    check(&document)?;
+
+    let mut backend = if options.mock {
+        Backend::mock(&config)?
+    } else {
+        Backend::nanonis(&config, confirm_signal_layout)?
+    };
+
+    let ctx = RoutineContext {
+        config: &config,
+        signals: &backend.signals,
+    };
+    let mut routine = match build(&document, &ctx) {
+        Ok(r) => r,
+        Err(e) => {
+            backend.controller.teardown();
+            return Err(e.into());
+        }
+    };
+    let routine_name = routine.name().to_string();
+
+    info!("=== rusty-tip: {routine_name} ===");
+    info!("Configuration: {}", options.config.display());
+
+    let events = event_bus(
+        &config,
+        &routine_name,
+        &backend.signals,
+        backend.stream_markers.take(),
+    )?;
+    let shutdown = shutdown_on_ctrl_c()?;
+
+    if !(options.yes || options.mock) {
+        wait_for_start(&routine_name)?;
+    }
+
+    let (result, report) = run_routine_reported(
+        backend.controller,
+        &events,
+        &shutdown,
+        &StdinOperator::new(),
+        routine.as_mut(),
+    );
+    print_run_report(&report);
+
+    match result {
+        Ok(Outcome::Completed) => {
+            info!("{routine_name} completed successfully");
+            Ok(())
+        }
+        Ok(Outcome::StoppedByUser) => {
+            info!("{routine_name} stopped by user");
+            Err(RunError::Incomplete)
+        }
+        Ok(Outcome::CycleLimit(n)) => {
+            error!("Max cycles ({}) exceeded", n);
+            Err(RunError::Incomplete)
+        }
+        Ok(Outcome::TimedOut(d)) => {
+            error!("Max duration ({:.0}s) exceeded", d.as_secs_f64());
+            Err(RunError::Incomplete)
+        }
+        Err(e) => {
+            error!("{routine_name} failed: {}", e);
+            Err(RunError::Fatal(Box::new(e)))
+        }
+    }
+}
@@ -7,24 +7,24 @@ use std::path::Path;
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+use rusty_tip::analyzer::NoiseReport;
 use rusty_tip::config::{
-    AppConfig, ConsoleConfig, DataAcquisitionConfig, ExperimentLoggingConfig, NanonisConfig,
-    SignalStabilityConfig, TcpChannelMapping, TimingConfig, TipPrepConfig,
-    load_config_with_fallback,
+    AppConfig, ConditioningConfig, ConfirmationConfig, ConsoleConfig, DataAcquisitionConfig,
+    ExperimentLoggingConfig, ImageCheckConfig, NanonisConfig, RepositionConfig,
+    SignalProfileConfig, SignalStabilityConfig, SignalUnitConfig, StreamRecordingConfig,
+    TcpChannelMapping, TimingConfig, TipPrepConfig, VirtualSignalConfig, load_config_with_fallback,
 };
-use rusty_tip::event::{
-    ChannelForwarder, ConsoleLogger, Event, EventAccumulator, EventBus, FileLogger,
-};
-use rusty_tip::mock_controller::{MockController, models};
-use rusty_tip::nanonis_controller::{NanonisController, NanonisSetupConfig, StreamSetup};
+use rusty_tip::event::{ChannelForwarder, Event, EventPayload};
+use rusty_tip::routine::noise_check::{NoiseCheck, NoiseCheckConfig};
+use rusty_tip::routine::operator::{ChannelOperator, OperatorInterface, PendingQuestion, Question};
+use rusty_tip::routine::{Routine, run_routine_with_operator};
+use rusty_tip::session::{Backend, event_bus};
 use rusty_tip::shutdown::ShutdownFlag;
-use rusty_tip::signal_registry::SignalRegistry;
-use rusty_tip::spm_controller::SpmController;
-use rusty_tip::spm_error::SpmError;
-use rusty_tip::tip_prep::{Outcome, TipPrepParams, run_tip_prep};
+use rusty_tip::tip_prep::{Outcome, TipPrep, TipPrepState as RoutineState};
+use rusty_tip::units::SignalUnit;
 use rusty_tip::{
-    BiasSweepPolarity, PolaritySign, PulseMethod, RandomPolaritySwitch, SignalIndex,
-    StabilityConfig,
+    BiasSweepPolarity, PolaritySign, PulseMethod, PulseWidthStepping, RandomPolaritySwitch,
+    SignalIndex, StabilityConfig, TipChangeDetection,
 };
 
 // ============================================================================
@@ -92,6 +92,14 @@ pub enum Tab {
 // Run Status for GUI
 // ============================================================================
 
+/// What the controller thread runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunTask {
+    TipPrep,
+    /// The `noise_check` routine on the frequency shift.
+    NoiseCheck,
+}
+
 #[derive(Debug, Clone)]
 pub enum RunStatus {
     Idle,
@@ -109,7 +117,10 @@ struct TipPrepState {
     cycle: usize,
     elapsed_secs: f64,
     freq_shift: Option<f64>,
+    /// Unit of the freq-shift signal, as labelled on its `stable_read` events.
+    freq_shift_unit: Option<SignalUnit>,
     pulse_voltage: f64,
+    pulse_width_ms: u64,
     phase: String,
     is_sharp: bool,
 }
@@ -171,6 +182,7 @@ pub enum PulseMethodType {
     #[default]
     Stepping,
     Linear,
+    Adaptive,
 }
 
 // ============================================================================
@@ -239,16 +251,44 @@ pub struct EditableConfig {
     pub linear_clamp_min: String,
     pub linear_clamp_max: String,
 
+    // Adaptive-specific
+    pub voltage_levels: String,
+    pub explore_polarity: bool,
+    pub exploration: String,
+    pub discount: String,
+
     // Random polarity switch
     pub random_polarity_enabled: bool,
     pub random_polarity_switch_every: String,
 
+    // Pulse width stepping
+    pub pulse_width_enabled: bool,
+    pub pulse_width_min: String,
+    pub pulse_width_max: String,
+    pub pulse_width_steps: String,
+    pub pulse_width_cycles: String,
+
     // TCP channel mapping
     pub tcp_channel_mappings: Vec<EditableTcpMapping>,
 
     // Carried through from the loaded config (no GUI widget yet) so the GUI
     // honors a custom [tip_prep.signal_stability] from the config file.
     pub signal_stability: SignalStabilityConfig,
+    // Likewise for [tip_prep.image_check], [tip_prep.conditioning],
+    // [tip_prep.reposition], [tip_prep.confirmation] and
+    // [tip_prep.stability.tip_change], [data_acquisition.recording],
+    // [signal_profile], [[signal_units]], [[virtual_signals]] and the
+    // tip-prep signal.
+    pub image_check: ImageCheckConfig,
+    pub conditioning: ConditioningConfig,
+    pub reposition: RepositionConfig,
+    pub confirmation: ConfirmationConfig,
+    pub tip_change: TipChangeDetection,
+    pub stream_recording: StreamRecordingConfig,
+    pub signal_profile: SignalProfileConfig,
+    pub signal_units: Vec<SignalUnitConfig>,
+    pub virtual_signals: Vec<VirtualSignalConfig>,
+    pub tip_prep_signal: String,
 }
 
 impl Default for EditableConfig {
@@ -290,10 +330,29 @@ impl Default for EditableConfig {
             threshold_value: "0.1".to_string(),
             linear_clamp_min: "-20.0".to_string(),
             linear_clamp_max: "0.0".to_string(),
+            voltage_levels: "5".to_string(),
+            explore_polarity: false,
+            exploration: "0.5".to_string(),
+            discount: "0.98".to_string(),
             random_polarity_enabled: false,
             random_polarity_switch_every: "10".to_string(),
+            pulse_width_enabled: false,
+            pulse_width_min: "50".to_string(),
+            pulse_width_max: "200".to_string(),
+            pulse_width_steps: "3".to_string(),
+            pulse_width_cycles: "2".to_string(),
             tcp_channel_mappings: Vec::new(),
             signal_stability: SignalStabilityConfig::default(),
+            image_check: ImageCheckConfig::default(),
+            conditioning: ConditioningConfig::default(),
+            reposition: RepositionConfig::default(),
+            confirmation: ConfirmationConfig::default(),
+            tip_change: TipChangeDetection::default(),
+            stream_recording: StreamRecordingConfig::default(),
+            signal_profile: SignalProfileConfig::default(),
+            signal_units: Vec::new(),
+            virtual_signals: Vec::new(),
+            tip_prep_signal: TipPrepConfig::default().signal,
         }
     }
 }
@@ -318,6 +377,7 @@ impl EditableConfig {
                 voltage,
                 polarity,
                 random_polarity_switch,
+                ..
             } => {
                 let (rp_enabled, rp_every) = match random_polarity_switch {
                     Some(rps) => (rps.enabled, rps.switch_every_n_pulses.to_string()),
@@ -345,6 +405,7 @@ impl EditableConfig {
                 threshold_value,
                 polarity,
                 random_polarity_switch,
+                ..
             } => {
                 let (rp_enabled, rp_every) = match random_polarity_switch {
                     Some(rps) => (rps.enabled, rps.switch_every_n_pulses.to_string()),
@@ -370,6 +431,7 @@ impl EditableConfig {
                 linear_clamp,
                 polarity,
                 random_polarity_switch,
+                ..
             } => {
                 let (rp_enabled, rp_every) = match random_polarity_switch {
                     Some(rps) => (rps.enabled, rps.switch_every_n_pulses.to_string()),
@@ -390,7 +452,76 @@ impl EditableConfig {
                     rp_every,
                 )
             }
+            PulseMethod::Adaptive {
+                voltage_bounds,
+                polarity,
+                random_polarity_switch,
+                ..
+            } => {
+                let (rp_enabled, rp_every) = match random_polarity_switch {
+                    Some(rps) => (rps.enabled, rps.switch_every_n_pulses.to_string()),
+                    None => (false, "10".to_string()),
+                };
+                (
+                    PulseMethodType::Adaptive,
+                    "6.0".to_string(),
+                    voltage_bounds.0.to_string(),
+                    voltage_bounds.1.to_string(),
+                    "4".to_string(),
+                    "2".to_string(),
+                    "0.1".to_string(),
+                    "-20.0".to_string(),
+                    "0.0".to_string(),
+                    *polarity,
+                    rp_enabled,
+                    rp_every,
+                )
+            }
         };
+        let defaults = Self::default();
+        let (
+            pulse_width_enabled,
+            pulse_width_min,
+            pulse_width_max,
+            pulse_width_steps,
+            pulse_width_cycles,
+        ) = match app_config.pulse_method.pulse_width() {
+            Some(w) => (
+                true,
+                w.width_bounds_ms.0.to_string(),
+                w.width_bounds_ms.1.to_string(),
+                w.width_steps.to_string(),
+                w.cycles_at_max_voltage.to_string(),
+            ),
+            None => (
+                false,
+                defaults.pulse_width_min.clone(),
+                defaults.pulse_width_max.clone(),
+                defaults.pulse_width_steps.clone(),
+                defaults.pulse_width_cycles.clone(),
+            ),
+        };
+        let (voltage_levels, explore_polarity, exploration, discount) =
+            match &app_config.pulse_method {
+                PulseMethod::Adaptive {
+                    voltage_levels,
+                    explore_polarity,
+                    exploration,
+                    discount,
+                    ..
+                } => (
+                    voltage_levels.to_string(),
+                    *explore_polarity,
+                    exploration.to_string(),
+                    discount.to_string(),
+                ),
+                _ => (
+                    defaults.voltage_levels,
+                    defaults.explore_polarity,
+                    defaults.exploration,
+                    defaults.discount,
+                ),
+            };
 
         Self {
             host_ip: app_config.nanonis.host_ip.clone(),
@@ -454,8 +585,17 @@ impl EditableConfig {
             threshold_value,
             linear_clamp_min,
             linear_clamp_max,
+            voltage_levels,
+            explore_polarity,
+            exploration,
+            discount,
             random_polarity_enabled,
             random_polarity_switch_every,
+            pulse_width_enabled,
+            pulse_width_min,
+            pulse_width_max,
+            pulse_width_steps,
+            pulse_width_cycles,
             tcp_channel_mappings: app_config
                 .tcp_channel_mapping
                 .as_ref()
@@ -470,6 +610,16 @@ impl EditableConfig {
                 })
                 .unwrap_or_default(),
             signal_stability: app_config.tip_prep.signal_stability.clone(),
+            image_check: app_config.tip_prep.image_check.clone(),
+            conditioning: app_config.tip_prep.conditioning.clone(),
+            reposition: app_config.tip_prep.reposition.clone(),
+            confirmation: app_config.tip_prep.confirmation.clone(),
+            tip_change: app_config.tip_prep.stability.tip_change.clone(),
+            stream_recording: app_config.data_acquisition.recording.clone(),
+            signal_profile: app_config.signal_profile.clone(),
+            signal_units: app_config.signal_units.clone(),
+            virtual_signals: app_config.virtual_signals.clone(),
+            tip_prep_signal: app_config.tip_prep.signal.clone(),
         }
     }
 
@@ -545,6 +695,29 @@ impl EditableConfig {
             None
         };
 
+        let pulse_width = if self.pulse_width_enabled {
+            Some(PulseWidthStepping {
+                width_bounds_ms: (
+                    self.pulse_width_min
+                        .parse()
+                        .map_err(|_| "Invalid pulse width min")?,
+                    self.pulse_width_max
+                        .parse()
+                        .map_err(|_| "Invalid pulse width max")?,
+                ),
+                width_steps: self
+                    .pulse_width_steps
+                    .parse()
+                    .map_err(|_| "Invalid pulse width steps")?,
+                cycles_at_max_voltage: self
+                    .pulse_width_cycles
+                    .parse()
+                    .map_err(|_| "Invalid cycles at max voltage")?,
+            })
+        } else {
+            None
+        };
+
         let pulse_method = match self.pulse_method_type {
             PulseMethodType::Fixed => PulseMethod::Fixed {
                 voltage: self
@@ -553,6 +726,7 @@ impl EditableConfig {
                     .map_err(|_| "Invalid pulse voltage")?,
                 polarity: self.pulse_polarity,
                 random_polarity_switch,
+                pulse_width,
             },
             PulseMethodType::Stepping => PulseMethod::Stepping {
                 voltage_bounds: (
@@ -577,6 +751,7 @@ impl EditableConfig {
                     .map_err(|_| "Invalid threshold value")?,
                 polarity: self.pulse_polarity,
                 random_polarity_switch,
+                pulse_width,
             },
             PulseMethodType::Linear => PulseMethod::Linear {
                 voltage_bounds: (
@@ -597,6 +772,30 @@ impl EditableConfig {
                 ),
                 polarity: self.pulse_polarity,
                 random_polarity_switch,
+                pulse_width,
+            },
+            PulseMethodType::Adaptive => PulseMethod::Adaptive {
+                voltage_bounds: (
+                    self.pulse_voltage_min
+                        .parse()
+                        .map_err(|_| "Invalid voltage min")?,
+                    self.pulse_voltage_max
+                        .parse()
+                        .map_err(|_| "Invalid voltage max")?,
+                ),
+                voltage_levels: self
+                    .voltage_levels
+                    .parse()
+                    .map_err(|_| "Invalid voltage levels")?,
+                explore_polarity: self.explore_polarity,
+                exploration: self
+                    .exploration
+                    .parse()
+                    .map_err(|_| "Invalid exploration")?,
+                discount: self.discount.parse().map_err(|_| "Invalid discount")?,
+                polarity: self.pulse_polarity,
+                random_polarity_switch,
+                pulse_width,
             },
         };
 
@@ -619,6 +818,7 @@ impl EditableConfig {
                 data_port,
                 sample_rate,
                 stable_signal_samples,
+                recording: self.stream_recording.clone(),
             },
             experiment_logging: ExperimentLoggingConfig {
                 enabled: self.logging_enabled,
@@ -628,6 +828,7 @@ impl EditableConfig {
                 verbosity: self.verbosity.clone(),
             },
             tip_prep: TipPrepConfig {
+                signal: self.tip_prep_signal.clone(),
                 sharp_tip_bounds: [sharp_tip_lower, sharp_tip_upper],
                 max_cycles,
                 max_duration_secs,
@@ -656,12 +857,17 @@ impl EditableConfig {
                         .map_err(|_| "Invalid stability max duration")?,
                     polarity_mode: self.polarity_mode,
                     scan_speed_m_s,
+                    tip_change: self.tip_change.clone(),
                 },
                 initial_bias_v: initial_bias_mv / 1000.0,
                 initial_z_setpoint_a: initial_z_setpoint_pa * 1e-12,
                 safe_tip_threshold: safe_tip_threshold_pa * 1e-12,
                 timing: TimingConfig::default(),
                 signal_stability: self.signal_stability.clone(),
+                image_check: self.image_check.clone(),
+                conditioning: self.conditioning.clone(),
+                reposition: self.reposition.clone(),
+                confirmation: self.confirmation.clone(),
             },
             pulse_method,
             tcp_channel_mapping: if self.tcp_channel_mappings.is_empty() {
@@ -685,6 +891,9 @@ impl EditableConfig {
                     .collect();
                 Some(mappings?)
             },
+            signal_profile: self.signal_profile.clone(),
+            signal_units: self.signal_units.clone(),
+            virtual_signals: self.virtual_signals.clone(),
         })
     }
 }
@@ -719,6 +928,10 @@ pub struct TipPrepApp {
 
     // Real-time state from V2 event stream
     event_receiver: Option<Receiver<Event>>,
+
+    // Operator questions from the running routine, shown as a modal dialog
+    question_receiver: Option<Receiver<PendingQuestion>>,
+    pending_question: Option<PendingQuestion>,
     tip_state: TipPrepState,
 
     // Time-series data for plots
@@ -731,6 +944,9 @@ pub struct TipPrepApp {
     // Last known action name from events
     current_action: String,
 
+    // Spectrum from the last noise check
+    noise_report: Option<NoiseReport>,
+
     // Messages
     message: Option<(String, bool)>,
 
@@ -753,11 +969,14 @@ impl TipPrepApp {
             simulate: false,
             theme: egui::ThemePreference::System,
             event_receiver: None,
+            question_receiver: None,
+            pending_question: None,
             tip_state: TipPrepState::default(),
             freq_shift_history: Vec::new(),
             voltage_history: Vec::new(),
             sharp_bounds: None,
             current_action: String::new(),
+            noise_report: None,
             message: None,
             log_messages: Vec::new(),
             log_receiver: None,
@@ -816,7 +1035,7 @@ impl TipPrepApp {
         }
     }
 
-    fn start_controller(&mut self) {
+    fn start_controller(&mut self, task: RunTask) {
         let config = match self.config.to_app_config() {
             Ok(c) => c,
             Err(e) => {
@@ -838,9 +1057,13 @@ impl TipPrepApp {
         let (event_tx, event_rx) = unbounded();
         self.event_receiver = Some(event_rx);
 
+        let (operator, question_rx) = ChannelOperator::new();
+        self.question_receiver = Some(question_rx);
+        self.pending_question = None;
+
         let simulate = self.simulate;
         let handle = thread::spawn(move || {
-            let result = run_controller(config, shutdown, event_tx, simulate);
+            let result = run_controller(config, shutdown, event_tx, operator, simulate, task);
             if let Err(ref e) = result {
                 error!("Controller error: {}", e);
             }
@@ -854,6 +1077,9 @@ impl TipPrepApp {
         self.freq_shift_history.clear();
         self.voltage_history.clear();
         self.current_action.clear();
+        if task == RunTask::NoiseCheck {
+            self.noise_report = None;
+        }
         self.log_messages.clear();
         info!("Controller started");
         self.message = Some(("Controller started".to_string(), false));
@@ -870,6 +1096,13 @@ impl TipPrepApp {
             }
         }
 
+        // One operator question at a time; the routine blocks until it is answered.
+        if self.pending_question.is_none()
+            && let Some(rx) = &self.question_receiver
+        {
+            self.pending_question = rx.try_recv().ok();
+        }
+
         // Poll events from the V2 event bus.
         //
         // One clock for every plotted series: the runner's own `elapsed_secs`
@@ -883,41 +1116,46 @@ impl TipPrepApp {
         if let Some(rx) = &self.event_receiver {
             while let Ok(event) = rx.try_recv() {
                 match &event {
-                    Event::Custom { kind, data } if kind == "tip_prep_state" => {
-                        // Parse TipPrepSnapshot from JSON
-                        if let Some(cycle) = data.get("cycle").and_then(|v| v.as_u64()) {
-                            self.tip_state.cycle = cycle as usize;
-                        }
-                        if let Some(elapsed) = data.get("elapsed_secs").and_then(|v| v.as_f64()) {
-                            self.tip_state.elapsed_secs = elapsed;
-                        }
-                        // Only the status readout — the freq-shift *plot* is fed
-                        // by the per-sample `signal_samples` stream below, which
-                        // covers these same reads at full resolution.
-                        if let Some(fs) = data.get("freq_shift").and_then(|v| v.as_f64()) {
-                            self.tip_state.freq_shift = Some(fs);
-                        }
-                        if let Some(pv) = data.get("pulse_voltage").and_then(|v| v.as_f64()) {
-                            self.tip_state.pulse_voltage = pv;
+                    Event::Custom { kind, .. } if kind == RoutineState::KIND => {
+                        let Some(state) = event.decode::<RoutineState>() else {
+                            continue;
+                        };
+                        if let Some(snapshot) = state.snapshot() {
+                            self.tip_state.cycle = snapshot.cycle;
+                            self.tip_state.elapsed_secs = snapshot.elapsed_secs;
+                            // Only the status readout — the freq-shift *plot* is
+                            // fed by the `stable_read` measurements below, which
+                            // cover these same reads.
+                            if let Some(fs) = snapshot.freq_shift {
+                                self.tip_state.freq_shift = Some(fs);
+                            }
+                            self.tip_state.pulse_voltage = snapshot.pulse_voltage;
                             self.voltage_history.push(DataPoint {
                                 time_s: elapsed_now,
-                                value: pv,
+                                value: snapshot.pulse_voltage,
                             });
+                            self.tip_state.pulse_width_ms = snapshot.pulse_width_ms;
+                            self.tip_state.is_sharp = snapshot.is_sharp;
                         }
-                        if let Some(sharp) = data.get("is_sharp").and_then(|v| v.as_bool()) {
-                            self.tip_state.is_sharp = sharp;
-                        }
-                        if let Some(phase) = data.get("phase").and_then(|v| v.as_str()) {
-                            self.tip_state.phase = phase.to_string();
-                        }
+                        self.tip_state.phase = state.phase().to_string();
                     }
                     Event::DataCollected { label, value, .. } if label == "stable_read" => {
                         // One point per measurement: a stable read *is* a single
                         // measurement of the frequency shift, averaged from a
                         // sample batch. Every stable read in the tip-prep routine
-                        // goes through `read_stable(.., freq_shift_index)`, so
-                        // this stream is the freq-shift channel. The event carries
-                        // `index` if that ever stops being true.
+                        // goes through `read_stable(.., criterion_index)`, so
+                        // this stream is the tip-prep signal: the freq shift
+                        // unless `tip_prep.signal` names another. The event
+                        // carries `index` if that ever stops being true.
+                        if let Some(symbol) = value.get("unit").and_then(|u| u.as_str()) {
+                            let precision = value
+                                .get("precision")
+                                .and_then(|p| p.as_u64())
+                                .and_then(|p| u8::try_from(p).ok())
+                                .unwrap_or(2);
+                            self.tip_state.freq_shift_unit =
+                                Some(SignalUnit::new(symbol, precision));
+                        }
                         if let Some(v) = value.get("value").and_then(|v| v.as_f64()) {
                             self.freq_shift_history.push(DataPoint {
                                 time_s: elapsed_now,
@@ -934,12 +1172,54 @@ impl TipPrepApp {
                     Event::ActionStarted { action, .. } => {
                         self.current_action = action.clone();
                     }
+                    Event::ActionCompleted { action, output, .. } if action == "analyze_noise" => {
+                        // The completed event carries the whole report,
+                        // spectrum included; the `noise_spectrum` event only
+                        // the peaks.
+                        match serde_json::from_value(output.clone()) {
+                            Ok(report) => self.noise_report = Some(report),
+                            Err(e) => error!("Unreadable noise report: {}", e),
+                        }
+                    }
                     _ => {}
                 }
             }
         }
     }
 
+    /// Modal dialog for the routine's pending operator question, if any.
+    /// Stays open until a choice is clicked: the routine is blocked on it,
+    /// so dismissing it by clicking outside would leave the run hanging.
+    fn render_operator_question(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.pending_question else {
+            return;
+        };
+        let mut chosen = None;
+        egui::Modal::new(egui::Id::new("operator_question")).show(ctx, |ui| {
+            ui.heading("Operator input needed");
+            ui.add_space(5.0);
+            ui.label(&pending.question.prompt);
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                for (i, choice) in pending.question.choices.iter().enumerate() {
+                    let text = if i == pending.question.default {
+                        egui::RichText::new(choice).strong()
+                    } else {
+                        egui::RichText::new(choice)
+                    };
+                    if ui.button(text).clicked() {
+                        chosen = Some(i);
+                    }
+                }
+            });
+        });
+        if let Some(i) = chosen
+            && let Some(pending) = self.pending_question.take()
+        {
+            pending.answer(i);
+        }
+    }
+
     fn stop_controller(&mut self) {
         if let Some(ref flag) = self.shutdown_flag {
             flag.request();
@@ -961,6 +1241,8 @@ impl TipPrepApp {
                 .expect("checked is_some above");
             self.shutdown_flag = None;
             self.event_receiver = None;
+            self.question_receiver = None;
+            self.pending_question = None;
 
             // The thread is finished, so join() returns immediately; it hands
             // back the runner's Result (or the panic payload).
@@ -1108,14 +1390,20 @@ impl TipPrepApp {
                             ui.label(
                                 self.tip_state
                                     .freq_shift
-                                    .map(|f| format!("{:.2} Hz", f))
+                                    .map(|f| match &self.tip_state.freq_shift_unit {
+                                        Some(unit) => unit.format(f),
+                                        None => format!("{:.2} Hz", f),
+                                    })
                                     .unwrap_or_else(|| "-".to_string()),
                             );
                             ui.end_row();
 
-                            ui.label("Pulse Voltage:");
+                            ui.label("Pulse:");
                             if self.tip_state.cycle > 0 {
-                                ui.label(format!("{:.2} V", self.tip_state.pulse_voltage));
+                                ui.label(format!(
+                                    "{:.2} V, {} ms",
+                                    self.tip_state.pulse_voltage, self.tip_state.pulse_width_ms
+                                ));
                             } else {
                                 ui.label("-");
                             }
@@ -1146,7 +1434,7 @@ impl TipPrepApp {
                         .clicked()
                     {
                         self.message = None;
-                        self.start_controller();
+                        self.start_controller(RunTask::TipPrep);
                     }
 
                     if ui
@@ -1156,6 +1444,19 @@ impl TipPrepApp {
                         self.stop_controller();
                     }
 
+                    if ui
+                        .add_enabled(!self.is_running(), egui::Button::new("Check Noise"))
+                        .on_hover_text(
+                            "Record the frequency shift for a few seconds and show \
+                             its noise spectrum, to find mains pickup or \
+                             vibrations before starting.",
+                        )
+                        .clicked()
+                    {
+                        self.message = None;
+                        self.start_controller(RunTask::NoiseCheck);
+                    }
+
                     ui.add_enabled_ui(!self.is_running(), |ui| {
                         ui.checkbox(&mut self.simulate, "Simulate").on_hover_text(
                             "Run against the in-memory mock controller \
@@ -1211,8 +1512,16 @@ impl TipPrepApp {
             .map(|dp| [dp.time_s, dp.value])
             .collect();
         let colors = PlotColors::for_theme(ui.visuals().dark_mode);
-        let fs_line =
-            Line::new("Freq Shift (Hz)", PlotPoints::from(fs_xy.clone())).color(colors.freq_shift);
+        let fs_unit = self
+            .tip_state
+            .freq_shift_unit
+            .as_ref()
+            .map_or("Hz", |u| u.symbol.as_str());
+        let fs_line = Line::new(
+            format!("Freq Shift ({fs_unit})"),
+            PlotPoints::from(fs_xy.clone()),
+        )
+        .color(colors.freq_shift);
         let fs_marks = Points::new("Measurements", PlotPoints::from(fs_xy))
             .color(colors.freq_shift)
             .radius(MARKER_RADIUS);
@@ -1223,7 +1532,7 @@ impl TipPrepApp {
             .allow_zoom(false)
             .allow_scroll(false)
             .x_axis_label("Time (s)")
-            .y_axis_label("Hz")
+            .y_axis_label(fs_unit)
             .show(ui, |plot_ui| {
                 plot_ui.line(fs_line);
                 plot_ui.points(fs_marks);
@@ -1269,6 +1578,45 @@ impl TipPrepApp {
                 plot_ui.line(v_line);
                 plot_ui.points(v_marks);
             });
+
+        if let Some(report) = &self.noise_report {
+            ui.add_space(5.0);
+            ui.label(format!(
+                "Noise Spectrum (rms {:.3} Hz, {:.0}% in peaks)",
+                report.rms,
+                report.peak_power_fraction * 100.0
+            ));
+            // Log scale: the peaks sit orders of magnitude above the floor.
+            let psd_xy: Vec<[f64; 2]> = report
+                .spectrum
+                .frequencies_hz
+                .iter()
+                .zip(&report.spectrum.psd)
+                .skip(1)
+                .map(|(&f, &p)| [f, p.max(f64::MIN_POSITIVE).log10()])
+                .collect();
+            let peaks_xy: Vec<[f64; 2]> = report
+                .peaks
+                .iter()
+                .map(|p| [p.frequency_hz, p.psd.max(f64::MIN_POSITIVE).log10()])
+                .collect();
+            Plot::new("noise_spectrum_plot")
+                .height(120.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .allow_scroll(false)
+                .x_axis_label("Frequency (Hz)")
+                .y_axis_label("log10 Hz²/Hz")
+                .show(ui, |plot_ui| {
+                    plot_ui
+                        .line(Line::new("PSD", PlotPoints::from(psd_xy)).color(colors.freq_shift));
+                    plot_ui.points(
+                        Points::new("Peaks", PlotPoints::from(peaks_xy))
+                            .color(colors.bounds)
+                            .radius(MARKER_RADIUS + 1.0),
+                    );
+                });
+        }
     }
 
     fn render_configuration_tab(&mut self, ui: &mut egui::Ui) {
@@ -1475,6 +1823,11 @@ impl TipPrepApp {
                         PulseMethodType::Linear,
                         "Linear",
                     );
+                    ui.selectable_value(
+                        &mut self.config.pulse_method_type,
+                        PulseMethodType::Adaptive,
+                        "Adaptive",
+                    );
                 });
 
                 ui.add_space(5.0);
@@ -1569,6 +1922,50 @@ impl TipPrepApp {
                                 });
                                 ui.end_row();
                             }
+                            PulseMethodType::Adaptive => {
+                                ui.label("Voltage Range (V):");
+                                ui.horizontal(|ui| {
+                                    ui.add(
+                                        egui::TextEdit::singleline(
+                                            &mut self.config.pulse_voltage_min,
+                                        )
+                                        .desired_width(60.0),
+                                    );
+                                    ui.label("to");
+                                    ui.add(
+                                        egui::TextEdit::singleline(
+                                            &mut self.config.pulse_voltage_max,
+                                        )
+                                        .desired_width(60.0),
+                                    );
+                                });
+                                ui.end_row();
+
+                                ui.label("Voltage Levels:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.config.voltage_levels)
+                                        .desired_width(60.0),
+                                );
+                                ui.end_row();
+
+                                ui.label("Exploration:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.config.exploration)
+                                        .desired_width(60.0),
+                                );
+                                ui.end_row();
+
+                                ui.label("Discount:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.config.discount)
+                                        .desired_width(60.0),
+                                );
+                                ui.end_row();
+
+                                ui.label("Explore Polarity:");
+                                ui.checkbox(&mut self.config.explore_polarity, "Both signs");
+                                ui.end_row();
+                            }
                         }
 
                         ui.label("Polarity:");
@@ -1600,6 +1997,40 @@ impl TipPrepApp {
                             );
                             ui.end_row();
                         }
+
+                        ui.label("Step Pulse Width:");
+                        ui.checkbox(&mut self.config.pulse_width_enabled, "At max voltage");
+                        ui.end_row();
+
+                        if self.config.pulse_width_enabled {
+                            ui.label("Width Range (ms):");
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.config.pulse_width_min)
+                                        .desired_width(60.0),
+                                );
+                                ui.label("to");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.config.pulse_width_max)
+                                        .desired_width(60.0),
+                                );
+                            });
+                            ui.end_row();
+
+                            ui.label("Width Steps:");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.config.pulse_width_steps)
+                                    .desired_width(60.0),
+                            );
+                            ui.end_row();
+
+                            ui.label("Pulses at Max Voltage:");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.config.pulse_width_cycles)
+                                    .desired_width(60.0),
+                            );
+                            ui.end_row();
+                        }
                     });
             });
 
@@ -1807,165 +2238,89 @@ fn run_controller(
     config: AppConfig,
     shutdown: ShutdownFlag,
     event_tx: Sender<Event>,
+    operator: ChannelOperator,
     simulate: bool,
+    task: RunTask,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (controller, freq_shift_index) = if simulate {
-        build_mock_backend(&config)?
+    let mut backend = if simulate {
+        Backend::mock(&config)?
     } else {
-        build_nanonis_backend(&config)?
+        info!("Setting up V2 controller...");
+        Backend::nanonis(&config, |changes| {
+            let list: Vec<String> = changes.iter().map(|c| format!("- {c}")).collect();
+            let question = Question::yes_no(format!(
+                "The controller's signal layout differs from the saved profile:\n{}\n\
+                 Accept the new layout and update the profile?",
+                list.join("\n")
+            ));
+            matches!(operator.ask(&question, &shutdown), Ok(0))
+        })?
+    };
+    // The signal tip prep judges, as configured; it may be virtual.
+    let criterion_index = backend.signals.resolve(&config.tip_prep.signal)?;
+    if task == RunTask::TipPrep {
+        let unit = backend
+            .signals
+            .get_by_name(&config.tip_prep.signal)
+            .and_then(|s| s.unit.as_ref());
+        config.tip_prep.check_criterion_unit(unit)?;
+    }
+    let current_index = backend.signals.resolve("current").ok();
+
+    let (mut routine, label): (Box<dyn Routine + '_>, &str) = match task {
+        RunTask::TipPrep => (
+            Box::new(tip_prep(&config, criterion_index, current_index)),
+            "Tip preparation",
+        ),
+        RunTask::NoiseCheck => (
+            Box::new(NoiseCheck::new(
+                criterion_index,
+                &NoiseCheckConfig::default(),
+            )),
+            "Noise check",
+        ),
     };
 
-    // Setup event bus with ChannelForwarder for GUI
-    let mut events = EventBus::new();
-    events.add_observer(Box::new(ConsoleLogger));
+    // Log and record like the command-line tools, and forward to the GUI
+    let mut events = event_bus(
+        &config,
+        routine.name(),
+        &backend.signals,
+        backend.stream_markers.take(),
+    )?;
     events.add_observer(Box::new(ChannelForwarder::new(event_tx)));
 
-    if config.experiment_logging.enabled {
-        let log_dir = std::path::PathBuf::from(&config.experiment_logging.output_path);
-        std::fs::create_dir_all(&log_dir)?;
-        let filename = format!(
-            "tip_prep_{}.jsonl",
-            chrono::Utc::now().format("%Y%m%d_%H%M%S")
-        );
-        let file = std::fs::File::create(log_dir.join(filename))?;
-        events.add_observer(Box::new(FileLogger::new(file)));
-    }
-
-    events.add_observer(Box::new(EventAccumulator::new(500)));
-
-    // Run tip preparation
-    let result = run_tip_prep(
-        controller,
-        TipPrepParams {
-            events: &events,
-            shutdown: &shutdown,
-            config: &config,
-            freq_shift: freq_shift_index,
-        },
+    // Run the routine; operator questions surface as a modal dialog
+    let result = run_routine_with_operator(
+        backend.controller,
+        &events,
+        &shutdown,
+        &operator,
+        routine.as_mut(),
     );
 
     match &result {
         Ok(Outcome::Completed) => {
-            info!("Tip preparation completed successfully!")
+            info!("{label} completed successfully!")
         }
-        Ok(Outcome::StoppedByUser) => info!("Tip preparation stopped by user"),
+        Ok(Outcome::StoppedByUser) => info!("{label} stopped by user"),
         Ok(Outcome::CycleLimit(n)) => error!("Max cycles ({}) exceeded", n),
         Ok(Outcome::TimedOut(d)) => {
             error!("Max duration ({:.0}s) exceeded", d.as_secs_f64())
         }
-        Err(e) => error!("Tip preparation failed: {}", e),
+        Err(e) => error!("{label} failed: {}", e),
     }
 
     Ok(())
 }
 
-/// Boxed controller plus the resolved freq-shift signal index.
-type Backend = (Box<dyn SpmController>, SignalIndex);
-
-/// Index of `"freq shift"` in [`MockController`]'s fixed channel layout. Checked
-/// against the registry in [`build_mock_backend`] so a change to the mock's
-/// `signal_names` surfaces as an error instead of a silently wrong channel.
-const MOCK_FREQ_SHIFT_INDEX: SignalIndex = SignalIndex(2);
-
-/// Connect to the real Nanonis system and set up its TCP data stream.
-fn build_nanonis_backend(
-    config: &AppConfig,
-) -> Result<Backend, Box<dyn std::error::Error + Send + Sync>> {
-    info!("Setting up V2 controller...");
-
-    let client = rusty_tip::NanonisClient::builder()
-        .address(&config.nanonis.host_ip)
-        .port(config.nanonis.control_ports[0])
-        .build()?;
-    let setup = NanonisSetupConfig {
-        layout_file: config.nanonis.layout_file.clone(),
-        settings_file: config.nanonis.settings_file.clone(),
-        safe_tip_threshold_a: config.tip_prep.safe_tip_threshold,
-        ..Default::default()
-    };
-    let mut controller = NanonisController::new(client, setup);
-    info!("Connected to Nanonis system");
-
-    let registry = build_signal_registry(&mut controller, config)?;
-    let freq_shift_index = registry
-        .get_by_name("freq shift")
-        .ok_or("Frequency shift signal not found in registry")?
-        .signal_index();
-
-    setup_tcp_stream(&mut controller, &registry, config)?;
-
-    Ok((Box::new(controller), freq_shift_index))
-}
-
-/// Drive the routine against the in-memory mock — no hardware, no TCP stream.
-///
-/// The tip model is `models::realistic`, so the frequency shift responds to the
-/// voltages the routine actually chooses and carries noise and drift, rather
-/// than stepping between two constants. Seeded, so a run is reproducible.
-fn build_mock_backend(
-    config: &AppConfig,
-) -> Result<Backend, Box<dyn std::error::Error + Send + Sync>> {
-    info!("Simulation mode: running against the mock controller (no hardware)");
-
-    let mut mock = MockController::builder()
-        .freq_shift_index(MOCK_FREQ_SHIFT_INDEX)
-        .freq_shift(models::realistic(models::RealisticParams::default()))
-        // Within-batch scatter, well under the default 1.0 Hz `max_std_dev` so
-        // ReadStableSignal's statistics do real work without stalling in retries.
-        .sample_noise_hz(0.25)
-        .build();
-
-    // Resolve the index through the same registry path the real backend uses,
-    // so simulation exercises the lookup instead of bypassing it.
-    let registry = build_signal_registry(&mut mock, config)?;
-    let resolved = registry
-        .get_by_name("freq shift")
-        .ok_or("Frequency shift signal not found in mock registry")?
-        .signal_index();
-
-    if resolved != MOCK_FREQ_SHIFT_INDEX {
-        return Err(format!(
-            "mock freq-shift index drifted: registry resolved {resolved}, \
-             mock model answers for {MOCK_FREQ_SHIFT_INDEX}"
-        )
-        .into());
+/// The routine, reading the current during stability sweeps when it is known.
+fn tip_prep(config: &AppConfig, signal: SignalIndex, current: Option<SignalIndex>) -> TipPrep<'_> {
+    let routine = TipPrep::new(config, signal);
+    match current {
+        Some(current) => routine.with_current(current),
+        None => routine,
     }
-
-    Ok((Box::new(mock), resolved))
-}
-
-fn build_signal_registry(
-    controller: &mut dyn SpmController,
-    config: &AppConfig,
-) -> Result<SignalRegistry, SpmError> {
-    let mut builder = SignalRegistry::builder().with_standard_map();
-
-    if let Some(ref mappings) = config.tcp_channel_mapping {
-        let tcp_map: Vec<(u8, u8)> = mappings
-            .iter()
-            .map(|m| (m.nanonis_index, m.tcp_channel))
-            .collect();
-        builder = builder.add_tcp_map(&tcp_map);
-    }
-
-    Ok(builder
-        .from_controller(controller)?
-        .create_aliases()
-        .build())
-}
-
-fn setup_tcp_stream(
-    controller: &mut NanonisController,
-    registry: &SignalRegistry,
-    config: &AppConfig,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let stream = StreamSetup::new(
-        &config.nanonis.host_ip,
-        config.data_acquisition.data_port,
-        config.data_acquisition.sample_rate as i32,
-    );
-    controller.start_streaming(registry, &stream)?;
-    Ok(())
 }
 
 impl eframe::App for TipPrepApp {
@@ -2037,5 +2392,7 @@ impl eframe::App for TipPrepApp {
                 Tab::Configuration => self.render_configuration_tab(ui),
             }
         });
+
+        self.render_operator_question(ctx);
     }
 }
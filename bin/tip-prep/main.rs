@@ -1,17 +1,28 @@
-use chrono::Utc;
-use clap::Parser;
-use env_logger::Env;
-use log::{LevelFilter, error, info};
-use std::{fs, io, path::PathBuf, process::ExitCode};
+use clap::{CommandFactory, Parser, Subcommand, error::ErrorKind};
+use log::{error, info};
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 use rusty_tip::config::{AppConfig, load_config};
-use rusty_tip::event::{ConsoleLogger, EventAccumulator, EventBus, FileLogger};
-use rusty_tip::nanonis_controller::{NanonisController, NanonisSetupConfig, StreamSetup};
-use rusty_tip::shutdown::ShutdownFlag;
-use rusty_tip::signal_registry::SignalRegistry;
-use rusty_tip::spm_controller::SpmController;
-use rusty_tip::spm_error::SpmError;
-use rusty_tip::tip_prep::{Outcome, TipPrepParams, run_tip_prep};
+use rusty_tip::routine::operator::StdinOperator;
+use rusty_tip::routine::run_routine_reported;
+use rusty_tip::session::{Backend, event_bus};
+use rusty_tip::tip_prep::{
+    Distribution, Outcome, SimulationSpec, SimulationSummary, TipPrep, simulate,
+};
+#[cfg(feature = "optimise")]
+use rusty_tip::tip_prep::{OptimiseSpec, ParameterRange};
+
+#[path = "../common/mod.rs"]
+mod common;
+#[cfg(feature = "optimise")]
+mod optimise;
+
+use common::{
+    confirm_signal_layout, init_logging, print_run_report, shutdown_on_ctrl_c, wait_for_start,
+};
 
 /// Rusty Tip Preparation Tool
 #[derive(Parser, Debug)]
@@ -19,12 +30,66 @@ use rusty_tip::tip_prep::{Outcome, TipPrepParams, run_tip_prep};
 #[command(about = "Automated tip preparation for STM/AFM", long_about = None)]
 struct Args {
     /// Path to configuration file (required)
-    #[arg(short, long, value_name = "FILE", required = true)]
-    config: PathBuf,
+    #[arg(short, long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
 
     /// Override log level (trace, debug, info, warn, error)
-    #[arg(short, long, value_name = "LEVEL")]
+    #[arg(short, long, value_name = "LEVEL", global = true)]
     log_level: Option<String>,
+
+    /// Without a command, prepare the tip on the instrument
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the config many times against the simulated tip and report how
+    /// it fares, without touching the instrument
+    Simulate {
+        /// Runs per config
+        #[arg(short, long, default_value_t = 100)]
+        runs: usize,
+
+        /// Seed of the first run; run i uses seed + i
+        #[arg(short, long, default_value_t = 1)]
+        seed: u64,
+
+        /// Another config to run on the same seeds, side by side (repeatable)
+        #[arg(long, value_name = "FILE")]
+        compare: Vec<PathBuf>,
+
+        /// Print the summaries as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Search config values for the shortest simulated time to a sharp tip
+    /// and write the best config out
+    #[cfg(feature = "optimise")]
+    Optimise {
+        /// A field to vary and its range, as PATH=LOW:HIGH, e.g.
+        /// pulse_method.voltage=2:8 or tip_prep.stability.bias_range.1=1:3
+        /// (repeatable)
+        #[arg(long, value_name = "PATH=LOW:HIGH", required = true)]
+        vary: Vec<ParameterRange>,
+
+        /// Candidate configs to try, the starting one included
+        #[arg(long, default_value_t = 40)]
+        candidates: usize,
+
+        /// Simulated runs per candidate
+        #[arg(short, long, default_value_t = 50)]
+        runs: usize,
+
+        /// Seed of the first run and of the search
+        #[arg(short, long, default_value_t = 1)]
+        seed: u64,
+
+        /// Where to write the best config [default: <config>_optimised.toml
+        /// next to the config]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
 }
 
 fn main() -> ExitCode {
@@ -59,18 +124,62 @@ fn run() -> Result<(), RunError> {
     ensure_console_allocated();
 
     let args = Args::parse();
-    let config = load_config(&args.config)?;
-
-    let log_level = args.log_level.unwrap_or(config.console.verbosity.clone());
-    initialize_logging(&log_level)?;
+    let Some(config_path) = args.config else {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required argument was not provided: --config <FILE>",
+            )
+            .exit();
+    };
+    let config = load_config(&config_path)?;
+
+    match args.command {
+        Some(Command::Simulate {
+            runs,
+            seed,
+            compare,
+            json,
+        }) => {
+            // Hundreds of runs: only warnings and errors unless asked for more.
+            init_logging(args.log_level.as_deref().unwrap_or("warn"));
+            return run_simulation(&config_path, config, &compare, runs, seed, json);
+        }
+        #[cfg(feature = "optimise")]
+        Some(Command::Optimise {
+            vary,
+            candidates,
+            runs,
+            seed,
+            output,
+        }) => {
+            init_logging(args.log_level.as_deref().unwrap_or("error"));
+            let spec = OptimiseSpec {
+                simulation: SimulationSpec {
+                    runs,
+                    first_seed: seed,
+                    ..Default::default()
+                },
+                candidates,
+                seed,
+                ..Default::default()
+            };
+            let output = output.unwrap_or_else(|| {
+                config_path.with_file_name(format!("{}_optimised.toml", config_label(&config_path)))
+            });
+            return optimise::run_optimisation(&config_path, &config, &vary, &spec, &output);
+        }
+        None => {}
+    }
 
-    info!("=== Rusty Tip Preparation Tool (v2) ===");
-    info!("Configuration: {}", args.config.display());
-    info!(
-        "Nanonis: {}:{}",
-        config.nanonis.host_ip, config.nanonis.control_ports[0]
+    init_logging(
+        args.log_level
+            .as_deref()
+            .unwrap_or(&config.console.verbosity),
     );
 
+    info!("=== Rusty Tip Preparation Tool (v2) ===");
+    info!("Configuration: {}", config_path.display());
     // Log configuration parameters
     info!(
         "Sharp tip bounds: {:.2} to {:.2}",
@@ -84,6 +193,21 @@ fn run() -> Result<(), RunError> {
         "Check stability: {}",
         config.tip_prep.stability.check_stability
     );
+    let confirmation = &config.tip_prep.confirmation;
+    info!(
+        "Confirmation: {:?}, up to {} sites",
+        confirmation.policy, confirmation.sites
+    );
+    let tip_change = &config.tip_prep.stability.tip_change;
+    if tip_change.enabled {
+        info!(
+            "Tip-change detection: jump {:.2} Hz, current {:.0}%, noise {:.2} Hz over {} samples",
+            tip_change.max_freq_shift_jump_hz,
+            tip_change.max_current_jump_fraction * 100.0,
+            tip_change.max_freq_shift_std_dev_hz,
+            tip_change.window
+        );
+    }
     match config.tip_prep.max_cycles {
         Some(n) => info!("Max cycles: {}", n),
         None => info!("Max cycles: unlimited"),
@@ -93,58 +217,61 @@ fn run() -> Result<(), RunError> {
         None => info!("Max duration: unlimited"),
     }
     log_pulse_method_config(&config.pulse_method);
+    let conditioning = &config.tip_prep.conditioning;
+    if conditioning.mode != rusty_tip::config::ConditioningMode::Pulse {
+        info!(
+            "Conditioning: {:?} (indent {:.2} nm to {:.2} nm at {:.2}V)",
+            conditioning.mode,
+            conditioning.depth_bounds_m.0 * 1e9,
+            conditioning.depth_bounds_m.1 * 1e9,
+            conditioning.bias_v
+        );
+    }
 
-    // Connect to hardware
-    let client = rusty_tip::NanonisClient::builder()
-        .address(&config.nanonis.host_ip)
-        .port(config.nanonis.control_ports[0])
-        .build()?;
-    let setup = NanonisSetupConfig {
-        layout_file: config.nanonis.layout_file.clone(),
-        settings_file: config.nanonis.settings_file.clone(),
-        safe_tip_threshold_a: config.tip_prep.safe_tip_threshold,
-        ..Default::default()
-    };
-    let mut controller = NanonisController::new(client, setup);
-    info!("Connected to Nanonis system");
-
-    // Build signal registry
-    let registry = build_signal_registry(&mut controller, &config)?;
-    let freq_shift_signal = registry
-        .get_by_name("freq shift")
-        .ok_or("Frequency shift signal not found in registry")?;
+    // Connect to hardware, check the signal layout and start the TCP stream
+    let mut backend = Backend::nanonis(&config, confirm_signal_layout)?;
+    let criterion_name = &config.tip_prep.signal;
+    let criterion_signal = backend
+        .signals
+        .get_by_name(criterion_name)
+        .ok_or_else(|| format!("Signal \"{criterion_name}\" not found in registry"))?;
     info!(
-        "Frequency shift signal: index {}{}",
-        freq_shift_signal.index,
-        freq_shift_signal
+        "Tip-prep signal: {} (index {}{})",
+        criterion_signal.name,
+        criterion_signal.index,
+        criterion_signal
             .tcp_channel
             .map(|ch| format!(", TCP channel {}", ch))
             .unwrap_or_default()
     );
-    let freq_shift_index = freq_shift_signal.signal_index();
-
-    // Setup TCP data stream for stable signal reading
-    setup_tcp_stream(&mut controller, &registry, &config)?;
-
-    // Setup event bus
-    let events = setup_event_bus(&config)?;
-
-    // Setup shutdown handler
-    let shutdown = setup_shutdown_handler();
-
-    // Wait for user confirmation
-    wait_for_user_confirmation()?;
-
-    // Run tip preparation using library function
-    let result = run_tip_prep(
-        Box::new(controller),
-        TipPrepParams {
-            events: &events,
-            shutdown: &shutdown,
-            config: &config,
-            freq_shift: freq_shift_index,
+    config
+        .tip_prep
+        .check_criterion_unit(criterion_signal.unit.as_ref())?;
+    let criterion_index = criterion_signal.signal_index();
+    let current_index = backend.signals.resolve("current").ok();
+
+    let events = event_bus(
+        &config,
+        "tip_prep",
+        &backend.signals,
+        backend.stream_markers.take(),
+    )?;
+    let shutdown = shutdown_on_ctrl_c()?;
+
+    wait_for_start("tip preparation")?;
+
+    // Run tip preparation; any operator questions are asked on the terminal
+    let (result, report) = run_routine_reported(
+        backend.controller,
+        &events,
+        &shutdown,
+        &StdinOperator::new(),
+        &mut match current_index {
+            Some(current) => TipPrep::new(&config, criterion_index).with_current(current),
+            None => TipPrep::new(&config, criterion_index),
         },
     );
+    print_run_report(&report);
 
     match result {
         Ok(Outcome::Completed) => {
@@ -171,29 +298,100 @@ fn run() -> Result<(), RunError> {
 }
 
 // ============================================================================
-// Setup helpers
+// Simulation
 // ============================================================================
 
-fn setup_tcp_stream(
-    controller: &mut NanonisController,
-    registry: &SignalRegistry,
-    config: &AppConfig,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let stream = StreamSetup::new(
-        &config.nanonis.host_ip,
-        config.data_acquisition.data_port,
-        config.data_acquisition.sample_rate as i32,
-    );
-    controller.start_streaming(registry, &stream)?;
+fn run_simulation(
+    config_path: &Path,
+    config: AppConfig,
+    compare: &[PathBuf],
+    runs: usize,
+    seed: u64,
+    json: bool,
+) -> Result<(), RunError> {
+    let mut configs = vec![(config_label(config_path), config)];
+    for path in compare {
+        configs.push((config_label(path), load_config(path)?));
+    }
+
+    let spec = SimulationSpec {
+        runs,
+        first_seed: seed,
+        ..Default::default()
+    };
+    let summaries: Vec<SimulationSummary> = configs
+        .iter()
+        .map(|(label, config)| SimulationSummary::of(label, &simulate(config, &spec)))
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+    } else {
+        print_simulation_table(&summaries, &spec);
+    }
     Ok(())
 }
 
+fn config_label(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn print_simulation_table(summaries: &[SimulationSummary], spec: &SimulationSpec) {
+    println!(
+        "{} runs per config, seeds {}..={}; ranges are median (p10-p90) over sharp runs",
+        spec.runs,
+        spec.first_seed,
+        spec.first_seed + spec.runs.saturating_sub(1) as u64
+    );
+    println!();
+    println!(
+        "{:<24} {:>7} {:>20} {:>24} {:>14} {:>18} {:>11}",
+        "config", "sharp", "cycles", "time (s)", "expected (s)", "pulses (all runs)", "stab. fail"
+    );
+    for s in summaries {
+        let stability = match s.stability_failure_rate {
+            Some(rate) => format!("{:.0}%", rate * 100.0),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<24} {:>6.0}% {:>20} {:>24} {:>14} {:>18} {:>11}",
+            s.label,
+            s.success_rate * 100.0,
+            format_spread(&s.cycles_to_sharp),
+            format_spread(&s.time_to_sharp_secs),
+            s.expected_time_to_sharp_secs
+                .map(|t| format!("{t:.0}"))
+                .unwrap_or_else(|| "-".to_string()),
+            format_spread(&s.pulses),
+            stability
+        );
+        if s.errors > 0 {
+            println!("{:<24} {} runs ended with an error", "", s.errors);
+        }
+    }
+}
+
+fn format_spread(d: &Option<Distribution>) -> String {
+    match d {
+        Some(d) => format!("{:.0} ({:.0}-{:.0})", d.median, d.p10, d.p90),
+        None => "-".to_string(),
+    }
+}
+
+// ============================================================================
+// Setup helpers
+// ============================================================================
+
 fn log_pulse_method_config(method: &rusty_tip::PulseMethod) {
     match method {
         rusty_tip::PulseMethod::Fixed {
             voltage,
             polarity,
             random_polarity_switch,
+            ..
         } => {
             info!("Pulse method: Fixed ({:.2}V, {:?})", voltage, polarity);
             log_random_switch(random_polarity_switch);
@@ -218,6 +416,7 @@ fn log_pulse_method_config(method: &rusty_tip::PulseMethod) {
             linear_clamp,
             polarity,
             random_polarity_switch,
+            ..
         } => {
             info!(
                 "Pulse method: Linear (voltage: {:.2}V to {:.2}V, freq_shift range: {:.2} to {:.2} Hz, {:?})",
@@ -225,6 +424,33 @@ fn log_pulse_method_config(method: &rusty_tip::PulseMethod) {
             );
             log_random_switch(random_polarity_switch);
         }
+        rusty_tip::PulseMethod::Adaptive {
+            voltage_bounds,
+            voltage_levels,
+            explore_polarity,
+            polarity,
+            random_polarity_switch,
+            ..
+        } => {
+            info!(
+                "Pulse method: Adaptive ({:.2}V to {:.2}V, {} levels, {:?}{})",
+                voltage_bounds.0,
+                voltage_bounds.1,
+                voltage_levels,
+                polarity,
+                if *explore_polarity { " + opposite" } else { "" }
+            );
+            log_random_switch(random_polarity_switch);
+        }
+    }
+    if let Some(width) = method.pulse_width() {
+        info!(
+            "Pulse width: {} ms to {} ms, {} steps, stepping after {} pulses at max voltage",
+            width.width_bounds_ms.0,
+            width.width_bounds_ms.1,
+            width.width_steps,
+            width.cycles_at_max_voltage
+        );
     }
 }
 
@@ -242,91 +468,6 @@ fn log_random_switch(switch: &Option<rusty_tip::RandomPolaritySwitch>) {
     }
 }
 
-fn build_signal_registry(
-    controller: &mut dyn SpmController,
-    config: &AppConfig,
-) -> Result<SignalRegistry, SpmError> {
-    let mut builder = SignalRegistry::builder().with_standard_map();
-
-    if let Some(ref mappings) = config.tcp_channel_mapping {
-        let tcp_map: Vec<(u8, u8)> = mappings
-            .iter()
-            .map(|m| (m.nanonis_index, m.tcp_channel))
-            .collect();
-        builder = builder.add_tcp_map(&tcp_map);
-    }
-
-    Ok(builder
-        .from_controller(controller)?
-        .create_aliases()
-        .build())
-}
-
-fn setup_event_bus(config: &AppConfig) -> Result<EventBus, Box<dyn std::error::Error>> {
-    let mut events = EventBus::new();
-    events.add_observer(Box::new(ConsoleLogger));
-
-    if config.experiment_logging.enabled {
-        let log_path = create_log_file_path(&config.experiment_logging.output_path)?;
-        info!("Event log: {}", log_path.display());
-        let file = fs::File::create(&log_path)?;
-        events.add_observer(Box::new(FileLogger::new(file)));
-    }
-
-    events.add_observer(Box::new(EventAccumulator::new(500)));
-
-    Ok(events)
-}
-
-fn setup_shutdown_handler() -> ShutdownFlag {
-    let shutdown = ShutdownFlag::new();
-    let flag = shutdown.clone();
-
-    ctrlc::set_handler(move || {
-        info!("Ctrl+C received - initiating graceful shutdown...");
-        flag.request();
-    })
-    .expect("Error setting Ctrl+C handler");
-
-    shutdown
-}
-
-fn wait_for_user_confirmation() -> Result<(), Box<dyn std::error::Error>> {
-    println!();
-    println!("Press Enter to start tip preparation (or Ctrl+C to cancel)...");
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    Ok(())
-}
-
-fn initialize_logging(log_level: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let level = match log_level.to_lowercase().as_str() {
-        "trace" => LevelFilter::Trace,
-        "debug" => LevelFilter::Debug,
-        "info" => LevelFilter::Info,
-        "warn" => LevelFilter::Warn,
-        "error" => LevelFilter::Error,
-        _ => {
-            eprintln!("Warning: Invalid log level '{}', using 'info'", log_level);
-            LevelFilter::Info
-        }
-    };
-
-    env_logger::Builder::from_env(Env::default())
-        .filter_level(level)
-        .format_timestamp_millis()
-        .init();
-
-    Ok(())
-}
-
-fn create_log_file_path(log_path: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let dir = PathBuf::from(log_path);
-    fs::create_dir_all(&dir)?;
-    let filename = format!("tip_prep_{}.jsonl", Utc::now().format("%Y%m%d_%H%M%S"));
-    Ok(dir.join(filename))
-}
-
 #[cfg(windows)]
 fn ensure_console_allocated() {
     unsafe {
@@ -0,0 +1,116 @@
+//! `tip-prep optimise`: search config values in simulation and write the
+//! best config out as TOML.
+
+use std::fs;
+use std::path::Path;
+
+use log::error;
+use rusty_tip::config::AppConfig;
+use rusty_tip::tip_prep::{Candidate, Optimisation, OptimiseSpec, ParameterRange, optimise};
+
+use super::{RunError, format_spread};
+
+pub(super) fn run_optimisation(
+    config_path: &Path,
+    config: &AppConfig,
+    ranges: &[ParameterRange],
+    spec: &OptimiseSpec,
+    output: &Path,
+) -> Result<(), RunError> {
+    println!(
+        "Trying {} candidates, {} simulated runs each",
+        spec.candidates, spec.simulation.runs
+    );
+    let mut best = f64::INFINITY;
+    let result = optimise(config, ranges, spec, |candidate| {
+        let score = candidate.score();
+        let marker = if score < best { "*" } else { " " };
+        best = best.min(score);
+        println!(
+            "{marker} {:>3}  {}  {}",
+            candidate.index,
+            format_values(ranges, &candidate.values),
+            match (&candidate.rejected, score.is_finite()) {
+                (Some(reason), _) => format!("rejected: {reason}"),
+                (None, true) => format!("{score:.0} s expected to sharp"),
+                (None, false) => "never sharp".to_string(),
+            }
+        );
+    })?;
+
+    let best = result.best();
+    let Some(best_config) = &best.config else {
+        return Err("no candidate passed validation".into());
+    };
+    if !best.score().is_finite() {
+        error!("No candidate got a tip sharp in simulation; nothing written");
+        return Err(RunError::Incomplete);
+    }
+    let header = optimisation_header(config_path, &result, spec);
+    fs::write(
+        output,
+        format!("{header}\n{}", toml::to_string_pretty(best_config)?),
+    )?;
+
+    println!();
+    println!("{header}");
+    println!("Best config written to {}", output.display());
+    Ok(())
+}
+
+/// The statistics of the best candidate, as TOML comments.
+fn optimisation_header(config_path: &Path, result: &Optimisation, spec: &OptimiseSpec) -> String {
+    let best = result.best();
+    let mut lines = vec![
+        format!(
+            "Optimised from {} over {} candidates, {} simulated runs each (seeds {}..={}).",
+            config_path.display(),
+            result.candidates.len(),
+            spec.simulation.runs,
+            spec.simulation.first_seed,
+            spec.simulation.first_seed + spec.simulation.runs.saturating_sub(1) as u64
+        ),
+        format!(
+            "Expected time to sharp: {} (starting config: {})",
+            format_secs(best),
+            format_secs(result.baseline())
+        ),
+    ];
+    if let Some(summary) = &best.summary {
+        lines.push(format!(
+            "Sharp in {:.0}% of runs; cycles {}, time {} s, pulses {} (median (p10-p90))",
+            summary.success_rate * 100.0,
+            format_spread(&summary.cycles_to_sharp),
+            format_spread(&summary.time_to_sharp_secs),
+            format_spread(&summary.pulses)
+        ));
+        if let Some(rate) = summary.stability_failure_rate {
+            lines.push(format!("Stability checks failed: {:.0}%", rate * 100.0));
+        }
+    }
+    for (range, value) in result.ranges.iter().zip(&best.values) {
+        lines.push(format!(
+            "{} = {value} (searched {} to {})",
+            range.path, range.low, range.high
+        ));
+    }
+    lines.iter().map(|line| format!("# {line}\n")).collect()
+}
+
+fn format_secs(candidate: &Candidate) -> String {
+    let score = candidate.score();
+    if score.is_finite() {
+        format!("{score:.0} s")
+    } else {
+        "never sharp".to_string()
+    }
+}
+
+fn format_values(ranges: &[ParameterRange], values: &[f64]) -> String {
+    ranges
+        .iter()
+        .zip(values)
+        .map(|(range, value)| format!("{}={value:.3}", range.path))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
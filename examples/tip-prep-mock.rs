@@ -144,9 +144,10 @@ fn main() {
     // would show.
     if !obs.freq_values.is_empty() {
         println!();
-        let _ = rusty_tip::plotting::plot_values(
+        let _ = rusty_tip::plotting::plot_values_with_unit(
             &obs.freq_values,
-            Some("freq shift per read (Hz)"),
+            "Hz",
+            Some("freq shift per read"),
             Some(100),
             Some(30),
         );
@@ -203,6 +204,7 @@ fn build_scenario(name: &str) -> Option<Scenario> {
                 threshold_value: 0.1,
                 polarity: PolaritySign::Positive,
                 random_polarity_switch: None,
+                pulse_width: None,
             };
             Scenario {
                 description: "Tip reads sharp, but the post-sweep measurement has drifted past the \
@@ -231,6 +233,7 @@ fn build_scenario(name: &str) -> Option<Scenario> {
                 linear_clamp: (-20.0, 0.0),
                 polarity: PolaritySign::Positive,
                 random_polarity_switch: None,
+                pulse_width: None,
             };
             Scenario {
                 description: "Stochastic tip matched to real conditioning data: each pulse re-rolls \
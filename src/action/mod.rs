@@ -17,6 +17,8 @@ pub use context::ActionContext;
 pub use output::ActionOutput;
 pub use store::DataStore;
 
+use serde::Serialize;
+
 use crate::spm_controller::{Capability, SpmController};
 use crate::spm_error::SpmError;
 
@@ -37,7 +39,10 @@ type Result<T> = std::result::Result<T, SpmError>;
 /// authoring API: routines reach them through the subsystem handles
 /// [`Rt`](crate::routine::Rt) hands out, which is where capability checks and
 /// event emission happen.
-pub trait Action: Send + Sync {
+///
+/// The [`ActionParams`] supertrait comes for free with `Serialize`, so an
+/// action's fields are what its `ActionStarted` event records.
+pub trait Action: ActionParams + Send + Sync {
     /// Unique identifier, e.g. "read_signal", "bias_pulse"
     fn name(&self) -> &str;
 
@@ -55,6 +60,23 @@ pub trait Action: Send + Sync {
     fn execute(&self, ctx: &mut ActionContext) -> Result<ActionOutput>;
 }
 
+/// The parameters an action runs with, as JSON.
+///
+/// Implemented for every `Serialize` type: the action struct *is* its
+/// parameter set. Actions without fields report an empty object.
+pub trait ActionParams {
+    fn params(&self) -> serde_json::Value;
+}
+
+impl<T: Serialize> ActionParams for T {
+    fn params(&self) -> serde_json::Value {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Null) | Err(_) => serde_json::json!({}),
+            Ok(value) => value,
+        }
+    }
+}
+
 /// Verify the controller supports every capability `action` requires.
 ///
 /// Every execution path calls this before running an action, so a
@@ -3,6 +3,7 @@ use std::time::Duration;
 use serde::{Deserialize, Serialize};
 
 use crate::action::{Action, ActionContext, ActionOutput};
+use crate::analyzer::noise_spectrum::{NoiseSpectrumSpec, analyze_noise};
 use crate::event::Event;
 use crate::signal_registry::SignalIndex;
 use crate::spm_controller::Capability;
@@ -200,7 +201,7 @@ impl Action for ReadStableSignal {
                 );
                 if attempt < self.max_retries {
                     let backoff_ms = 100u64 * (1 << attempt);
-                    std::thread::sleep(Duration::from_millis(backoff_ms));
+                    ctx.controller.pause(Duration::from_millis(backoff_ms));
                     continue;
                 }
                 // Fall through to compute on partial data as last resort
@@ -249,7 +250,7 @@ impl Action for ReadStableSignal {
                     attempt + 1,
                     backoff_ms
                 );
-                std::thread::sleep(Duration::from_millis(backoff_ms));
+                ctx.controller.pause(Duration::from_millis(backoff_ms));
             } else {
                 log::warn!(
                     "ReadStableSignal: signal not stable after {} retries (std_dev={:.4}/{:.4} Hz, drift={:.4}/{:.4} Hz/s, n={}), using mean={:.6}",
@@ -279,8 +280,241 @@ impl Action for ReadStableSignal {
     }
 }
 
+/// The noise and drift gates one signal of a [`ReadStableSignals`] batch
+/// must pass, in that signal's units.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalGate {
+    pub index: SignalIndex,
+    /// Noise gate: maximum standard deviation of the batch.
+    #[serde(default = "default_max_std_dev")]
+    pub max_std_dev: f64,
+    /// Drift gate: maximum regression slope, per second.
+    #[serde(default = "default_max_slope")]
+    pub max_slope: f64,
+}
+
+/// Read several stable signals from the same stream samples.
+///
+/// Like [`ReadStableSignal`], but the batches of all signals are collected
+/// together (see
+/// [`read_signals_samples`](crate::spm_controller::SpmController::read_signals_samples)),
+/// so current and frequency shift, say, describe the same moment. Each
+/// signal is judged by its own [`SignalGate`]; the batch is retried with
+/// exponential backoff until every signal passes. Returns the means as
+/// `signal_<index>` values, in the order of `signals`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadStableSignals {
+    pub signals: Vec<SignalGate>,
+    #[serde(default = "default_num_samples")]
+    pub num_samples: usize,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: usize,
+    #[serde(default = "default_sample_rate_hz")]
+    pub sample_rate_hz: f64,
+}
+
+impl Default for ReadStableSignals {
+    fn default() -> Self {
+        Self {
+            signals: vec![],
+            num_samples: default_num_samples(),
+            max_retries: default_max_retries(),
+            sample_rate_hz: default_sample_rate_hz(),
+        }
+    }
+}
+
+impl Action for ReadStableSignals {
+    fn name(&self) -> &str {
+        "read_stable_signals"
+    }
+    fn description(&self) -> &str {
+        "Read several stable signals from the same samples, each with its own gates"
+    }
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Signals]
+    }
+
+    fn execute(&self, ctx: &mut ActionContext) -> super::Result<ActionOutput> {
+        let indices: Vec<SignalIndex> = self.signals.iter().map(|g| g.index).collect();
+        for attempt in 0..=self.max_retries {
+            let batches = ctx
+                .controller
+                .read_signals_samples(&indices, self.num_samples)?;
+            if batches.len() != indices.len() {
+                return Err(crate::spm_error::SpmError::Protocol(format!(
+                    "read_signals_samples: requested {} signals but got {} batches",
+                    indices.len(),
+                    batches.len(),
+                )));
+            }
+
+            // Too few samples of any signal counts as unstable, as in
+            // ReadStableSignal.
+            let complete = batches.iter().all(|b| b.len() >= self.num_samples / 2);
+            let judged: Vec<BatchStats> = self
+                .signals
+                .iter()
+                .zip(&batches)
+                .map(|(gate, samples)| {
+                    let (mean, std_dev, slope_per_sample) = compute_stability_metrics(samples);
+                    let drift = slope_per_sample * self.sample_rate_hz;
+                    BatchStats {
+                        n: samples.len(),
+                        mean,
+                        std_dev,
+                        drift,
+                        slope_per_sample,
+                        stable: complete
+                            && std_dev <= gate.max_std_dev
+                            && drift.abs() <= gate.max_slope,
+                    }
+                })
+                .collect();
+            let all_stable = judged.iter().all(|j| j.stable);
+
+            if all_stable || attempt == self.max_retries {
+                if !all_stable {
+                    log::warn!(
+                        "ReadStableSignals: signals {:?} not stable after {} retries, using the means",
+                        self.signals
+                            .iter()
+                            .zip(&judged)
+                            .filter(|(_, j)| !j.stable)
+                            .map(|(g, _)| g.index.0)
+                            .collect::<Vec<_>>(),
+                        self.max_retries,
+                    );
+                }
+                for (gate, j) in self.signals.iter().zip(&judged) {
+                    emit_measurement(
+                        ctx,
+                        gate.index,
+                        j.n,
+                        j.mean,
+                        j.std_dev,
+                        j.drift,
+                        j.slope_per_sample,
+                        j.stable,
+                    );
+                }
+                return Ok(ActionOutput::Values(
+                    self.signals
+                        .iter()
+                        .zip(&judged)
+                        .map(|(gate, j)| (format!("signal_{}", gate.index), j.mean))
+                        .collect(),
+                ));
+            }
+
+            let backoff_ms = 100u64 * (1 << attempt);
+            log::debug!(
+                "ReadStableSignals: not all stable (attempt {}), retry in {}ms",
+                attempt,
+                backoff_ms
+            );
+            ctx.controller.pause(Duration::from_millis(backoff_ms));
+        }
+
+        unreachable!()
+    }
+}
+
+/// Record a long run of stream samples and analyze their noise spectrum
+/// (see [`analyzer::noise_spectrum`](crate::analyzer::noise_spectrum)).
+///
+/// Returns the full [`NoiseReport`](crate::analyzer::noise_spectrum::NoiseReport)
+/// as data and publishes it, without the
+/// spectrum itself, as a `noise_spectrum` event. Needs the data stream: the
+/// polling fallback of `read_signal_samples` does not sample at a fixed
+/// rate, so its spectrum would be meaningless, and the action fails with
+/// [`SpmError::Unsupported`](crate::spm_error::SpmError::Unsupported)
+/// without one. The spectrum is computed at the stream's own frame rate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalyzeNoise {
+    pub index: SignalIndex,
+    #[serde(default = "default_noise_samples")]
+    pub num_samples: usize,
+    #[serde(default)]
+    pub spectrum: NoiseSpectrumSpec,
+}
+
+fn default_noise_samples() -> usize {
+    16384
+}
+
+impl Default for AnalyzeNoise {
+    fn default() -> Self {
+        Self {
+            index: SignalIndex(0),
+            num_samples: default_noise_samples(),
+            spectrum: NoiseSpectrumSpec::default(),
+        }
+    }
+}
+
+impl Action for AnalyzeNoise {
+    fn name(&self) -> &str {
+        "analyze_noise"
+    }
+    fn description(&self) -> &str {
+        "Power spectral density of a streamed signal and its dominant noise peaks"
+    }
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Signals]
+    }
+
+    fn execute(&self, ctx: &mut ActionContext) -> super::Result<ActionOutput> {
+        let Some(sample_rate_hz) = ctx.controller.stream_sample_rate_hz() else {
+            return Err(crate::spm_error::SpmError::Unsupported(
+                "analyze_noise needs the data stream; polled samples have no fixed rate".into(),
+            ));
+        };
+
+        let samples = ctx
+            .controller
+            .read_signal_samples(self.index, self.num_samples)?;
+        let report = analyze_noise(&samples, sample_rate_hz, &self.spectrum)?;
+        log::debug!(
+            "AnalyzeNoise: index={}, samples={}, rms={:.4}, {} peaks ({:.0}% of the power)",
+            self.index,
+            report.samples,
+            report.rms,
+            report.peaks.len(),
+            report.peak_power_fraction * 100.0
+        );
+        ctx.events.emit(Event::data_collected(
+            "noise_spectrum",
+            serde_json::json!({
+                "index": self.index,
+                "samples": report.samples,
+                "sample_rate_hz": report.sample_rate_hz,
+                "resolution_hz": report.spectrum.resolution_hz,
+                "rms": report.rms,
+                "peak_power_fraction": report.peak_power_fraction,
+                "peaks": report.peaks,
+            }),
+        ));
+        let data = serde_json::to_value(&report)
+            .map_err(|e| crate::spm_error::SpmError::Protocol(e.to_string()))?;
+        Ok(ActionOutput::Data(data))
+    }
+}
+
+/// Statistics of one signal's batch in a [`ReadStableSignals`] attempt.
+struct BatchStats {
+    n: usize,
+    mean: f64,
+    std_dev: f64,
+    /// Per second.
+    drift: f64,
+    slope_per_sample: f64,
+    stable: bool,
+}
+
 /// Publish the outcome of a [`ReadStableSignal`] as one measurement: the value
-/// plus the batch statistics it was derived from.
+/// plus the batch statistics it was derived from. [`ReadStableSignals`]
+/// publishes one per signal.
 ///
 /// One event per read, deliberately — a stable read *is* a single measurement of
 /// the signal, and the raw sample batch is an implementation detail of how it was
@@ -22,8 +22,9 @@ impl Action for Wait {
     fn description(&self) -> &str {
         "Wait for a specified duration in milliseconds"
     }
-    fn execute(&self, _ctx: &mut ActionContext) -> super::Result<ActionOutput> {
-        std::thread::sleep(Duration::from_millis(self.duration_ms));
+    fn execute(&self, ctx: &mut ActionContext) -> super::Result<ActionOutput> {
+        ctx.controller
+            .pause(Duration::from_millis(self.duration_ms));
         Ok(ActionOutput::Unit)
     }
 }
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use serde::Serialize;
+
 use crate::action::scan::DEFAULT_SCAN_FRAME_KEY;
 use crate::action::{Action, ActionContext, ActionOutput};
 use crate::spm_error::SpmError;
@@ -20,7 +22,12 @@ use super::{Analyzer, AnalyzerInput};
 /// ```
 ///
 /// **Writes** `"<analyzer_name>"` key with the analyzer's output JSON.
+///
+/// Its `ActionStarted` parameters are the fields below; the analyzer itself
+/// is named by the action name.
+#[derive(Serialize)]
 pub struct RunAnalyzer {
+    #[serde(skip)]
     analyzer: Arc<dyn Analyzer>,
     /// Optional calibration override (metres per pixel).
     /// If `None`, the adapter does not provide calibration to the analyzer.
@@ -1,8 +1,12 @@
 mod adapter;
 pub mod cuox_rows;
+pub mod noise_spectrum;
+pub mod tip_quality;
 
 pub use adapter::RunAnalyzer;
 pub use cuox_rows::CuoxRowDetector;
+pub use noise_spectrum::{NoisePeak, NoiseReport, NoiseSpectrumSpec, analyze_noise};
+pub use tip_quality::{TipQuality, TipQualityAnalyzer};
 
 use crate::spm_error::SpmError;
 
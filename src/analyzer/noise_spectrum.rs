@@ -0,0 +1,421 @@
+//! Power spectral density of a streamed signal and its dominant noise peaks.
+//!
+//! When stable reads keep failing their `max_std_dev` gate, the spectrum
+//! tells a tip problem from the instrument's surroundings: mains pickup and
+//! vibrations show up as narrow peaks (50/60 Hz and harmonics, a pump at a
+//! fixed frequency), while an unstable tip raises the broadband floor.
+//!
+//! The PSD is estimated with Welch's method: Hann-windowed, mean-removed
+//! segments overlapping by half, their periodograms averaged. It is
+//! one-sided and in signal units²/Hz, so integrating it over frequency gives
+//! the signal's variance.
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::spm_error::SpmError;
+
+type Result<T> = std::result::Result<T, SpmError>;
+
+/// Shortest segment worth estimating a spectrum from.
+const MIN_SEGMENT_LEN: usize = 16;
+
+/// Bins on each side of a bin whose median is its background level.
+const BACKGROUND_HALF_WIDTH: usize = 16;
+
+/// Bins on each side of a peak counted into its power; covers the main lobe
+/// of the Hann window.
+const PEAK_HALF_WIDTH: usize = 2;
+
+/// Mains frequencies a peak is checked against.
+const MAINS_HZ: [f64; 2] = [50.0, 60.0];
+
+/// How the spectrum is estimated and which peaks are reported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseSpectrumSpec {
+    /// Welch segment length in samples, rounded down to a power of two.
+    /// Longer segments resolve finer frequencies but average fewer segments.
+    pub segment_len: usize,
+    /// Report at most this many peaks, strongest first.
+    pub max_peaks: usize,
+    /// A peak must rise this many times above the local background PSD.
+    pub min_prominence: f64,
+}
+
+impl Default for NoiseSpectrumSpec {
+    fn default() -> Self {
+        Self {
+            segment_len: 2048,
+            max_peaks: 5,
+            min_prominence: 10.0,
+        }
+    }
+}
+
+/// A one-sided power spectral density.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spectrum {
+    /// Bin frequencies, 0 Hz to Nyquist.
+    pub frequencies_hz: Vec<f64>,
+    /// Power spectral density per bin, in signal units²/Hz.
+    pub psd: Vec<f64>,
+    /// Bin spacing (Hz).
+    pub resolution_hz: f64,
+    /// Segments averaged.
+    pub segments: usize,
+}
+
+/// A mains frequency a peak sits on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MainsHarmonic {
+    pub mains_hz: f64,
+    /// 1 for the fundamental.
+    pub harmonic: u32,
+}
+
+/// A narrow spectral peak.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoisePeak {
+    pub frequency_hz: f64,
+    /// PSD at the peak bin (units²/Hz).
+    pub psd: f64,
+    /// Peak PSD over the local background.
+    pub prominence: f64,
+    /// RMS amplitude the peak adds above the background, in signal units.
+    pub rms: f64,
+    /// Set when the peak lies on a multiple of 50 or 60 Hz.
+    pub mains: Option<MainsHarmonic>,
+}
+
+/// What [`analyze_noise`] found in a batch of samples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseReport {
+    pub sample_rate_hz: f64,
+    pub samples: usize,
+    /// Total RMS noise (units) from the spectrum, DC excluded; comparable
+    /// to the standard deviation a stable read judges.
+    pub rms: f64,
+    /// Share of the noise power in the reported peaks, 0..1. Near 1 the
+    /// noise is pickup or vibration; near 0 it is broadband.
+    pub peak_power_fraction: f64,
+    /// Strongest peaks first.
+    pub peaks: Vec<NoisePeak>,
+    pub spectrum: Spectrum,
+}
+
+impl NoiseReport {
+    /// A few lines for the operator: the totals, a verdict, and one line
+    /// per peak.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "rms {:.4} over {} samples at {} Hz ({:.2} Hz resolution)",
+            self.rms, self.samples, self.sample_rate_hz, self.spectrum.resolution_hz
+        )];
+        lines.push(if self.peaks.is_empty() {
+            "no narrow peaks: the noise is broadband".to_string()
+        } else if self.peak_power_fraction >= 0.5 {
+            format!(
+                "{:.0}% of the noise power is in {} narrow peak(s): pickup or vibration",
+                self.peak_power_fraction * 100.0,
+                self.peaks.len()
+            )
+        } else {
+            format!(
+                "{:.0}% of the noise power is in {} narrow peak(s); the rest is broadband",
+                self.peak_power_fraction * 100.0,
+                self.peaks.len()
+            )
+        });
+        for peak in &self.peaks {
+            let mains = match peak.mains {
+                Some(MainsHarmonic {
+                    mains_hz,
+                    harmonic: 1,
+                }) => format!(", {mains_hz} Hz mains"),
+                Some(MainsHarmonic { mains_hz, harmonic }) => {
+                    format!(", harmonic {harmonic} of {mains_hz} Hz mains")
+                }
+                None => String::new(),
+            };
+            lines.push(format!(
+                "  {:>8.2} Hz  rms {:.4}  {:.0}x background{mains}",
+                peak.frequency_hz, peak.rms, peak.prominence
+            ));
+        }
+        lines
+    }
+}
+
+/// Welch estimate of the PSD of `samples` taken at `sample_rate_hz`.
+///
+/// Fails if there are fewer samples than the shortest usable segment.
+pub fn welch_psd(samples: &[f64], sample_rate_hz: f64, segment_len: usize) -> Result<Spectrum> {
+    if !sample_rate_hz.is_finite() || sample_rate_hz <= 0.0 {
+        return Err(SpmError::Workflow(format!(
+            "noise spectrum: sample rate must be positive, got {sample_rate_hz}"
+        )));
+    }
+    let len = power_of_two_below(segment_len.min(samples.len()));
+    if len < MIN_SEGMENT_LEN {
+        return Err(SpmError::Workflow(format!(
+            "noise spectrum: need at least {MIN_SEGMENT_LEN} samples and segment length, \
+             got {} samples, segment {segment_len}",
+            samples.len()
+        )));
+    }
+
+    let window: Vec<f64> = (0..len)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / len as f64).cos())
+        .collect();
+    let window_power: f64 = window.iter().map(|w| w * w).sum();
+
+    let bins = len / 2 + 1;
+    let mut power = vec![0.0; bins];
+    let mut segments = 0;
+    let mut re = vec![0.0; len];
+    let mut im = vec![0.0; len];
+    for start in (0..=samples.len() - len).step_by(len / 2) {
+        let segment = &samples[start..start + len];
+        let mean = segment.iter().sum::<f64>() / len as f64;
+        for (i, (&x, &w)) in segment.iter().zip(&window).enumerate() {
+            re[i] = (x - mean) * w;
+            im[i] = 0.0;
+        }
+        fft(&mut re, &mut im);
+        for (k, p) in power.iter_mut().enumerate() {
+            *p += re[k] * re[k] + im[k] * im[k];
+        }
+        segments += 1;
+    }
+
+    let scale = 1.0 / (segments as f64 * sample_rate_hz * window_power);
+    let psd = power
+        .iter()
+        .enumerate()
+        .map(|(k, p)| {
+            // One-sided: fold the negative frequencies onto the positive
+            // ones, except at DC and Nyquist which have no mirror.
+            let fold = if k == 0 || k == len / 2 { 1.0 } else { 2.0 };
+            p * scale * fold
+        })
+        .collect();
+    let resolution_hz = sample_rate_hz / len as f64;
+    Ok(Spectrum {
+        frequencies_hz: (0..bins).map(|k| k as f64 * resolution_hz).collect(),
+        psd,
+        resolution_hz,
+        segments,
+    })
+}
+
+/// Estimate the spectrum of `samples` and pick out its dominant peaks.
+pub fn analyze_noise(
+    samples: &[f64],
+    sample_rate_hz: f64,
+    spec: &NoiseSpectrumSpec,
+) -> Result<NoiseReport> {
+    let spectrum = welch_psd(samples, sample_rate_hz, spec.segment_len)?;
+    let df = spectrum.resolution_hz;
+    let total_power: f64 = spectrum.psd.iter().skip(1).sum::<f64>() * df;
+
+    let background = running_median(&spectrum.psd, BACKGROUND_HALF_WIDTH);
+    let psd = &spectrum.psd;
+    let mut peaks: Vec<NoisePeak> = (1..psd.len())
+        .filter(|&k| psd[k] >= psd[k - 1] && psd.get(k + 1).is_none_or(|&next| psd[k] > next))
+        .filter_map(|k| {
+            let prominence = psd[k] / background[k].max(f64::MIN_POSITIVE);
+            if prominence < spec.min_prominence {
+                return None;
+            }
+            let lobe =
+                k.saturating_sub(PEAK_HALF_WIDTH).max(1)..(k + PEAK_HALF_WIDTH + 1).min(psd.len());
+            let excess: f64 = lobe.map(|j| (psd[j] - background[j]).max(0.0)).sum::<f64>() * df;
+            let frequency_hz = spectrum.frequencies_hz[k];
+            Some(NoisePeak {
+                frequency_hz,
+                psd: psd[k],
+                prominence,
+                rms: excess.sqrt(),
+                mains: mains_harmonic(frequency_hz, df),
+            })
+        })
+        .collect();
+    peaks.sort_by(|a, b| b.rms.total_cmp(&a.rms));
+    peaks.truncate(spec.max_peaks);
+
+    let peak_power: f64 = peaks.iter().map(|p| p.rms * p.rms).sum();
+    Ok(NoiseReport {
+        sample_rate_hz,
+        samples: samples.len(),
+        rms: total_power.sqrt(),
+        peak_power_fraction: if total_power > 0.0 {
+            (peak_power / total_power).min(1.0)
+        } else {
+            0.0
+        },
+        peaks,
+        spectrum,
+    })
+}
+
+/// The mains harmonic `frequency_hz` lies on, within a bin (but at least
+/// 0.5 Hz, since mains drifts), preferring the closer of 50 and 60 Hz.
+fn mains_harmonic(frequency_hz: f64, resolution_hz: f64) -> Option<MainsHarmonic> {
+    let tolerance = resolution_hz.max(0.5);
+    MAINS_HZ
+        .iter()
+        .filter_map(|&mains_hz| {
+            let harmonic = (frequency_hz / mains_hz).round();
+            let offset = (frequency_hz - harmonic * mains_hz).abs();
+            (harmonic >= 1.0 && offset <= tolerance).then_some((
+                offset,
+                MainsHarmonic {
+                    mains_hz,
+                    harmonic: harmonic as u32,
+                },
+            ))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, harmonic)| harmonic)
+}
+
+fn power_of_two_below(n: usize) -> usize {
+    if n == 0 { 0 } else { 1 << n.ilog2() }
+}
+
+/// Median of each value's neighbourhood, `half_width` on each side.
+fn running_median(values: &[f64], half_width: usize) -> Vec<f64> {
+    (0..values.len())
+        .map(|i| {
+            let lo = i.saturating_sub(half_width);
+            let hi = (i + half_width + 1).min(values.len());
+            let mut window = values[lo..hi].to_vec();
+            let mid = window.len() / 2;
+            *window.select_nth_unstable_by(mid, f64::total_cmp).1
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT; `re.len()` must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Rng;
+
+    const RATE: f64 = 2000.0;
+
+    fn signal(n: usize, tones: &[(f64, f64)], noise: f64) -> Vec<f64> {
+        let mut rng = Rng::new(7);
+        (0..n)
+            .map(|i| {
+                let t = i as f64 / RATE;
+                let hum: f64 = tones
+                    .iter()
+                    .map(|&(f, a)| a * (2.0 * PI * f * t).sin())
+                    .sum();
+                -5.0 + hum + noise * rng.normal()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_spectrum_integrates_to_the_variance() {
+        let samples = signal(16384, &[], 0.3);
+        let spectrum = welch_psd(&samples, RATE, 1024).unwrap();
+        assert_eq!(spectrum.frequencies_hz.len(), 513);
+        assert_eq!(spectrum.resolution_hz, RATE / 1024.0);
+        assert_eq!(spectrum.segments, 31);
+        let variance: f64 = spectrum.psd.iter().skip(1).sum::<f64>() * spectrum.resolution_hz;
+        assert!(
+            (variance.sqrt() - 0.3).abs() < 0.02,
+            "rms {}",
+            variance.sqrt()
+        );
+    }
+
+    #[test]
+    fn mains_pickup_and_a_pump_line_are_found() {
+        let samples = signal(16384, &[(50.0, 1.0), (150.0, 0.3), (87.0, 0.5)], 0.1);
+        let report = analyze_noise(&samples, RATE, &NoiseSpectrumSpec::default()).unwrap();
+
+        assert_eq!(report.peaks.len(), 3, "{:?}", report.peaks);
+        let [mains, pump, third] = &report.peaks[..] else {
+            unreachable!()
+        };
+        assert!((mains.frequency_hz - 50.0).abs() <= report.spectrum.resolution_hz);
+        assert!(
+            (mains.rms - 1.0 / 2f64.sqrt()).abs() < 0.07,
+            "{}",
+            mains.rms
+        );
+        assert_eq!(
+            mains.mains,
+            Some(MainsHarmonic {
+                mains_hz: 50.0,
+                harmonic: 1
+            })
+        );
+        assert!((pump.frequency_hz - 87.0).abs() <= report.spectrum.resolution_hz);
+        assert_eq!(pump.mains, None);
+        assert_eq!(third.mains.map(|m| m.harmonic), Some(3));
+        assert!(
+            report.peak_power_fraction > 0.9,
+            "{}",
+            report.peak_power_fraction
+        );
+    }
+
+    #[test]
+    fn white_noise_has_no_peaks() {
+        let samples = signal(16384, &[], 0.5);
+        let report = analyze_noise(&samples, RATE, &NoiseSpectrumSpec::default()).unwrap();
+        assert!(report.peaks.is_empty(), "{:?}", report.peaks);
+        assert_eq!(report.peak_power_fraction, 0.0);
+    }
+
+    #[test]
+    fn too_few_samples_are_refused() {
+        assert!(welch_psd(&[1.0; 8], RATE, 1024).is_err());
+        assert!(welch_psd(&[1.0; 64], 0.0, 1024).is_err());
+        // Fewer samples than the segment: the largest power of two that fits,
+        // overlapping by half.
+        let spectrum = welch_psd(&signal(100, &[], 0.1), RATE, 1024).unwrap();
+        assert_eq!((spectrum.segments, spectrum.psd.len()), (2, 33));
+    }
+}
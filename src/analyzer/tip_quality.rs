@@ -0,0 +1,414 @@
+// Indexed `for r in .. { for c in .. }` loops are the natural idiom for the
+// shift arithmetic of the autocorrelation; see cuox_rows.rs.
+#![allow(clippy::needless_range_loop)]
+
+use serde::{Deserialize, Serialize};
+
+use crate::spm_error::SpmError;
+
+use super::{Analyzer, AnalyzerInput, AnalyzerOutput, Result};
+
+/// Smallest usable frame, in complete rows and columns.
+const MIN_SIZE: usize = 8;
+
+/// Largest autocorrelation shift examined, in pixels. Bounds the cost on
+/// large frames; tip artefacts worth rejecting show up well inside it.
+const MAX_SHIFT: usize = 32;
+
+/// Judges tip quality from a small scan image: double tips, streaks and
+/// resolution.
+///
+/// The metrics:
+/// - **Double-tip score**: the strongest autocorrelation peak outside the
+///   central one. A second tip apex images every feature twice at a fixed
+///   offset, which shows up as a side peak of about 0.5 (two equal apexes).
+///   Periodic surfaces (atomic lattices, reconstructions) also produce side
+///   peaks, so raise or disable this check when imaging one.
+/// - **Streak fraction**: the share of scan lines whose mean jumps away from
+///   the previous line by more than `streak_sigma` robust standard
+///   deviations — the signature of a tip changing mid-frame.
+/// - **Correlation length**: the shift (px) at which the line-flattened
+///   autocorrelation falls below 0.5. Blunt tips blur features and lengthen
+///   it.
+///
+/// Each check is skipped when its threshold is `None`. Rows containing
+/// non-finite pixels (lines Nanonis has not scanned yet) are dropped.
+pub struct TipQualityAnalyzer {
+    /// Reject above this double-tip score (0..1, default: 0.35).
+    pub max_double_tip_score: Option<f64>,
+    /// Reject above this fraction of streaky lines (default: 0.05).
+    pub max_streak_fraction: Option<f64>,
+    /// Reject above this correlation length in pixels (default: off).
+    pub max_correlation_length_px: Option<f64>,
+    /// Line-jump size, in robust standard deviations, that counts as a
+    /// streak (default: 6).
+    pub streak_sigma: f64,
+}
+
+impl Default for TipQualityAnalyzer {
+    fn default() -> Self {
+        Self {
+            max_double_tip_score: Some(0.35),
+            max_streak_fraction: Some(0.05),
+            max_correlation_length_px: None,
+            streak_sigma: 6.0,
+        }
+    }
+}
+
+/// Metrics and verdict of one [`TipQualityAnalyzer`] run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TipQuality {
+    pub double_tip_score: f64,
+    pub streak_fraction: f64,
+    pub correlation_length_px: f64,
+    /// Correlation length in metres, when the input carries a calibration.
+    pub correlation_length_m: Option<f64>,
+    /// Rows that went into the analysis.
+    pub rows_used: usize,
+    pub accepted: bool,
+    /// The checks that failed, e.g. `"double_tip"`.
+    pub rejected_by: Vec<String>,
+}
+
+impl TipQualityAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compute the metrics and the verdict for one frame.
+    ///
+    /// Fails with `SpmError::Workflow` if fewer than 8 complete rows or
+    /// columns are available.
+    pub fn assess(&self, input: &AnalyzerInput) -> Result<TipQuality> {
+        let image: Vec<Vec<f64>> = input
+            .data
+            .iter()
+            .filter(|row| row.iter().all(|v| v.is_finite()))
+            .map(|row| row.iter().map(|&v| v as f64).collect())
+            .collect();
+        let cols = image.first().map_or(0, |r| r.len());
+        if image.len() < MIN_SIZE || cols < MIN_SIZE || image.iter().any(|r| r.len() != cols) {
+            return Err(SpmError::Workflow(format!(
+                "tip_quality: need at least {MIN_SIZE}x{MIN_SIZE} complete pixels, got {} of {} rows x {} cols",
+                image.len(),
+                input.rows(),
+                input.cols()
+            )));
+        }
+
+        let leveled = subtract_plane(&image);
+        let streak_fraction = streak_fraction(&leveled, self.streak_sigma);
+
+        let flat = flatten_lines(&leveled);
+        let (correlation_length_px, double_tip_score) = autocorrelation_metrics(&flat);
+
+        let mut rejected_by = Vec::new();
+        if self
+            .max_double_tip_score
+            .is_some_and(|max| double_tip_score > max)
+        {
+            rejected_by.push("double_tip".to_string());
+        }
+        if self
+            .max_streak_fraction
+            .is_some_and(|max| streak_fraction > max)
+        {
+            rejected_by.push("streaks".to_string());
+        }
+        if self
+            .max_correlation_length_px
+            .is_some_and(|max| correlation_length_px > max)
+        {
+            rejected_by.push("resolution".to_string());
+        }
+
+        Ok(TipQuality {
+            double_tip_score,
+            streak_fraction,
+            correlation_length_px,
+            correlation_length_m: input
+                .calibration_m_per_px
+                .map(|m| m * correlation_length_px),
+            rows_used: image.len(),
+            accepted: rejected_by.is_empty(),
+            rejected_by,
+        })
+    }
+}
+
+impl Analyzer for TipQualityAnalyzer {
+    fn name(&self) -> &str {
+        "tip_quality"
+    }
+
+    fn description(&self) -> &str {
+        "Judges tip quality from a scan image (double tip, streaks, resolution)"
+    }
+
+    fn analyze(&self, input: &AnalyzerInput) -> Result<AnalyzerOutput> {
+        let quality = self.assess(input)?;
+        Ok(AnalyzerOutput {
+            data: serde_json::to_value(&quality).unwrap_or_default(),
+            annotated_image: None,
+        })
+    }
+}
+
+/// Least-squares plane `a + b*x + c*y` removed from the image.
+fn subtract_plane(image: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let rows = image.len();
+    let cols = image[0].len();
+    let n = (rows * cols) as f64;
+    // On a full grid x and y are uncorrelated, so the fit separates into two
+    // centred slopes.
+    let x_mean = (cols - 1) as f64 / 2.0;
+    let y_mean = (rows - 1) as f64 / 2.0;
+    let mean = image.iter().flatten().sum::<f64>() / n;
+
+    let (mut sxz, mut syz, mut sxx, mut syy) = (0.0, 0.0, 0.0, 0.0);
+    for r in 0..rows {
+        for c in 0..cols {
+            let dx = c as f64 - x_mean;
+            let dy = r as f64 - y_mean;
+            let dz = image[r][c] - mean;
+            sxz += dx * dz;
+            syz += dy * dz;
+            sxx += dx * dx;
+            syy += dy * dy;
+        }
+    }
+    let bx = sxz / sxx;
+    let by = syz / syy;
+
+    (0..rows)
+        .map(|r| {
+            (0..cols)
+                .map(|c| image[r][c] - mean - bx * (c as f64 - x_mean) - by * (r as f64 - y_mean))
+                .collect()
+        })
+        .collect()
+}
+
+/// Each line with its own mean removed, so line offsets (streaks) do not
+/// dominate the autocorrelation.
+fn flatten_lines(image: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    image
+        .iter()
+        .map(|row| {
+            let mean = row.iter().sum::<f64>() / row.len() as f64;
+            row.iter().map(|v| v - mean).collect()
+        })
+        .collect()
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Fraction of line-to-line mean jumps larger than `sigma` robust standard
+/// deviations (MAD-based) of all jumps.
+fn streak_fraction(image: &[Vec<f64>], sigma: f64) -> f64 {
+    let means: Vec<f64> = image
+        .iter()
+        .map(|row| row.iter().sum::<f64>() / row.len() as f64)
+        .collect();
+    let jumps: Vec<f64> = means.windows(2).map(|w| w[1] - w[0]).collect();
+
+    let center = median(&mut jumps.clone());
+    let mut deviations: Vec<f64> = jumps.iter().map(|j| (j - center).abs()).collect();
+    let spread = 1.4826 * median(&mut deviations);
+    // A perfectly clean synthetic frame has no spread at all; floor it at a
+    // small fraction of the image range so rounding noise is not a streak.
+    let (lo, hi) = image
+        .iter()
+        .flatten()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    let floor = 1e-3 * (hi - lo);
+    let limit = sigma * spread.max(floor);
+
+    let streaks = jumps.iter().filter(|j| (*j - center).abs() > limit).count();
+    streaks as f64 / jumps.len() as f64
+}
+
+/// Correlation length (px) and the strongest side peak of the normalised
+/// autocorrelation, over shifts up to a quarter of the frame.
+fn autocorrelation_metrics(image: &[Vec<f64>]) -> (f64, f64) {
+    let rows = image.len();
+    let cols = image[0].len();
+    let max_dy = (rows / 4).clamp(1, MAX_SHIFT);
+    let max_dx = (cols / 4).clamp(1, MAX_SHIFT);
+
+    let variance = image.iter().flatten().map(|v| v * v).sum::<f64>() / (rows * cols) as f64;
+    if variance <= f64::EPSILON {
+        // A featureless frame carries no information about the tip.
+        return (0.0, 0.0);
+    }
+
+    // The autocorrelation is point-symmetric, so non-negative dy covers it.
+    let mut correlation = vec![vec![0.0; 2 * max_dx + 1]; max_dy + 1];
+    for dy in 0..=max_dy {
+        for (i, dx) in (-(max_dx as isize)..=max_dx as isize).enumerate() {
+            let mut sum = 0.0;
+            let mut count = 0usize;
+            for r in 0..rows - dy {
+                for c in 0..cols {
+                    let c2 = c as isize + dx;
+                    if c2 < 0 || c2 >= cols as isize {
+                        continue;
+                    }
+                    sum += image[r][c] * image[r + dy][c2 as usize];
+                    count += 1;
+                }
+            }
+            correlation[dy][i] = sum / count as f64 / variance;
+        }
+    }
+    let shifts = || {
+        (0..=max_dy).flat_map(move |dy| {
+            (0..=2 * max_dx).map(move |i| {
+                let dx = i as f64 - max_dx as f64;
+                ((dx * dx + (dy * dy) as f64).sqrt(), dy, i)
+            })
+        })
+    };
+
+    // Correlation length: first radius whose ring average drops below 0.5.
+    let max_radius = max_dx.min(max_dy);
+    let correlation_length = (1..=max_radius)
+        .find(|&radius| {
+            let ring: Vec<f64> = shifts()
+                .filter(|(d, _, _)| (d - radius as f64).abs() < 0.5)
+                .map(|(_, dy, i)| correlation[dy][i])
+                .collect();
+            ring.iter().sum::<f64>() / (ring.len() as f64) < 0.5
+        })
+        .unwrap_or(max_radius) as f64;
+
+    // Side peak: the largest value clear of the central peak.
+    let exclusion = (2.0 * correlation_length).max(2.0);
+    let side_peak = shifts()
+        .filter(|(d, _, _)| *d > exclusion)
+        .map(|(_, dy, i)| correlation[dy][i])
+        .fold(0.0, f64::max);
+
+    (correlation_length, side_peak)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random bumps, the stand-in for surface features.
+    fn features(rows: usize, cols: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x2545_F491u32;
+        let mut image = vec![vec![0.0f32; cols]; rows];
+        for _ in 0..(rows * cols / 40) {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let (r0, c0) = ((state as usize) % rows, (state as usize / rows) % cols);
+            for r in 0..rows {
+                for c in 0..cols {
+                    let d2 = (r as f32 - r0 as f32).powi(2) + (c as f32 - c0 as f32).powi(2);
+                    image[r][c] += (-d2 / 2.0).exp();
+                }
+            }
+        }
+        image
+    }
+
+    fn input(data: Vec<Vec<f32>>) -> AnalyzerInput {
+        AnalyzerInput {
+            channel_name: "Z".into(),
+            data,
+            calibration_m_per_px: None,
+        }
+    }
+
+    #[test]
+    fn a_clean_image_is_accepted() {
+        let quality = TipQualityAnalyzer::new()
+            .assess(&input(features(64, 64)))
+            .unwrap();
+        assert!(quality.accepted, "{quality:?}");
+        assert!(quality.double_tip_score < 0.35, "{quality:?}");
+        assert_eq!(quality.streak_fraction, 0.0);
+    }
+
+    #[test]
+    fn a_doubled_image_is_rejected_as_a_double_tip() {
+        let single = features(64, 80);
+        // A second apex 9 px to the side images every feature twice.
+        let doubled: Vec<Vec<f32>> = single
+            .iter()
+            .map(|row| (0..64).map(|c| row[c] + row[c + 9]).collect())
+            .collect();
+        let quality = TipQualityAnalyzer::new().assess(&input(doubled)).unwrap();
+        assert!(quality.double_tip_score > 0.35, "{quality:?}");
+        assert_eq!(quality.rejected_by, vec!["double_tip"]);
+    }
+
+    #[test]
+    fn line_jumps_are_counted_as_streaks() {
+        let mut image = features(64, 64);
+        // The tip hops for a few single lines.
+        for r in [10, 25, 40, 55] {
+            for v in image[r].iter_mut() {
+                *v += 5.0;
+            }
+        }
+        let quality = TipQualityAnalyzer::new().assess(&input(image)).unwrap();
+        assert!(quality.streak_fraction > 0.05, "{quality:?}");
+        assert!(quality.rejected_by.iter().any(|r| r == "streaks"));
+    }
+
+    #[test]
+    fn blurred_features_lengthen_the_correlation_length() {
+        let sharp = features(64, 64);
+        let mut blurred = sharp.clone();
+        for _ in 0..3 {
+            let source = blurred.clone();
+            for r in 1..63 {
+                for c in 1..63 {
+                    blurred[r][c] = (source[r - 1][c]
+                        + source[r + 1][c]
+                        + source[r][c - 1]
+                        + source[r][c + 1]
+                        + source[r][c])
+                        / 5.0;
+                }
+            }
+        }
+        let analyzer = TipQualityAnalyzer::new();
+        let sharp = analyzer.assess(&input(sharp)).unwrap();
+        let blurred = analyzer.assess(&input(blurred)).unwrap();
+        assert!(
+            blurred.correlation_length_px > sharp.correlation_length_px,
+            "sharp {sharp:?}, blurred {blurred:?}"
+        );
+    }
+
+    #[test]
+    fn unscanned_lines_are_dropped_and_tiny_frames_refused() {
+        let mut image = features(32, 32);
+        for row in &mut image[24..] {
+            row.fill(f32::NAN);
+        }
+        let quality = TipQualityAnalyzer::new().assess(&input(image)).unwrap();
+        assert_eq!(quality.rows_used, 24);
+
+        let err = TipQualityAnalyzer::new()
+            .assess(&input(vec![vec![0.0; 2]; 2]))
+            .unwrap_err();
+        assert!(matches!(err, SpmError::Workflow(_)), "{err}");
+    }
+}
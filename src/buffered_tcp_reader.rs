@@ -1,13 +1,19 @@
 //! Buffered TCP Reader for continuous signal data collection
 //!
 //! This module provides a BufferedTCPReader that automatically buffers TCP logger data
-//! in the background using a lightweight time-series database approach. It leverages
-//! the existing TCPLoggerStream infrastructure while providing efficient time-windowed
-//! queries for synchronized data collection during SPM experiments.
+//! in the background. Every buffered frame gets a sequence number, so readers
+//! hold a [`FrameCursor`] instead of a timestamp: they never see a frame twice,
+//! never skip one silently, and learn how many frames they missed when the
+//! buffer overflowed under them or the stream itself dropped frames.
+//!
+//! Frame times come from a sample clock inferred from the oversampling
+//! (`BASE_ACQUISITION_RATE_HZ / oversampling`) and the Nanonis frame counter,
+//! so frames that arrive in one TCP burst still get distinct, evenly spaced
+//! times.
 
 use crate::NanonisError;
-use crate::types::TimestampedSignalFrame;
-use nanonis_rs::TCPLoggerStream;
+use crate::stream_recorder::RecorderTap;
+use nanonis_rs::{BASE_ACQUISITION_RATE_HZ, TCPLoggerStream};
 use parking_lot::{Mutex, RwLock};
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -15,35 +21,172 @@ use std::sync::{Arc, mpsc};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
-// TODO: For 2kHz sampling, consider replacing with:
-// use crossbeam::queue::ArrayQueue; // Lock-free ring buffer
-// use parking_lot::RwLock;          // Faster reader-writer lock
+/// One frame of the TCP stream, as buffered.
+#[derive(Debug, Clone)]
+pub struct BufferedFrame {
+    /// Position in the buffer's stream: 0 for the first frame received,
+    /// then consecutive, whatever happened to the frames around it.
+    pub seq: u64,
+    /// Frame counter sent by the Nanonis TCP logger.
+    pub counter: u64,
+    /// Frames the stream skipped just before this one (a gap in `counter`).
+    pub dropped_before: u64,
+    /// When the frame was sampled, on the inferred sample clock: the first
+    /// frame's arrival plus one sample period per counter step.
+    pub sampled: Instant,
+    /// One value per configured channel.
+    pub data: Vec<f32>,
+}
+
+/// A reader's position in the stream; see [`BufferedTCPReader::read_frames`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameCursor {
+    next: u64,
+}
+
+/// What one [`BufferedTCPReader::read_frames`] call went through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameRead {
+    /// Frames handed to the visitor.
+    pub frames: usize,
+    /// Frames the cursor should have seen but were pushed out of the full
+    /// buffer before this read.
+    pub overrun: u64,
+    /// Frames the stream itself dropped among the frames read.
+    pub dropped: u64,
+}
+
+/// Totals since the reader started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Frames received and buffered.
+    pub received: u64,
+    /// Frames missing from the stream, counted from gaps in the frame counter.
+    pub dropped: u64,
+    /// Frames pushed out of the full buffer to make room for new ones.
+    pub evicted: u64,
+}
+
+/// The sequence-numbered ring behind [`BufferedTCPReader`], kept apart from
+/// the TCP thread so it can be tested on its own.
+struct FrameRing {
+    frames: VecDeque<BufferedFrame>,
+    capacity: usize,
+    next_seq: u64,
+    /// Frames below this were discarded by `clear`, not by overflow.
+    cleared_below: u64,
+    sample_period: Duration,
+    /// Counter and receive time the sample clock counts from.
+    clock_origin: Option<(u64, Instant)>,
+    last_counter: Option<u64>,
+    stats: StreamStats,
+}
+
+impl FrameRing {
+    fn new(capacity: usize, sample_period: Duration) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            next_seq: 0,
+            cleared_below: 0,
+            sample_period,
+            clock_origin: None,
+            last_counter: None,
+            stats: StreamStats::default(),
+        }
+    }
+
+    /// Append a frame received at `received`; returns it as buffered.
+    fn push(&mut self, counter: u64, data: Vec<f32>, received: Instant) -> &BufferedFrame {
+        let dropped_before = match self.last_counter {
+            Some(last) if counter > last => counter - last - 1,
+            Some(last) => {
+                // The counter went backwards: the logger restarted, so
+                // restart the sample clock from here.
+                log::debug!("TCP frame counter restarted ({last} -> {counter})");
+                self.clock_origin = None;
+                0
+            }
+            None => 0,
+        };
+        self.last_counter = Some(counter);
+        let (origin_counter, origin) = *self.clock_origin.get_or_insert((counter, received));
+        let steps = u32::try_from(counter - origin_counter).unwrap_or(u32::MAX);
+
+        self.frames.push_back(BufferedFrame {
+            seq: self.next_seq,
+            counter,
+            dropped_before,
+            sampled: origin + self.sample_period * steps,
+            data,
+        });
+        self.next_seq += 1;
+        self.stats.received += 1;
+        self.stats.dropped += dropped_before;
+        if self.frames.len() > self.capacity {
+            self.frames.pop_front();
+            self.stats.evicted += 1;
+        }
+        self.frames.back().expect("frame just pushed")
+    }
+
+    fn clear(&mut self) {
+        self.frames.clear();
+        self.cleared_below = self.next_seq;
+    }
+
+    fn cursor(&self) -> FrameCursor {
+        FrameCursor {
+            next: self.next_seq,
+        }
+    }
 
-/// Buffered TCP reader that continuously collects timestamped signal data
+    fn read(
+        &self,
+        cursor: &mut FrameCursor,
+        mut visit: impl FnMut(&BufferedFrame) -> bool,
+    ) -> FrameRead {
+        let oldest = self.frames.front().map_or(self.next_seq, |f| f.seq);
+        let wanted = cursor.next.max(self.cleared_below);
+        let mut read = FrameRead {
+            overrun: oldest.saturating_sub(wanted),
+            ..FrameRead::default()
+        };
+        cursor.next = wanted.max(oldest);
+
+        let skip = (cursor.next - oldest) as usize;
+        for frame in self.frames.iter().skip(skip) {
+            if !visit(frame) {
+                break;
+            }
+            cursor.next = frame.seq + 1;
+            read.frames += 1;
+            read.dropped += frame.dropped_before;
+        }
+        read
+    }
+}
+
+/// Buffered TCP reader that continuously collects signal frames
 ///
 /// This component creates a background thread that reads lightweight SignalFrame data
-/// from TCPLoggerStream's channel and buffers it with high-resolution timestamps in a
-/// circular buffer. It provides time-windowed query methods for retrieving data before,
-/// during, and after specific time periods.
+/// from TCPLoggerStream's channel and appends it to a bounded, sequence-numbered
+/// buffer. Readers take a [`FrameCursor`] with [`cursor`](Self::cursor) and
+/// walk new frames with [`read_frames`](Self::read_frames), which borrows the
+/// frames in place instead of copying them out.
 ///
 /// # High-Frequency Performance (2kHz+)
-/// **IMPORTANT**: At sampling rates above 1kHz, lock contention becomes critical:
-/// - Current implementation uses `Mutex<VecDeque>` suitable for <1kHz
-/// - For 2kHz+, consider `crossbeam::queue::ArrayQueue` (lock-free)
-/// - Alternative: `parking_lot::RwLock` for multiple concurrent readers
-/// - Query methods must complete in <0.1ms to avoid data loss
-///
-/// # Memory Efficiency
-/// Works with lightweight SignalFrame structures (just counter + data) throughout the
-/// entire pipeline, avoiding the overhead of full TCPLoggerData per frame.
+/// Reads hold the buffer's read lock only while visiting frames, and the
+/// buffering thread only needs the write lock to append one frame, so keep
+/// visitors short (copy out the values you need).
 ///
 /// # Architecture
 /// - TCPLoggerStream converts protocol data to SignalFrame (protocol → lightweight conversion)
-/// - BufferedTCPReader adds timestamps to SignalFrame (timing layer)
-/// - Thread-safe time-windowed queries while continuous collection runs in background
+/// - BufferedTCPReader numbers frames and puts them on the sample clock (timing layer)
+/// - Cursor-based reads while continuous collection runs in background
 pub struct BufferedTCPReader {
-    /// Thread-safe circular buffer of timestamped signal frames
-    buffer: Arc<RwLock<VecDeque<TimestampedSignalFrame>>>,
+    /// Thread-safe circular buffer of numbered signal frames
+    ring: Arc<RwLock<FrameRing>>,
     /// Background thread handle for buffering operations
     buffering_thread: Option<JoinHandle<Result<(), NanonisError>>>,
     /// Signal to shut down background thread
@@ -51,6 +194,10 @@ pub struct BufferedTCPReader {
     /// Error from the TCP stream reader thread, if it died unexpectedly.
     /// Set by the buffering thread when it detects the stream disconnected.
     stream_error: Arc<Mutex<Option<String>>>,
+    /// Recorder every buffered frame is also forwarded to, if any.
+    recorder_tap: Arc<Mutex<Option<RecorderTap>>>,
+    /// Nominal frame rate from the oversampling.
+    sample_rate_hz: f64,
 }
 
 impl BufferedTCPReader {
@@ -63,20 +210,32 @@ impl BufferedTCPReader {
     /// * `host` - TCP server host address (e.g., "127.0.0.1")
     /// * `port` - TCP logger data stream port (typically 6590)
     /// * `buffer_size` - Maximum number of frames to keep in circular buffer
+    /// * `oversampling` - Oversampling the TCP logger was configured with; sets
+    ///   the sample clock
     ///
     /// # Returns
     /// A BufferedTCPReader with active background collection, ready for queries
     ///
     /// # Implementation Notes
     /// - Creates TCPLoggerStream and gets its background reader channel
-    /// - Starts buffering thread that converts SignalFrame to TimestampedSignalFrame
-    /// - Implements circular buffer behavior (drops oldest when full)
-    pub fn new(host: &str, port: u16, buffer_size: usize) -> Result<Self, NanonisError> {
+    /// - Starts buffering thread that numbers each SignalFrame into a [`BufferedFrame`]
+    /// - Implements circular buffer behavior (drops oldest when full, counted
+    ///   in [`StreamStats::evicted`])
+    pub fn new(
+        host: &str,
+        port: u16,
+        buffer_size: usize,
+        oversampling: i32,
+    ) -> Result<Self, NanonisError> {
         let tcp_stream = TCPLoggerStream::new(host, port)?;
         let (tcp_receiver, stream_handle) = tcp_stream.spawn_background_reader();
 
-        let buffer = Arc::new(RwLock::new(VecDeque::with_capacity(buffer_size)));
-        let buffer_clone = buffer.clone();
+        let sample_rate_hz = BASE_ACQUISITION_RATE_HZ / f64::from(oversampling.max(1));
+        let ring = Arc::new(RwLock::new(FrameRing::new(
+            buffer_size,
+            Duration::from_secs_f64(1.0 / sample_rate_hz),
+        )));
+        let ring_clone = ring.clone();
 
         let shutdown_signal = Arc::new(AtomicBool::new(false));
         let shutdown_clone = shutdown_signal.clone();
@@ -84,7 +243,8 @@ impl BufferedTCPReader {
         let stream_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
         let stream_error_clone = stream_error.clone();
 
-        let start_time = Instant::now();
+        let recorder_tap: Arc<Mutex<Option<RecorderTap>>> = Arc::new(Mutex::new(None));
+        let recorder_tap_clone = recorder_tap.clone();
 
         // Don't block waiting for first frame - let background thread handle it
         // The TCP logger might not be started yet when this constructor runs
@@ -105,16 +265,11 @@ impl BufferedTCPReader {
                                 continue;
                             }
 
-                            let timestamped_frame =
-                                TimestampedSignalFrame::new(signal_frame, start_time);
-
-                            {
-                                let mut buffer = buffer_clone.write();
-                                buffer.push_back(timestamped_frame);
-
-                                if buffer.len() > buffer_size {
-                                    buffer.pop_front();
-                                }
+                            let mut ring = ring_clone.write();
+                            let frame =
+                                ring.push(signal_frame.counter, signal_frame.data, Instant::now());
+                            if let Some(tap) = recorder_tap_clone.lock().as_ref() {
+                                tap.frame(frame.counter, frame.sampled, &frame.data);
                             }
                         }
                         Err(mpsc::RecvTimeoutError::Timeout) => {
@@ -146,13 +301,21 @@ impl BufferedTCPReader {
             .expect("failed to spawn tcp-logger-buffer thread");
 
         Ok(Self {
-            buffer,
+            ring,
             buffering_thread: Some(buffering_thread),
             shutdown_signal,
             stream_error,
+            recorder_tap,
+            sample_rate_hz,
         })
     }
 
+    /// Forward every frame buffered from now on to a stream recorder as
+    /// well, stamped with its sample-clock time, or stop forwarding with `None`.
+    pub fn set_recorder_tap(&self, tap: Option<RecorderTap>) {
+        *self.recorder_tap.lock() = tap;
+    }
+
     /// Check if the background buffering thread is still active.
     ///
     /// Returns `false` if shutdown was requested OR if the background
@@ -176,42 +339,47 @@ impl BufferedTCPReader {
         self.stream_error.lock().clone()
     }
 
-    /// Get all signal data since a specific timestamp
-    ///
-    /// # Arguments
-    /// * `since` - Timestamp to start collecting data from
+    /// A cursor at the end of the stream: reading it yields only frames
+    /// received from now on.
+    pub fn cursor(&self) -> FrameCursor {
+        self.ring.read().cursor()
+    }
+
+    /// Visit the frames after `cursor` in order, advancing it past each one.
     ///
-    /// # Returns
-    /// Vector of timestamped signal frames from the specified time onwards
+    /// Stops early when `visit` returns `false`; that frame stays unread.
+    /// Frames are borrowed under the buffer's read lock, so `visit` should
+    /// only copy out what it needs.
     ///
-    /// # Thread Safety
-    /// This method acquires a lock on the buffer briefly to copy matching frames.
-    /// Lock is held for minimal time to avoid blocking the buffering thread.
-    pub fn get_data_since(&self, since: Instant) -> Vec<TimestampedSignalFrame> {
-        let buffer = self.buffer.read();
-        buffer
-            .iter()
-            .filter(|frame| frame.timestamp >= since)
-            .cloned()
-            .collect()
+    /// If the cursor fell so far behind that frames it had not read were
+    /// pushed out of the buffer, it jumps to the oldest frame left and the
+    /// loss is reported in [`FrameRead::overrun`]. Frames discarded by
+    /// [`clear_buffer`](Self::clear_buffer) are skipped without being counted.
+    pub fn read_frames(
+        &self,
+        cursor: &mut FrameCursor,
+        visit: impl FnMut(&BufferedFrame) -> bool,
+    ) -> FrameRead {
+        self.ring.read().read(cursor, visit)
+    }
+
+    /// Frame totals since the reader started.
+    pub fn stats(&self) -> StreamStats {
+        self.ring.read().stats
+    }
+
+    /// Nominal frame rate of the stream, `BASE_ACQUISITION_RATE_HZ / oversampling`.
+    pub fn sample_rate_hz(&self) -> f64 {
+        self.sample_rate_hz
     }
 
     /// Clear all buffered data
     ///
     /// This removes all frames from the buffer, effectively resetting it to an empty state.
     /// The background thread continues to run and will start filling the buffer again.
-    /// This is useful when you want to discard old data and start fresh.
-    ///
-    /// # Example
-    /// ```rust,ignore
-    /// // Clear any stale data before starting a new measurement
-    /// tcp_reader.clear_buffer();
-    /// thread::sleep(Duration::from_millis(500)); // Wait for fresh data
-    /// let fresh_data = tcp_reader.get_recent_data(Duration::from_millis(100));
-    /// ```
+    /// Cursors taken before the clear resume at the first frame received after it.
     pub fn clear_buffer(&self) {
-        let mut buffer = self.buffer.write();
-        buffer.clear();
+        self.ring.write().clear();
         log::debug!("Cleared TCP reader buffer");
     }
 
@@ -242,3 +410,100 @@ impl Drop for BufferedTCPReader {
         let _ = self.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(1);
+
+    fn ring_with(capacity: usize, counters: impl IntoIterator<Item = u64>) -> FrameRing {
+        let mut ring = FrameRing::new(capacity, PERIOD);
+        let received = Instant::now();
+        for counter in counters {
+            ring.push(counter, vec![counter as f32], received);
+        }
+        ring
+    }
+
+    fn read_counters(ring: &FrameRing, cursor: &mut FrameCursor) -> (Vec<u64>, FrameRead) {
+        let mut counters = Vec::new();
+        let read = ring.read(cursor, |frame| {
+            counters.push(frame.counter);
+            true
+        });
+        (counters, read)
+    }
+
+    #[test]
+    fn a_cursor_sees_each_frame_once() {
+        let mut ring = ring_with(10, 1..=3);
+        let mut cursor = FrameCursor { next: 0 };
+
+        assert_eq!(read_counters(&ring, &mut cursor).0, [1, 2, 3]);
+        assert!(read_counters(&ring, &mut cursor).0.is_empty());
+        ring.push(4, vec![4.0], Instant::now());
+        assert_eq!(read_counters(&ring, &mut cursor).0, [4]);
+        assert_eq!(cursor.next, 4);
+        assert_eq!(
+            read_counters(&ring, &mut ring.cursor()).0,
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn a_stopped_visit_leaves_the_frame_unread() {
+        let ring = ring_with(10, 1..=5);
+        let mut cursor = FrameCursor { next: 0 };
+
+        let mut taken = Vec::new();
+        let read = ring.read(&mut cursor, |frame| {
+            if taken.len() == 2 {
+                return false;
+            }
+            taken.push(frame.counter);
+            true
+        });
+
+        assert_eq!((taken, read.frames), (vec![1, 2], 2));
+        assert_eq!(read_counters(&ring, &mut cursor).0, [3, 4, 5]);
+    }
+
+    #[test]
+    fn overflow_is_reported_as_overrun_but_clearing_is_not() {
+        let mut ring = ring_with(3, 1..=5);
+        let mut cursor = FrameCursor { next: 0 };
+
+        let (counters, read) = read_counters(&ring, &mut cursor);
+        assert_eq!(counters, [3, 4, 5]);
+        assert_eq!(read.overrun, 2);
+        assert_eq!(ring.stats.evicted, 2);
+
+        let mut stale = ring.cursor();
+        ring.push(6, vec![6.0], Instant::now());
+        ring.clear();
+        ring.push(7, vec![7.0], Instant::now());
+        let (counters, read) = read_counters(&ring, &mut stale);
+        assert_eq!((counters, read.overrun), (vec![7], 0));
+    }
+
+    #[test]
+    fn counter_gaps_count_as_dropped_frames() {
+        let ring = ring_with(10, [1, 2, 5, 6, 10]);
+        let (_, read) = read_counters(&ring, &mut FrameCursor { next: 0 });
+
+        assert_eq!(read.dropped, 5);
+        assert_eq!(ring.stats.dropped, 5);
+        assert_eq!(ring.stats.received, 5);
+    }
+
+    #[test]
+    fn frames_of_one_burst_are_spread_on_the_sample_clock() {
+        let ring = ring_with(10, [7, 8, 10]);
+        let frames: Vec<&BufferedFrame> = ring.frames.iter().collect();
+
+        assert_eq!(frames[1].sampled - frames[0].sampled, PERIOD);
+        assert_eq!(frames[2].sampled - frames[0].sampled, 3 * PERIOD);
+        assert_eq!(frames.iter().map(|f| f.seq).collect::<Vec<_>>(), [0, 1, 2]);
+    }
+}
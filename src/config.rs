@@ -2,7 +2,8 @@ use config::{Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
-use crate::controller_types::{PulseMethod, StabilityConfig};
+use crate::controller_types::{PulseMethod, StabilityConfig, TipChangeDetection};
+use crate::units::SignalUnit;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TcpChannelMapping {
@@ -10,6 +11,78 @@ pub struct TcpChannelMapping {
     pub tcp_channel: u8,
 }
 
+/// A `[[signal_units]]` entry: the unit and/or display precision of one
+/// signal, overriding the unit its Nanonis name implies.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SignalUnitConfig {
+    /// Signal name or alias, e.g. `"current"`.
+    pub signal: String,
+    /// Unit symbol without prefix (`"A"`, `"V"`, `"m"`, `"Hz"`).
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Decimals shown after scaling to an SI prefix.
+    #[serde(default)]
+    pub precision: Option<u8>,
+}
+
+impl SignalUnitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.signal.trim().is_empty() {
+            return Err("signal_units: signal must not be empty".to_string());
+        }
+        if self.unit.is_none() && self.precision.is_none() {
+            return Err(format!(
+                "signal_units for \"{}\": set unit, precision or both",
+                self.signal
+            ));
+        }
+        if let Some(precision) = self.precision
+            && precision > 12
+        {
+            return Err(format!(
+                "signal_units for \"{}\": precision must be at most 12, got {}",
+                self.signal, precision
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A `[[virtual_signals]]` entry: a signal computed from real signals by an
+/// expression (see [`crate::virtual_signals`]).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VirtualSignalConfig {
+    /// Name the signal is read by, e.g. `"conductance"`.
+    pub name: String,
+    /// Expression over real signals, e.g. `"current / bias"`.
+    pub expression: String,
+    /// Unit symbol without prefix; dimensionless if unset.
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Decimals shown after scaling to an SI prefix.
+    #[serde(default)]
+    pub precision: Option<u8>,
+}
+
+impl VirtualSignalConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("virtual_signals: name must not be empty".to_string());
+        }
+        crate::virtual_signals::Expression::parse(&self.expression)
+            .map_err(|e| format!("virtual signal \"{}\": {e}", self.name))?;
+        if let Some(precision) = self.precision
+            && precision > 12
+        {
+            return Err(format!(
+                "virtual signal \"{}\": precision must be at most 12, got {}",
+                self.name, precision
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct AppConfig {
     pub nanonis: NanonisConfig,
@@ -20,15 +93,27 @@ pub struct AppConfig {
     pub pulse_method: PulseMethod,
     #[serde(default)]
     pub tcp_channel_mapping: Option<Vec<TcpChannelMapping>>,
+    #[serde(default)]
+    pub signal_units: Vec<SignalUnitConfig>,
+    #[serde(default)]
+    pub signal_profile: SignalProfileConfig,
+    #[serde(default)]
+    pub virtual_signals: Vec<VirtualSignalConfig>,
 }
 
 impl AppConfig {
     pub fn validate(&self) -> Result<(), ConfigError> {
-        // Validate stability config
-        self.tip_prep
-            .stability
+        self.tip_prep.validate().map_err(ConfigError::Message)?;
+        self.data_acquisition
+            .recording
             .validate()
             .map_err(ConfigError::Message)?;
+        for entry in &self.signal_units {
+            entry.validate().map_err(ConfigError::Message)?;
+        }
+        for entry in &self.virtual_signals {
+            entry.validate().map_err(ConfigError::Message)?;
+        }
 
         // Validate pulse method
         self.pulse_method
@@ -39,6 +124,30 @@ impl AppConfig {
     }
 }
 
+/// What to do when the controller's signal layout differs from the saved
+/// profile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnProfileMismatch {
+    /// Refuse to start.
+    #[default]
+    Error,
+    /// List the changes and ask whether to accept the new layout.
+    Prompt,
+    /// Log the changes and carry on with the new layout.
+    Warn,
+}
+
+/// The `[signal_profile]` section: a saved signal layout checked on every
+/// connect (see [`crate::signal_profile`]). Off unless `path` is set.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct SignalProfileConfig {
+    /// Profile file; written on the first connect if missing.
+    pub path: Option<String>,
+    pub on_mismatch: OnProfileMismatch,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct NanonisConfig {
     pub host_ip: String,
@@ -60,6 +169,60 @@ pub struct DataAcquisitionConfig {
     /// Number of TCP stream samples to average for a stable signal read.
     #[serde(default = "default_stable_signal_samples")]
     pub stable_signal_samples: usize,
+    #[serde(default)]
+    pub recording: StreamRecordingConfig,
+}
+
+/// Continuous recording of every streamed frame to rotating `.rtstream`
+/// files (format in [`crate::stream_recorder`]), with a marker for every
+/// routine event. Off by default.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct StreamRecordingConfig {
+    pub enabled: bool,
+    /// Directory the files are written to; created if missing.
+    pub output_path: String,
+    /// Start a new file once the current one reaches this size (MB).
+    pub max_file_mb: u64,
+    /// ...or has been open this long (s), whichever comes first.
+    pub max_file_secs: u64,
+}
+
+impl Default for StreamRecordingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output_path: "./recordings".to_string(),
+            max_file_mb: 256,
+            max_file_secs: 3600,
+        }
+    }
+}
+
+impl StreamRecordingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.output_path.trim().is_empty() {
+            return Err("data_acquisition.recording.output_path must not be empty".to_string());
+        }
+        if self.max_file_mb == 0 {
+            return Err(
+                "data_acquisition.recording.max_file_mb must be greater than zero".to_string(),
+            );
+        }
+        if self.max_file_secs == 0 {
+            return Err(
+                "data_acquisition.recording.max_file_secs must be greater than zero".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn rotation(&self) -> crate::stream_recorder::Rotation {
+        crate::stream_recorder::Rotation {
+            max_bytes: self.max_file_mb.saturating_mul(1024 * 1024),
+            max_duration: std::time::Duration::from_secs(self.max_file_secs),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -73,6 +236,9 @@ pub struct ConsoleConfig {
     pub verbosity: String,
 }
 
+fn default_tip_prep_signal() -> String {
+    "freq shift".to_string()
+}
 fn default_initial_bias_v() -> f64 {
     -500e-3
 }
@@ -165,15 +331,21 @@ fn default_read_retry_count() -> u32 {
     3
 }
 
-/// Signal-read stability thresholds: how clean a frequency-shift reading must
-/// be to be trusted as a measurement. Loosen these for noisier tips, tighten
-/// for cleaner ones. Tunable at runtime via the config file.
+/// Signal-read stability thresholds: how clean a reading of the tip-prep
+/// signal must be to be trusted as a measurement. Loosen these for noisier
+/// tips, tighten for cleaner ones. Tunable at runtime via the config file.
+///
+/// The thresholds are in the units of `tip_prep.signal`; the names and
+/// defaults are for the frequency shift (see
+/// [`TipPrepConfig::check_criterion_unit`]).
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SignalStabilityConfig {
-    /// Maximum standard deviation (Hz) of the reading to count as stable.
+    /// Maximum standard deviation of the reading to count as stable (Hz for
+    /// the frequency shift, else the signal's unit).
     #[serde(default = "default_max_std_dev_hz")]
     pub max_std_dev_hz: f64,
-    /// Maximum drift rate (Hz/s) of the reading to count as stable.
+    /// Maximum drift rate of the reading to count as stable (Hz/s for the
+    /// frequency shift, else the signal's unit per second).
     #[serde(default = "default_max_slope_hz_per_s")]
     pub max_slope_hz_per_s: f64,
     /// Data-collection window (ms) for one stable read.
@@ -207,8 +379,328 @@ impl Default for SignalStabilityConfig {
     }
 }
 
+/// Image-based acceptance check, run after a tip has been judged sharp (and
+/// stable): scan one small frame and reject the tip if the
+/// [`TipQualityAnalyzer`](crate::analyzer::TipQualityAnalyzer) finds a double
+/// tip, streaks or poor resolution. Off by default.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+// Field-level defaults via the struct-level `#[serde(default)]`, as for
+// `StabilityConfig`: naming the table should not force every field.
+#[serde(default)]
+pub struct ImageCheckConfig {
+    pub enabled: bool,
+    /// Edge length (m) of the square frame scanned around the current frame
+    /// center. `None` scans the frame as set up in Nanonis.
+    pub frame_size_m: Option<f64>,
+    /// Scan speed (m/s) for the check. `None` keeps the current speed.
+    pub scan_speed_m_s: Option<f64>,
+    /// Scan buffer channel to analyze (0-based).
+    pub channel_index: u32,
+    /// Analyze the forward (`true`) or backward scan direction.
+    pub forward: bool,
+    /// Longest the frame may take before the check fails.
+    pub timeout_secs: u64,
+    /// Reject above this double-tip score (side autocorrelation peak, 0..1).
+    /// Periodic surfaces score high too; set 1.0 to disable.
+    pub max_double_tip_score: Option<f64>,
+    /// Reject above this fraction of streaky scan lines.
+    pub max_streak_fraction: Option<f64>,
+    /// Reject above this correlation length (px), a resolution proxy.
+    pub max_correlation_length_px: Option<f64>,
+    /// Line-jump size, in robust standard deviations, that counts as a streak.
+    pub streak_sigma: f64,
+}
+
+impl Default for ImageCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            frame_size_m: Some(20e-9),
+            scan_speed_m_s: None,
+            channel_index: 0,
+            forward: true,
+            timeout_secs: 120,
+            max_double_tip_score: Some(0.35),
+            max_streak_fraction: Some(0.05),
+            max_correlation_length_px: None,
+            streak_sigma: 6.0,
+        }
+    }
+}
+
+impl ImageCheckConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.frame_size_m.is_some_and(|size| size <= 0.0) {
+            return Err("image_check.frame_size_m must be positive".to_string());
+        }
+        if self.scan_speed_m_s.is_some_and(|speed| speed <= 0.0) {
+            return Err("image_check.scan_speed_m_s must be positive".to_string());
+        }
+        if self.timeout_secs == 0 {
+            return Err("image_check.timeout_secs must be greater than zero".to_string());
+        }
+        if self.streak_sigma <= 0.0 {
+            return Err(format!(
+                "image_check.streak_sigma must be positive, got: {}",
+                self.streak_sigma
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Order in which [`RepositionConfig`] visits surface sites.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepositionPattern {
+    /// The same `reposition_steps` move every time: a straight line.
+    #[default]
+    Line,
+    /// Square spiral outward from the start site.
+    Spiral,
+    /// Back-and-forth rows inside a `raster_size` box from the start site.
+    Raster,
+    /// Random sites within `radius_sites` of the start site.
+    Random,
+}
+
+/// Where the tip goes on each reposition. Positions are tracked in *sites*:
+/// one site is `timing.reposition_steps` coarse-motor steps in x and y,
+/// counted from where the first run started.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RepositionConfig {
+    pub pattern: RepositionPattern,
+    /// Raster box (columns, rows) in sites.
+    pub raster_size: [u32; 2],
+    /// Random (and spiral) reach from the start site, in sites.
+    pub radius_sites: u32,
+    /// Random pattern seed; the same seed repeats the same walk.
+    pub seed: u64,
+    /// Sample boundaries `[x_min, x_max, y_min, y_max]` in sites from the
+    /// start site. Sites outside are never visited.
+    pub bounds_sites: Option<[i32; 4]>,
+    /// Sites this close (Chebyshev distance, in sites) to a pulsed site are
+    /// avoided. 0 avoids only the pulsed site itself.
+    pub avoid_radius_sites: u32,
+    /// JSON file holding the visited-site map across runs. The map assumes
+    /// the sample and coarse position are untouched between runs; delete the
+    /// file after moving either. `None` keeps the map in memory.
+    pub map_file: Option<String>,
+}
+
+impl Default for RepositionConfig {
+    fn default() -> Self {
+        Self {
+            pattern: RepositionPattern::Line,
+            raster_size: [10, 10],
+            radius_sites: 10,
+            seed: 1,
+            bounds_sites: None,
+            avoid_radius_sites: 0,
+            map_file: None,
+        }
+    }
+}
+
+impl RepositionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.pattern == RepositionPattern::Raster
+            && (self.raster_size[0] == 0 || self.raster_size[1] == 0)
+        {
+            return Err(format!(
+                "reposition.raster_size must be positive (got [{}, {}])",
+                self.raster_size[0], self.raster_size[1]
+            ));
+        }
+        if matches!(
+            self.pattern,
+            RepositionPattern::Spiral | RepositionPattern::Random
+        ) && self.radius_sites == 0
+        {
+            return Err("reposition.radius_sites must be greater than zero".to_string());
+        }
+        if let Some([x_min, x_max, y_min, y_max]) = self.bounds_sites
+            && (x_min > x_max || y_min > y_max)
+        {
+            return Err(format!(
+                "reposition.bounds_sites: min must not exceed max (got [{}, {}, {}, {}])",
+                x_min, x_max, y_min, y_max
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How a sharp reading is confirmed before the stability check.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmationPolicy {
+    /// Every one of `sites` readings must land in the sharp window.
+    #[default]
+    Fixed,
+    /// A confidence interval on the mean reading decides, after each site
+    /// from `min_sites` on.
+    Sequential,
+}
+
+/// Confirmation of a sharp tip: fresh sites are read until the policy
+/// accepts or rejects the tip. With `sequential`, the interval width comes
+/// from the spread of the readings, but never narrower than what the
+/// stable-read noise gate allows a single reading.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ConfirmationConfig {
+    pub policy: ConfirmationPolicy,
+    /// Sites read at most (fixed: exactly, when all are sharp).
+    pub sites: usize,
+    /// Sequential: sites read before the interval may decide.
+    pub min_sites: usize,
+    /// Sequential: two-sided confidence level of the interval, in (0, 1).
+    pub confidence: f64,
+    /// Sequential: a reading this far outside the sharp window rejects at
+    /// once, in the units of `tip_prep.signal` (Hz for the frequency shift).
+    pub outlier_hz: f64,
+}
+
+impl Default for ConfirmationConfig {
+    fn default() -> Self {
+        Self {
+            policy: ConfirmationPolicy::Fixed,
+            sites: 3,
+            min_sites: 2,
+            confidence: 0.95,
+            outlier_hz: 3.0,
+        }
+    }
+}
+
+impl ConfirmationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sites == 0 {
+            return Err("confirmation.sites must be greater than zero".to_string());
+        }
+        if self.min_sites == 0 || self.min_sites > self.sites {
+            return Err(format!(
+                "confirmation.min_sites must be between 1 and sites ({}), got: {}",
+                self.sites, self.min_sites
+            ));
+        }
+        if self.confidence <= 0.0 || self.confidence >= 1.0 {
+            return Err(format!(
+                "confirmation.confidence must be in (0, 1), got: {}",
+                self.confidence
+            ));
+        }
+        if self.outlier_hz < 0.0 {
+            return Err(format!(
+                "confirmation.outlier_hz must not be negative, got: {}",
+                self.outlier_hz
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How a tip-prep cycle reshapes the tip.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConditioningMode {
+    /// Bias pulses chosen by `[pulse_method]`.
+    #[default]
+    Pulse,
+    /// Tip-shaper indentations into the substrate, no pulses.
+    Indent,
+    /// Pulses, with an indentation after `pulses_before_indent` pulses in a
+    /// row leave the tip blunt.
+    Mixed,
+}
+
+/// Indentation conditioning through the tip shaper: push the tip a
+/// controlled depth into the substrate at a set bias, then lift it back out.
+/// The depth starts at the minimum and steps deeper while indents fail.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ConditioningConfig {
+    pub mode: ConditioningMode,
+    /// Indentation depth range (m), positive magnitudes, shallowest first.
+    pub depth_bounds_m: (f64, f64),
+    /// Number of steps between the minimum and maximum depth.
+    pub depth_steps: u16,
+    /// Indents at one depth that leave the tip blunt before going deeper.
+    pub indents_before_step: u16,
+    /// Bias (V) while the tip is in the surface.
+    pub bias_v: f64,
+    /// Time (ms) to move down to the indentation depth.
+    pub indent_time_ms: u64,
+    /// Lift (m) after the indent, relative to the starting height.
+    pub lift_height_m: f64,
+    /// Bias (V) applied for the lift.
+    pub lift_bias_v: f64,
+    /// Time (ms) to lift the tip back out.
+    pub lift_time_ms: u64,
+    /// Mixed mode: blunt pulses in a row before an indentation.
+    pub pulses_before_indent: u32,
+}
+
+impl Default for ConditioningConfig {
+    fn default() -> Self {
+        Self {
+            mode: ConditioningMode::Pulse,
+            depth_bounds_m: (0.5e-9, 2e-9),
+            depth_steps: 3,
+            indents_before_step: 2,
+            bias_v: 0.05,
+            indent_time_ms: 200,
+            lift_height_m: 2e-9,
+            lift_bias_v: 0.05,
+            lift_time_ms: 200,
+            pulses_before_indent: 5,
+        }
+    }
+}
+
+impl ConditioningConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = self.depth_bounds_m;
+        if min <= 0.0 || max <= 0.0 {
+            return Err(format!(
+                "conditioning.depth_bounds_m must be positive (got [{}, {}]). Depths are magnitudes into the surface.",
+                min, max
+            ));
+        }
+        if min > max {
+            return Err(format!(
+                "conditioning.depth_bounds_m: min ({}) must not exceed max ({})",
+                min, max
+            ));
+        }
+        if self.depth_steps == 0 {
+            return Err("conditioning.depth_steps must be greater than zero".to_string());
+        }
+        if self.lift_height_m < 0.0 {
+            return Err(format!(
+                "conditioning.lift_height_m must not be negative, got: {}",
+                self.lift_height_m
+            ));
+        }
+        if self.mode == ConditioningMode::Mixed && self.pulses_before_indent == 0 {
+            return Err(
+                "conditioning.pulses_before_indent must be greater than zero in mixed mode"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TipPrepConfig {
+    /// Signal judged against `sharp_tip_bounds`, by name or alias; may be a
+    /// virtual signal. The read gates are in its units, see
+    /// [`check_criterion_unit`](Self::check_criterion_unit).
+    #[serde(default = "default_tip_prep_signal")]
+    pub signal: String,
     pub sharp_tip_bounds: [f64; 2],
     pub max_cycles: Option<usize>,
     pub max_duration_secs: Option<u64>,
@@ -225,6 +717,18 @@ pub struct TipPrepConfig {
     /// Signal-read stability thresholds (frequency-shift noise/drift gates)
     #[serde(default)]
     pub signal_stability: SignalStabilityConfig,
+    /// Scan-and-analyze acceptance check after the tip is judged sharp
+    #[serde(default)]
+    pub image_check: ImageCheckConfig,
+    /// Pulse, indent or mixed conditioning, and the indentation parameters
+    #[serde(default)]
+    pub conditioning: ConditioningConfig,
+    /// Reposition pattern and the visited-site map
+    #[serde(default)]
+    pub reposition: RepositionConfig,
+    /// How a sharp reading is confirmed before the stability check
+    #[serde(default)]
+    pub confirmation: ConfirmationConfig,
 }
 
 impl Default for NanonisConfig {
@@ -244,6 +748,7 @@ impl Default for DataAcquisitionConfig {
             data_port: 6590,
             sample_rate: 2000,
             stable_signal_samples: default_stable_signal_samples(),
+            recording: StreamRecordingConfig::default(),
         }
     }
 }
@@ -265,9 +770,103 @@ impl Default for ConsoleConfig {
     }
 }
 
+impl TipPrepConfig {
+    /// Gates judged in the units of `signal` that still hold their defaults,
+    /// which are frequency shifts in Hz, by config path.
+    pub fn gates_at_hz_defaults(&self) -> Vec<&'static str> {
+        let reads = SignalStabilityConfig::default();
+        let confirmation = ConfirmationConfig::default();
+        let stability = StabilityConfig::default();
+        let tip_change = &self.stability.tip_change;
+        let tip_change_defaults = TipChangeDetection::default();
+        [
+            (
+                "tip_prep.signal_stability.max_std_dev_hz",
+                self.signal_stability.max_std_dev_hz == reads.max_std_dev_hz,
+            ),
+            (
+                "tip_prep.signal_stability.max_slope_hz_per_s",
+                self.signal_stability.max_slope_hz_per_s == reads.max_slope_hz_per_s,
+            ),
+            (
+                "tip_prep.confirmation.outlier_hz",
+                self.confirmation.outlier_hz == confirmation.outlier_hz,
+            ),
+            (
+                "tip_prep.stability.stable_tip_allowed_change",
+                self.stability.check_stability
+                    && self.stability.stable_tip_allowed_change
+                        == stability.stable_tip_allowed_change,
+            ),
+            (
+                "tip_prep.stability.tip_change.max_freq_shift_jump_hz",
+                tip_change.enabled
+                    && tip_change.max_freq_shift_jump_hz
+                        == tip_change_defaults.max_freq_shift_jump_hz,
+            ),
+            (
+                "tip_prep.stability.tip_change.max_freq_shift_std_dev_hz",
+                tip_change.enabled
+                    && tip_change.max_freq_shift_std_dev_hz
+                        == tip_change_defaults.max_freq_shift_std_dev_hz,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(path, at_default)| at_default.then_some(path))
+        .collect()
+    }
+
+    /// The gates named `*_hz` are judged in the units of `signal`. Refuse a
+    /// criterion `unit` other than Hz while any of them still holds its Hz
+    /// default: a 1.5 Hz noise gate on a conductance is no gate at all.
+    /// A signal of unknown unit passes.
+    pub fn check_criterion_unit(&self, unit: Option<&SignalUnit>) -> Result<(), String> {
+        let Some(unit) = unit.filter(|u| u.symbol != "Hz") else {
+            return Ok(());
+        };
+        let gates = self.gates_at_hz_defaults();
+        if gates.is_empty() {
+            return Ok(());
+        }
+        let measured = if unit.symbol.is_empty() {
+            "dimensionless".to_string()
+        } else {
+            format!("in {}", unit.symbol)
+        };
+        Err(format!(
+            "tip_prep.signal \"{}\" is {measured}, but these gates judged in its units still \
+             hold their Hz defaults: {}. Set them for the signal",
+            self.signal,
+            gates.join(", ")
+        ))
+    }
+
+    /// Check the sharp window and every sub-section.
+    pub fn validate(&self) -> Result<(), String> {
+        self.validate_sharp_tip_bounds()?;
+        self.stability.validate()?;
+        self.image_check.validate()?;
+        self.conditioning.validate()?;
+        self.reposition.validate()?;
+        self.confirmation.validate()
+    }
+
+    /// The sharp window must have its lower bound below its upper one.
+    pub fn validate_sharp_tip_bounds(&self) -> Result<(), String> {
+        let [low, high] = self.sharp_tip_bounds;
+        if low >= high {
+            return Err(format!(
+                "sharp_tip_bounds: lower bound ({low}) must be below the upper bound ({high})"
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl Default for TipPrepConfig {
     fn default() -> Self {
         Self {
+            signal: default_tip_prep_signal(),
             sharp_tip_bounds: [-2.0, 0.0],
             max_cycles: Some(10000),
             max_duration_secs: Some(12000),
@@ -277,6 +876,10 @@ impl Default for TipPrepConfig {
             safe_tip_threshold: default_safe_tip_threshold(),
             timing: TimingConfig::default(),
             signal_stability: SignalStabilityConfig::default(),
+            image_check: ImageCheckConfig::default(),
+            conditioning: ConditioningConfig::default(),
+            reposition: RepositionConfig::default(),
+            confirmation: ConfirmationConfig::default(),
         }
     }
 }
@@ -285,6 +888,23 @@ impl Default for TipPrepConfig {
 ///
 /// Returns an error if the file does not exist or is invalid.
 pub fn load_config(config_path: &Path) -> Result<AppConfig, ConfigError> {
+    let config = file_with_env(config_path)?;
+    let app_config = config.try_deserialize::<AppConfig>()?;
+    app_config.validate()?;
+    Ok(app_config)
+}
+
+/// Load a config file as an untyped document, with the same environment
+/// overrides as [`load_config`].
+///
+/// Routine config sections are looked up in it by name (see
+/// [`RoutineRegistry`](crate::routine::registry::RoutineRegistry)), including
+/// sections [`AppConfig`] knows nothing about.
+pub fn load_config_document(config_path: &Path) -> Result<serde_json::Value, ConfigError> {
+    file_with_env(config_path)?.try_deserialize()
+}
+
+fn file_with_env(config_path: &Path) -> Result<Config, ConfigError> {
     let mut builder = Config::builder();
 
     if config_path.exists() {
@@ -302,10 +922,7 @@ pub fn load_config(config_path: &Path) -> Result<AppConfig, ConfigError> {
             .try_parsing(true),
     );
 
-    let config = builder.build()?;
-    let app_config = config.try_deserialize::<AppConfig>()?;
-    app_config.validate()?;
-    Ok(app_config)
+    builder.build()
 }
 
 /// Load configuration with optional path and fallback behavior.
@@ -50,6 +50,8 @@ pub struct StabilityConfig {
     pub polarity_mode: BiasSweepPolarity,
     /// Scan speed for stability check in m/s (None = use current scan speed)
     pub scan_speed_m_s: Option<f64>,
+    /// Live tip-change detection during the sweep
+    pub tip_change: TipChangeDetection,
 }
 
 impl Default for StabilityConfig {
@@ -63,6 +65,7 @@ impl Default for StabilityConfig {
             max_duration_secs: 100,
             polarity_mode: BiasSweepPolarity::Both,
             scan_speed_m_s: Some(5e-9), // 5 nm/s default
+            tip_change: TipChangeDetection::default(),
         }
     }
 }
@@ -91,6 +94,65 @@ impl StabilityConfig {
         if self.bias_steps == 0 {
             return Err("bias_steps must be greater than zero".to_string());
         }
+        self.tip_change.validate()
+    }
+}
+
+// ============================================================================
+// TIP CHANGE DETECTION
+// ============================================================================
+
+/// Live detection of tip changes while the stability sweep runs.
+/// The stream samples of the frequency shift (and the current, if the
+/// signal is known) taken during a sweep step are averaged into
+/// quarter-step readings, each compared with the median of the last
+/// `window` readings. A jump or a noisy window ends the sweep early as
+/// unstable. Skipped, with a warning, without a data stream.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TipChangeDetection {
+    /// Whether to judge the signals throughout every sweep step
+    pub enabled: bool,
+    /// Readings (four per step) the reference median and noise are computed over
+    pub window: usize,
+    /// Max freq-shift deviation from the reference median (Hz)
+    pub max_freq_shift_jump_hz: f64,
+    /// Max current deviation from the reference median, as a fraction of it
+    pub max_current_jump_fraction: f64,
+    /// Max freq-shift standard deviation over the window (Hz)
+    pub max_freq_shift_std_dev_hz: f64,
+}
+
+impl Default for TipChangeDetection {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: 10,
+            max_freq_shift_jump_hz: 1.0,
+            max_current_jump_fraction: 0.5,
+            max_freq_shift_std_dev_hz: 0.5,
+        }
+    }
+}
+
+impl TipChangeDetection {
+    /// Validate configuration values
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window < 2 {
+            return Err(format!(
+                "tip_change.window must be at least 2, got: {}",
+                self.window
+            ));
+        }
+        for (name, value) in [
+            ("max_freq_shift_jump_hz", self.max_freq_shift_jump_hz),
+            ("max_current_jump_fraction", self.max_current_jump_fraction),
+            ("max_freq_shift_std_dev_hz", self.max_freq_shift_std_dev_hz),
+        ] {
+            if value <= 0.0 {
+                return Err(format!("tip_change.{name} must be positive, got: {value}"));
+            }
+        }
         Ok(())
     }
 }
@@ -131,6 +193,59 @@ fn default_enabled() -> bool {
     true
 }
 
+// ============================================================================
+// PULSE WIDTH STEPPING
+// ============================================================================
+
+/// Pulse width as a second dimension of any pulse method.
+/// width_bounds_ms: (min_width, max_width) - pulses start at min_width
+/// width_steps: number of steps between min and max width
+/// cycles_at_max_voltage: pulses at the method's max voltage before the
+/// width steps up; the width drops back to min once the voltage does
+/// (the Fixed method never drops, so its escalation lasts until a failed
+/// stability check resets the pulse state; the Adaptive method learns the
+/// width instead)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PulseWidthStepping {
+    pub width_bounds_ms: (u64, u64),
+    pub width_steps: u16,
+    #[serde(default = "default_cycles_at_max_voltage")]
+    pub cycles_at_max_voltage: u16,
+}
+
+fn default_cycles_at_max_voltage() -> u16 {
+    2
+}
+
+impl PulseWidthStepping {
+    /// Width of the `step`-th escalation, clamped to the max width.
+    pub fn width_at(&self, step: u16) -> u64 {
+        let (min, max) = self.width_bounds_ms;
+        let step_size = (max - min) as f64 / self.width_steps.max(1) as f64;
+        ((min as f64 + step_size * step as f64).round() as u64).min(max)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let (min, max) = self.width_bounds_ms;
+        if min == 0 || max == 0 {
+            return Err(format!(
+                "pulse_width width_bounds_ms must be positive (got [{}, {}])",
+                min, max
+            ));
+        }
+        if min >= max {
+            return Err(format!(
+                "pulse_width width_bounds_ms: min ({}) must be less than max ({})",
+                min, max
+            ));
+        }
+        if self.width_steps == 0 {
+            return Err("width_steps must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
 // ============================================================================
 // PULSE METHOD
 // ============================================================================
@@ -144,6 +259,8 @@ pub enum PulseMethod {
         polarity: PolaritySign,
         #[serde(default, alias = "random_switch")]
         random_polarity_switch: Option<RandomPolaritySwitch>,
+        #[serde(default)]
+        pulse_width: Option<PulseWidthStepping>,
     },
     Stepping {
         voltage_bounds: (f64, f64),
@@ -154,10 +271,13 @@ pub enum PulseMethod {
         polarity: PolaritySign,
         #[serde(default, alias = "random_switch")]
         random_polarity_switch: Option<RandomPolaritySwitch>,
+        #[serde(default)]
+        pulse_width: Option<PulseWidthStepping>,
     },
-    /// Linear response based on frequency shift
+    /// Linear response based on the tip-prep signal
     /// voltage_bounds: (min_voltage, max_voltage) - pulse voltage range in V
-    /// linear_clamp: (min_freq, max_freq) - frequency shift range in Hz
+    /// linear_clamp: (min, max) - range of the tip-prep signal, in its units
+    /// (Hz for the frequency shift)
     /// If freq_shift is outside linear_clamp range, pulse with max voltage
     /// If freq_shift is inside linear_clamp range, linearly interpolate voltage
     Linear {
@@ -167,9 +287,47 @@ pub enum PulseMethod {
         polarity: PolaritySign,
         #[serde(default, alias = "random_switch")]
         random_polarity_switch: Option<RandomPolaritySwitch>,
+        #[serde(default)]
+        pulse_width: Option<PulseWidthStepping>,
+    },
+    /// Learn which pulse works during the run (discounted UCB bandit)
+    /// voltage_bounds: (min_voltage, max_voltage) - spanned by voltage_levels arms
+    /// explore_polarity: also try every voltage at the opposite polarity
+    /// exploration: weight of the exploration bonus (0 = always exploit)
+    /// discount: per-pulse decay of past results, in (0, 1]; 1 = never forget
+    /// pulse_width: its width_steps + 1 widths become an arm dimension,
+    /// learned with the voltage instead of escalating at the max voltage
+    Adaptive {
+        voltage_bounds: (f64, f64),
+        #[serde(default = "default_voltage_levels")]
+        voltage_levels: u16,
+        #[serde(default)]
+        explore_polarity: bool,
+        #[serde(default = "default_exploration")]
+        exploration: f64,
+        #[serde(default = "default_discount")]
+        discount: f64,
+        #[serde(default)]
+        polarity: PolaritySign,
+        #[serde(default, alias = "random_switch")]
+        random_polarity_switch: Option<RandomPolaritySwitch>,
+        #[serde(default)]
+        pulse_width: Option<PulseWidthStepping>,
     },
 }
 
+fn default_voltage_levels() -> u16 {
+    5
+}
+
+fn default_exploration() -> f64 {
+    0.5
+}
+
+fn default_discount() -> f64 {
+    0.98
+}
+
 impl PulseMethod {
     #[allow(dead_code)]
     pub fn stepping_fixed_threshold(
@@ -187,6 +345,7 @@ impl PulseMethod {
             threshold_value: threshold_value.abs(),
             polarity,
             random_polarity_switch,
+            pulse_width: None,
         }
     }
 
@@ -195,6 +354,7 @@ impl PulseMethod {
             PulseMethod::Fixed { .. } => "Fixed",
             PulseMethod::Stepping { .. } => "Stepping",
             PulseMethod::Linear { .. } => "Linear",
+            PulseMethod::Adaptive { .. } => "Adaptive",
         }
     }
 
@@ -204,11 +364,25 @@ impl PulseMethod {
             PulseMethod::Fixed { voltage, .. } => *voltage,
             PulseMethod::Stepping { voltage_bounds, .. } => voltage_bounds.1,
             PulseMethod::Linear { voltage_bounds, .. } => voltage_bounds.1,
+            PulseMethod::Adaptive { voltage_bounds, .. } => voltage_bounds.1,
+        }
+    }
+
+    /// Width stepping shared by all methods, if configured
+    pub fn pulse_width(&self) -> Option<&PulseWidthStepping> {
+        match self {
+            PulseMethod::Fixed { pulse_width, .. }
+            | PulseMethod::Stepping { pulse_width, .. }
+            | PulseMethod::Linear { pulse_width, .. }
+            | PulseMethod::Adaptive { pulse_width, .. } => pulse_width.as_ref(),
         }
     }
 
     /// Validate pulse method configuration
     pub fn validate(&self) -> Result<(), String> {
+        if let Some(width) = self.pulse_width() {
+            width.validate()?;
+        }
         match self {
             PulseMethod::Fixed { voltage, .. } => {
                 if *voltage <= 0.0 {
@@ -263,6 +437,38 @@ impl PulseMethod {
                     ));
                 }
             }
+            PulseMethod::Adaptive {
+                voltage_bounds,
+                voltage_levels,
+                exploration,
+                discount,
+                ..
+            } => {
+                if voltage_bounds.0 <= 0.0 || voltage_bounds.1 <= 0.0 {
+                    return Err(format!(
+                        "Adaptive voltage_bounds must be positive (got [{}, {}]). Use polarity to control sign.",
+                        voltage_bounds.0, voltage_bounds.1
+                    ));
+                }
+                if voltage_bounds.0 > voltage_bounds.1 {
+                    return Err(format!(
+                        "Adaptive voltage_bounds: min ({}) must not exceed max ({})",
+                        voltage_bounds.0, voltage_bounds.1
+                    ));
+                }
+                if *voltage_levels == 0 {
+                    return Err("voltage_levels must be greater than zero".to_string());
+                }
+                if *exploration < 0.0 {
+                    return Err(format!(
+                        "exploration must not be negative, got: {}",
+                        exploration
+                    ));
+                }
+                if *discount <= 0.0 || *discount > 1.0 {
+                    return Err(format!("discount must be in (0, 1], got: {}", discount));
+                }
+            }
         }
         Ok(())
     }
@@ -277,6 +483,7 @@ impl Default for PulseMethod {
             threshold_value: 0.1,
             polarity: PolaritySign::Positive,
             random_polarity_switch: None,
+            pulse_width: None,
         }
     }
 }
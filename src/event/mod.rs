@@ -1,18 +1,25 @@
 mod observer;
+mod record;
 
 pub use observer::{ChannelForwarder, ConsoleLogger, EventAccumulator, FileLogger, Observer};
+pub use record::{EVENT_SCHEMA_VERSION, EventRecord, read_event_log};
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, SystemTime};
 
-use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 use crate::action::ActionOutput;
+use crate::signal_registry::SignalIndex;
+use crate::units::SignalUnits;
 
 /// Structured event emitted during execution.
 ///
 /// All variants are `Clone + Serialize` so they can be broadcast to multiple
-/// observers, written to JSONL logs, and accumulated for LLM context windows.
-#[derive(Debug, Clone, Serialize)]
+/// observers, written to JSONL logs, and accumulated for LLM context windows,
+/// and `Deserialize` so logs can be read back (see [`read_event_log`]).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Event {
     /// Emitted by the executor before running an action.
@@ -48,6 +55,8 @@ pub enum Event {
         timestamp: SystemTime,
     },
     /// Escape hatch for domain-specific events from user-defined actions.
+    /// Kinds with a fixed shape implement [`EventPayload`] so they can be
+    /// built with [`Event::payload`] and read with [`Event::decode`].
     Custom {
         kind: String,
         data: serde_json::Value,
@@ -101,6 +110,35 @@ impl Event {
             data,
         }
     }
+
+    /// A `Custom` event of kind `P::KIND` carrying `payload`.
+    pub fn payload<P: EventPayload>(payload: &P) -> Self {
+        let data = serde_json::to_value(payload).unwrap_or_else(|e| {
+            log::warn!("Failed to serialize {} payload: {e}", P::KIND);
+            serde_json::Value::Null
+        });
+        Self::custom(P::KIND, data)
+    }
+
+    /// The typed payload of a `Custom` event of kind `P::KIND`; `None` for
+    /// any other event, or (with a warning) data that does not match `P`.
+    pub fn decode<P: EventPayload>(&self) -> Option<P> {
+        let Event::Custom { kind, data } = self else {
+            return None;
+        };
+        if kind != P::KIND {
+            return None;
+        }
+        serde_json::from_value(data.clone())
+            .inspect_err(|e| log::warn!("Malformed {kind} event: {e}"))
+            .ok()
+    }
+}
+
+/// The typed data of one kind of [`Event::Custom`], e.g. the tip-prep
+/// routine's state snapshots. `KIND` is the event's `kind`.
+pub trait EventPayload: Serialize + DeserializeOwned {
+    const KIND: &'static str;
 }
 
 /// Trait for broadcasting events. Passed into `ActionContext`.
@@ -109,20 +147,84 @@ pub trait EventEmitter: Send + Sync {
 }
 
 /// Broadcasts events to multiple observers.
+///
+/// Each bus is one run: it stamps every event with its run ID and a
+/// sequence number into an [`EventRecord`] before handing it out.
 pub struct EventBus {
     observers: Vec<Box<dyn Observer>>,
+    units: SignalUnits,
+    run_id: String,
+    seq: AtomicU64,
 }
 
 impl EventBus {
     pub fn new() -> Self {
         Self {
             observers: Vec::new(),
+            units: SignalUnits::default(),
+            run_id: new_run_id(),
+            seq: AtomicU64::new(0),
         }
     }
 
+    /// ID of this bus's run, e.g. `20261018T093012.402Z-1a2b`: its start time
+    /// (UTC) and process ID.
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Use `run_id` instead of the generated one, e.g. to continue a run's
+    /// log under the ID of an earlier session.
+    pub fn set_run_id(&mut self, run_id: impl Into<String>) {
+        self.run_id = run_id.into();
+    }
+
     pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
         self.observers.push(observer);
     }
+
+    /// Units to label measurements with. A `DataCollected` event whose value
+    /// names a signal `index` and a numeric `value` (like `stable_read`)
+    /// gains that signal's `unit`, `precision` and a formatted `display`
+    /// string before observers see it.
+    pub fn set_signal_units(&mut self, units: SignalUnits) {
+        self.units = units;
+    }
+}
+
+fn new_run_id() -> String {
+    format!(
+        "{}-{:x}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+        std::process::id()
+    )
+}
+
+/// Add the unit of the measured signal to a measurement event, if known.
+fn annotate_units(event: &mut Event, units: &SignalUnits) {
+    let Event::DataCollected { value, .. } = event else {
+        return;
+    };
+    let Some(fields) = value.as_object_mut() else {
+        return;
+    };
+    if fields.contains_key("unit") {
+        return;
+    }
+    let index = fields.get("index").and_then(serde_json::Value::as_u64);
+    let measured = fields.get("value").and_then(serde_json::Value::as_f64);
+    let (Some(index), Some(measured)) = (index, measured) else {
+        return;
+    };
+    let Some(unit) = u32::try_from(index)
+        .ok()
+        .and_then(|index| units.get(SignalIndex(index)))
+    else {
+        return;
+    };
+    fields.insert("unit".into(), unit.symbol.clone().into());
+    fields.insert("precision".into(), unit.precision.into());
+    fields.insert("display".into(), unit.format(measured).into());
 }
 
 impl Default for EventBus {
@@ -132,9 +234,18 @@ impl Default for EventBus {
 }
 
 impl EventEmitter for EventBus {
-    fn emit(&self, event: Event) {
+    fn emit(&self, mut event: Event) {
+        if !self.units.is_empty() {
+            annotate_units(&mut event, &self.units);
+        }
+        let record = EventRecord {
+            schema: EVENT_SCHEMA_VERSION,
+            run_id: self.run_id.clone(),
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            event,
+        };
         for observer in &self.observers {
-            observer.on_event(&event);
+            observer.on_record(&record);
         }
     }
 }
@@ -149,8 +260,8 @@ impl EventEmitter for NullEmitter {
 // -- Serde helpers --
 
 mod system_time_serde {
-    use serde::Serializer;
-    use std::time::SystemTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, SystemTime};
 
     pub fn serialize<S: Serializer>(time: &SystemTime, ser: S) -> Result<S::Ok, S::Error> {
         let duration = time
@@ -158,15 +269,27 @@ mod system_time_serde {
             .unwrap_or_default();
         ser.serialize_f64(duration.as_secs_f64())
     }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<SystemTime, D::Error> {
+        let secs = f64::deserialize(de)?;
+        Duration::try_from_secs_f64(secs)
+            .map(|since_epoch| SystemTime::UNIX_EPOCH + since_epoch)
+            .map_err(serde::de::Error::custom)
+    }
 }
 
 mod duration_ms_serde {
-    use serde::Serializer;
+    use serde::{Deserialize, Deserializer, Serializer};
     use std::time::Duration;
 
     pub fn serialize<S: Serializer>(dur: &Duration, ser: S) -> Result<S::Ok, S::Error> {
         ser.serialize_f64(dur.as_secs_f64() * 1000.0)
     }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Duration, D::Error> {
+        let ms = f64::deserialize(de)?;
+        Duration::try_from_secs_f64(ms / 1000.0).map_err(serde::de::Error::custom)
+    }
 }
 
 #[cfg(test)]
@@ -255,6 +378,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn payloads_round_trip_and_decode_only_their_kind() {
+        use crate::tip_prep::{TipPrepSnapshot, TipPrepState};
+
+        let event = Event::payload(&TipPrepState::Pulsing(TipPrepSnapshot {
+            cycle: 3,
+            elapsed_secs: 1.5,
+            freq_shift: Some(-4.0),
+            pulse_voltage: 5.0,
+            pulse_width_ms: 50,
+            indent_depth_m: None,
+            is_sharp: false,
+        }));
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["kind"], "tip_prep_state");
+        assert_eq!(json["data"]["phase"], "pulsing");
+        assert_eq!(json["data"]["cycle"], 3);
+
+        let back: Event = serde_json::from_value(json).unwrap();
+        let state = back.decode::<TipPrepState>().unwrap();
+        assert_eq!(state.phase(), "pulsing");
+        assert_eq!(state.snapshot().unwrap().pulse_width_ms, 50);
+
+        let other = Event::custom("pulse_arms", serde_json::json!({}));
+        assert!(other.decode::<TipPrepState>().is_none());
+        let malformed = Event::custom("tip_prep_state", serde_json::json!({"phase": "nope"}));
+        assert!(malformed.decode::<TipPrepState>().is_none());
+    }
+
     // -- Serialization tests --
 
     #[test]
@@ -323,6 +475,42 @@ mod tests {
         // No panic = pass
     }
 
+    #[test]
+    fn measurements_of_a_signal_with_a_unit_are_labelled() {
+        let seen = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        struct Recorder(Arc<parking_lot::Mutex<Vec<Event>>>);
+        impl Observer for Recorder {
+            fn on_event(&self, event: &Event) {
+                self.0.lock().push(event.clone());
+            }
+        }
+        let mut units = SignalUnits::default();
+        units.insert(SignalIndex(2), crate::units::SignalUnit::new("Hz", 2));
+        let mut bus = EventBus::new();
+        bus.add_observer(Box::new(Recorder(seen.clone())));
+        bus.set_signal_units(units);
+
+        bus.emit(Event::data_collected(
+            "stable_read",
+            serde_json::json!({ "index": 2, "value": -12.345 }),
+        ));
+        bus.emit(Event::data_collected(
+            "stable_read",
+            serde_json::json!({ "index": 7, "value": 1.0 }),
+        ));
+
+        let seen = seen.lock();
+        let Event::DataCollected { value, .. } = &seen[0] else {
+            panic!("wrong variant");
+        };
+        assert_eq!(value["unit"], "Hz");
+        assert_eq!(value["display"], "-12.35 Hz");
+        let Event::DataCollected { value, .. } = &seen[1] else {
+            panic!("wrong variant");
+        };
+        assert!(value.get("unit").is_none());
+    }
+
     #[test]
     fn null_emitter_does_not_panic() {
         let emitter = NullEmitter;
@@ -3,14 +3,25 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::sync::Mutex;
 
-use super::Event;
+use super::{Event, EventRecord};
 
 /// Trait for consuming events.
 pub trait Observer: Send + Sync {
     fn on_event(&self, event: &Event);
+
+    /// An event with its run ID, sequence number and schema version, as the
+    /// [`EventBus`](super::EventBus) emits it. Defaults to
+    /// [`on_event`](Self::on_event); override to keep the envelope.
+    fn on_record(&self, record: &EventRecord) {
+        self.on_event(&record.event);
+    }
 }
 
 /// Writes events as JSONL (one JSON object per line) to a file.
+///
+/// Events from an [`EventBus`](super::EventBus) are written as
+/// [`EventRecord`]s: the event's fields plus `schema`, `run_id` and `seq`.
+/// Read them back with [`read_event_log`](super::read_event_log).
 pub struct FileLogger {
     writer: Mutex<BufWriter<File>>,
 }
@@ -23,8 +34,8 @@ impl FileLogger {
     }
 }
 
-impl Observer for FileLogger {
-    fn on_event(&self, event: &Event) {
+impl FileLogger {
+    fn write<T: serde::Serialize>(&self, entry: &T) {
         // Recover from a poisoned mutex so one panicking observer elsewhere
         // doesn't silently stop event logging for the rest of the session.
         let mut w = match self.writer.lock() {
@@ -34,7 +45,7 @@ impl Observer for FileLogger {
                 poison.into_inner()
             }
         };
-        let json = match serde_json::to_string(event) {
+        let json = match serde_json::to_string(entry) {
             Ok(j) => j,
             Err(e) => {
                 log::warn!("FileLogger: failed to serialize event: {e}");
@@ -47,6 +58,16 @@ impl Observer for FileLogger {
     }
 }
 
+impl Observer for FileLogger {
+    fn on_event(&self, event: &Event) {
+        self.write(event);
+    }
+
+    fn on_record(&self, record: &EventRecord) {
+        self.write(record);
+    }
+}
+
 // `BufWriter` swallows write errors encountered during its own Drop-time flush.
 // Explicit flush surfaces any late failures (disk full, broken pipe) instead of
 // losing the tail of the log silently.
@@ -143,7 +164,7 @@ impl Observer for EventAccumulator {
 /// - `[action] starting: <name>`
 /// - `[action] completed: <name> (<ms>ms)`
 /// - `[action] FAILED: <name> (<ms>ms): <error>`
-/// - `[data] collected: <label>`
+/// - `[data] collected: <label>` (`= <display>` for measurements with a unit)
 /// - `[event] <kind>`
 pub struct ConsoleLogger;
 
@@ -172,8 +193,11 @@ impl Observer for ConsoleLogger {
                     duration.as_secs_f64() * 1000.0
                 );
             }
-            Event::DataCollected { label, .. } => {
-                eprintln!("[data] collected: {label}");
+            Event::DataCollected { label, value, .. } => {
+                match value.get("display").and_then(|d| d.as_str()) {
+                    Some(display) => eprintln!("[data] collected: {label} = {display}"),
+                    None => eprintln!("[data] collected: {label}"),
+                }
             }
             Event::Custom { kind, .. } => {
                 eprintln!("[event] {kind}");
@@ -0,0 +1,143 @@
+//! Events as written to JSONL logs, and reading those logs back.
+
+use std::io::{self, BufRead};
+
+use serde::{Deserialize, Serialize};
+
+use super::Event;
+use crate::spm_error::SpmError;
+
+/// Version of the event log format. Bump it when an event's fields change
+/// in a way older readers would misread.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// One event of a run, as the [`EventBus`](super::EventBus) emits it and
+/// [`FileLogger`](super::FileLogger) writes it: the event's own fields, with
+/// `type`, next to the envelope fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Schema version the record was written with; 0 for logs written
+    /// before records carried one.
+    #[serde(default)]
+    pub schema: u32,
+    /// ID of the run the event belongs to; empty in version-0 logs.
+    #[serde(default)]
+    pub run_id: String,
+    /// Position of the event in its run, from 0.
+    #[serde(default)]
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Read a JSONL event log line by line. Blank lines are skipped; a line
+/// that is not an event, or was written with a newer schema than
+/// [`EVENT_SCHEMA_VERSION`], is an error for that line only.
+pub fn read_event_log<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<EventRecord, SpmError>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(text) if text.trim().is_empty()))
+        .map(|(i, line)| {
+            let line_no = i + 1;
+            let text = line.map_err(|source| SpmError::Io {
+                source,
+                context: format!("reading event log line {line_no}"),
+            })?;
+            let record: EventRecord = serde_json::from_str(&text).map_err(|e| SpmError::Io {
+                source: io::Error::new(io::ErrorKind::InvalidData, e),
+                context: format!("parsing event log line {line_no}"),
+            })?;
+            if record.schema > EVENT_SCHEMA_VERSION {
+                return Err(SpmError::Workflow(format!(
+                    "Event log line {line_no} has schema {}, this build reads up to {}",
+                    record.schema, EVENT_SCHEMA_VERSION
+                )));
+            }
+            Ok(record)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::ActionOutput;
+    use crate::event::{EventBus, EventEmitter, FileLogger};
+    use std::time::Duration;
+
+    #[test]
+    fn a_logged_run_reads_back_with_its_envelope() {
+        let tmp = std::env::temp_dir().join("rusty_tip_test_event_record.jsonl");
+        let run_id;
+        {
+            let mut bus = EventBus::new();
+            run_id = bus.run_id().to_string();
+            bus.add_observer(Box::new(FileLogger::new(
+                std::fs::File::create(&tmp).unwrap(),
+            )));
+            bus.emit(Event::action_started(
+                "set_bias",
+                serde_json::json!({ "voltage": 1.5 }),
+            ));
+            bus.emit(Event::action_completed(
+                "set_bias",
+                &ActionOutput::Unit,
+                Duration::from_millis(12),
+            ));
+            bus.emit(Event::action_failed(
+                "withdraw",
+                "timeout",
+                Duration::from_millis(500),
+            ));
+            bus.emit(Event::data_collected(
+                "stable_read",
+                serde_json::json!({ "value": -1.0 }),
+            ));
+            bus.emit(Event::custom("note", serde_json::json!({ "n": 1 })));
+        }
+        let file = std::io::BufReader::new(std::fs::File::open(&tmp).unwrap());
+        let records: Vec<EventRecord> = read_event_log(file).map(Result::unwrap).collect();
+        let _ = std::fs::remove_file(&tmp);
+
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|r| r.schema == EVENT_SCHEMA_VERSION));
+        assert!(records.iter().all(|r| r.run_id == run_id));
+        let seqs: Vec<u64> = records.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, vec![0, 1, 2, 3, 4]);
+        match &records[0].event {
+            Event::ActionStarted { action, params, .. } => {
+                assert_eq!(action, "set_bias");
+                assert_eq!(params["voltage"], 1.5);
+            }
+            other => panic!("Wrong variant: {other:?}"),
+        }
+        match &records[1].event {
+            Event::ActionCompleted { duration, .. } => {
+                assert_eq!(*duration, Duration::from_millis(12))
+            }
+            other => panic!("Wrong variant: {other:?}"),
+        }
+        assert!(matches!(&records[4].event, Event::Custom { kind, .. } if kind == "note"));
+    }
+
+    #[test]
+    fn version_0_lines_read_and_newer_schemas_are_refused() {
+        let log = concat!(
+            r#"{"type":"custom","kind":"old","data":null}"#,
+            "\n\n",
+            r#"{"schema":99,"run_id":"r","seq":0,"type":"custom","kind":"new","data":null}"#,
+            "\n",
+            "not json\n",
+        );
+        let records: Vec<_> = read_event_log(log.as_bytes()).collect();
+
+        assert_eq!(records.len(), 3);
+        let old = records[0].as_ref().unwrap();
+        assert_eq!(old.schema, 0);
+        assert!(old.run_id.is_empty());
+        assert!(matches!(records[1], Err(SpmError::Workflow(_))));
+        assert!(matches!(records[2], Err(SpmError::Io { .. })));
+    }
+}
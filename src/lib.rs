@@ -13,22 +13,29 @@ pub mod tip_prep;
 pub mod config;
 pub mod controller_types;
 pub mod event;
+pub mod session;
 pub mod shutdown;
+pub mod signal_profile;
 pub mod signal_registry;
+pub mod stream_recorder;
+pub mod virtual_signals;
 
 // -- Analysis and display --
 pub mod analyzer;
 pub mod plotting;
 pub mod types;
+pub mod units;
 
 // -- Internal plumbing (not part of the public API) --
 mod buffered_tcp_reader;
 pub(crate) mod utils;
 
+pub use buffered_tcp_reader::StreamStats;
 pub use controller_types::{
-    BiasSweepPolarity, PolaritySign, PulseMethod, RandomPolaritySwitch, StabilityConfig,
+    BiasSweepPolarity, PolaritySign, PulseMethod, PulseWidthStepping, RandomPolaritySwitch,
+    StabilityConfig, TipChangeDetection,
 };
-pub use plotting::{plot_values, plot_values_with_range};
+pub use plotting::{plot_values, plot_values_with_range, plot_values_with_unit};
 pub use routine::{Outcome, Routine, Rt, run_routine};
 pub use shutdown::ShutdownFlag;
 pub use signal_registry::{Signal, SignalIndex, SignalRegistry};
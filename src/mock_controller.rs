@@ -50,7 +50,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use parking_lot::Mutex;
 
@@ -58,7 +58,8 @@ use nanonis_rs::Position;
 use nanonis_rs::motor::{MotorDirection, MotorDisplacement, MovementMode, Position3D};
 use nanonis_rs::oscilloscope::OsciData;
 use nanonis_rs::scan::{
-    AutopasteMode, AutosaveMode, ScanAction, ScanConfig, ScanDirection, ScanProps, ScanPropsBuilder,
+    AutopasteMode, AutosaveMode, ScanAction, ScanConfig, ScanDirection, ScanFrame, ScanProps,
+    ScanPropsBuilder,
 };
 use nanonis_rs::tcplog::TCPLogStatus;
 use nanonis_rs::tip_recovery::TipShaperConfig;
@@ -69,6 +70,7 @@ use crate::spm_controller::{
     ZControllerStatus, ZHomeMode,
 };
 use crate::spm_error::SpmError;
+use crate::utils::Rng;
 
 /// A scriptable freq-shift model: given the current [`MockObservations`],
 /// return the frequency shift (Hz) the virtual tip exhibits on this read.
@@ -76,6 +78,12 @@ use crate::spm_error::SpmError;
 /// `FnMut` (not `Fn`) so models may carry their own evolving state.
 pub type FreqShiftModel = Box<dyn FnMut(&MockObservations) -> f64 + Send>;
 
+/// A per-sample disturbance on the freq-shift stream: given the current
+/// [`MockObservations`] and a sample's position in its batch, return the
+/// offset (Hz) added to that sample. For changes that happen *inside* a
+/// batch, which a [`FreqShiftModel`] (one call per batch) cannot express.
+pub type SampleOffset = Box<dyn FnMut(&MockObservations, usize) -> f64 + Send>;
+
 /// The kind of error a [scheduled fault](MockControllerBuilder::fail_on_call)
 /// produces when it fires.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -128,6 +136,10 @@ pub struct MockObservations {
     pub bias: f64,
     /// Every voltage passed to `bias_pulse`, in order. `len()` is the pulse count.
     pub pulses: Vec<f64>,
+    /// Width of every `bias_pulse`, in the same order as `pulses`.
+    pub pulse_widths: Vec<Duration>,
+    /// Every config passed to `tip_shaper`, in order.
+    pub tip_shapes: Vec<TipShaperConfig>,
     /// Most recent z-controller setpoint.
     pub z_setpoint: f64,
     /// Current safe-tip enable state.
@@ -144,6 +156,8 @@ pub struct MockObservations {
     pub withdraw_count: usize,
     /// Number of coarse-motor moves (`move_motor` + `move_motor_3d`).
     pub motor_moves: usize,
+    /// Every `move_motor_3d` displacement `(x, y, z)` in steps, in order.
+    pub motor_displacements: Vec<(i16, i16, i16)>,
     /// Number of freq-shift reads served by the tip model.
     pub freq_reads: usize,
     /// Every value the tip model returned, in order. `len()` equals
@@ -152,6 +166,9 @@ pub struct MockObservations {
     pub freq_values: Vec<f64>,
     /// Connection health; flipped to `false` by a [`FaultKind::Disconnect`].
     pub connected: bool,
+    /// Total of every [`pause`](SpmController::pause) on a mock built with
+    /// [`simulated_time`](MockControllerBuilder::simulated_time).
+    pub waited: Duration,
 }
 
 impl Default for MockObservations {
@@ -161,6 +178,8 @@ impl Default for MockObservations {
             call_counts: HashMap::new(),
             bias: 0.0,
             pulses: Vec::new(),
+            pulse_widths: Vec::new(),
+            tip_shapes: Vec::new(),
             z_setpoint: 0.0,
             safe_tip_enabled: false,
             scan_running: false,
@@ -169,9 +188,11 @@ impl Default for MockObservations {
             approach_count: 0,
             withdraw_count: 0,
             motor_moves: 0,
+            motor_displacements: Vec::new(),
             freq_reads: 0,
             freq_values: Vec::new(),
             connected: true,
+            waited: Duration::ZERO,
         }
     }
 }
@@ -198,47 +219,6 @@ impl MockObservations {
     }
 }
 
-/// Small deterministic PRNG (xorshift64\*) so mock runs are reproducible —
-/// a figure generated from a mock run can be regenerated exactly.
-///
-/// Deliberately not `rand`: the crate isn't a dependency, and a fixed-seed
-/// generator is the property we actually want here.
-#[derive(Debug, Clone, Copy)]
-struct Rng(u64);
-
-impl Rng {
-    fn new(seed: u64) -> Self {
-        // Guard the all-zero state, which xorshift can never escape.
-        Rng(if seed == 0 {
-            0x9E37_79B9_7F4A_7C15
-        } else {
-            seed
-        })
-    }
-
-    fn next_u64(&mut self) -> u64 {
-        let mut x = self.0;
-        x ^= x >> 12;
-        x ^= x << 25;
-        x ^= x >> 27;
-        self.0 = x;
-        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
-    }
-
-    /// Uniform in `[0, 1)`.
-    fn uniform(&mut self) -> f64 {
-        // Top 53 bits land exactly in an f64 mantissa, so no rounding bias.
-        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
-    }
-
-    /// Standard normal, via Box-Muller.
-    fn normal(&mut self) -> f64 {
-        let u1 = self.uniform().max(f64::MIN_POSITIVE);
-        let u2 = self.uniform();
-        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
-    }
-}
-
 /// A fault scheduled to fire on a specific call number of a method.
 #[derive(Debug, Clone, Copy)]
 struct ScheduledFault {
@@ -247,6 +227,12 @@ struct ScheduledFault {
     kind: FaultKind,
 }
 
+/// Index of `"freq shift"` in the mock's fixed signal layout (see
+/// [`SpmController::signal_names`]). Pass it to
+/// [`MockControllerBuilder::freq_shift_index`] so the tip model answers on
+/// the channel a registry built from the mock resolves.
+pub const FREQ_SHIFT_INDEX: SignalIndex = SignalIndex(2);
+
 /// An in-memory [`SpmController`] for tests and dry-runs. Build via
 /// [`MockController::builder`].
 pub struct MockController {
@@ -260,6 +246,13 @@ pub struct MockController {
     sample_noise_hz: f64,
     /// PRNG state for [`Self::sample_noise_hz`].
     noise_rng: Rng,
+    /// Sine disturbances (frequency Hz, amplitude Hz) on the freq-shift
+    /// samples, timed by `sample_rate_hz` and a running sample count.
+    sample_tones: Vec<(f64, f64)>,
+    sample_rate_hz: f64,
+    sample_clock: u64,
+    /// Added to each freq-shift sample of a batch.
+    sample_offset: Option<SampleOffset>,
     /// Faults that fire on a specific call ordinal, keyed by method name.
     faults_once: HashMap<&'static str, Vec<ScheduledFault>>,
     /// Faults that fire on *every* call, keyed by method name.
@@ -267,6 +260,20 @@ pub struct MockController {
     capabilities: HashSet<Capability>,
     position: Position,
     scan_config: ScanConfig,
+    scan_frame: ScanFrame,
+    /// Pixels returned by `scan_frame_data_grab`.
+    scan_image: Vec<Vec<f32>>,
+    /// Last continuous-scan setting from `scan_props_set`. A single-frame
+    /// scan finishes on the `frame_len`-th `scan_status` poll after it starts.
+    continuous_scan: bool,
+    frame_polls: usize,
+    frame_len: usize,
+    /// Waits are recorded in `obs.waited` instead of slept.
+    simulated_time: bool,
+    /// Simulated time starts here and runs on by `obs.waited`.
+    epoch: Instant,
+    /// Whether stream samples count as coming from a data stream.
+    streaming: bool,
 }
 
 impl MockController {
@@ -334,6 +341,62 @@ impl MockController {
         drop(obs);
         value
     }
+
+    /// One batch of `num_samples` stream samples of `index`.
+    fn sample_batch(&mut self, index: SignalIndex, num_samples: usize) -> Vec<f64> {
+        // One model call per *batch*, not per sample: the tip model owns the
+        // slow behavior (pulse response, drift), and scatter is layered on top.
+        // Keeping drift out of the within-batch samples matters — it would show
+        // up as a regression slope and trip `ReadStableSignal`'s `max_slope`
+        // (0.5 Hz/s by default), stalling the routine in retry backoff.
+        let value = self.signal_value(index);
+
+        if (self.sample_noise_hz > 0.0 || !self.sample_tones.is_empty())
+            && index == self.freq_shift_index
+        {
+            let sigma = self.sample_noise_hz;
+            let mut rng = self.noise_rng;
+            let samples = (0..num_samples)
+                .map(|i| {
+                    let t = (self.sample_clock + i as u64) as f64 / self.sample_rate_hz;
+                    let hum: f64 = self
+                        .sample_tones
+                        .iter()
+                        .map(|&(f, a)| a * (2.0 * std::f64::consts::PI * f * t).sin())
+                        .sum();
+                    let scatter = if sigma > 0.0 {
+                        rng.normal() * sigma
+                    } else {
+                        0.0
+                    };
+                    value + hum + scatter
+                })
+                .collect();
+            self.noise_rng = rng;
+            self.sample_clock += num_samples as u64;
+            return samples;
+        }
+
+        // Default: constant samples => std_dev = 0, slope = 0 => always
+        // "stable", so ReadStableSignal returns on the first attempt with no
+        // backoff sleeps. Keeps the test suite fast and deterministic.
+        vec![value; num_samples]
+    }
+
+    /// One batch of stream samples of `index`, with the
+    /// [`SampleOffset`] applied to the freq-shift channel.
+    fn stream_batch(&mut self, index: SignalIndex, num_samples: usize) -> Vec<f64> {
+        let mut samples = self.sample_batch(index, num_samples);
+        if index == self.freq_shift_index
+            && let Some(offset) = self.sample_offset.as_mut()
+        {
+            let obs = self.obs.lock();
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample += offset(&obs, i);
+            }
+        }
+        samples
+    }
 }
 
 impl SpmController for MockController {
@@ -394,27 +457,24 @@ impl SpmController for MockController {
                 "read_signal_samples: num_samples must be > 0".into(),
             ));
         }
-        // One model call per *batch*, not per sample: the tip model owns the
-        // slow behavior (pulse response, drift), and scatter is layered on top.
-        // Keeping drift out of the within-batch samples matters — it would show
-        // up as a regression slope and trip `ReadStableSignal`'s `max_slope`
-        // (0.5 Hz/s by default), stalling the routine in retry backoff.
-        let value = self.signal_value(index);
+        Ok(self.stream_batch(index, num_samples))
+    }
 
-        if self.sample_noise_hz > 0.0 && index == self.freq_shift_index {
-            let sigma = self.sample_noise_hz;
-            let mut rng = self.noise_rng;
-            let samples = (0..num_samples)
-                .map(|_| value + rng.normal() * sigma)
-                .collect();
-            self.noise_rng = rng;
-            return Ok(samples);
+    fn read_signals_samples(
+        &mut self,
+        indices: &[SignalIndex],
+        num_samples: usize,
+    ) -> Result<Vec<Vec<f64>>> {
+        self.enter("read_signals_samples")?;
+        if num_samples == 0 || indices.is_empty() {
+            return Err(SpmError::Protocol(
+                "read_signals_samples: need at least one signal and one sample".into(),
+            ));
         }
-
-        // Default: constant samples => std_dev = 0, slope = 0 => always
-        // "stable", so ReadStableSignal returns on the first attempt with no
-        // backoff sleeps. Keeps the test suite fast and deterministic.
-        Ok(vec![value; num_samples])
+        Ok(indices
+            .iter()
+            .map(|&index| self.stream_batch(index, num_samples))
+            .collect())
     }
 
     // -- Bias --
@@ -433,12 +493,14 @@ impl SpmController for MockController {
     fn bias_pulse(
         &mut self,
         voltage: f64,
-        _width: Duration,
+        width: Duration,
         _z_hold: bool,
         _absolute: bool,
     ) -> Result<()> {
         self.enter("bias_pulse")?;
-        self.obs.lock().pulses.push(voltage);
+        let mut obs = self.obs.lock();
+        obs.pulses.push(voltage);
+        obs.pulse_widths.push(width);
         Ok(())
     }
 
@@ -500,9 +562,12 @@ impl SpmController for MockController {
         Ok(())
     }
 
-    fn move_motor_3d(&mut self, _displacement: MotorDisplacement, _wait: bool) -> Result<()> {
+    fn move_motor_3d(&mut self, displacement: MotorDisplacement, _wait: bool) -> Result<()> {
         self.enter("move_motor_3d")?;
-        self.obs.lock().motor_moves += 1;
+        let mut obs = self.obs.lock();
+        obs.motor_moves += 1;
+        obs.motor_displacements
+            .push((displacement.x, displacement.y, displacement.z));
         Ok(())
     }
 
@@ -523,7 +588,10 @@ impl SpmController for MockController {
         self.enter("scan_action")?;
         let mut obs = self.obs.lock();
         match action {
-            ScanAction::Start | ScanAction::Resume => obs.scan_running = true,
+            ScanAction::Start | ScanAction::Resume => {
+                obs.scan_running = true;
+                self.frame_polls = 0;
+            }
             ScanAction::Stop | ScanAction::Pause => obs.scan_running = false,
             // Freeze/Unfreeze/GoToCenter don't change the running flag.
             _ => {}
@@ -533,16 +601,29 @@ impl SpmController for MockController {
 
     fn scan_status(&mut self) -> Result<bool> {
         self.enter("scan_status")?;
-        Ok(self.obs.lock().scan_running)
+        let mut obs = self.obs.lock();
+        if obs.scan_running && !self.continuous_scan {
+            if self.frame_polls + 1 >= self.frame_len {
+                obs.scan_running = false;
+            }
+            self.frame_polls += 1;
+        }
+        Ok(obs.scan_running)
     }
 
     fn scan_props_get(&mut self) -> Result<ScanProps> {
         self.enter("scan_props_get")?;
-        Ok(mock_scan_props())
+        Ok(ScanProps {
+            continuous_scan: self.continuous_scan,
+            ..mock_scan_props()
+        })
     }
 
-    fn scan_props_set(&mut self, _props: ScanPropsBuilder) -> Result<()> {
+    fn scan_props_set(&mut self, props: ScanPropsBuilder) -> Result<()> {
         self.enter("scan_props_set")?;
+        if let Some(continuous) = props.continuous_scan {
+            self.continuous_scan = continuous;
+        }
         Ok(())
     }
 
@@ -557,14 +638,24 @@ impl SpmController for MockController {
         Ok(())
     }
 
+    fn scan_frame_get(&mut self) -> Result<ScanFrame> {
+        self.enter("scan_frame_get")?;
+        Ok(self.scan_frame)
+    }
+
+    fn scan_frame_set(&mut self, frame: ScanFrame) -> Result<()> {
+        self.enter("scan_frame_set")?;
+        self.scan_frame = frame;
+        Ok(())
+    }
+
     fn scan_frame_data_grab(
         &mut self,
         _channel_index: u32,
         forward: bool,
     ) -> Result<(String, Vec<Vec<f32>>, bool)> {
         self.enter("scan_frame_data_grab")?;
-        // 2x2 flat frame is enough for routines that only check shape.
-        Ok(("mock_channel".into(), vec![vec![0.0; 2]; 2], forward))
+        Ok(("mock_channel".into(), self.scan_image.clone(), forward))
     }
 
     // -- Oscilloscope --
@@ -583,11 +674,12 @@ impl SpmController for MockController {
 
     fn tip_shaper(
         &mut self,
-        _config: &TipShaperConfig,
+        config: &TipShaperConfig,
         _wait: bool,
         _timeout: Duration,
     ) -> Result<()> {
         self.enter("tip_shaper")?;
+        self.obs.lock().tip_shapes.push(config.clone());
         Ok(())
     }
 
@@ -651,6 +743,30 @@ impl SpmController for MockController {
     fn clear_data_buffer(&mut self) {
         let _ = self.enter("clear_data_buffer");
     }
+
+    fn pause(&self, duration: Duration) {
+        if self.simulated_time {
+            self.obs.lock().waited += duration;
+        } else {
+            std::thread::sleep(duration);
+        }
+    }
+
+    fn simulated_time(&self) -> bool {
+        self.simulated_time
+    }
+
+    fn now(&self) -> Instant {
+        if self.simulated_time {
+            self.epoch + self.obs.lock().waited
+        } else {
+            Instant::now()
+        }
+    }
+
+    fn stream_sample_rate_hz(&self) -> Option<f64> {
+        self.streaming.then_some(self.sample_rate_hz)
+    }
 }
 
 /// Builder for [`MockController`].
@@ -660,10 +776,17 @@ pub struct MockControllerBuilder {
     default_signal: f64,
     sample_noise_hz: f64,
     noise_seed: u64,
+    sample_tones: Vec<(f64, f64)>,
+    sample_rate_hz: f64,
+    sample_offset: Option<SampleOffset>,
     faults_once: HashMap<&'static str, Vec<ScheduledFault>>,
     faults_always: HashMap<&'static str, FaultKind>,
     capabilities: HashSet<Capability>,
     start_connected: bool,
+    scan_image: Vec<Vec<f32>>,
+    scan_frame_polls: usize,
+    simulated_time: bool,
+    streaming: bool,
 }
 
 impl MockControllerBuilder {
@@ -677,10 +800,18 @@ impl MockControllerBuilder {
             default_signal: 0.0,
             sample_noise_hz: 0.0,
             noise_seed: 0x5EED_5EED,
+            sample_tones: Vec::new(),
+            sample_rate_hz: 2000.0,
+            sample_offset: None,
             faults_once: HashMap::new(),
             faults_always: HashMap::new(),
             capabilities: all_capabilities(),
             start_connected: true,
+            // 2x2 flat frame is enough for routines that only check shape.
+            scan_image: vec![vec![0.0; 2]; 2],
+            scan_frame_polls: 2,
+            simulated_time: false,
+            streaming: true,
         }
     }
 
@@ -724,6 +855,28 @@ impl MockControllerBuilder {
         self
     }
 
+    /// Add a sine of `amplitude_hz` at `frequency_hz` to the freq-shift
+    /// samples, as mains pickup or a vibration would. Repeat for several
+    /// tones. The phase runs on across batches.
+    pub fn sample_tone(mut self, frequency_hz: f64, amplitude_hz: f64) -> Self {
+        self.sample_tones.push((frequency_hz, amplitude_hz));
+        self
+    }
+
+    /// Rate the mock's stream samples are taken at, which times
+    /// [`sample_tone`](Self::sample_tone)s (default 2000 Hz).
+    pub fn sample_rate_hz(mut self, rate: f64) -> Self {
+        self.sample_rate_hz = rate;
+        self
+    }
+
+    /// Add a [`SampleOffset`] to every freq-shift stream sample, e.g. a jump
+    /// halfway through a batch.
+    pub fn sample_offset(mut self, offset: SampleOffset) -> Self {
+        self.sample_offset = Some(offset);
+        self
+    }
+
     /// Schedule `method` to fail on its `nth` call (1-based) with `kind`.
     ///
     /// `method` is the [`SpmController`] method name, e.g. `"auto_approach"`,
@@ -748,6 +901,36 @@ impl MockControllerBuilder {
         self
     }
 
+    /// Pixels every `scan_frame_data_grab` returns (default: a flat 2x2
+    /// frame). Supply a real image to exercise image analysis.
+    pub fn scan_image(mut self, data: Vec<Vec<f32>>) -> Self {
+        self.scan_image = data;
+        self
+    }
+
+    /// The `scan_status` poll, counted from the scan's start, on which a
+    /// single-frame scan finishes (default 2). `usize::MAX` never finishes.
+    pub fn scan_frame_polls(mut self, polls: usize) -> Self {
+        self.scan_frame_polls = polls;
+        self
+    }
+
+    /// Skip every wait: `pause` and `Rt::settle` return at once and add the
+    /// time to [`MockObservations::waited`]. For simulations that want the
+    /// instrument time a run would take without spending it.
+    pub fn simulated_time(mut self) -> Self {
+        self.simulated_time = true;
+        self
+    }
+
+    /// Report no data stream (`stream_sample_rate_hz` is `None`), like a
+    /// controller that polls its samples. The samples themselves still
+    /// come out as usual.
+    pub fn without_data_stream(mut self) -> Self {
+        self.streaming = false;
+        self
+    }
+
     /// Start in the disconnected state (`is_connected()` returns `false` until
     /// `reconnect()` is called).
     pub fn start_disconnected(mut self) -> Self {
@@ -768,11 +951,23 @@ impl MockControllerBuilder {
             default_signal: self.default_signal,
             sample_noise_hz: self.sample_noise_hz,
             noise_rng: Rng::new(self.noise_seed),
+            sample_tones: self.sample_tones,
+            sample_rate_hz: self.sample_rate_hz,
+            sample_clock: 0,
+            sample_offset: self.sample_offset,
             faults_once: self.faults_once,
             faults_always: self.faults_always,
             capabilities: self.capabilities,
             position: Position::new(0.0, 0.0),
             scan_config: mock_scan_config(),
+            scan_frame: ScanFrame::new(Position::new(0.0, 0.0), 50e-9, 50e-9, 0.0),
+            scan_image: self.scan_image,
+            continuous_scan: false,
+            frame_polls: 0,
+            frame_len: self.scan_frame_polls,
+            simulated_time: self.simulated_time,
+            epoch: Instant::now(),
+            streaming: self.streaming,
         }
     }
 }
@@ -925,7 +1120,7 @@ pub mod models {
     ///
     /// Seeded, so the same params always produce the same run.
     pub fn realistic(params: RealisticParams) -> FreqShiftModel {
-        let mut rng = super::Rng::new(params.seed);
+        let mut rng = crate::utils::Rng::new(params.seed);
         let mut seen_pulses = 0usize;
         let mut drift = 0.0f64;
         // A one-read outlier (deep excursion or insulating patch) awaiting report.
@@ -1026,6 +1221,13 @@ fn mock_scan_props() -> ScanProps {
 mod tests {
     use super::*;
 
+    #[test]
+    fn freq_shift_index_matches_the_signal_layout() {
+        let mut mock = MockController::builder().build();
+        let registry = crate::SignalRegistry::from_controller(&mut mock).unwrap();
+        assert_eq!(registry.resolve("freq shift").unwrap(), FREQ_SHIFT_INDEX);
+    }
+
     #[test]
     fn model_drives_freq_shift_channel_only() {
         let mut mock = MockController::builder()
@@ -1112,6 +1314,22 @@ mod tests {
         assert!(samples.iter().all(|&v| v == 7.0));
     }
 
+    #[test]
+    fn simulated_time_records_pauses_instead_of_sleeping() {
+        let mock = MockController::builder().simulated_time().build();
+        let obs = mock.observations();
+        assert!(mock.simulated_time());
+
+        let started = std::time::Instant::now();
+        let clock = mock.now();
+        mock.pause(Duration::from_secs(60));
+        mock.pause(Duration::from_millis(500));
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(obs.lock().waited, Duration::from_millis(60_500));
+        assert_eq!(mock.now() - clock, Duration::from_millis(60_500));
+    }
+
     #[test]
     fn realistic_is_reproducible_for_a_seed() {
         let params = models::RealisticParams::default();
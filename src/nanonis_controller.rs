@@ -5,19 +5,21 @@ use nanonis_rs::{
     NanonisClient, Position,
     motor::{MotorDirection, MotorDisplacement, MotorGroup, MovementMode, Position3D},
     oscilloscope::OsciData,
-    scan::{ScanAction, ScanConfig, ScanDirection, ScanProps, ScanPropsBuilder},
+    scan::{ScanAction, ScanConfig, ScanDirection, ScanFrame, ScanProps, ScanPropsBuilder},
     tip_recovery::TipShaperConfig,
 };
 
 use std::collections::HashSet;
 
-use crate::buffered_tcp_reader::BufferedTCPReader;
+use crate::buffered_tcp_reader::{BufferedTCPReader, StreamStats};
+use crate::config::StreamRecordingConfig;
 use crate::signal_registry::{SignalIndex, SignalRegistry};
 use crate::spm_controller::{
     AcquisitionMode, Capability, DataStreamStatus, Result, SpmController, TriggerSetup,
     ZControllerStatus, ZHomeMode,
 };
 use crate::spm_error::SpmError;
+use crate::stream_recorder::{StreamMarkers, StreamRecorder};
 use crate::utils::{PollError, poll_until};
 
 /// Configuration consumed by `NanonisController::prepare()`.
@@ -87,6 +89,10 @@ pub struct NanonisController {
     /// Number of channels configured in the TCP data stream.
     /// Set by `data_stream_configure`, used by `start_tcp_reader`.
     configured_channel_count: Option<u32>,
+    /// Signal name per position in `SignalFrame.data`, for recordings.
+    stream_channel_names: Vec<String>,
+    /// Records the TCP stream to disk while set; see `start_recording`.
+    recorder: Option<StreamRecorder>,
     /// Guards against double-teardown (manual call + Drop).
     torn_down: bool,
 }
@@ -99,6 +105,8 @@ impl NanonisController {
             tcp_reader: None,
             signal_to_data_position: HashMap::new(),
             configured_channel_count: None,
+            stream_channel_names: Vec::new(),
+            recorder: None,
             torn_down: false,
         }
     }
@@ -115,20 +123,23 @@ impl NanonisController {
 
     /// Collect `num_samples` data points from the TCP stream for a given data position.
     ///
-    /// Uses timestamp tracking to avoid re-reading already-seen frames.
+    /// Reads through a frame cursor, so every frame received after the call
+    /// is considered exactly once; frames lost to buffer overflow or dropped
+    /// by the stream are logged.
     /// Timeout scales with sample count (minimum 5s, +1s per 100 samples).
     fn collect_tcp_samples(
         reader: &BufferedTCPReader,
-        data_position: usize,
+        data_positions: &[usize],
         num_samples: usize,
-    ) -> Result<Vec<f32>> {
+    ) -> Result<Vec<Vec<f32>>> {
         let timeout_secs = 5 + (num_samples as u64 / 100);
         let timeout = Duration::from_secs(timeout_secs);
         let start = std::time::Instant::now();
-        let mut collected: Vec<f32> = Vec::with_capacity(num_samples);
+        // One frame per entry, holding the value at every requested position.
+        let mut collected: Vec<Vec<f32>> = Vec::with_capacity(num_samples);
 
-        // Track the timestamp of the last consumed frame to avoid duplicates.
-        let mut cursor = std::time::Instant::now();
+        let mut cursor = reader.cursor();
+        let (mut overrun, mut dropped) = (0, 0);
 
         while collected.len() < num_samples && start.elapsed() < timeout {
             // Check if the stream reader thread died before waiting the
@@ -145,21 +156,36 @@ impl NanonisController {
                 });
             }
 
-            let new_frames = reader.get_data_since(cursor);
-            for frame in &new_frames {
-                cursor = frame.timestamp + Duration::from_nanos(1);
-                if let Some(&value) = frame.signal_frame.data.get(data_position) {
-                    collected.push(value);
-                    if collected.len() >= num_samples {
-                        break;
-                    }
+            let read = reader.read_frames(&mut cursor, |frame| {
+                if collected.len() >= num_samples {
+                    return false;
                 }
-            }
+                // Take a frame only if it carries every signal, so the
+                // batches stay aligned sample for sample.
+                let values: Option<Vec<f32>> = data_positions
+                    .iter()
+                    .map(|&p| frame.data.get(p).copied())
+                    .collect();
+                if let Some(values) = values {
+                    collected.push(values);
+                }
+                true
+            });
+            overrun += read.overrun;
+            dropped += read.dropped;
             if collected.len() < num_samples {
                 std::thread::sleep(Duration::from_millis(10));
             }
         }
 
+        if overrun > 0 || dropped > 0 {
+            log::warn!(
+                "TCP sample collection: {} frames lost to buffer overflow, {} dropped by the stream",
+                overrun,
+                dropped
+            );
+        }
+
         if collected.is_empty() {
             return Err(SpmError::Timeout(
                 "No TCP stream data collected within timeout".into(),
@@ -174,7 +200,29 @@ impl NanonisController {
             );
         }
 
-        Ok(collected)
+        Ok((0..data_positions.len())
+            .map(|i| collected.iter().map(|frame| frame[i]).collect())
+            .collect())
+    }
+
+    /// Stream position of every signal in `indices`, failing on the first
+    /// signal without a TCP channel mapping.
+    fn data_positions(&self, indices: &[SignalIndex]) -> Result<Vec<usize>> {
+        indices
+            .iter()
+            .map(|index| {
+                self.signal_to_data_position
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| {
+                        SpmError::Protocol(format!(
+                            "Signal index {} has no TCP channel mapping. \
+                             Call set_channel_mapping with a mapping that includes this signal.",
+                            index
+                        ))
+                    })
+            })
+            .collect()
     }
 
     /// Configure and start the TCP logger data stream for every signal in
@@ -216,11 +264,15 @@ impl NanonisController {
             .collect();
 
         let mut signal_mapping: HashMap<SignalIndex, usize> = HashMap::new();
+        let mut channel_names = vec![String::new(); tcp_channels.len()];
         for signal in &tcp_signals {
             if let Some(tcp_ch) = signal.tcp_channel
                 && let Some(&position) = tcp_to_position.get(&tcp_ch)
             {
                 signal_mapping.insert(signal.signal_index(), position);
+                if channel_names[position].is_empty() {
+                    channel_names[position] = signal.name.clone();
+                }
             }
         }
 
@@ -232,6 +284,7 @@ impl NanonisController {
 
         self.data_stream_configure(&tcp_channels, setup.oversampling)?;
         self.set_channel_mapping(signal_mapping);
+        self.stream_channel_names = channel_names;
 
         // Stop any lingering stream from a prior session, then start a fresh
         // one BEFORE attaching the reader — otherwise the reader's first
@@ -241,7 +294,12 @@ impl NanonisController {
         std::thread::sleep(Duration::from_millis(200));
         self.data_stream_start()?;
 
-        self.start_tcp_reader(&setup.host, setup.data_port, setup.buffer_size)?;
+        self.start_tcp_reader(
+            &setup.host,
+            setup.data_port,
+            setup.buffer_size,
+            setup.oversampling,
+        )?;
         log::info!("TCP data stream started");
         Ok(true)
     }
@@ -262,7 +320,13 @@ impl NanonisController {
     /// Call `set_channel_mapping` and `data_stream_configure` before this.
     /// Connects to the TCP logger data port and spawns the background
     /// buffering thread.
-    fn start_tcp_reader(&mut self, host: &str, data_port: u16, buffer_size: usize) -> Result<()> {
+    fn start_tcp_reader(
+        &mut self,
+        host: &str,
+        data_port: u16,
+        buffer_size: usize,
+        oversampling: i32,
+    ) -> Result<()> {
         if self.tcp_reader.is_some() {
             log::warn!("TCP reader already running, stopping previous instance");
             self.stop_tcp_reader()?;
@@ -280,22 +344,74 @@ impl NanonisController {
             ));
         }
 
-        let reader = BufferedTCPReader::new(host, data_port, buffer_size)
+        let reader = BufferedTCPReader::new(host, data_port, buffer_size, oversampling)
             .map_err(|e| SpmError::Protocol(format!("Failed to start TCP reader: {}", e)))?;
 
+        let reader_rate = reader.sample_rate_hz();
         self.tcp_reader = Some(reader);
         log::info!(
-            "TCP reader started on {}:{} with {} channels",
+            "TCP reader started on {}:{} with {} channels at {:.1} Hz",
             host,
             data_port,
-            num_channels
+            num_channels,
+            reader_rate
         );
         Ok(())
     }
 
-    /// Stop the background TCP reader if running.
+    /// Record every frame of the running TCP stream to rotating files in
+    /// `config.output_path` until `stop_recording` (or teardown).
+    ///
+    /// Returns an observer to add to the routine's event bus: it writes each
+    /// event as a marker on the recording's time axis.
+    pub fn start_recording(&mut self, config: &StreamRecordingConfig) -> Result<StreamMarkers> {
+        let Some(reader) = &self.tcp_reader else {
+            return Err(SpmError::Protocol(
+                "Cannot record: the TCP data stream is not running".into(),
+            ));
+        };
+        if let Some(previous) = self.recorder.take() {
+            reader.set_recorder_tap(None);
+            drop(previous);
+        }
+        let recorder = StreamRecorder::start(
+            std::path::Path::new(&config.output_path),
+            self.stream_channel_names.clone(),
+            config.rotation(),
+        )
+        .map_err(|e| SpmError::Protocol(format!("Failed to start stream recording: {}", e)))?;
+        reader.set_recorder_tap(Some(recorder.tap()));
+        let markers = recorder.markers();
+        self.recorder = Some(recorder);
+        Ok(markers)
+    }
+
+    /// Stop recording the TCP stream; returns the files written.
+    pub fn stop_recording(&mut self) -> Result<Vec<std::path::PathBuf>> {
+        let Some(recorder) = self.recorder.take() else {
+            return Ok(Vec::new());
+        };
+        if let Some(reader) = &self.tcp_reader {
+            reader.set_recorder_tap(None);
+        }
+        recorder
+            .finish()
+            .map_err(|e| SpmError::Protocol(format!("Stream recording failed: {}", e)))
+    }
+
+    /// Stop the background TCP reader if running. Ends any recording first.
     pub fn stop_tcp_reader(&mut self) -> Result<()> {
+        if let Err(e) = self.stop_recording() {
+            log::warn!("{}", e);
+        }
         if let Some(mut reader) = self.tcp_reader.take() {
+            let stats = reader.stats();
+            log::info!(
+                "TCP reader stopped: {} frames received, {} dropped by the stream, {} evicted from the buffer",
+                stats.received,
+                stats.dropped,
+                stats.evicted
+            );
             reader
                 .stop()
                 .map_err(|e| SpmError::Protocol(format!("Failed to stop TCP reader: {}", e)))?;
@@ -303,6 +419,11 @@ impl NanonisController {
         Ok(())
     }
 
+    /// Frame totals of the running TCP reader, if any.
+    pub fn stream_stats(&self) -> Option<StreamStats> {
+        self.tcp_reader.as_ref().map(BufferedTCPReader::stats)
+    }
+
     /// Clear the TCP reader buffer (discard stale data before a fresh measurement).
     pub fn clear_tcp_buffer(&self) {
         if let Some(reader) = &self.tcp_reader {
@@ -710,6 +831,14 @@ impl SpmController for NanonisController {
         Ok(self.client.scan_config_set(config)?)
     }
 
+    fn scan_frame_get(&mut self) -> Result<ScanFrame> {
+        Ok(self.client.scan_frame_get()?)
+    }
+
+    fn scan_frame_set(&mut self, frame: ScanFrame) -> Result<()> {
+        Ok(self.client.scan_frame_set(frame)?)
+    }
+
     fn scan_frame_data_grab(
         &mut self,
         channel_index: u32,
@@ -827,6 +956,12 @@ impl SpmController for NanonisController {
 
     // -- Signal Reading (TCP stream override) --
 
+    fn stream_sample_rate_hz(&self) -> Option<f64> {
+        self.tcp_reader
+            .as_ref()
+            .map(BufferedTCPReader::sample_rate_hz)
+    }
+
     fn read_signal_samples(&mut self, index: SignalIndex, num_samples: usize) -> Result<Vec<f64>> {
         if num_samples == 0 {
             return Err(SpmError::Protocol(
@@ -846,16 +981,39 @@ impl SpmController for NanonisController {
             }
         };
 
-        let &data_position = self.signal_to_data_position.get(&index).ok_or_else(|| {
-            SpmError::Protocol(format!(
-                "Signal index {} has no TCP channel mapping. \
-                 Call set_channel_mapping with a mapping that includes this signal.",
-                index
-            ))
-        })?;
+        let positions = self.data_positions(&[index])?;
+        let collected = Self::collect_tcp_samples(reader, &positions, num_samples)?;
+        Ok(collected[0].iter().map(|&v| v as f64).collect())
+    }
+
+    fn read_signals_samples(
+        &mut self,
+        indices: &[SignalIndex],
+        num_samples: usize,
+    ) -> Result<Vec<Vec<f64>>> {
+        if num_samples == 0 || indices.is_empty() {
+            return Err(SpmError::Protocol(
+                "read_signals_samples: need at least one signal and one sample".into(),
+            ));
+        }
+        let Some(reader) = &self.tcp_reader else {
+            log::debug!("No TCP reader available, falling back to polling read_signals");
+            let mut samples = vec![Vec::with_capacity(num_samples); indices.len()];
+            for _ in 0..num_samples {
+                let snapshot = self.read_signals(indices, true)?;
+                for (signal, value) in samples.iter_mut().zip(snapshot) {
+                    signal.push(value);
+                }
+            }
+            return Ok(samples);
+        };
 
-        let collected = Self::collect_tcp_samples(reader, data_position, num_samples)?;
-        Ok(collected.into_iter().map(|v| v as f64).collect())
+        let positions = self.data_positions(indices)?;
+        let collected = Self::collect_tcp_samples(reader, &positions, num_samples)?;
+        Ok(collected
+            .into_iter()
+            .map(|signal| signal.into_iter().map(|v| v as f64).collect())
+            .collect())
     }
 }
 
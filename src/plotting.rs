@@ -1,17 +1,16 @@
 use textplots::{Chart, Plot};
 
-/// Determine the best scale and unit for a given maximum value
+/// Determine the best scale and unit prefix for a given maximum value
 fn determine_scale(max_value: f64) -> (f64, &'static str) {
-    if max_value >= 1.0 {
-        (1.0, "")
-    } else if max_value >= 1e-3 {
-        (1e3, "m")
-    } else if max_value >= 1e-6 {
-        (1e6, "μ")
-    } else if max_value >= 1e-9 {
-        (1e9, "n")
+    crate::units::si_prefix(max_value)
+}
+
+/// Axis label for `prefix` + `unit`; "units" when the unit is unknown.
+fn axis_unit(prefix: &str, unit: &str) -> String {
+    if unit.is_empty() {
+        format!("{prefix}units")
     } else {
-        (1e12, "p")
+        format!("{prefix}{unit}")
     }
 }
 
@@ -35,6 +34,27 @@ pub fn plot_values(
     title: Option<&str>,
     width: Option<usize>,
     height: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    plot_values_with_unit(values, "", title, width, height)
+}
+
+/// [`plot_values`] for values in a known unit (e.g. a signal's
+/// [`SignalUnit::symbol`](crate::units::SignalUnit::symbol)), so the axis
+/// reads `pA` rather than `punits`.
+///
+/// # Examples
+/// ```
+/// use rusty_tip::plotting::plot_values_with_unit;
+///
+/// let data = vec![1e-12, 2e-12, 1.5e-12, 3e-12];
+/// plot_values_with_unit(&data, "A", Some("Current"), None, None).unwrap();
+/// ```
+pub fn plot_values_with_unit(
+    values: &[f64],
+    unit: &str,
+    title: Option<&str>,
+    width: Option<usize>,
+    height: Option<usize>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if values.is_empty() {
         return Err("Cannot plot empty data".into());
@@ -68,13 +88,14 @@ pub fn plot_values(
     } else {
         println!("Data Plot");
     }
-    println!("X-axis: Sample Index | Y-axis: {}units", value_unit);
+    let axis = axis_unit(value_unit, unit);
+    println!("X-axis: Sample Index | Y-axis: {}", axis);
     println!(
-        "Range: {} samples | Values: {:.3} to {:.3} {}units",
+        "Range: {} samples | Values: {:.3} to {:.3} {}",
         values.len(),
         scaled_min,
         scaled_max,
-        value_unit
+        axis
     );
     println!("{}", "─".repeat(width));
 
@@ -129,13 +150,14 @@ pub fn plot_values_with_range(
     } else {
         println!("Data Plot (Clipped to Range)");
     }
-    println!("X-axis: Sample Index | Y-axis: {}units", value_unit);
+    let axis = axis_unit(value_unit, "");
+    println!("X-axis: Sample Index | Y-axis: {}", axis);
     println!(
-        "Range: {} samples | Y-Range: {:.3} to {:.3} {}units (clipped)",
+        "Range: {} samples | Y-Range: {:.3} to {:.3} {} (clipped)",
         values.len(),
         scaled_y_min,
         scaled_y_max,
-        value_unit
+        axis
     );
     println!("{}", "─".repeat(width));
 
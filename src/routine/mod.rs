@@ -6,7 +6,9 @@
 //! routine otherwise reimplements:
 //!
 //! - **Subsystem access**: `rt.bias()?`, `rt.z()?`, `rt.signals()?`,
-//!   `rt.motor()?`, `rt.scan()?`. Each accessor checks the controller's
+//!   `rt.motor()?`, `rt.scan()?`, `rt.osci()?`, `rt.tip_shaper()?`,
+//!   `rt.pll()?`, `rt.piezo()?`, `rt.safe_tip()?`, `rt.data_stream()?`.
+//!   Each accessor checks the controller's
 //!   capabilities, so running a routine against hardware that lacks a
 //!   subsystem fails with a clear `Unsupported` error at the call site.
 //!   Every operation is logged to the [`EventBus`] automatically.
@@ -16,6 +18,16 @@
 //!   [`Outcome::StoppedByUser`].
 //! - **Budgets**: [`Rt::cycles`] drives the main loop and turns cycle and
 //!   time limits into [`Outcome`]s instead of hand-rolled checks.
+//!   [`Rt::with_deadline`] bounds a single step ("this approach must finish
+//!   in 60 s"): waits inside the scope fail with [`SpmError::Timeout`] once
+//!   its deadline passes.
+//! - **Condition waits**: [`Rt::wait_until`] blocks until a signal meets a
+//!   [`Condition`] ("current below 10 pA"); [`Rt::wait_for_drift`] and
+//!   [`Rt::wait_settled`] until it stops drifting. They are interruptible and
+//!   log what was awaited and for how long.
+//! - **Operator prompts**: [`Rt::ask`] and [`Rt::confirm`] put a question to
+//!   the human at the instrument through the run's [`OperatorInterface`]
+//!   (terminal, GUI dialog, or automatic answers for unattended runs).
 //! - **Cleanup**: [`Rt::guarded`] runs a body with a cleanup that executes
 //!   no matter how the body ends, for hardware that must be restored
 //!   (a running scan, a modified scan speed) even when a sweep fails.
@@ -24,6 +36,8 @@
 //! `prepare()`, runs the routine, withdraws the tip, and calls `teardown()`,
 //! whatever the outcome. The shipped tip-prep routine
 //! ([`crate::tip_prep::TipPrep`]) is the reference implementation.
+//! [`registry`] makes routines runnable by name from the `rusty-tip` CLI,
+//! and [`script`] runs Rhai scripts as routines without recompiling.
 //!
 //! ```no_run
 //! use rusty_tip::event::EventBus;
@@ -58,15 +72,31 @@
 //! # }
 //! ```
 
+pub mod noise_check;
+pub mod operator;
+pub mod registry;
+mod report;
 mod rt;
+pub mod script;
 mod subsystems;
+mod wait;
 
+pub use operator::{OperatorInterface, Question};
+pub use report::{ActionFailure, InstrumentSnapshot, RunReport};
 pub use rt::{Cycles, Rt};
-pub use subsystems::{Bias, Motor, RepositionSpec, Scan, Signals, StableReadSpec, ZCtrl};
+pub use subsystems::{
+    Bias, DataStream, Motor, Osci, Piezo, Pll, RepositionSpec, SafeTip, Scan, Signals,
+    StableReadSpec, TipShaper, ZCtrl,
+};
+pub use wait::{Condition, SettleSpec, SignalStats};
+
+pub use crate::action::signals::SignalGate;
 
 use std::panic::{self, AssertUnwindSafe};
 use std::time::Duration;
 
+use serde::Serialize;
+
 use crate::event::{Event, EventBus, EventEmitter};
 use crate::shutdown::ShutdownFlag;
 use crate::spm_controller::SpmController;
@@ -76,7 +106,8 @@ use crate::spm_error::SpmError;
 ///
 /// Everything here is an expected ending, not an error: a stop request or an
 /// exhausted budget is a normal way for lab automation to finish.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Outcome {
     /// The routine reached its goal.
     Completed,
@@ -84,8 +115,12 @@ pub enum Outcome {
     StoppedByUser,
     /// The cycle budget ran out first.
     CycleLimit(usize),
-    /// The time budget ran out first.
-    TimedOut(Duration),
+    /// The time budget ran out first. Serialised in seconds.
+    TimedOut(#[serde(serialize_with = "serialize_secs")] Duration),
+}
+
+fn serialize_secs<S: serde::Serializer>(duration: &Duration, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_f64(duration.as_secs_f64())
 }
 
 /// An automation routine, runnable via [`run_routine`].
@@ -102,6 +137,12 @@ pub trait Routine {
     /// (it is what `rt.settle` and `rt.check_shutdown` produce); the harness
     /// converts it to `Ok(Outcome::StoppedByUser)`.
     fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError>;
+
+    /// Routine-specific results for the [`RunReport`], collected after `run`
+    /// returns (including when it failed). Defaults to `null`.
+    fn summary(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
 }
 
 /// Run a routine, owning the controller life cycle around it.
@@ -127,14 +168,66 @@ pub trait Routine {
 /// the spot and costs an approach cycle); both are deferred until a second
 /// routine exists to design them against.
 pub fn run_routine(
-    mut controller: Box<dyn SpmController>,
+    controller: Box<dyn SpmController>,
     events: &EventBus,
     shutdown: &ShutdownFlag,
     routine: &mut dyn Routine,
 ) -> Result<Outcome, SpmError> {
-    controller.prepare()?;
+    run_routine_with_operator(
+        controller,
+        events,
+        shutdown,
+        &operator::AutoOperator::defaults(),
+        routine,
+    )
+}
 
-    let mut rt = Rt::new(&mut *controller, events, shutdown);
+/// [`run_routine`], with the routine's [`Rt::ask`] questions going to
+/// `operator` instead of being answered with their defaults.
+pub fn run_routine_with_operator(
+    controller: Box<dyn SpmController>,
+    events: &EventBus,
+    shutdown: &ShutdownFlag,
+    operator: &dyn OperatorInterface,
+    routine: &mut dyn Routine,
+) -> Result<Outcome, SpmError> {
+    run_routine_reported(controller, events, shutdown, operator, routine).0
+}
+
+/// [`run_routine_with_operator`], also returning a [`RunReport`] of the run.
+///
+/// The report is built however the run ended: an error is recorded in it
+/// as well as returned. A failing `prepare()` ends the run before the
+/// routine starts, and the report says so.
+pub fn run_routine_reported(
+    mut controller: Box<dyn SpmController>,
+    events: &EventBus,
+    shutdown: &ShutdownFlag,
+    operator: &dyn OperatorInterface,
+    routine: &mut dyn Routine,
+) -> (Result<Outcome, SpmError>, RunReport) {
+    let started_at = chrono::Utc::now();
+    let clock = std::time::Instant::now();
+
+    if let Err(e) = controller.prepare() {
+        let report = RunReport {
+            routine: routine.name().to_string(),
+            outcome: None,
+            error: Some(format!("prepare failed: {e}")),
+            started_at,
+            ended_at: chrono::Utc::now(),
+            duration_secs: clock.elapsed().as_secs_f64(),
+            cycles: 0,
+            actions: Default::default(),
+            failures: Vec::new(),
+            cleanup_errors: Vec::new(),
+            final_state: InstrumentSnapshot::default(),
+            summary: serde_json::Value::Null,
+        };
+        return (Err(e), report);
+    }
+
+    let mut rt = Rt::new(&mut *controller, events, shutdown).with_operator(operator);
     // AssertUnwindSafe is honest here: nothing observes `rt` or `routine`
     // after a panic except the cleanup below, which only restores hardware
     // before re-raising.
@@ -154,20 +247,39 @@ pub fn run_routine(
         Ok(mut z) => {
             if let Err(e) = z.withdraw() {
                 log::warn!("Cleanup withdrawal failed: {}", e);
+                rt.record_cleanup_error(&e);
             }
         }
         Err(e) => log::warn!("Cleanup withdrawal skipped: {}", e),
     }
+    let (tally, cycles) = rt.take_tally();
     drop(rt);
+    let final_state = InstrumentSnapshot::capture(&mut *controller);
     controller.teardown();
     log::info!("Cleanup complete");
 
-    match caught {
+    let result = match caught {
         Ok(Err(SpmError::ShutdownRequested)) => Ok(Outcome::StoppedByUser),
         Ok(other) => other,
         // Hardware is restored; hand the panic back to the caller untouched.
         Err(payload) => panic::resume_unwind(payload),
-    }
+    };
+
+    let report = RunReport {
+        routine: routine.name().to_string(),
+        outcome: result.as_ref().ok().copied(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        started_at,
+        ended_at: chrono::Utc::now(),
+        duration_secs: clock.elapsed().as_secs_f64(),
+        cycles,
+        actions: tally.actions,
+        failures: tally.failures,
+        cleanup_errors: tally.cleanup_errors,
+        final_state,
+        summary: routine.summary(),
+    };
+    (result, report)
 }
 
 /// Best-effort rendering of a caught panic payload, which is a `&str` for
@@ -187,6 +299,7 @@ mod tests {
     use super::*;
     use crate::event::Observer;
     use crate::mock_controller::MockController;
+    use crate::signal_registry::SignalIndex;
 
     #[derive(Clone, Default)]
     struct Recorder {
@@ -295,6 +408,367 @@ mod tests {
         );
     }
 
+    #[test]
+    fn safe_tip_switch_goes_through_its_actions_but_status_is_silent() {
+        let mut mock = MockController::builder().build();
+        let obs = mock.observations();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        rt.safe_tip().unwrap().set_enabled(true).unwrap();
+        assert!(rt.safe_tip().unwrap().enabled().unwrap());
+        rt.safe_tip().unwrap().status().unwrap();
+
+        assert!(obs.lock().safe_tip_enabled);
+        let events = events.lock().unwrap();
+        let params = started_params(&events, "safe_tip_set")
+            .expect("enabling safe-tip must reach the event log");
+        assert_eq!(params["enabled"], true);
+        assert!(started_params(&events, "read_safe_tip_status").is_some());
+        assert!(started_params(&events, "safe_tip_status").is_none());
+    }
+
+    #[test]
+    fn osci_and_piezo_reads_round_trip_through_the_action_layer() {
+        let mut mock = MockController::builder().build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let trace = rt.osci().unwrap().read(0, Default::default()).unwrap();
+        assert_eq!(trace.size, 4);
+        assert_eq!(trace.data.len(), 4);
+
+        rt.piezo().unwrap().set(1e-9, -2e-9).unwrap();
+        let pos = rt.piezo().unwrap().get().unwrap();
+        assert_eq!((pos.x, pos.y), (1e-9, -2e-9));
+
+        let events = events.lock().unwrap();
+        assert!(started_params(&events, "osci_read").is_some());
+        assert!(started_params(&events, "set_position").is_some());
+    }
+
+    #[test]
+    fn action_started_events_carry_the_action_parameters() {
+        let mut mock = MockController::builder().build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        rt.bias().unwrap().pulse(3.5, 40).unwrap();
+        rt.bias().unwrap().get().unwrap();
+
+        let events = events.lock().unwrap();
+        let pulse = started_params(&events, "bias_pulse").unwrap();
+        assert_eq!(pulse["voltage"], 3.5);
+        assert_eq!(pulse["duration_ms"], 40);
+        assert_eq!(pulse["z_hold"], true);
+        assert_eq!(
+            started_params(&events, "read_bias").unwrap(),
+            serde_json::json!({}),
+            "an action without fields reports an empty object"
+        );
+    }
+
+    #[test]
+    fn several_signals_are_read_stable_together_with_their_own_gates() {
+        let mut mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .freq_shift(crate::mock_controller::models::always(-1.0))
+            .sample_noise_hz(0.3)
+            .default_signal(1e-10)
+            .build();
+        let obs = mock.observations();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+        let spec = StableReadSpec {
+            num_samples: 200,
+            max_std_dev: 1.5,
+            max_slope: 1e9,
+            max_retries: 0,
+            sample_rate_hz: 2000.0,
+        };
+
+        // The current is judged in amperes, not by the Hz gate of the spec.
+        let gates = [
+            spec.gate(SignalIndex(2)),
+            SignalGate {
+                max_std_dev: 1e-12,
+                ..spec.gate(SignalIndex(0))
+            },
+        ];
+        let values = rt
+            .signals()
+            .unwrap()
+            .read_stable_many(&gates, &spec)
+            .unwrap();
+        assert!((values[0] - -1.0).abs() < 0.1, "{values:?}");
+        assert!((values[1] - 1e-10).abs() < 1e-20, "{values:?}");
+        // One synchronised collection, not a batch per signal.
+        assert_eq!(obs.lock().count("read_signals_samples"), 1);
+        assert_eq!(obs.lock().count("read_signal_samples"), 0);
+
+        // A gate too tight for the freq-shift scatter marks only that signal.
+        let tight = [
+            SignalGate {
+                max_std_dev: 0.01,
+                ..spec.gate(SignalIndex(2))
+            },
+            spec.gate(SignalIndex(0)),
+        ];
+        rt.signals()
+            .unwrap()
+            .read_stable_many(&tight, &spec)
+            .unwrap();
+        let events = events.lock().unwrap();
+        let stable: Vec<bool> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::DataCollected { label, value, .. } if label == "stable_read" => {
+                    value["stable"].as_bool()
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stable, [true, true, false, true]);
+    }
+
+    #[test]
+    fn a_missing_capability_fails_at_the_accessor() {
+        let mut mock = MockController::builder()
+            .capabilities(std::collections::HashSet::from([
+                crate::spm_controller::Capability::Bias,
+            ]))
+            .build();
+        let bus = EventBus::new();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        assert!(matches!(rt.tip_shaper(), Err(SpmError::Unsupported(_))));
+        assert!(matches!(rt.data_stream(), Err(SpmError::Unsupported(_))));
+        assert!(rt.bias().is_ok());
+    }
+
+    #[test]
+    fn a_settle_past_the_deadline_times_out_early_and_reports_the_scope() {
+        let mut mock = MockController::builder().build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let started = std::time::Instant::now();
+        let result = rt.with_deadline(Duration::from_millis(30), |rt| rt.settle(10_000));
+
+        assert!(matches!(result, Err(SpmError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The scope is closed again: waits outside it are unaffected.
+        rt.settle(1).unwrap();
+        rt.check_deadline().unwrap();
+
+        let events = events.lock().unwrap();
+        let data =
+            custom_event(&events, "deadline_expired").expect("the expiry must reach the event log");
+        assert_eq!(data["depth"], 1);
+        assert_eq!(data["budget_ms"], 30);
+        assert!(data["scope"].as_str().unwrap().contains("mod.rs"));
+    }
+
+    #[test]
+    fn deadlines_run_on_simulated_time() {
+        let mut mock = MockController::builder().simulated_time().build();
+        let obs = mock.observations();
+        let (bus, _events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        // A polling loop that only ever settles: the simulated clock, not
+        // the wall clock, must bring the deadline round.
+        let started = std::time::Instant::now();
+        let result = rt.with_deadline(Duration::from_secs(600), |rt| {
+            loop {
+                rt.settle(100)?;
+            }
+        });
+
+        assert!(matches!(result, Err::<(), _>(SpmError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(obs.lock().waited, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn nested_deadlines_enforce_the_tighter_scope() {
+        let mut mock = MockController::builder().build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        // Inner is tighter: the inner scope expires.
+        let inner = rt.with_deadline(Duration::from_secs(60), |rt| {
+            rt.with_deadline(Duration::from_millis(20), |rt| rt.settle(10_000))
+        });
+        assert!(matches!(inner, Err(SpmError::Timeout(_))));
+
+        // Outer is tighter: an inner scope cannot extend it.
+        let outer = rt.with_deadline(Duration::from_millis(20), |rt| {
+            rt.with_deadline(Duration::from_secs(60), |rt| rt.settle(10_000))
+        });
+        assert!(matches!(outer, Err(SpmError::Timeout(_))));
+
+        let events = events.lock().unwrap();
+        let depths: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Custom { kind, data } if kind == "deadline_expired" => {
+                    Some(data["depth"].as_u64().unwrap())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(depths, vec![2, 1]);
+    }
+
+    #[test]
+    fn questions_and_answers_reach_the_event_log() {
+        let mut mock = MockController::builder().build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let operator = operator::AutoOperator::scripted([0]);
+        let rt = Rt::new(&mut mock, &bus, &shutdown).with_operator(&operator);
+
+        assert!(rt.confirm("Confirm coarse move of 500 steps?").unwrap());
+        // Script exhausted: the default ("no") applies.
+        assert!(!rt.confirm("Scan looks clean, continue?").unwrap());
+
+        let events = events.lock().unwrap();
+        let question = custom_event(&events, "operator_question").unwrap();
+        assert_eq!(question["prompt"], "Confirm coarse move of 500 steps?");
+        assert_eq!(question["operator"], "auto");
+        let answer = custom_event(&events, "operator_answer").unwrap();
+        assert_eq!(answer["answer"], "yes");
+        assert_eq!(answer["choice"], 0);
+    }
+
+    #[test]
+    fn an_out_of_range_answer_is_an_error() {
+        struct Wild;
+        impl OperatorInterface for Wild {
+            fn name(&self) -> &str {
+                "wild"
+            }
+            fn ask(&self, _: &Question, _: &ShutdownFlag) -> Result<usize, SpmError> {
+                Ok(7)
+            }
+        }
+
+        let mut mock = MockController::builder().build();
+        let bus = EventBus::new();
+        let shutdown = ShutdownFlag::new();
+        let rt = Rt::new(&mut mock, &bus, &shutdown).with_operator(&Wild);
+
+        assert!(matches!(
+            rt.confirm("continue?"),
+            Err(SpmError::Workflow(_))
+        ));
+    }
+
+    #[test]
+    fn a_stop_request_interrupts_a_pending_question() {
+        let mut mock = MockController::builder().build();
+        let bus = EventBus::new();
+        let shutdown = ShutdownFlag::new();
+        let (operator, _questions) = operator::ChannelOperator::new();
+        let rt = Rt::new(&mut mock, &bus, &shutdown).with_operator(&operator);
+
+        let stopper = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            stopper.request();
+        });
+
+        assert!(matches!(
+            rt.confirm("continue?"),
+            Err(SpmError::ShutdownRequested)
+        ));
+    }
+
+    struct FlakyCounter;
+
+    impl Routine for FlakyCounter {
+        fn name(&self) -> &str {
+            "flaky_counter"
+        }
+
+        fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError> {
+            let mut cycles = rt.cycles(Some(3), None);
+            while cycles.next().is_some() {
+                rt.bias()?.set(0.5)?;
+                // The second read fails (scheduled fault); recover and go on.
+                let _ = rt.bias()?.get();
+            }
+            Ok(cycles.outcome())
+        }
+
+        fn summary(&self) -> serde_json::Value {
+            serde_json::json!({ "note": "done" })
+        }
+    }
+
+    #[test]
+    fn the_run_report_tallies_actions_failures_and_final_state() {
+        let mock = MockController::builder()
+            .fail_on_call("get_bias", 2, crate::mock_controller::FaultKind::Timeout)
+            .build();
+        let bus = EventBus::new();
+
+        let (result, report) = run_routine_reported(
+            Box::new(mock),
+            &bus,
+            &ShutdownFlag::new(),
+            &operator::AutoOperator::defaults(),
+            &mut FlakyCounter,
+        );
+
+        assert_eq!(result.unwrap(), Outcome::CycleLimit(3));
+        assert_eq!(report.outcome, Some(Outcome::CycleLimit(3)));
+        assert_eq!(report.cycles, 3);
+        assert_eq!(report.actions["set_bias"], 3);
+        assert_eq!(report.actions["read_bias"], 3);
+        assert_eq!(report.actions["withdraw"], 1);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].action, "read_bias");
+        assert!(report.cleanup_errors.is_empty());
+        assert_eq!(report.final_state.bias_v, Some(0.5));
+        assert_eq!(report.summary["note"], "done");
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["outcome"]["cycle_limit"], 3);
+        assert!(json["started_at"].is_string());
+    }
+
+    #[test]
+    fn the_run_report_records_an_error_ending() {
+        let mock = MockController::builder()
+            .fail_every("set_bias", crate::mock_controller::FaultKind::Protocol)
+            .fail_every("withdraw", crate::mock_controller::FaultKind::Timeout)
+            .build();
+        let bus = EventBus::new();
+
+        let (result, report) = run_routine_reported(
+            Box::new(mock),
+            &bus,
+            &ShutdownFlag::new(),
+            &operator::AutoOperator::defaults(),
+            &mut FlakyCounter,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(report.outcome, None);
+        assert!(report.error.unwrap().contains("protocol"));
+        assert_eq!(report.cleanup_errors.len(), 1, "the final withdraw failed");
+    }
+
     #[test]
     fn guarded_emits_the_cleanup_error_it_swallows() {
         let mut mock = MockController::builder().build();
@@ -318,4 +792,119 @@ mod tests {
         assert_eq!(data["body_error"], "body failed");
         assert_eq!(data["cleanup_error"], "cleanup failed");
     }
+
+    #[test]
+    fn wait_until_returns_the_value_that_met_the_condition() {
+        let mut mock = MockController::builder()
+            .freq_shift_index(crate::SignalIndex(2))
+            .freq_shift(crate::mock_controller::models::scripted(vec![
+                -5.0, -4.0, -1.0,
+            ]))
+            .build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let value = rt
+            .wait_until(
+                crate::SignalIndex(2),
+                Condition::above(-2.0),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        assert_eq!(value, -1.0);
+
+        let events = events.lock().unwrap();
+        let started = custom_event(&events, "wait_started").unwrap();
+        assert_eq!(started["condition"], Condition::above(-2.0).description());
+        let finished = custom_event(&events, "wait_finished").unwrap();
+        assert_eq!(finished["result"], "met");
+        assert_eq!(finished["polls"], 3);
+        assert_eq!(finished["value"], -1.0);
+        assert!(
+            started_params(&events, "read_signal").is_none(),
+            "polls stay out of the action log"
+        );
+    }
+
+    #[test]
+    fn wait_until_times_out_with_the_last_value() {
+        let mut mock = MockController::builder()
+            .freq_shift_index(crate::SignalIndex(2))
+            .freq_shift(crate::mock_controller::models::always(-5.0))
+            .build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let result = rt.wait_until(
+            crate::SignalIndex(2),
+            Condition::above(-2.0),
+            Duration::from_millis(150),
+        );
+        assert!(matches!(result, Err(SpmError::Timeout(_))), "{result:?}");
+
+        let events = events.lock().unwrap();
+        let finished = custom_event(&events, "wait_finished").unwrap();
+        assert_eq!(finished["result"], "timed_out");
+        assert_eq!(finished["value"], -5.0);
+        assert!(finished["waited_ms"].as_u64().unwrap() >= 150);
+    }
+
+    #[test]
+    fn wait_settled_holds_for_the_requested_time() {
+        let mut mock = MockController::builder().build();
+        let (bus, _events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let started = std::time::Instant::now();
+        let stats = rt
+            .wait_settled(
+                crate::SignalIndex(0),
+                &SettleSpec {
+                    hold: Duration::from_millis(200),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(stats.drift, 0.0);
+    }
+
+    #[test]
+    fn wait_for_drift_needs_the_stream_rate() {
+        let mut mock = MockController::builder().without_data_stream().build();
+        let (bus, _events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let result = rt.wait_for_drift(crate::SignalIndex(0), 0.1, Duration::from_secs(1));
+        assert!(matches!(result, Err(SpmError::Unsupported(_))));
+    }
+
+    #[test]
+    fn a_condition_wait_wakes_on_shutdown() {
+        let mut mock = MockController::builder().build();
+        let (bus, events) = recording_bus();
+        let shutdown = ShutdownFlag::new();
+        let stopper = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            stopper.request();
+        });
+        let mut rt = Rt::new(&mut mock, &bus, &shutdown);
+
+        let result = rt.wait_until(
+            crate::SignalIndex(0),
+            Condition::new("never", |_| false),
+            Duration::from_secs(30),
+        );
+        assert!(matches!(result, Err(SpmError::ShutdownRequested)));
+        let events = events.lock().unwrap();
+        assert_eq!(
+            custom_event(&events, "wait_finished").unwrap()["result"],
+            "stopped"
+        );
+    }
 }
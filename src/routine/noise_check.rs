@@ -0,0 +1,182 @@
+//! The `noise_check` routine: a spectrum of one streamed signal, before
+//! tip preparation starts.
+//!
+//! Run it when stable reads keep failing their `max_std_dev` gate, to tell
+//! mains pickup or a vibration (narrow peaks) from a noisy tip (a raised
+//! broadband floor). See [`analyzer::noise_spectrum`](crate::analyzer::noise_spectrum).
+
+use serde::{Deserialize, Serialize};
+
+use crate::analyzer::noise_spectrum::{NoiseReport, NoiseSpectrumSpec};
+use crate::signal_registry::SignalIndex;
+use crate::spm_error::SpmError;
+
+use super::registry::RoutineConfig;
+use super::{Outcome, Routine, Rt};
+
+/// The `[noise_check]` config section; every field has a default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseCheckConfig {
+    /// Signal to analyze, by name or alias.
+    pub signal: String,
+    /// Stream samples to record; at 2 kHz the default takes about 8 s.
+    pub num_samples: usize,
+    #[serde(flatten)]
+    pub spectrum: NoiseSpectrumSpec,
+}
+
+impl Default for NoiseCheckConfig {
+    fn default() -> Self {
+        Self {
+            signal: "freq shift".to_string(),
+            num_samples: 16384,
+            spectrum: NoiseSpectrumSpec::default(),
+        }
+    }
+}
+
+impl RoutineConfig for NoiseCheckConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.spectrum.segment_len < 16 {
+            return Err(format!(
+                "segment_len must be at least 16, got {}",
+                self.spectrum.segment_len
+            ));
+        }
+        if self.num_samples < self.spectrum.segment_len {
+            return Err(format!(
+                "num_samples ({}) must be at least segment_len ({})",
+                self.num_samples, self.spectrum.segment_len
+            ));
+        }
+        if self.spectrum.min_prominence <= 1.0 {
+            return Err(format!(
+                "min_prominence must be above 1, got {}",
+                self.spectrum.min_prominence
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Record one signal, log its noise spectrum summary and keep the report.
+pub struct NoiseCheck {
+    index: SignalIndex,
+    num_samples: usize,
+    spectrum: NoiseSpectrumSpec,
+    report: Option<NoiseReport>,
+}
+
+impl NoiseCheck {
+    /// Analyze the stream samples of `index`.
+    pub fn new(index: SignalIndex, config: &NoiseCheckConfig) -> Self {
+        Self {
+            index,
+            num_samples: config.num_samples,
+            spectrum: config.spectrum.clone(),
+            report: None,
+        }
+    }
+
+    /// The report of the last run.
+    pub fn report(&self) -> Option<&NoiseReport> {
+        self.report.as_ref()
+    }
+}
+
+impl Routine for NoiseCheck {
+    fn name(&self) -> &str {
+        "noise_check"
+    }
+
+    fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError> {
+        let duration = rt
+            .controller()
+            .stream_sample_rate_hz()
+            .map_or_else(String::new, |rate| {
+                format!(" ({:.1} s)", self.num_samples as f64 / rate)
+            });
+        log::info!(
+            "Noise check: recording {} samples of signal {}{}",
+            self.num_samples,
+            self.index,
+            duration
+        );
+        let report = rt
+            .signals()?
+            .analyze_noise(self.index, self.num_samples, &self.spectrum)?;
+        for line in report.summary() {
+            log::info!("{line}");
+        }
+        self.report = Some(report);
+        Ok(Outcome::Completed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EventBus;
+    use crate::mock_controller::MockController;
+    use crate::routine::run_routine;
+    use crate::shutdown::ShutdownFlag;
+
+    #[test]
+    fn a_mains_hum_on_the_freq_shift_is_reported() {
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .sample_noise_hz(0.05)
+            .sample_tone(50.0, 0.8)
+            .build();
+        let mut routine = NoiseCheck::new(SignalIndex(2), &NoiseCheckConfig::default());
+
+        let outcome = run_routine(
+            Box::new(mock),
+            &EventBus::new(),
+            &ShutdownFlag::new(),
+            &mut routine,
+        )
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Completed);
+        let report = routine.report().unwrap();
+        assert_eq!(report.samples, 16384);
+        let strongest = &report.peaks[0];
+        assert!((strongest.frequency_hz - 50.0).abs() < 1.0, "{strongest:?}");
+        assert_eq!(strongest.mains.map(|m| m.mains_hz), Some(50.0));
+        assert!(report.summary().iter().any(|l| l.contains("50 Hz mains")));
+    }
+
+    #[test]
+    fn polled_samples_are_refused() {
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .without_data_stream()
+            .build();
+        let mut routine = NoiseCheck::new(SignalIndex(2), &NoiseCheckConfig::default());
+
+        let result = run_routine(
+            Box::new(mock),
+            &EventBus::new(),
+            &ShutdownFlag::new(),
+            &mut routine,
+        );
+
+        assert!(
+            matches!(result, Err(SpmError::Unsupported(_))),
+            "{result:?}"
+        );
+        assert!(routine.report().is_none());
+    }
+
+    #[test]
+    fn segments_longer_than_the_recording_are_refused() {
+        let config = NoiseCheckConfig {
+            num_samples: 512,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(NoiseCheckConfig::default().validate().is_ok());
+    }
+}
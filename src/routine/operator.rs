@@ -0,0 +1,356 @@
+//! Operator prompts: questions a routine puts to the human at the instrument.
+//!
+//! A routine asks through [`Rt::ask`](super::Rt::ask) or
+//! [`Rt::confirm`](super::Rt::confirm); who answers is decided by the
+//! [`OperatorInterface`] the run was started with:
+//!
+//! - [`StdinOperator`] for the command line,
+//! - [`ChannelOperator`] for a GUI, which receives [`PendingQuestion`]s on a
+//!   channel and answers them from a modal dialog,
+//! - [`AutoOperator`] for unattended runs and tests, which answers every
+//!   question with its default (or a scripted sequence of choices).
+//!
+//! Asking blocks the routine until an answer arrives, but a shutdown request
+//! always wins: every implementation wakes and returns
+//! `Err(SpmError::ShutdownRequested)` instead of waiting on a human who has
+//! already pressed stop.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::shutdown::ShutdownFlag;
+use crate::spm_error::SpmError;
+
+/// How often a blocked `ask` re-checks the shutdown flag.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A question for the operator: a prompt and the choices they may pick from.
+#[derive(Debug, Clone, Serialize)]
+pub struct Question {
+    pub prompt: String,
+    pub choices: Vec<String>,
+    /// Index into `choices` taken by [`AutoOperator`] and by an empty reply
+    /// on the command line. Make it the safe choice.
+    pub default: usize,
+}
+
+impl Question {
+    /// A yes/no question. The default is "no", so an unattended run never
+    /// agrees to something on the operator's behalf; use
+    /// [`default_choice`](Self::default_choice) to flip it.
+    pub fn yes_no(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            choices: vec!["yes".into(), "no".into()],
+            default: 1,
+        }
+    }
+
+    /// A question with arbitrary choices, defaulting to the first.
+    pub fn choose(prompt: impl Into<String>, choices: &[&str]) -> Self {
+        Self {
+            prompt: prompt.into(),
+            choices: choices.iter().map(|c| c.to_string()).collect(),
+            default: 0,
+        }
+    }
+
+    /// Set the default choice by index.
+    pub fn default_choice(mut self, index: usize) -> Self {
+        self.default = index;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), SpmError> {
+        if self.choices.is_empty() {
+            return Err(SpmError::Workflow(format!(
+                "question '{}' has no choices",
+                self.prompt
+            )));
+        }
+        if self.default >= self.choices.len() {
+            return Err(SpmError::Workflow(format!(
+                "question '{}': default {} is out of range for {} choices",
+                self.prompt,
+                self.default,
+                self.choices.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Who answers a routine's [`Question`]s.
+pub trait OperatorInterface: Send + Sync {
+    /// Short identifier recorded with each answer ("stdin", "gui", "auto").
+    fn name(&self) -> &str;
+
+    /// Put `question` to the operator and block until they pick a choice,
+    /// returning its index. Must return `Err(SpmError::ShutdownRequested)`
+    /// promptly once `shutdown` is requested.
+    fn ask(&self, question: &Question, shutdown: &ShutdownFlag) -> Result<usize, SpmError>;
+}
+
+// ============================================================================
+// Auto
+// ============================================================================
+
+/// Answers without a human: scripted choices first, then each question's
+/// default. What [`run_routine`](super::run_routine) uses when no operator
+/// is attached.
+#[derive(Debug, Default)]
+pub struct AutoOperator {
+    script: Mutex<VecDeque<usize>>,
+}
+
+impl AutoOperator {
+    /// Answer every question with its default.
+    pub fn defaults() -> Self {
+        Self::default()
+    }
+
+    /// Answer the first questions with `choices`, in order, then fall back
+    /// to defaults. An out-of-range scripted choice is an error.
+    pub fn scripted(choices: impl IntoIterator<Item = usize>) -> Self {
+        Self {
+            script: Mutex::new(choices.into_iter().collect()),
+        }
+    }
+}
+
+impl OperatorInterface for AutoOperator {
+    fn name(&self) -> &str {
+        "auto"
+    }
+
+    fn ask(&self, question: &Question, shutdown: &ShutdownFlag) -> Result<usize, SpmError> {
+        if shutdown.is_requested() {
+            return Err(SpmError::ShutdownRequested);
+        }
+        let choice = self.script.lock().pop_front().unwrap_or(question.default);
+        if choice >= question.choices.len() {
+            return Err(SpmError::Workflow(format!(
+                "scripted answer {} is out of range for '{}'",
+                choice, question.prompt
+            )));
+        }
+        Ok(choice)
+    }
+}
+
+// ============================================================================
+// Stdin
+// ============================================================================
+
+/// Asks on the terminal: prints the prompt and reads the reply from stdin.
+///
+/// The operator may type the choice itself or its 1-based number; an empty
+/// line takes the default, anything else re-prompts. Reading happens on a
+/// background thread so the routine's wait stays interruptible.
+pub struct StdinOperator {
+    lines: Receiver<String>,
+}
+
+impl StdinOperator {
+    pub fn new() -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { lines: rx }
+    }
+
+    fn parse(question: &Question, reply: &str) -> Option<usize> {
+        let reply = reply.trim();
+        if reply.is_empty() {
+            return Some(question.default);
+        }
+        if let Ok(n) = reply.parse::<usize>() {
+            return (1..=question.choices.len()).contains(&n).then(|| n - 1);
+        }
+        question
+            .choices
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(reply))
+    }
+
+    fn print_prompt(question: &Question) {
+        let choices: Vec<String> = question
+            .choices
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                if i == question.default {
+                    format!("{}) [{}]", i + 1, c)
+                } else {
+                    format!("{}) {}", i + 1, c)
+                }
+            })
+            .collect();
+        print!("\n{}\n  {} > ", question.prompt, choices.join("  "));
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Default for StdinOperator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OperatorInterface for StdinOperator {
+    fn name(&self) -> &str {
+        "stdin"
+    }
+
+    fn ask(&self, question: &Question, shutdown: &ShutdownFlag) -> Result<usize, SpmError> {
+        // Lines typed while nobody was asking must not answer this question.
+        while self.lines.try_recv().is_ok() {}
+        Self::print_prompt(question);
+        loop {
+            if shutdown.is_requested() {
+                return Err(SpmError::ShutdownRequested);
+            }
+            match self.lines.recv_timeout(POLL_INTERVAL) {
+                Ok(reply) => match Self::parse(question, &reply) {
+                    Some(choice) => return Ok(choice),
+                    None => Self::print_prompt(question),
+                },
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(SpmError::Workflow(
+                        "stdin closed while waiting for an operator answer".into(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Channel (GUI)
+// ============================================================================
+
+/// A question waiting for an answer from the other end of a
+/// [`ChannelOperator`].
+pub struct PendingQuestion {
+    pub question: Question,
+    reply: Sender<usize>,
+}
+
+impl PendingQuestion {
+    /// Answer with the choice at `index`. Dropping the question unanswered
+    /// fails the routine's `ask`.
+    pub fn answer(self, index: usize) {
+        // The routine may have been stopped meanwhile; nobody is listening then.
+        let _ = self.reply.send(index);
+    }
+}
+
+/// Forwards questions over a channel, for a front end running on another
+/// thread (the GUI shows each [`PendingQuestion`] as a modal dialog).
+pub struct ChannelOperator {
+    sender: Sender<PendingQuestion>,
+}
+
+impl ChannelOperator {
+    /// The operator and the receiving end the front end polls.
+    pub fn new() -> (Self, Receiver<PendingQuestion>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        (Self { sender }, receiver)
+    }
+}
+
+impl OperatorInterface for ChannelOperator {
+    fn name(&self) -> &str {
+        "gui"
+    }
+
+    fn ask(&self, question: &Question, shutdown: &ShutdownFlag) -> Result<usize, SpmError> {
+        let (reply, answers) = crossbeam_channel::bounded(1);
+        self.sender
+            .send(PendingQuestion {
+                question: question.clone(),
+                reply,
+            })
+            .map_err(|_| {
+                SpmError::Workflow("operator front end is gone; nobody can answer".into())
+            })?;
+        loop {
+            if shutdown.is_requested() {
+                return Err(SpmError::ShutdownRequested);
+            }
+            match answers.recv_timeout(POLL_INTERVAL) {
+                Ok(choice) if choice < question.choices.len() => return Ok(choice),
+                Ok(choice) => {
+                    return Err(SpmError::Workflow(format!(
+                        "answer {} is out of range for '{}'",
+                        choice, question.prompt
+                    )));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(SpmError::Workflow(format!(
+                        "question '{}' was dismissed without an answer",
+                        question.prompt
+                    )));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stdin_replies_parse_by_number_name_or_default() {
+        let q = Question::choose("which?", &["left", "right"]).default_choice(1);
+        assert_eq!(StdinOperator::parse(&q, ""), Some(1));
+        assert_eq!(StdinOperator::parse(&q, "1"), Some(0));
+        assert_eq!(StdinOperator::parse(&q, " RIGHT "), Some(1));
+        assert_eq!(StdinOperator::parse(&q, "3"), None);
+        assert_eq!(StdinOperator::parse(&q, "up"), None);
+    }
+
+    #[test]
+    fn channel_operator_returns_the_front_end_answer() {
+        let (operator, questions) = ChannelOperator::new();
+        let front_end = std::thread::spawn(move || {
+            let pending = questions.recv().unwrap();
+            assert_eq!(pending.question.prompt, "continue?");
+            pending.answer(0);
+        });
+
+        let choice = operator
+            .ask(&Question::yes_no("continue?"), &ShutdownFlag::new())
+            .unwrap();
+        front_end.join().unwrap();
+        assert_eq!(choice, 0);
+    }
+
+    #[test]
+    fn channel_operator_wakes_on_shutdown() {
+        let (operator, _questions) = ChannelOperator::new();
+        let shutdown = ShutdownFlag::new();
+        let stopper = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            stopper.request();
+        });
+
+        let result = operator.ask(&Question::yes_no("continue?"), &shutdown);
+        assert!(matches!(result, Err(SpmError::ShutdownRequested)));
+    }
+}
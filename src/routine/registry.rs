@@ -0,0 +1,448 @@
+//! Routines by name: what the generic `rusty-tip` runner lists, validates and
+//! runs.
+//!
+//! Each entry ties a routine name to the config section it reads, the type
+//! that section deserialises into, and a constructor. The runner does the
+//! shared setup (connection, signal registry, data stream, event bus, Ctrl+C)
+//! once and hands the constructor a [`RoutineContext`]; a new routine only
+//! needs to [`register`](RoutineRegistry::register) itself, not a binary of
+//! its own.
+//!
+//! ```no_run
+//! use rusty_tip::routine::registry::{RoutineConfig, RoutineRegistry};
+//! use rusty_tip::routine::{Outcome, Routine, Rt};
+//! use rusty_tip::spm_error::SpmError;
+//!
+//! #[derive(serde::Deserialize)]
+//! struct BiasCheckConfig {
+//!     target_v: f64,
+//! }
+//!
+//! impl RoutineConfig for BiasCheckConfig {}
+//!
+//! struct BiasCheck(BiasCheckConfig);
+//!
+//! impl Routine for BiasCheck {
+//!     fn name(&self) -> &str {
+//!         "bias_check"
+//!     }
+//!
+//!     fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError> {
+//!         rt.bias()?.set(self.0.target_v)?;
+//!         Ok(Outcome::Completed)
+//!     }
+//! }
+//!
+//! let mut registry = RoutineRegistry::with_builtins();
+//! registry.register::<BiasCheckConfig>(
+//!     "bias_check",
+//!     "bias_check",
+//!     "Set the bias and read it back",
+//!     |config, _ctx| Ok(Box::new(BiasCheck(config))),
+//! );
+//! ```
+
+use config::ConfigError;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::config::{AppConfig, TipPrepConfig};
+use crate::signal_registry::{SignalIndex, SignalRegistry};
+use crate::spm_error::SpmError;
+use crate::tip_prep::TipPrep;
+
+use super::Routine;
+use super::noise_check::{NoiseCheck, NoiseCheckConfig};
+
+/// A routine's config section: deserialised from the config file, then
+/// checked before anything connects to the instrument.
+pub trait RoutineConfig: DeserializeOwned {
+    /// Reject values that parse but make no sense. Defaults to accepting
+    /// everything.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl RoutineConfig for TipPrepConfig {
+    fn validate(&self) -> Result<(), String> {
+        TipPrepConfig::validate(self)
+    }
+}
+
+/// What the runner has set up by the time a routine is constructed.
+pub struct RoutineContext<'a> {
+    /// The whole application config (connection, acquisition, logging).
+    pub config: &'a AppConfig,
+    /// Signals of the connected controller.
+    pub signals: &'a SignalRegistry,
+}
+
+impl RoutineContext<'_> {
+    /// Resolve a signal by name or alias; see [`SignalRegistry::resolve`].
+    pub fn signal(&self, name: &str) -> Result<SignalIndex, SpmError> {
+        self.signals.resolve(name)
+    }
+}
+
+/// Builds a routine from its deserialised config section.
+pub type RoutineConstructor<C> =
+    for<'a> fn(C, &RoutineContext<'a>) -> Result<Box<dyn Routine + 'a>, SpmError>;
+
+type ErasedValidator = Box<dyn Fn(&Value) -> Result<(), ConfigError> + Send + Sync>;
+
+type ErasedConstructor = Box<
+    dyn for<'a> Fn(&Value, &RoutineContext<'a>) -> Result<Box<dyn Routine + 'a>, SpmError>
+        + Send
+        + Sync,
+>;
+
+/// One registered routine.
+pub struct RoutineEntry {
+    name: &'static str,
+    section: &'static str,
+    description: &'static str,
+    validate: ErasedValidator,
+    construct: ErasedConstructor,
+}
+
+impl RoutineEntry {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Top-level config key the routine reads, e.g. `tip_prep` for
+    /// `[tip_prep]`.
+    pub fn section(&self) -> &'static str {
+        self.section
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+}
+
+impl std::fmt::Debug for RoutineEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RoutineEntry")
+            .field("name", &self.name)
+            .field("section", &self.section)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The routines a runner can start, in registration order.
+#[derive(Debug, Default)]
+pub struct RoutineRegistry {
+    entries: Vec<RoutineEntry>,
+}
+
+impl RoutineRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding the routines shipped with the crate.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register::<TipPrepConfig>(
+            "tip_prep",
+            "tip_prep",
+            "Pulse and reposition until the tip is sharp and stable",
+            |section, ctx| {
+                // TipPrep also reads the pulse method and acquisition settings,
+                // so the parsed section goes into a copy of the full config.
+                let criterion = ctx.signal(&section.signal)?;
+                let unit = ctx
+                    .signals
+                    .get_by_name(&section.signal)
+                    .and_then(|s| s.unit.as_ref());
+                section
+                    .check_criterion_unit(unit)
+                    .map_err(SpmError::Workflow)?;
+                let config = AppConfig {
+                    tip_prep: section,
+                    ..ctx.config.clone()
+                };
+                let mut routine = TipPrep::owned(config, criterion);
+                // The current is optional: tip-change detection falls back to
+                // the frequency shift alone.
+                if let Ok(current) = ctx.signal("current") {
+                    routine = routine.with_current(current);
+                }
+                Ok(Box::new(routine))
+            },
+        );
+        registry.register::<NoiseCheckConfig>(
+            "noise_check",
+            "noise_check",
+            "Noise spectrum of a streamed signal: mains pickup, vibrations or a noisy tip",
+            |section, ctx| {
+                let index = ctx.signal(&section.signal)?;
+                Ok(Box::new(NoiseCheck::new(index, &section)))
+            },
+        );
+        registry
+    }
+
+    /// Register a routine reading config section `section` as a `C`.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is already registered.
+    pub fn register<C: RoutineConfig + 'static>(
+        &mut self,
+        name: &'static str,
+        section: &'static str,
+        description: &'static str,
+        constructor: RoutineConstructor<C>,
+    ) {
+        assert!(
+            self.get(name).is_none(),
+            "routine '{name}' is registered twice"
+        );
+        self.entries.push(RoutineEntry {
+            name,
+            section,
+            description,
+            validate: Box::new(move |document| parse_section::<C>(document, section).map(drop)),
+            construct: Box::new(move |document, ctx| {
+                let config = parse_section::<C>(document, section)
+                    .map_err(|e| SpmError::Workflow(e.to_string()))?;
+                constructor(config, ctx)
+            }),
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RoutineEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Registered routines, in registration order.
+    pub fn entries(&self) -> impl Iterator<Item = &RoutineEntry> {
+        self.entries.iter()
+    }
+
+    /// Check `name`'s config section in `document` (the whole config file,
+    /// see [`load_config_document`](crate::config::load_config_document)).
+    pub fn validate(&self, name: &str, document: &Value) -> Result<(), ConfigError> {
+        let entry = self.get(name).ok_or_else(|| self.unknown(name))?;
+        (entry.validate)(document)
+    }
+
+    /// Construct `name` from its config section in `document`.
+    pub fn build<'a>(
+        &self,
+        name: &str,
+        document: &Value,
+        ctx: &RoutineContext<'a>,
+    ) -> Result<Box<dyn Routine + 'a>, SpmError> {
+        let entry = self
+            .get(name)
+            .ok_or_else(|| SpmError::Workflow(self.unknown(name).to_string()))?;
+        (entry.construct)(document, ctx)
+    }
+
+    fn unknown(&self, name: &str) -> ConfigError {
+        let known: Vec<&str> = self.entries.iter().map(|e| e.name).collect();
+        ConfigError::Message(format!(
+            "unknown routine '{name}' (known: {})",
+            known.join(", ")
+        ))
+    }
+}
+
+/// Deserialise and validate `section` of `document`. A missing section parses
+/// as an empty table, so a config type whose fields all have defaults needs
+/// no section at all.
+fn parse_section<C: RoutineConfig>(document: &Value, section: &str) -> Result<C, ConfigError> {
+    let value = document
+        .get(section)
+        .cloned()
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    let config: C = serde_json::from_value(value)
+        .map_err(|e| ConfigError::Message(format!("[{section}]: {e}")))?;
+    config
+        .validate()
+        .map_err(|e| ConfigError::Message(format!("[{section}]: {e}")))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use crate::mock_controller::MockController;
+    use crate::routine::{Outcome, Rt};
+
+    #[derive(Deserialize)]
+    struct HoldConfig {
+        #[serde(default = "default_hold_ms")]
+        hold_ms: u64,
+    }
+
+    fn default_hold_ms() -> u64 {
+        10
+    }
+
+    impl RoutineConfig for HoldConfig {
+        fn validate(&self) -> Result<(), String> {
+            if self.hold_ms > 1000 {
+                return Err(format!("hold_ms {} exceeds 1000", self.hold_ms));
+            }
+            Ok(())
+        }
+    }
+
+    struct Hold(u64);
+
+    impl Routine for Hold {
+        fn name(&self) -> &str {
+            "hold"
+        }
+
+        fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError> {
+            rt.settle(self.0)?;
+            Ok(Outcome::Completed)
+        }
+    }
+
+    fn registry() -> RoutineRegistry {
+        let mut registry = RoutineRegistry::with_builtins();
+        registry.register::<HoldConfig>("hold", "hold", "Wait a while", |config, _ctx| {
+            Ok(Box::new(Hold(config.hold_ms)))
+        });
+        registry
+    }
+
+    fn signals() -> SignalRegistry {
+        let mut mock = MockController::builder().build();
+        SignalRegistry::builder()
+            .with_standard_map()
+            .from_controller(&mut mock)
+            .unwrap()
+            .create_aliases()
+            .build()
+    }
+
+    #[test]
+    fn lists_builtins_before_registered_routines() {
+        let names: Vec<_> = registry().entries().map(|e| e.name()).collect();
+        assert_eq!(names, ["tip_prep", "noise_check", "hold"]);
+    }
+
+    #[test]
+    fn validation_reports_the_section_and_the_problem() {
+        let registry = registry();
+        registry.validate("hold", &json!({})).unwrap();
+        registry
+            .validate("hold", &json!({ "hold": { "hold_ms": 20 } }))
+            .unwrap();
+
+        let err = registry
+            .validate("hold", &json!({ "hold": { "hold_ms": 5000 } }))
+            .unwrap_err();
+        assert!(err.to_string().contains("[hold]: hold_ms 5000"), "{err}");
+
+        let err = registry.validate("tip_prep", &json!({})).unwrap_err();
+        assert!(err.to_string().contains("sharp_tip_bounds"), "{err}");
+
+        let err = registry.validate("nope", &json!({})).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("known: tip_prep, noise_check, hold"),
+            "{err}"
+        );
+
+        registry.validate("noise_check", &json!({})).unwrap();
+        let err = registry
+            .validate(
+                "noise_check",
+                &json!({ "noise_check": { "num_samples": 100 } }),
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("[noise_check]: num_samples"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn builds_routines_from_their_section() {
+        let registry = registry();
+        let config = AppConfig::default();
+        let signals = signals();
+        let ctx = RoutineContext {
+            config: &config,
+            signals: &signals,
+        };
+        let document = serde_json::to_value(&config).unwrap();
+
+        let tip_prep = registry.build("tip_prep", &document, &ctx).unwrap();
+        assert_eq!(tip_prep.name(), "tip_prep");
+        let noise_check = registry.build("noise_check", &document, &ctx).unwrap();
+        assert_eq!(noise_check.name(), "noise_check");
+        let hold = registry.build("hold", &document, &ctx).unwrap();
+        assert_eq!(hold.name(), "hold");
+    }
+
+    #[test]
+    fn tip_prep_is_built_from_the_parsed_section() {
+        let registry = registry();
+        let config = AppConfig::default();
+        let signals = signals();
+        let ctx = RoutineContext {
+            config: &config,
+            signals: &signals,
+        };
+        let mut document = serde_json::to_value(&config).unwrap();
+        document["tip_prep"]["signal"] = json!("no such signal");
+
+        let err = match registry.build("tip_prep", &document, &ctx) {
+            Err(e) => e,
+            Ok(_) => panic!("the section's signal should have been resolved"),
+        };
+        assert!(err.to_string().contains("no such signal"), "{err}");
+    }
+
+    #[test]
+    fn a_criterion_in_other_units_needs_its_own_gates() {
+        let registry = registry();
+        let config = AppConfig::default();
+        let signals = signals();
+        let ctx = RoutineContext {
+            config: &config,
+            signals: &signals,
+        };
+        let mut document = serde_json::to_value(&config).unwrap();
+        document["tip_prep"]["signal"] = json!("current");
+
+        let err = match registry.build("tip_prep", &document, &ctx) {
+            Err(e) => e,
+            Ok(_) => panic!("Hz gates on a current should be refused"),
+        };
+        let message = err.to_string();
+        assert!(message.contains("is in A"), "{message}");
+        assert!(message.contains("max_std_dev_hz"), "{message}");
+
+        let section = &mut document["tip_prep"];
+        section["signal_stability"]["max_std_dev_hz"] = json!(5e-12);
+        section["signal_stability"]["max_slope_hz_per_s"] = json!(1e-12);
+        section["confirmation"]["outlier_hz"] = json!(50e-12);
+        section["stability"]["stable_tip_allowed_change"] = json!(10e-12);
+        assert!(registry.build("tip_prep", &document, &ctx).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn duplicate_names_are_rejected() {
+        let mut registry = registry();
+        registry.register::<HoldConfig>("hold", "hold", "", |config, _ctx| {
+            Ok(Box::new(Hold(config.hold_ms)))
+        });
+    }
+}
@@ -0,0 +1,113 @@
+//! The structured record of one routine run, built by
+//! [`run_routine_reported`](super::run_routine_reported).
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::spm_controller::{Capability, SpmController};
+
+use super::Outcome;
+
+/// What happened during a routine run, without replaying the event stream.
+///
+/// Serialises to JSON for logs and post-run tooling; `tip-prep` prints it on
+/// exit.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    /// [`Routine::name`](super::Routine::name) of the routine that ran.
+    pub routine: String,
+    /// How the run ended; `None` if it ended with an error.
+    pub outcome: Option<Outcome>,
+    /// The error the run ended with, if any.
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub duration_secs: f64,
+    /// Cycles handed out by [`Rt::cycles`](super::Rt::cycles).
+    pub cycles: usize,
+    /// How often each action (and logged controller operation) ran, by name.
+    pub actions: BTreeMap<String, usize>,
+    /// Every action that failed, in order, including ones the routine
+    /// recovered from.
+    pub failures: Vec<ActionFailure>,
+    /// Cleanup steps that failed: errors swallowed by
+    /// [`Rt::guarded`](super::Rt::guarded) and a failed final withdraw.
+    pub cleanup_errors: Vec<String>,
+    /// The instrument after cleanup, just before teardown.
+    pub final_state: InstrumentSnapshot,
+    /// Routine-specific results from [`Routine::summary`](super::Routine::summary).
+    pub summary: serde_json::Value,
+}
+
+/// One failed action in a [`RunReport`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionFailure {
+    pub action: String,
+    pub error: String,
+}
+
+/// Best-effort readback of the instrument state. A field is `None` when the
+/// controller lacks the subsystem or the read failed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstrumentSnapshot {
+    pub bias_v: Option<f64>,
+    pub z_controller: Option<String>,
+    pub position_m: Option<(f64, f64)>,
+    pub scan_running: Option<bool>,
+    pub safe_tip_enabled: Option<bool>,
+}
+
+impl InstrumentSnapshot {
+    /// Read what the controller supports. Reads go straight to the
+    /// controller, so they stay out of the event log and the action counts.
+    pub(crate) fn capture(controller: &mut dyn SpmController) -> Self {
+        if !controller.is_connected() {
+            return Self::default();
+        }
+        let caps = controller.capabilities();
+        let has = |cap| caps.contains(&cap);
+        Self {
+            bias_v: has(Capability::Bias)
+                .then(|| controller.get_bias().ok())
+                .flatten(),
+            z_controller: has(Capability::ZController)
+                .then(|| controller.z_controller_status().ok())
+                .flatten()
+                .map(|s| format!("{s:?}")),
+            position_m: has(Capability::PiezoPosition)
+                .then(|| controller.get_position(false).ok())
+                .flatten()
+                .map(|p| (p.x, p.y)),
+            scan_running: has(Capability::Scanning)
+                .then(|| controller.scan_status().ok())
+                .flatten(),
+            safe_tip_enabled: has(Capability::SafeTip)
+                .then(|| controller.safe_tip_enabled().ok())
+                .flatten(),
+        }
+    }
+}
+
+/// What [`Rt`](super::Rt) tallies while a routine runs, turned into a
+/// [`RunReport`] when it ends.
+#[derive(Debug, Default)]
+pub(crate) struct RunTally {
+    pub actions: BTreeMap<String, usize>,
+    pub failures: Vec<ActionFailure>,
+    pub cleanup_errors: Vec<String>,
+}
+
+impl RunTally {
+    pub fn started(&mut self, action: &str) {
+        *self.actions.entry(action.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn failed(&mut self, action: &str, error: &str) {
+        self.failures.push(ActionFailure {
+            action: action.to_string(),
+            error: error.to_string(),
+        });
+    }
+}
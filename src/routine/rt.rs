@@ -1,3 +1,6 @@
+use std::panic::Location;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 use crate::action::{Action, ActionContext, ActionOutput, DataStore};
@@ -7,7 +10,11 @@ use crate::spm_controller::{Capability, SpmController};
 use crate::spm_error::SpmError;
 
 use super::Outcome;
-use super::subsystems::{Bias, Motor, Scan, Signals, ZCtrl};
+use super::operator::{AutoOperator, OperatorInterface, Question};
+use super::report::RunTally;
+use super::subsystems::{
+    Bias, DataStream, Motor, Osci, Piezo, Pll, SafeTip, Scan, Signals, TipShaper, ZCtrl,
+};
 
 /// The routine runtime: what a [`super::Routine`] runs against.
 ///
@@ -21,6 +28,22 @@ pub struct Rt<'a> {
     events: &'a EventBus,
     shutdown: &'a ShutdownFlag,
     store: DataStore,
+    /// Open [`Rt::with_deadline`] scopes, outermost first.
+    deadlines: Vec<DeadlineScope>,
+    /// Who answers [`Rt::ask`]; `None` answers every question with its default.
+    operator: Option<&'a dyn OperatorInterface>,
+    /// Action counts, failures and cleanup errors for the run report.
+    tally: RunTally,
+    /// Cycles handed out by every [`Cycles`] this runtime created.
+    cycles_run: Arc<AtomicUsize>,
+}
+
+/// One open [`Rt::with_deadline`] scope.
+struct DeadlineScope {
+    deadline: Instant,
+    budget: Duration,
+    /// Where `with_deadline` was called; identifies the scope in events.
+    location: &'static Location<'static>,
 }
 
 impl<'a> Rt<'a> {
@@ -34,9 +57,20 @@ impl<'a> Rt<'a> {
             events,
             shutdown,
             store: DataStore::new(),
+            deadlines: Vec::new(),
+            operator: None,
+            tally: RunTally::default(),
+            cycles_run: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    /// Route [`ask`](Self::ask) to `operator`. Without one, every question is
+    /// answered with its default, as by [`AutoOperator::defaults`].
+    pub fn with_operator(mut self, operator: &'a dyn OperatorInterface) -> Self {
+        self.operator = Some(operator);
+        self
+    }
+
     // -- Subsystems --
 
     /// Bias voltage control. Errors if the controller lacks [`Capability::Bias`].
@@ -69,6 +103,42 @@ impl<'a> Rt<'a> {
         Ok(Scan { rt: self })
     }
 
+    /// Oscilloscope acquisition. Errors if the controller lacks [`Capability::Oscilloscope`].
+    pub fn osci(&mut self) -> Result<Osci<'_, 'a>, SpmError> {
+        self.require(Capability::Oscilloscope)?;
+        Ok(Osci { rt: self })
+    }
+
+    /// Tip shaper. Errors if the controller lacks [`Capability::TipShaper`].
+    pub fn tip_shaper(&mut self) -> Result<TipShaper<'_, 'a>, SpmError> {
+        self.require(Capability::TipShaper)?;
+        Ok(TipShaper { rt: self })
+    }
+
+    /// Phase-locked loop. Errors if the controller lacks [`Capability::Pll`].
+    pub fn pll(&mut self) -> Result<Pll<'_, 'a>, SpmError> {
+        self.require(Capability::Pll)?;
+        Ok(Pll { rt: self })
+    }
+
+    /// Piezo fine positioning. Errors if the controller lacks [`Capability::PiezoPosition`].
+    pub fn piezo(&mut self) -> Result<Piezo<'_, 'a>, SpmError> {
+        self.require(Capability::PiezoPosition)?;
+        Ok(Piezo { rt: self })
+    }
+
+    /// Tip-crash protection. Errors if the controller lacks [`Capability::SafeTip`].
+    pub fn safe_tip(&mut self) -> Result<SafeTip<'_, 'a>, SpmError> {
+        self.require(Capability::SafeTip)?;
+        Ok(SafeTip { rt: self })
+    }
+
+    /// Data stream control. Errors if the controller lacks [`Capability::DataStream`].
+    pub fn data_stream(&mut self) -> Result<DataStream<'_, 'a>, SpmError> {
+        self.require(Capability::DataStream)?;
+        Ok(DataStream { rt: self })
+    }
+
     /// Escape hatch: the bare controller, for operations the subsystem
     /// handles don't cover. Calls made through this bypass event logging.
     pub fn controller(&mut self) -> &mut dyn SpmController {
@@ -79,11 +149,52 @@ impl<'a> Rt<'a> {
 
     /// Wait for `ms` milliseconds, waking early on a shutdown request
     /// (which surfaces as `Err(SpmError::ShutdownRequested)`).
+    ///
+    /// Inside a [`with_deadline`](Self::with_deadline) scope the wait is cut
+    /// short at the deadline and fails with `Err(SpmError::Timeout)`, so a
+    /// polling loop built on `settle` needs no deadline handling of its own.
+    /// On a controller with [simulated time](SpmController::simulated_time)
+    /// the wait goes through [`SpmController::pause`] instead; deadlines are
+    /// always measured on [the controller's clock](SpmController::now).
     pub fn settle(&self, ms: u64) -> Result<(), SpmError> {
-        if self.shutdown.wait_timeout(Duration::from_millis(ms)) {
-            Err(SpmError::ShutdownRequested)
+        let wanted = Duration::from_millis(ms);
+        let (wait, expiring) = match self.nearest_deadline() {
+            Some((depth, scope)) => {
+                let left = scope
+                    .deadline
+                    .saturating_duration_since(self.controller.now());
+                if left <= wanted {
+                    (left, Some(depth))
+                } else {
+                    (wanted, None)
+                }
+            }
+            None => (wanted, None),
+        };
+        let interrupted = if self.controller.simulated_time() {
+            self.controller.pause(wait);
+            self.shutdown.is_requested()
         } else {
-            Ok(())
+            self.shutdown.wait_timeout(wait)
+        };
+        if interrupted {
+            return Err(SpmError::ShutdownRequested);
+        }
+        match expiring {
+            Some(depth) => Err(self.deadline_expired(depth)),
+            None => Ok(()),
+        }
+    }
+
+    /// Bail out with `Err(SpmError::Timeout)` if the deadline of any open
+    /// [`with_deadline`](Self::with_deadline) scope has passed. For loops
+    /// that make progress without calling [`settle`](Self::settle).
+    pub fn check_deadline(&self) -> Result<(), SpmError> {
+        match self.nearest_deadline() {
+            Some((depth, scope)) if scope.deadline <= self.controller.now() => {
+                Err(self.deadline_expired(depth))
+            }
+            _ => Ok(()),
         }
     }
 
@@ -106,6 +217,64 @@ impl<'a> Rt<'a> {
         self.events.emit(event);
     }
 
+    /// Put a question to the operator and block until it is answered,
+    /// returning the index of the chosen [`Question::choices`] entry.
+    ///
+    /// The wait wakes on a stop request (`Err(SpmError::ShutdownRequested)`);
+    /// an answer outside the choices fails with `Err(SpmError::Workflow)`.
+    /// Both sides go to the event log: an `operator_question` event when
+    /// asked, an `operator_answer` event with the choice once answered.
+    pub fn ask(&self, question: &Question) -> Result<usize, SpmError> {
+        question.validate()?;
+        let fallback = AutoOperator::defaults();
+        let operator = self.operator.unwrap_or(&fallback);
+
+        log::info!("Asking operator: {}", question.prompt);
+        self.events.emit(Event::custom(
+            "operator_question",
+            serde_json::json!({
+                "prompt": question.prompt,
+                "choices": question.choices,
+                "default": question.default,
+                "operator": operator.name(),
+            }),
+        ));
+
+        let start = Instant::now();
+        let choice = operator.ask(question, self.shutdown)?;
+        if choice >= question.choices.len() {
+            return Err(SpmError::Workflow(format!(
+                "operator '{}' answered {} to '{}', which has {} choices",
+                operator.name(),
+                choice,
+                question.prompt,
+                question.choices.len()
+            )));
+        }
+        log::info!(
+            "Operator answered '{}': {}",
+            question.prompt,
+            question.choices[choice]
+        );
+        self.events.emit(Event::custom(
+            "operator_answer",
+            serde_json::json!({
+                "prompt": question.prompt,
+                "choice": choice,
+                "answer": question.choices[choice],
+                "operator": operator.name(),
+                "waited_ms": start.elapsed().as_millis() as u64,
+            }),
+        ));
+        Ok(choice)
+    }
+
+    /// Ask a yes/no question; `true` means the operator said yes. Defaults
+    /// to no (see [`Question::yes_no`]).
+    pub fn confirm(&self, prompt: &str) -> Result<bool, SpmError> {
+        Ok(self.ask(&Question::yes_no(prompt))? == 0)
+    }
+
     /// Start a budgeted main loop. `None` means unlimited.
     ///
     /// The returned [`Cycles`] is independent of `rt` (it snapshots the
@@ -126,6 +295,7 @@ impl<'a> Rt<'a> {
             max_duration,
             completed: 0,
             ending: None,
+            total: Arc::clone(&self.cycles_run),
         }
     }
 
@@ -152,10 +322,14 @@ impl<'a> Rt<'a> {
         let result = body(self);
         match (result, cleanup(self)) {
             (Ok(value), Ok(())) => Ok(value),
-            (Ok(_), Err(cleanup_err)) => Err(cleanup_err),
+            (Ok(_), Err(cleanup_err)) => {
+                self.tally.cleanup_errors.push(cleanup_err.to_string());
+                Err(cleanup_err)
+            }
             (Err(body_err), Ok(())) => Err(body_err),
             (Err(body_err), Err(cleanup_err)) => {
                 log::error!("Cleanup after a failure also failed: {}", cleanup_err);
+                self.tally.cleanup_errors.push(cleanup_err.to_string());
                 self.events.emit(Event::custom(
                     "cleanup_failed",
                     serde_json::json!({
@@ -168,8 +342,97 @@ impl<'a> Rt<'a> {
         }
     }
 
+    /// Run `body` with a time budget: once `budget` has passed, every
+    /// [`settle`](Self::settle) and [`check_deadline`](Self::check_deadline)
+    /// inside it fails with `Err(SpmError::Timeout)`.
+    ///
+    /// Scopes nest, and an inner scope can only tighten the budget, never
+    /// extend it: the earliest open deadline is the one enforced. The expiry
+    /// is emitted as a `deadline_expired` event naming the scope by the
+    /// source location of its `with_deadline` call, its nesting depth
+    /// (1 = outermost) and its budget. The timeout is an ordinary error, so
+    /// a routine that does not handle it ends there and [`run_routine`]
+    /// withdraws the tip.
+    ///
+    /// ```ignore
+    /// rt.with_deadline(Duration::from_secs(60), |rt| {
+    ///     rt.z()?.auto_approach()?;
+    ///     rt.settle(500)
+    /// })?;
+    /// ```
+    ///
+    /// The deadline is checked at wait points, not enforced preemptively: a
+    /// blocking controller call that is already running (an auto-approach,
+    /// a long motor move) is not interrupted, and the scope expires at the
+    /// next wait after it returns.
+    ///
+    /// [`run_routine`]: super::run_routine
+    #[track_caller]
+    pub fn with_deadline<T>(
+        &mut self,
+        budget: Duration,
+        body: impl FnOnce(&mut Rt<'a>) -> Result<T, SpmError>,
+    ) -> Result<T, SpmError> {
+        self.deadlines.push(DeadlineScope {
+            deadline: self.controller.now() + budget,
+            budget,
+            location: Location::caller(),
+        });
+        let result = body(self);
+        self.deadlines.pop();
+        result
+    }
+
     // -- Internal --
 
+    /// Hand the run's tally and cycle count to the report.
+    pub(crate) fn take_tally(&mut self) -> (RunTally, usize) {
+        (
+            std::mem::take(&mut self.tally),
+            self.cycles_run.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Record a cleanup failure that happened outside [`guarded`](Self::guarded).
+    pub(crate) fn record_cleanup_error(&mut self, error: &SpmError) {
+        self.tally.cleanup_errors.push(error.to_string());
+    }
+
+    /// The open scope whose deadline comes first, with its 1-based depth.
+    /// On a tie the outer scope wins: it is the one whose budget ran out.
+    fn nearest_deadline(&self) -> Option<(usize, &DeadlineScope)> {
+        self.deadlines
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, scope)| scope.deadline)
+            .map(|(i, scope)| (i + 1, scope))
+    }
+
+    /// Report the expiry of the scope at `depth` and build its error.
+    fn deadline_expired(&self, depth: usize) -> SpmError {
+        let scope = &self.deadlines[depth - 1];
+        let location = scope.location.to_string();
+        log::warn!(
+            "Deadline of {:.1} s expired (scope at {}, depth {})",
+            scope.budget.as_secs_f64(),
+            location,
+            depth
+        );
+        self.events.emit(Event::custom(
+            "deadline_expired",
+            serde_json::json!({
+                "scope": location,
+                "depth": depth,
+                "budget_ms": scope.budget.as_millis() as u64,
+            }),
+        ));
+        SpmError::Timeout(format!(
+            "deadline of {:.1} s for the scope at {} expired",
+            scope.budget.as_secs_f64(),
+            location
+        ))
+    }
+
     fn require(&self, cap: Capability) -> Result<(), SpmError> {
         if self.controller.capabilities().contains(&cap) {
             Ok(())
@@ -200,6 +463,7 @@ impl<'a> Rt<'a> {
         op: impl FnOnce(&mut dyn SpmController) -> Result<T, SpmError>,
     ) -> Result<T, SpmError> {
         let start = Instant::now();
+        self.tally.started(name);
         self.events.emit(Event::action_started(name, params));
         match op(&mut *self.controller) {
             Ok(value) => {
@@ -211,6 +475,7 @@ impl<'a> Rt<'a> {
                 Ok(value)
             }
             Err(e) => {
+                self.tally.failed(name, &e.to_string());
                 self.events
                     .emit(Event::action_failed(name, &e.to_string(), start.elapsed()));
                 Err(e)
@@ -223,8 +488,9 @@ impl<'a> Rt<'a> {
     pub(crate) fn exec(&mut self, action: &dyn Action) -> Result<ActionOutput, SpmError> {
         let name = action.name().to_string();
         let start = Instant::now();
+        self.tally.started(&name);
         self.events
-            .emit(Event::action_started(&name, serde_json::json!({})));
+            .emit(Event::action_started(&name, action.params()));
         let mut ctx = ActionContext {
             controller: self.controller,
             store: &mut self.store,
@@ -241,6 +507,7 @@ impl<'a> Rt<'a> {
                 Ok(output)
             }
             Err(e) => {
+                self.tally.failed(&name, &e.to_string());
                 self.events
                     .emit(Event::action_failed(&name, &e.to_string(), start.elapsed()));
                 Err(e)
@@ -263,6 +530,8 @@ pub struct Cycles {
     max_duration: Option<Duration>,
     completed: usize,
     ending: Option<Outcome>,
+    /// Shared with the [`Rt`] that created this, for the run report.
+    total: Arc<AtomicUsize>,
 }
 
 impl Cycles {
@@ -290,6 +559,7 @@ impl Cycles {
             return None;
         }
         self.completed += 1;
+        self.total.fetch_add(1, Ordering::Relaxed);
         Some(self.completed)
     }
 
@@ -298,6 +568,11 @@ impl Cycles {
         self.started.elapsed()
     }
 
+    /// Why the loop stopped, or `None` while it is still running.
+    pub(crate) fn ending(&self) -> Option<Outcome> {
+        self.ending
+    }
+
     /// Why the loop stopped. Call after `next()` has returned `None`.
     ///
     /// # Panics
@@ -324,6 +599,7 @@ mod tests {
             max_duration: None,
             completed: 0,
             ending: None,
+            total: Default::default(),
         };
         let mut seen = Vec::new();
         while let Some(c) = cycles.next() {
@@ -343,6 +619,7 @@ mod tests {
             max_duration: None,
             completed: 0,
             ending: None,
+            total: Default::default(),
         };
         assert_eq!(cycles.next(), Some(1));
         shutdown.request();
@@ -360,6 +637,7 @@ mod tests {
             max_duration: Some(Duration::from_secs(1)),
             completed: 0,
             ending: None,
+            total: Default::default(),
         };
         assert_eq!(cycles.next(), None);
         assert_eq!(cycles.outcome(), Outcome::TimedOut(Duration::from_secs(1)));
@@ -376,6 +654,7 @@ mod tests {
             max_duration: None,
             completed: 0,
             ending: None,
+            total: Default::default(),
         };
         let _ = cycles.outcome();
     }
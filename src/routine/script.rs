@@ -0,0 +1,963 @@
+//! Ad-hoc routines written in [Rhai](https://rhai.rs), run without
+//! recompiling.
+//!
+//! A [`ScriptRoutine`] is an ordinary [`Routine`]: it runs under
+//! [`run_routine`](super::run_routine), so a script gets the same capability
+//! checks, event logging, deadlines and withdraw-on-exit as compiled code.
+//! The bindings mirror the Rust API:
+//!
+//! ```text
+//! let fs = signal("freq shift");
+//! bias().set(-0.5);
+//! z().auto_approach();
+//! settle(500);
+//!
+//! let c = cycles(20);
+//! for n in c {
+//!     bias().pulse(4.0, 50);
+//!     let df = signals().read_stable(fs);
+//!     emit("script_reading", #{ cycle: n, freq_shift: df });
+//!     if df > -1.5 { return; }
+//! }
+//! c.outcome()
+//! ```
+//!
+//! Available to scripts:
+//!
+//! - subsystem handles `bias()`, `z()`, `signals()`, `motor()`, `scan()`,
+//!   `osci()`, `tip_shaper()`, `pll()`, `piezo()`, `safe_tip()`,
+//!   `data_stream()`, with the methods of their Rust counterparts;
+//! - `signal(name)` to resolve a signal index through the registry;
+//! - `settle(ms)`, `check_shutdown()`, `cycles([max[, secs]])`,
+//!   `guarded(body, cleanup)`, `with_deadline(ms, body)` (bodies are
+//!   function pointers, e.g. `|| z().auto_approach()`);
+//! - `wait_below(signal, limit, ms)`, `wait_above(signal, limit, ms)` and
+//!   `wait_for_drift(signal, max_drift, ms)`, the condition waits of [`Rt`];
+//! - `emit(kind, map)`, `confirm(prompt)`, `ask(prompt, choices)`; `print`
+//!   goes to the log.
+//!
+//! A script that returns the `outcome()` of its cycles ends with that
+//! outcome; any other return value means [`Outcome::Completed`]. Scripts are
+//! sandboxed: no `eval`, no module imports, no file or network access, and a
+//! shutdown request terminates even a script stuck in a busy loop.
+//!
+//! The script runs on its own thread; every binding that touches the
+//! instrument is shipped to the routine thread and executed there against
+//! [`Rt`], so the script never holds the runtime itself.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use rhai::{AST, Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, NativeCallContext, Position};
+
+use crate::action::oscilloscope::AcquisitionModeParam;
+use crate::action::scan::ScanDirectionParam;
+use crate::action::tip_shaper::TipShaperParams;
+use crate::event::Event;
+use crate::shutdown::ShutdownFlag;
+use crate::signal_registry::{SignalIndex, SignalRegistry};
+use crate::spm_error::SpmError;
+
+use super::operator::Question;
+use super::registry::RoutineContext;
+use super::{Condition, Cycles, Outcome, RepositionSpec, Routine, Rt, StableReadSpec};
+
+type RhaiResultOf<T> = Result<T, Box<EvalAltResult>>;
+
+/// A routine whose body is a Rhai script.
+pub struct ScriptRoutine {
+    name: String,
+    ast: AST,
+    signals: SignalRegistry,
+    read_spec: StableReadSpec,
+    reposition: RepositionSpec,
+}
+
+impl ScriptRoutine {
+    /// Compile `source`. Syntax errors are reported here, before anything
+    /// touches the instrument. Stable-read and reposition defaults come from
+    /// the config, as for the tip-prep routine.
+    pub fn compile(
+        name: impl Into<String>,
+        source: &str,
+        ctx: &RoutineContext<'_>,
+    ) -> Result<Self, SpmError> {
+        let name = name.into();
+        let ast = sandboxed_engine()
+            .compile(source)
+            .map_err(|e| SpmError::Workflow(format!("script '{name}': {e}")))?;
+
+        let config = ctx.config;
+        let gates = &config.tip_prep.signal_stability;
+        let timing = &config.tip_prep.timing;
+        Ok(Self {
+            name,
+            ast,
+            signals: ctx.signals.clone(),
+            read_spec: StableReadSpec {
+                num_samples: config.data_acquisition.stable_signal_samples,
+                max_std_dev: gates.max_std_dev_hz,
+                max_slope: gates.max_slope_hz_per_s,
+                max_retries: gates.read_retry_count as usize,
+                sample_rate_hz: config.data_acquisition.sample_rate as f64,
+            },
+            reposition: RepositionSpec {
+                post_move_settle_ms: timing.post_move_settle_ms,
+                post_approach_settle_ms: timing.post_reposition_settle_ms,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Read and compile a script file, named after its file stem.
+    pub fn from_file(path: &Path, ctx: &RoutineContext<'_>) -> Result<Self, SpmError> {
+        let source = std::fs::read_to_string(path).map_err(|source| SpmError::Io {
+            source,
+            context: format!("reading script {}", path.display()),
+        })?;
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "script".into());
+        Self::compile(name, &source, ctx)
+    }
+
+    /// Evaluate the script on this (script) thread, driving `host`.
+    fn evaluate(&self, host: Host, shutdown: ShutdownFlag) -> Result<Outcome, SpmError> {
+        let mut engine = sandboxed_engine();
+        // Stop a script that is busy computing, but never mid-cleanup.
+        let in_cleanup = Arc::clone(&host.in_cleanup);
+        engine.on_progress(move |_| {
+            (shutdown.is_requested() && in_cleanup.load(Ordering::Relaxed) == 0)
+                .then_some(Dynamic::UNIT)
+        });
+        register_bindings(&mut engine, &host, self);
+
+        let result = engine.eval_ast::<Dynamic>(&self.ast);
+        // Release the routine thread whatever happened.
+        host.end(Ok(()));
+        match result {
+            Ok(value) => Ok(value
+                .try_cast::<ScriptCycles>()
+                .and_then(|c| c.0.lock().ending())
+                .unwrap_or(Outcome::Completed)),
+            Err(e) => Err(to_spm_error(&self.name, &e)),
+        }
+    }
+}
+
+impl Routine for ScriptRoutine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError> {
+        let (calls_tx, calls_rx) = crossbeam_channel::unbounded();
+        let (replies_tx, replies_rx) = crossbeam_channel::unbounded();
+        let server = Server {
+            calls: calls_rx,
+            replies: replies_tx,
+        };
+        let host = Host {
+            calls: calls_tx,
+            replies: replies_rx,
+            in_cleanup: Arc::default(),
+        };
+        let shutdown = rt.shutdown().clone();
+        let this = &*self;
+
+        std::thread::scope(|scope| {
+            let script = scope.spawn(move || this.evaluate(host, shutdown));
+            // Ends when the script does; its result comes from the thread.
+            let _ = server.serve(rt);
+            script
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+}
+
+// ============================================================================
+// Script thread <-> routine thread
+// ============================================================================
+
+/// Work for the routine thread. Runs against the live [`Rt`]; scoped
+/// bindings (`guarded`, `with_deadline`) keep serving through `&Server`
+/// while their scope is open.
+type Call = Box<dyn FnOnce(&mut Rt<'_>, &Server) -> Result<Dynamic, SpmError> + Send>;
+
+enum Message {
+    Call(Call),
+    /// The script left the innermost open scope (or finished), with the
+    /// status the scope should end with.
+    End(Result<(), SpmError>),
+}
+
+/// Routine-thread end: executes calls until the script ends the scope.
+struct Server {
+    calls: Receiver<Message>,
+    replies: Sender<Result<Dynamic, SpmError>>,
+}
+
+impl Server {
+    fn serve(&self, rt: &mut Rt<'_>) -> Result<(), SpmError> {
+        loop {
+            match self.calls.recv() {
+                Ok(Message::Call(call)) => {
+                    let _ = self.replies.send(call(rt, self));
+                }
+                Ok(Message::End(status)) => return status,
+                Err(_) => {
+                    return Err(SpmError::Workflow(
+                        "script stopped without closing its scope".into(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Script-thread end, shared by every binding.
+#[derive(Clone)]
+struct Host {
+    calls: Sender<Message>,
+    replies: Receiver<Result<Dynamic, SpmError>>,
+    /// Open `guarded` cleanups; a shutdown does not terminate those.
+    in_cleanup: Arc<AtomicUsize>,
+}
+
+impl Host {
+    /// Run `f` on the routine thread and wait for its result.
+    fn call<T: Clone + Send + Sync + 'static>(
+        &self,
+        f: impl FnOnce(&mut Rt<'_>) -> Result<T, SpmError> + Send + 'static,
+    ) -> RhaiResultOf<T> {
+        self.send(move |rt, _| f(rt).map(Dynamic::from))?;
+        self.reply()?
+            .try_cast::<T>()
+            .ok_or_else(|| script_error("binding returned an unexpected type"))
+    }
+
+    fn send(
+        &self,
+        f: impl FnOnce(&mut Rt<'_>, &Server) -> Result<Dynamic, SpmError> + Send + 'static,
+    ) -> RhaiResultOf<()> {
+        self.calls
+            .send(Message::Call(Box::new(f)))
+            .map_err(|_| script_error("the routine is no longer running"))
+    }
+
+    fn reply(&self) -> RhaiResultOf<Dynamic> {
+        match self.replies.recv() {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(RtError::raise(e)),
+            Err(_) => Err(script_error("the routine is no longer running")),
+        }
+    }
+
+    fn end(&self, status: Result<(), SpmError>) {
+        let _ = self.calls.send(Message::End(status));
+    }
+
+    /// Run `body` inside a scope the routine thread opened with `open`.
+    fn scoped(
+        &self,
+        open: impl FnOnce(&mut Rt<'_>, &Server) -> Result<(), SpmError> + Send + 'static,
+        body: impl FnOnce() -> RhaiResultOf<Dynamic>,
+    ) -> RhaiResultOf<Dynamic> {
+        self.send(move |rt, server| open(rt, server).map(|()| Dynamic::UNIT))?;
+        let result = body();
+        self.end(status_of(&result));
+        let scope = self.reply();
+        let value = result?;
+        scope.map(|_| value)
+    }
+}
+
+// ============================================================================
+// Errors
+// ============================================================================
+
+/// An [`SpmError`] travelling through a script as a Rhai exception, so
+/// `try`/`catch` sees it and the harness gets the original error back
+/// (a shutdown still becomes `StoppedByUser`, a deadline still a `Timeout`).
+#[derive(Clone)]
+struct RtError(Arc<SpmError>);
+
+impl RtError {
+    fn raise(error: SpmError) -> Box<EvalAltResult> {
+        EvalAltResult::ErrorRuntime(Dynamic::from(RtError(Arc::new(error))), Position::NONE).into()
+    }
+}
+
+fn script_error(message: &str) -> Box<EvalAltResult> {
+    message.into()
+}
+
+/// The error a script failure maps to. `name` prefixes errors raised by
+/// the script itself.
+fn to_spm_error(name: &str, error: &EvalAltResult) -> SpmError {
+    match error.unwrap_inner() {
+        EvalAltResult::ErrorTerminated(..) => SpmError::ShutdownRequested,
+        EvalAltResult::ErrorRuntime(value, _) if value.is::<RtError>() => {
+            duplicate(&value.clone_cast::<RtError>().0)
+        }
+        _ => SpmError::Workflow(format!("script '{name}': {error}")),
+    }
+}
+
+fn status_of(result: &RhaiResultOf<Dynamic>) -> Result<(), SpmError> {
+    result
+        .as_ref()
+        .map(|_| ())
+        .map_err(|e| to_spm_error("scope", e))
+}
+
+/// `SpmError` is not `Clone` (it can carry an `io::Error`); rebuild it.
+fn duplicate(error: &SpmError) -> SpmError {
+    match error {
+        SpmError::Io { source, context } => SpmError::Io {
+            source: std::io::Error::new(source.kind(), source.to_string()),
+            context: context.clone(),
+        },
+        SpmError::Timeout(s) => SpmError::Timeout(s.clone()),
+        SpmError::Protocol(s) => SpmError::Protocol(s.clone()),
+        SpmError::Hardware { code, message } => SpmError::Hardware {
+            code: *code,
+            message: message.clone(),
+        },
+        SpmError::Workflow(s) => SpmError::Workflow(s.clone()),
+        SpmError::Unsupported(s) => SpmError::Unsupported(s.clone()),
+        SpmError::ShutdownRequested => SpmError::ShutdownRequested,
+    }
+}
+
+// ============================================================================
+// Engine and bindings
+// ============================================================================
+
+/// An engine with everything outside the instrument switched off.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.on_print(|s| log::info!("[script] {s}"));
+    engine.on_debug(|s, _, pos| log::debug!("[script] {pos:?}: {s}"));
+    engine
+}
+
+/// A `cycles()` loop, shared between the `for` loop and `outcome()`.
+#[derive(Clone)]
+struct ScriptCycles(Arc<Mutex<Cycles>>);
+
+impl IntoIterator for ScriptCycles {
+    type Item = i64;
+    type IntoIter = std::iter::FromFn<Box<dyn FnMut() -> Option<i64> + Send + Sync>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        std::iter::from_fn(Box::new(move || self.0.lock().next().map(|n| n as i64)))
+    }
+}
+
+macro_rules! handles {
+    ($($handle:ident => $script_name:literal, $getter:ident;)*) => {
+        $(
+            #[derive(Clone)]
+            struct $handle(Host);
+        )*
+
+        fn register_handles(engine: &mut Engine, host: &Host) {
+            $(
+                engine.register_type_with_name::<$handle>($script_name);
+                let h = host.clone();
+                // Fetching the handle checks the capability, as `rt.bias()?` does.
+                engine.register_fn(stringify!($getter), move || -> RhaiResultOf<$handle> {
+                    h.call(|rt| rt.$getter().map(|_| ()))?;
+                    Ok($handle(h.clone()))
+                });
+            )*
+        }
+    };
+}
+
+handles! {
+    BiasHandle => "Bias", bias;
+    ZHandle => "ZCtrl", z;
+    SignalsHandle => "Signals", signals;
+    MotorHandle => "Motor", motor;
+    ScanHandle => "Scan", scan;
+    OsciHandle => "Osci", osci;
+    TipShaperHandle => "TipShaper", tip_shaper;
+    PllHandle => "Pll", pll;
+    PiezoHandle => "Piezo", piezo;
+    SafeTipHandle => "SafeTip", safe_tip;
+    DataStreamHandle => "DataStream", data_stream;
+}
+
+fn register_bindings(engine: &mut Engine, host: &Host, routine: &ScriptRoutine) {
+    register_handles(engine, host);
+    register_runtime(engine, host, routine);
+    register_subsystems(engine, routine);
+}
+
+fn register_runtime(engine: &mut Engine, host: &Host, routine: &ScriptRoutine) {
+    let h = host.clone();
+    engine.register_fn("settle", move |ms: i64| {
+        h.call(move |rt| rt.settle(ms.max(0) as u64))
+    });
+    let h = host.clone();
+    engine.register_fn("check_shutdown", move || h.call(|rt| rt.check_shutdown()));
+
+    type MakeCondition = fn(f64) -> Condition;
+    for (name, condition) in [
+        ("wait_below", Condition::below as MakeCondition),
+        ("wait_above", Condition::above),
+    ] {
+        let (h, registry) = (host.clone(), routine.signals.clone());
+        engine.register_fn(name, move |signal: Dynamic, limit: f64, ms: i64| {
+            let index = resolve(&registry, &signal)?;
+            let timeout = Duration::from_millis(ms.max(0) as u64);
+            h.call(move |rt| rt.wait_until(index, condition(limit), timeout))
+        });
+    }
+    let (h, registry) = (host.clone(), routine.signals.clone());
+    engine.register_fn(
+        "wait_for_drift",
+        move |signal: Dynamic, max_drift: f64, ms: i64| -> RhaiResultOf<f64> {
+            let index = resolve(&registry, &signal)?;
+            let timeout = Duration::from_millis(ms.max(0) as u64);
+            h.call(move |rt| rt.wait_for_drift(index, max_drift, timeout).map(|s| s.mean))
+        },
+    );
+
+    let signals = routine.signals.clone();
+    engine.register_fn("signal", move |name: &str| -> RhaiResultOf<i64> {
+        signals
+            .resolve(name)
+            .map(|index| index.0 as i64)
+            .map_err(|e| script_error(&e.to_string()))
+    });
+
+    let h = host.clone();
+    engine.register_fn(
+        "emit",
+        move |kind: &str, data: Dynamic| -> RhaiResultOf<()> {
+            let data: serde_json::Value = rhai::serde::from_dynamic(&data)?;
+            let kind = kind.to_string();
+            h.call(move |rt| {
+                rt.emit(Event::custom(&kind, data));
+                Ok(())
+            })
+        },
+    );
+
+    let h = host.clone();
+    engine.register_fn("confirm", move |prompt: &str| {
+        let prompt = prompt.to_string();
+        h.call(move |rt| rt.confirm(&prompt))
+    });
+    let h = host.clone();
+    engine.register_fn(
+        "ask",
+        move |prompt: &str, choices: Array| -> RhaiResultOf<i64> {
+            let choices: Vec<String> = choices.into_iter().map(|c| c.to_string()).collect();
+            let choices: Vec<&str> = choices.iter().map(String::as_str).collect();
+            let question = Question::choose(prompt, &choices);
+            h.call(move |rt| rt.ask(&question).map(|i| i as i64))
+        },
+    );
+
+    // cycles() / cycles(max) / cycles(max, secs)
+    engine.register_type_with_name::<ScriptCycles>("Cycles");
+    engine.register_iterator::<ScriptCycles>();
+    for (max, secs) in [(false, false), (true, false), (true, true)] {
+        let h = host.clone();
+        let make = move |max_cycles: Option<usize>, max_secs: Option<u64>| {
+            h.call(move |rt| {
+                let cycles = rt.cycles(max_cycles, max_secs.map(Duration::from_secs));
+                Ok(ScriptCycles(Arc::new(Mutex::new(cycles))))
+            })
+        };
+        match (max, secs) {
+            (false, _) => engine.register_fn("cycles", move || make(None, None)),
+            (true, false) => {
+                engine.register_fn("cycles", move |n: i64| make(Some(n.max(0) as usize), None))
+            }
+            (true, true) => engine.register_fn("cycles", move |n: i64, s: i64| {
+                make(Some(n.max(0) as usize), Some(s.max(0) as u64))
+            }),
+        };
+    }
+    engine.register_fn("next", |c: &mut ScriptCycles| -> Dynamic {
+        c.0.lock()
+            .next()
+            .map_or(Dynamic::UNIT, |n| Dynamic::from(n as i64))
+    });
+    engine.register_fn("elapsed", |c: &mut ScriptCycles| {
+        c.0.lock().elapsed().as_secs_f64()
+    });
+    // The loop is returned as-is; `evaluate` reads the ending off it.
+    engine.register_fn(
+        "outcome",
+        |c: &mut ScriptCycles| -> RhaiResultOf<ScriptCycles> {
+            match c.0.lock().ending() {
+                Some(_) => Ok(c.clone()),
+                None => Err(script_error(
+                    "outcome() called before the cycles loop ended",
+                )),
+            }
+        },
+    );
+
+    let h = host.clone();
+    engine.register_fn(
+        "guarded",
+        move |ctx: NativeCallContext, body: FnPtr, cleanup: FnPtr| -> RhaiResultOf<Dynamic> {
+            h.send(|rt, server| {
+                rt.guarded(|rt| server.serve(rt), |rt| server.serve(rt))
+                    .map(|()| Dynamic::UNIT)
+            })?;
+            let body_result = body.call_within_context::<Dynamic>(&ctx, ());
+            h.end(status_of(&body_result));
+            h.in_cleanup.fetch_add(1, Ordering::Relaxed);
+            let cleanup_result = cleanup.call_within_context::<Dynamic>(&ctx, ());
+            h.in_cleanup.fetch_sub(1, Ordering::Relaxed);
+            h.end(status_of(&cleanup_result));
+            let guarded = h.reply();
+            // The script's own errors win over their SpmError translations.
+            let value = body_result?;
+            cleanup_result.and(guarded).map(|_| value)
+        },
+    );
+
+    let h = host.clone();
+    engine.register_fn(
+        "with_deadline",
+        move |ctx: NativeCallContext, ms: i64, body: FnPtr| -> RhaiResultOf<Dynamic> {
+            let budget = Duration::from_millis(ms.max(0) as u64);
+            h.scoped(
+                move |rt, server| rt.with_deadline(budget, |rt| server.serve(rt)),
+                || body.call_within_context::<Dynamic>(&ctx, ()),
+            )
+        },
+    );
+
+    engine.register_type_with_name::<RtError>("RtError");
+    engine.register_fn("to_string", |e: &mut RtError| e.0.to_string());
+}
+
+/// Accept a signal as an index or a registry name.
+fn resolve(signals: &SignalRegistry, signal: &Dynamic) -> RhaiResultOf<SignalIndex> {
+    if let Ok(index) = signal.as_int() {
+        return Ok(SignalIndex(index as u32));
+    }
+    signals
+        .resolve(&signal.to_string())
+        .map_err(|e| script_error(&e.to_string()))
+}
+
+/// `value` with the fields of `overrides` replaced.
+fn merged<T: serde::Serialize + serde::de::DeserializeOwned>(
+    value: &T,
+    overrides: Map,
+) -> RhaiResultOf<T> {
+    let mut json = serde_json::to_value(value).map_err(|e| script_error(&e.to_string()))?;
+    let overrides: serde_json::Value = rhai::serde::from_dynamic(&overrides.into())?;
+    if let (Some(base), Some(overrides)) = (json.as_object_mut(), overrides.as_object()) {
+        base.extend(overrides.clone());
+    }
+    serde_json::from_value(json).map_err(|e| script_error(&e.to_string()))
+}
+
+fn parse_param<T: serde::de::DeserializeOwned>(value: &str) -> RhaiResultOf<T> {
+    serde_json::from_value(serde_json::Value::String(value.into()))
+        .map_err(|_| script_error(&format!("unknown option '{value}'")))
+}
+
+fn steps(n: i64) -> RhaiResultOf<i16> {
+    i16::try_from(n).map_err(|_| script_error(&format!("{n} motor steps is out of range")))
+}
+
+fn register_subsystems(engine: &mut Engine, routine: &ScriptRoutine) {
+    // Bias
+    engine.register_fn("get", |b: &mut BiasHandle| b.0.call(|rt| rt.bias()?.get()));
+    engine.register_fn("set", |b: &mut BiasHandle, v: f64| {
+        b.0.call(move |rt| rt.bias()?.set(v))
+    });
+    engine.register_fn("pulse", |b: &mut BiasHandle, v: f64, width_ms: i64| {
+        b.0.call(move |rt| rt.bias()?.pulse(v, width_ms.max(0) as u64))
+    });
+
+    // Z controller
+    engine.register_fn("withdraw", |z: &mut ZHandle| {
+        z.0.call(|rt| rt.z()?.withdraw())
+    });
+    engine.register_fn("auto_approach", |z: &mut ZHandle| {
+        z.0.call(|rt| rt.z()?.auto_approach())
+    });
+    engine.register_fn("calibrated_approach", |z: &mut ZHandle| {
+        z.0.call(|rt| rt.z()?.calibrated_approach())
+    });
+    engine.register_fn("set_setpoint", |z: &mut ZHandle, a: f64| {
+        z.0.call(move |rt| rt.z()?.set_setpoint(a))
+    });
+
+    // Signals
+    let registry = routine.signals.clone();
+    engine.register_fn("read", move |s: &mut SignalsHandle, signal: Dynamic| {
+        let index = resolve(&registry, &signal)?;
+        s.0.call(move |rt| rt.signals()?.read(index))
+    });
+    let (registry, spec) = (routine.signals.clone(), routine.read_spec.clone());
+    engine.register_fn(
+        "read_stable",
+        move |s: &mut SignalsHandle, signal: Dynamic| {
+            let index = resolve(&registry, &signal)?;
+            let spec = spec.clone();
+            s.0.call(move |rt| rt.signals()?.read_stable(index, &spec))
+        },
+    );
+    let (registry, spec) = (routine.signals.clone(), routine.read_spec.clone());
+    engine.register_fn(
+        "read_stable",
+        move |s: &mut SignalsHandle, signal: Dynamic, overrides: Map| {
+            let index = resolve(&registry, &signal)?;
+            let mut spec = spec.clone();
+            for (key, value) in overrides {
+                let number = value
+                    .as_float()
+                    .or_else(|_| value.as_int().map(|i| i as f64))
+                    .map_err(|_| script_error(&format!("read_stable: '{key}' must be a number")))?;
+                match key.as_str() {
+                    "num_samples" => spec.num_samples = number as usize,
+                    "max_std_dev" => spec.max_std_dev = number,
+                    "max_slope" => spec.max_slope = number,
+                    "max_retries" => spec.max_retries = number as usize,
+                    "sample_rate_hz" => spec.sample_rate_hz = number,
+                    other => {
+                        return Err(script_error(&format!(
+                            "read_stable: unknown option '{other}'"
+                        )));
+                    }
+                }
+            }
+            s.0.call(move |rt| rt.signals()?.read_stable(index, &spec))
+        },
+    );
+    engine.register_fn("clear_buffer", |s: &mut SignalsHandle| {
+        s.0.call(|rt| {
+            rt.signals()?.clear_buffer();
+            Ok(())
+        })
+    });
+
+    // Motor
+    let reposition = routine.reposition.clone();
+    engine.register_fn("reposition", move |m: &mut MotorHandle, x: i64, y: i64| {
+        let spec = RepositionSpec {
+            x_steps: steps(x)?,
+            y_steps: steps(y)?,
+            ..reposition.clone()
+        };
+        m.0.call(move |rt| rt.motor()?.reposition(&spec))
+    });
+    engine.register_fn("move_3d", |m: &mut MotorHandle, x: i64, y: i64, z: i64| {
+        let (x, y, z) = (steps(x)?, steps(y)?, steps(z)?);
+        m.0.call(move |rt| rt.motor()?.move_3d(x, y, z))
+    });
+
+    // Scan
+    engine.register_fn("start", |s: &mut ScanHandle| {
+        s.0.call(|rt| rt.scan()?.start(ScanDirectionParam::default()))
+    });
+    engine.register_fn("start", |s: &mut ScanHandle, direction: &str| {
+        let direction: ScanDirectionParam = parse_param(direction)?;
+        s.0.call(move |rt| rt.scan()?.start(direction))
+    });
+    engine.register_fn("stop", |s: &mut ScanHandle| {
+        s.0.call(|rt| rt.scan()?.stop())
+    });
+    engine.register_fn("status", |s: &mut ScanHandle| {
+        s.0.call(|rt| rt.scan()?.status())
+    });
+
+    // Oscilloscope: a trace comes back as #{ t0, dt, data }
+    fn osci_read(o: &OsciHandle, channel: i64, mode: AcquisitionModeParam) -> RhaiResultOf<Map> {
+        let trace = o.0.call(move |rt| rt.osci()?.read(channel as i32, mode))?;
+        let mut map = Map::new();
+        map.insert("t0".into(), trace.t0.into());
+        map.insert("dt".into(), trace.dt.into());
+        map.insert(
+            "data".into(),
+            trace
+                .data
+                .into_iter()
+                .map(Dynamic::from)
+                .collect::<Array>()
+                .into(),
+        );
+        Ok(map)
+    }
+    engine.register_fn("read", |o: &mut OsciHandle, channel: i64| {
+        osci_read(o, channel, AcquisitionModeParam::default())
+    });
+    engine.register_fn("read", |o: &mut OsciHandle, channel: i64, mode: &str| {
+        osci_read(o, channel, parse_param(mode)?)
+    });
+
+    // Tip shaper: fields not given keep their defaults
+    engine.register_fn("shape", |t: &mut TipShaperHandle, overrides: Map| {
+        let params = merged(&TipShaperParams::default(), overrides)?;
+        t.0.call(move |rt| rt.tip_shaper()?.shape(&params))
+    });
+
+    // PLL
+    engine.register_fn("center_freq_shift", |p: &mut PllHandle| {
+        p.0.call(|rt| rt.pll()?.center_freq_shift())
+    });
+
+    // Piezo: positions are [x, y] in meters
+    engine.register_fn("get", |p: &mut PiezoHandle| -> RhaiResultOf<Array> {
+        let pos = p.0.call(|rt| rt.piezo()?.get())?;
+        Ok(vec![pos.x.into(), pos.y.into()])
+    });
+    engine.register_fn("set", |p: &mut PiezoHandle, x: f64, y: f64| {
+        p.0.call(move |rt| rt.piezo()?.set(x, y))
+    });
+
+    // Safe tip
+    engine.register_fn("set_enabled", |s: &mut SafeTipHandle, enabled: bool| {
+        s.0.call(move |rt| rt.safe_tip()?.set_enabled(enabled))
+    });
+    engine.register_fn("enabled", |s: &mut SafeTipHandle| {
+        s.0.call(|rt| rt.safe_tip()?.enabled())
+    });
+
+    // Data stream
+    engine.register_fn("start", |d: &mut DataStreamHandle| {
+        d.0.call(|rt| rt.data_stream()?.start())
+    });
+    engine.register_fn("stop", |d: &mut DataStreamHandle| {
+        d.0.call(|rt| rt.data_stream()?.stop())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::event::{EventBus, Observer};
+    use crate::mock_controller::{MockController, models};
+    use crate::routine::run_routine;
+    use crate::spm_controller::Capability;
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<StdMutex<Vec<Event>>>,
+    }
+
+    impl Observer for Recorder {
+        fn on_event(&self, event: &Event) {
+            self.events.lock().unwrap().push(event.clone());
+        }
+    }
+
+    /// Run `source` against `mock` with default config.
+    fn run_script(
+        mock: MockController,
+        source: &str,
+        shutdown: &ShutdownFlag,
+    ) -> (Result<Outcome, SpmError>, Vec<Event>) {
+        let mut mock = mock;
+        let signals = SignalRegistry::builder()
+            .with_standard_map()
+            .from_controller(&mut mock)
+            .unwrap()
+            .create_aliases()
+            .build();
+        let config = AppConfig::default();
+        let ctx = RoutineContext {
+            config: &config,
+            signals: &signals,
+        };
+        let mut routine = ScriptRoutine::compile("test_script", source, &ctx).unwrap();
+
+        let recorder = Recorder::default();
+        let events = Arc::clone(&recorder.events);
+        let mut bus = EventBus::new();
+        bus.add_observer(Box::new(recorder));
+
+        let result = run_routine(Box::new(mock), &bus, shutdown, &mut routine);
+        let events = events.lock().unwrap().clone();
+        (result, events)
+    }
+
+    fn custom_event(events: &[Event], wanted: &str) -> Option<serde_json::Value> {
+        events.iter().find_map(|e| match e {
+            Event::Custom { kind, data } if kind == wanted => Some(data.clone()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn scripts_drive_subsystems_through_the_runtime() {
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .freq_shift(models::always(-3.0))
+            .build();
+        let obs = mock.observations();
+
+        let (result, events) = run_script(
+            mock,
+            r#"
+                bias().set(-0.25);
+                bias().pulse(4.0, 50);
+                let df = signals().read(signal("freq shift"));
+                emit("script_reading", #{ freq_shift: df });
+            "#,
+            &ShutdownFlag::new(),
+        );
+
+        assert_eq!(result.unwrap(), Outcome::Completed);
+        let obs = obs.lock();
+        assert_eq!(obs.bias, -0.25);
+        assert_eq!(obs.pulses, [4.0]);
+        assert!(obs.withdraw_count >= 1, "the harness still withdraws");
+        assert_eq!(
+            custom_event(&events, "script_reading").unwrap()["freq_shift"],
+            -3.0
+        );
+        assert!(
+            events.iter().any(
+                |e| matches!(e, Event::ActionStarted { action, .. } if action == "bias_pulse")
+            ),
+            "script actions are logged like compiled ones"
+        );
+    }
+
+    #[test]
+    fn a_cycles_loop_ends_the_script_with_its_outcome() {
+        let (result, _) = run_script(
+            MockController::builder().build(),
+            r#"
+                let c = cycles(3);
+                let seen = 0;
+                for n in c { seen = n; }
+                if seen != 3 { throw "expected 3 cycles"; }
+                c.outcome()
+            "#,
+            &ShutdownFlag::new(),
+        );
+        assert_eq!(result.unwrap(), Outcome::CycleLimit(3));
+    }
+
+    #[test]
+    fn runtime_errors_keep_their_kind_and_can_be_caught() {
+        let caps = HashSet::from([Capability::Bias]);
+        let (result, _) = run_script(
+            MockController::builder().capabilities(caps.clone()).build(),
+            "tip_shaper();",
+            &ShutdownFlag::new(),
+        );
+        assert!(
+            matches!(result, Err(SpmError::Unsupported(_))),
+            "{result:?}"
+        );
+
+        let (result, events) = run_script(
+            MockController::builder().capabilities(caps).build(),
+            r#"
+                try { pll(); } catch (e) { emit("caught", #{ error: e.to_string() }); }
+            "#,
+            &ShutdownFlag::new(),
+        );
+        assert_eq!(result.unwrap(), Outcome::Completed);
+        let caught = custom_event(&events, "caught").unwrap();
+        assert!(caught["error"].as_str().unwrap().contains("Unsupported"));
+    }
+
+    #[test]
+    fn guarded_cleanup_runs_after_a_failing_body_and_the_body_error_wins() {
+        let mock = MockController::builder().build();
+        let obs = mock.observations();
+        let (result, _) = run_script(
+            mock,
+            r#"
+                guarded(|| { scan().start(); throw "sweep failed"; },
+                        || scan().stop());
+            "#,
+            &ShutdownFlag::new(),
+        );
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("sweep failed"), "{err}");
+        assert!(!obs.lock().scan_running, "cleanup must stop the scan");
+    }
+
+    #[test]
+    fn condition_waits_are_available_to_scripts() {
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .freq_shift(models::scripted(vec![-5.0, -1.0]))
+            .build();
+        let (result, events) = run_script(
+            mock,
+            r#"
+                let df = wait_above("freq shift", -2.0, 5000);
+                emit("waited", #{ freq_shift: df });
+            "#,
+            &ShutdownFlag::new(),
+        );
+        assert_eq!(result.unwrap(), Outcome::Completed);
+        assert_eq!(custom_event(&events, "waited").unwrap()["freq_shift"], -1.0);
+        assert_eq!(custom_event(&events, "wait_finished").unwrap()["polls"], 2);
+    }
+
+    #[test]
+    fn a_deadline_bounds_waits_inside_its_scope() {
+        let (result, _) = run_script(
+            MockController::builder().build(),
+            "with_deadline(30, || settle(10000));",
+            &ShutdownFlag::new(),
+        );
+        assert!(matches!(result, Err(SpmError::Timeout(_))), "{result:?}");
+    }
+
+    #[test]
+    fn shutdown_stops_a_busy_script() {
+        let shutdown = ShutdownFlag::new();
+        let stopper = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            stopper.request();
+        });
+        let (result, _) = run_script(
+            MockController::builder().build(),
+            "let x = 0; loop { x += 1; }",
+            &shutdown,
+        );
+        assert_eq!(result.unwrap(), Outcome::StoppedByUser);
+    }
+
+    #[test]
+    fn syntax_errors_and_sandbox_escapes_fail_at_compile_time() {
+        let mut mock = MockController::builder().build();
+        let signals = SignalRegistry::builder()
+            .from_controller(&mut mock)
+            .unwrap()
+            .build();
+        let config = AppConfig::default();
+        let ctx = RoutineContext {
+            config: &config,
+            signals: &signals,
+        };
+        assert!(ScriptRoutine::compile("bad", "bias().set(", &ctx).is_err());
+        assert!(ScriptRoutine::compile("bad", r#"eval("1")"#, &ctx).is_err());
+    }
+}
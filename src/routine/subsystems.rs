@@ -6,15 +6,29 @@
 //! straight to the controller. Fetch a handle per statement:
 //! `rt.bias()?.pulse(4.0, 50)?`.
 
-use nanonis_rs::scan::{ScanConfig, ScanProps, ScanPropsBuilder};
+use nanonis_rs::Position;
+use nanonis_rs::oscilloscope::OsciData;
+use nanonis_rs::scan::{ScanConfig, ScanFrame, ScanProps, ScanPropsBuilder};
 
 use crate::action::ActionOutput;
 use crate::action::bias::{BiasPulse, ReadBias, SetBias};
+use crate::action::data_stream::{ConfigureDataStream, StartDataStream, StopDataStream};
 use crate::action::motor::{MoveMotor3D, Reposition};
-use crate::action::scan::{ScanActionParam, ScanControl, ScanDirectionParam};
-use crate::action::signals::{ReadSignal, ReadStableSignal};
-use crate::action::z_controller::{AutoApproach, CalibratedApproach, SetZSetpoint, Withdraw};
+use crate::action::oscilloscope::{AcquisitionModeParam, OsciRead};
+use crate::action::pll::CenterFreqShift;
+use crate::action::position::{ReadPosition, SetPosition};
+use crate::action::scan::{GrabScanFrame, ScanActionParam, ScanControl, ScanDirectionParam};
+use crate::action::signals::{
+    AnalyzeNoise, ReadSignal, ReadStableSignal, ReadStableSignals, SignalGate,
+};
+use crate::action::tip_shaper::{TipShape, TipShaperParams};
+use crate::action::z_controller::{
+    AutoApproach, CalibratedApproach, ReadSafeTipStatus, SafeTipSet, SetZSetpoint, Withdraw,
+};
+use crate::analyzer::AnalyzerInput;
+use crate::analyzer::noise_spectrum::{NoiseReport, NoiseSpectrumSpec};
 use crate::signal_registry::SignalIndex;
+use crate::spm_controller::DataStreamStatus;
 use crate::spm_error::SpmError;
 
 use super::Rt;
@@ -30,6 +44,19 @@ fn expect_value(name: &str, output: ActionOutput) -> Result<f64> {
     }
 }
 
+fn expect_named(name: &str, output: &ActionOutput, key: &str) -> Result<f64> {
+    match output {
+        ActionOutput::Values(values) => values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| *v)
+            .ok_or_else(|| SpmError::Protocol(format!("{name} returned no '{key}' value"))),
+        other => Err(SpmError::Protocol(format!(
+            "{name} returned unexpected output: {other:?}"
+        ))),
+    }
+}
+
 // ============================================================================
 // Bias
 // ============================================================================
@@ -119,6 +146,19 @@ pub struct StableReadSpec {
     pub sample_rate_hz: f64,
 }
 
+impl StableReadSpec {
+    /// A [`SignalGate`] for `index` with this spec's thresholds, for
+    /// [`Signals::read_stable_many`]; override fields for signals in other
+    /// units.
+    pub fn gate(&self, index: SignalIndex) -> SignalGate {
+        SignalGate {
+            index,
+            max_std_dev: self.max_std_dev,
+            max_slope: self.max_slope,
+        }
+    }
+}
+
 /// Signal reading, from [`Rt::signals`].
 pub struct Signals<'r, 'a> {
     pub(crate) rt: &'r mut Rt<'a>,
@@ -148,6 +188,68 @@ impl Signals<'_, '_> {
         expect_value("read_stable_signal", output)
     }
 
+    /// Read several gated signals from the same stream samples, each judged
+    /// by its own gate; `spec` supplies the batch size, retries and sample
+    /// rate. Returns the means in the order of `gates`, and emits one
+    /// `stable_read` event per signal.
+    pub fn read_stable_many(
+        &mut self,
+        gates: &[SignalGate],
+        spec: &StableReadSpec,
+    ) -> Result<Vec<f64>> {
+        let output = self.rt.exec(&ReadStableSignals {
+            signals: gates.to_vec(),
+            num_samples: spec.num_samples,
+            max_retries: spec.max_retries,
+            sample_rate_hz: spec.sample_rate_hz,
+        })?;
+        match output {
+            ActionOutput::Values(values) if values.len() == gates.len() => {
+                Ok(values.into_iter().map(|(_, v)| v).collect())
+            }
+            other => Err(SpmError::Protocol(format!(
+                "read_stable_signals returned unexpected output: {other:?}"
+            ))),
+        }
+    }
+
+    /// Record `num_samples` stream samples of `index` and analyze their
+    /// noise spectrum at the stream's frame rate. Emits a `noise_spectrum`
+    /// event with the peaks found.
+    /// Fails with [`SpmError::Unsupported`] without a data stream.
+    pub fn analyze_noise(
+        &mut self,
+        index: SignalIndex,
+        num_samples: usize,
+        spectrum: &NoiseSpectrumSpec,
+    ) -> Result<NoiseReport> {
+        let output = self.rt.exec(&AnalyzeNoise {
+            index,
+            num_samples,
+            spectrum: spectrum.clone(),
+        })?;
+        let ActionOutput::Data(json) = output else {
+            return Err(SpmError::Protocol(format!(
+                "analyze_noise returned unexpected output: {output:?}"
+            )));
+        };
+        serde_json::from_value(json)
+            .map_err(|e| SpmError::Protocol(format!("analyze_noise report: {e}")))
+    }
+
+    /// The next `num_samples` stream samples of every signal in `indices`,
+    /// taken together: `result[i][k]` is sample `k` of `indices[i]`. Raw
+    /// data for the caller to judge, so no events are emitted.
+    pub fn read_samples(
+        &mut self,
+        indices: &[SignalIndex],
+        num_samples: usize,
+    ) -> Result<Vec<Vec<f64>>> {
+        self.rt
+            .controller()
+            .read_signals_samples(indices, num_samples)
+    }
+
     /// Discard buffered stream samples so the next read sees only fresh data.
     pub fn clear_buffer(&mut self) {
         self.rt.controller().clear_data_buffer();
@@ -224,9 +326,11 @@ impl Motor<'_, '_> {
 /// Scan control, from [`Rt::scan`].
 ///
 /// Everything that changes the scanner's state emits events: `start`/`stop`
-/// through the action layer, `props_set`/`speed_set` directly. The reads
-/// (`status`, `props_get`, `speed_get`) stay silent — `status` in particular
-/// is polled in a loop, and logging a control-flow poll buries the run.
+/// through the action layer, `props_set`/`speed_set`/`frame_set` directly.
+/// `grab_frame` returns measured data, so it is logged like a signal read.
+/// The other reads (`status`, `props_get`, `speed_get`, `frame_get`) stay
+/// silent — `status` in particular is polled in a loop, and logging a
+/// control-flow poll buries the run.
 pub struct Scan<'r, 'a> {
     pub(crate) rt: &'r mut Rt<'a>,
 }
@@ -286,4 +390,247 @@ impl Scan<'_, '_> {
             |c| c.scan_speed_set(config),
         )
     }
+
+    /// Current scan frame (for save/restore).
+    pub fn frame_get(&mut self) -> Result<ScanFrame> {
+        self.rt.controller().scan_frame_get()
+    }
+
+    /// Apply a scan frame (center, size, angle).
+    pub fn frame_set(&mut self, frame: ScanFrame) -> Result<()> {
+        self.rt.logged(
+            "scan_frame_set",
+            serde_json::json!({
+                "center_x_m": frame.center.x,
+                "center_y_m": frame.center.y,
+                "width_m": frame.width_m,
+                "height_m": frame.height_m,
+                "angle_deg": frame.angle_deg,
+            }),
+            |c| c.scan_frame_set(frame),
+        )
+    }
+
+    /// Grab the pixels of the current frame, ready for an
+    /// [`Analyzer`](crate::analyzer::Analyzer). The calibration comes from
+    /// the frame width.
+    pub fn grab_frame(&mut self, channel_index: u32, forward: bool) -> Result<AnalyzerInput> {
+        let output = self.rt.exec(&GrabScanFrame {
+            channel_index,
+            forward,
+        })?;
+        let ActionOutput::Data(frame) = output else {
+            return Err(SpmError::Protocol(format!(
+                "grab_scan_frame returned unexpected output: {output:?}"
+            )));
+        };
+        let data: Vec<Vec<f32>> = serde_json::from_value(frame["data"].clone())
+            .map_err(|e| SpmError::Protocol(format!("grab_scan_frame data: {e}")))?;
+        let channel_name = frame["channel_name"]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string();
+
+        let cols = data.first().map_or(0, |r| r.len());
+        let width_m = self.frame_get()?.width_m as f64;
+        Ok(AnalyzerInput {
+            channel_name,
+            data,
+            calibration_m_per_px: (cols > 0 && width_m > 0.0).then(|| width_m / cols as f64),
+        })
+    }
+}
+
+// ============================================================================
+// Oscilloscope
+// ============================================================================
+
+/// Oscilloscope acquisition, from [`Rt::osci`].
+pub struct Osci<'r, 'a> {
+    pub(crate) rt: &'r mut Rt<'a>,
+}
+
+impl Osci<'_, '_> {
+    /// Acquire one trace of `channel`. The trace is the measurement, so it
+    /// lands in the event log with the completed event.
+    pub fn read(&mut self, channel: i32, mode: AcquisitionModeParam) -> Result<OsciData> {
+        let output = self.rt.exec(&OsciRead { channel, mode })?;
+        let ActionOutput::Data(json) = output else {
+            return Err(SpmError::Protocol(format!(
+                "osci_read returned unexpected output: {output:?}"
+            )));
+        };
+        let field = |key: &str| {
+            json[key]
+                .as_f64()
+                .ok_or_else(|| SpmError::Protocol(format!("osci_read returned no '{key}'")))
+        };
+        let data = json["data"]
+            .as_array()
+            .map(|values| values.iter().filter_map(|v| v.as_f64()).collect())
+            .ok_or_else(|| SpmError::Protocol("osci_read returned no 'data'".into()))?;
+        Ok(OsciData::new(
+            field("t0")?,
+            field("dt")?,
+            field("size")? as i32,
+            data,
+        ))
+    }
+}
+
+// ============================================================================
+// Tip shaper
+// ============================================================================
+
+/// Tip shaper (controlled lift/bias/restore conditioning), from [`Rt::tip_shaper`].
+pub struct TipShaper<'r, 'a> {
+    pub(crate) rt: &'r mut Rt<'a>,
+}
+
+impl TipShaper<'_, '_> {
+    /// Run the tip shaper with `params` and wait for it to finish.
+    pub fn shape(&mut self, params: &TipShaperParams) -> Result<()> {
+        self.rt.exec(&TipShape {
+            config: params.clone(),
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// PLL
+// ============================================================================
+
+/// Phase-locked loop control, from [`Rt::pll`].
+pub struct Pll<'r, 'a> {
+    pub(crate) rt: &'r mut Rt<'a>,
+}
+
+impl Pll<'_, '_> {
+    /// Auto-center the frequency shift.
+    pub fn center_freq_shift(&mut self) -> Result<()> {
+        self.rt.exec(&CenterFreqShift)?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Piezo position
+// ============================================================================
+
+/// Piezo fine positioning (x, y in meters), from [`Rt::piezo`].
+pub struct Piezo<'r, 'a> {
+    pub(crate) rt: &'r mut Rt<'a>,
+}
+
+impl Piezo<'_, '_> {
+    /// Read the current piezo position.
+    pub fn get(&mut self) -> Result<Position> {
+        let output = self.rt.exec(&ReadPosition::default())?;
+        let x = expect_named("read_position", &output, "x")?;
+        let y = expect_named("read_position", &output, "y")?;
+        Ok(Position::new(x, y))
+    }
+
+    /// Move the piezo to `(x, y)` and wait for it to arrive.
+    pub fn set(&mut self, x: f64, y: f64) -> Result<()> {
+        self.rt.exec(&SetPosition { x, y, wait: true })?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Safe tip
+// ============================================================================
+
+/// Safe-tip crash protection, from [`Rt::safe_tip`].
+///
+/// Enabling and reading the switch go through the `safe_tip_set` and
+/// `read_safe_tip_status` actions. The configuration has no action behind
+/// it: `configure` emits its event directly and `status` stays silent.
+pub struct SafeTip<'r, 'a> {
+    pub(crate) rt: &'r mut Rt<'a>,
+}
+
+impl SafeTip<'_, '_> {
+    /// Configure the protection: auto-recovery, auto-pausing the scan, and
+    /// the current threshold (A) that counts as a crash.
+    pub fn configure(
+        &mut self,
+        auto_recovery: bool,
+        auto_pause_scan: bool,
+        threshold: f64,
+    ) -> Result<()> {
+        self.rt.logged(
+            "safe_tip_configure",
+            serde_json::json!({
+                "auto_recovery": auto_recovery,
+                "auto_pause_scan": auto_pause_scan,
+                "threshold": threshold,
+            }),
+            |c| c.safe_tip_configure(auto_recovery, auto_pause_scan, threshold),
+        )
+    }
+
+    /// Enable or disable the protection.
+    pub fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.rt.exec(&SafeTipSet { enabled })?;
+        Ok(())
+    }
+
+    /// Current configuration as `(auto_recovery, auto_pause_scan, threshold)`.
+    pub fn status(&mut self) -> Result<(bool, bool, f64)> {
+        self.rt.controller().safe_tip_status()
+    }
+
+    /// Whether the protection is currently enabled.
+    pub fn enabled(&mut self) -> Result<bool> {
+        let output = self.rt.exec(&ReadSafeTipStatus)?;
+        let ActionOutput::Data(json) = output else {
+            return Err(SpmError::Protocol(format!(
+                "read_safe_tip_status returned unexpected output: {output:?}"
+            )));
+        };
+        json["enabled"]
+            .as_bool()
+            .ok_or_else(|| SpmError::Protocol("read_safe_tip_status returned no 'enabled'".into()))
+    }
+}
+
+// ============================================================================
+// Data stream
+// ============================================================================
+
+/// High-throughput data stream (TCP logger) control, from [`Rt::data_stream`].
+pub struct DataStream<'r, 'a> {
+    pub(crate) rt: &'r mut Rt<'a>,
+}
+
+impl DataStream<'_, '_> {
+    /// Select the streamed channels and the oversampling factor.
+    pub fn configure(&mut self, channels: &[i32], oversampling: i32) -> Result<()> {
+        self.rt.exec(&ConfigureDataStream {
+            channels: channels.to_vec(),
+            oversampling,
+        })?;
+        Ok(())
+    }
+
+    /// Start streaming.
+    pub fn start(&mut self) -> Result<()> {
+        self.rt.exec(&StartDataStream)?;
+        Ok(())
+    }
+
+    /// Stop streaming.
+    pub fn stop(&mut self) -> Result<()> {
+        self.rt.exec(&StopDataStream)?;
+        Ok(())
+    }
+
+    /// Current stream status.
+    pub fn status(&mut self) -> Result<DataStreamStatus> {
+        self.rt.controller().data_stream_status()
+    }
 }
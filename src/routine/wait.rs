@@ -0,0 +1,278 @@
+//! Condition waits: block until a signal does something, instead of
+//! hand-rolled `settle` loops.
+//!
+//! Every wait polls [`read_signal_samples`] silently and sleeps between
+//! polls with [`Rt::settle`], so it wakes on a shutdown request and respects
+//! an enclosing [`Rt::with_deadline`]. Each wait is bracketed by a
+//! `wait_started` event (signal, condition, timeout) and a `wait_finished`
+//! event (result, time waited, number of polls, final value), so the event
+//! log shows what the routine was waiting for and for how long.
+//!
+//! [`read_signal_samples`]: crate::spm_controller::SpmController::read_signal_samples
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::action::signals::compute_stability_metrics;
+use crate::event::Event;
+use crate::signal_registry::SignalIndex;
+use crate::spm_error::SpmError;
+
+use super::Rt;
+
+/// Samples averaged into one [`Rt::wait_until`] poll.
+const UNTIL_BATCH: usize = 10;
+
+/// Pause between polls.
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// A described predicate on a signal value, for [`Rt::wait_until`].
+pub struct Condition {
+    description: String,
+    test: Box<dyn Fn(f64) -> bool + Send + Sync>,
+}
+
+impl Condition {
+    /// An arbitrary predicate. `description` is what the events show.
+    pub fn new(
+        description: impl Into<String>,
+        test: impl Fn(f64) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            description: description.into(),
+            test: Box::new(test),
+        }
+    }
+
+    pub fn below(limit: f64) -> Self {
+        Self::new(format!("< {limit:e}"), move |v| v < limit)
+    }
+
+    pub fn above(limit: f64) -> Self {
+        Self::new(format!("> {limit:e}"), move |v| v > limit)
+    }
+
+    /// Inside `[low, high]`.
+    pub fn between(low: f64, high: f64) -> Self {
+        Self::new(format!("in [{low:e}, {high:e}]"), move |v| {
+            (low..=high).contains(&v)
+        })
+    }
+
+    /// Magnitude below `limit`, e.g. a current of either sign.
+    pub fn abs_below(limit: f64) -> Self {
+        Self::new(format!("|x| < {limit:e}"), move |v| v.abs() < limit)
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn holds(&self, value: f64) -> bool {
+        (self.test)(value)
+    }
+}
+
+/// When a signal counts as settled, for [`Rt::wait_settled`].
+#[derive(Debug, Clone)]
+pub struct SettleSpec {
+    /// Samples per batch.
+    pub num_samples: usize,
+    /// Rate the samples arrive at, to turn the per-sample slope into
+    /// units/s (as in [`StableReadSpec`](super::StableReadSpec)).
+    pub sample_rate_hz: f64,
+    /// Maximum |drift| (units/s) of a batch.
+    pub max_drift: f64,
+    /// Maximum standard deviation of a batch; `None` gates on drift only.
+    pub max_std_dev: Option<f64>,
+    /// How long consecutive batches must pass before the signal counts as
+    /// settled. Zero accepts the first passing batch.
+    pub hold: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for SettleSpec {
+    fn default() -> Self {
+        Self {
+            num_samples: 100,
+            sample_rate_hz: 2000.0,
+            max_drift: 0.1,
+            max_std_dev: None,
+            hold: Duration::ZERO,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Statistics of the batch that satisfied a settling wait.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SignalStats {
+    pub mean: f64,
+    pub std_dev: f64,
+    /// Regression slope in units/s.
+    pub drift: f64,
+}
+
+impl Rt<'_> {
+    /// Wait until the signal's value (a short averaged batch) satisfies
+    /// `condition`, polling every 100 ms. Returns the value that did.
+    ///
+    /// Fails with `Err(SpmError::Timeout)` after `timeout`, and with
+    /// `Err(SpmError::ShutdownRequested)` on a stop request.
+    ///
+    /// ```ignore
+    /// let current = rt.wait_until(current_index, Condition::abs_below(10e-12), Duration::from_secs(20))?;
+    /// ```
+    pub fn wait_until(
+        &mut self,
+        signal: SignalIndex,
+        condition: Condition,
+        timeout: Duration,
+    ) -> Result<f64, SpmError> {
+        self.wait_for(signal, condition.description(), timeout, |rt| {
+            let samples = rt.controller().read_signal_samples(signal, UNTIL_BATCH)?;
+            let value = samples.iter().sum::<f64>() / samples.len() as f64;
+            Ok((value, condition.holds(value).then_some(value)))
+        })
+    }
+
+    /// Wait until the signal's drift is below `max_drift` (units/s), judged
+    /// on batches of the [`SettleSpec`] default size at the data stream's
+    /// rate.
+    ///
+    /// Fails with `Err(SpmError::Unsupported)` when the controller has no
+    /// data stream to give the rate.
+    pub fn wait_for_drift(
+        &mut self,
+        signal: SignalIndex,
+        max_drift: f64,
+        timeout: Duration,
+    ) -> Result<SignalStats, SpmError> {
+        let sample_rate_hz = self.controller().stream_sample_rate_hz().ok_or_else(|| {
+            SpmError::Unsupported("wait_for_drift needs the data stream's sample rate".into())
+        })?;
+        self.wait_settled(
+            signal,
+            &SettleSpec {
+                sample_rate_hz,
+                max_drift,
+                timeout,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Wait until batches of the signal stay within the drift (and
+    /// optionally noise) gates of `spec` for `spec.hold`. A failing batch
+    /// restarts the hold. Returns the statistics of the last batch.
+    pub fn wait_settled(
+        &mut self,
+        signal: SignalIndex,
+        spec: &SettleSpec,
+    ) -> Result<SignalStats, SpmError> {
+        let mut description = format!("|drift| < {:e}/s", spec.max_drift);
+        if let Some(max) = spec.max_std_dev {
+            description.push_str(&format!(", std_dev < {max:e}"));
+        }
+        if !spec.hold.is_zero() {
+            description.push_str(&format!(" for {} ms", spec.hold.as_millis()));
+        }
+
+        let mut passing_since: Option<Instant> = None;
+        self.wait_for(signal, &description, spec.timeout, |rt| {
+            let samples = rt
+                .controller()
+                .read_signal_samples(signal, spec.num_samples)?;
+            let (mean, std_dev, slope) = compute_stability_metrics(&samples);
+            let stats = SignalStats {
+                mean,
+                std_dev,
+                drift: slope * spec.sample_rate_hz,
+            };
+            let passes = stats.drift.abs() < spec.max_drift
+                && spec.max_std_dev.is_none_or(|max| stats.std_dev < max);
+            if !passes {
+                passing_since = None;
+                return Ok((mean, None));
+            }
+            let now = rt.controller().now();
+            let since = *passing_since.get_or_insert(now);
+            Ok((mean, (now - since >= spec.hold).then_some(stats)))
+        })
+    }
+
+    /// Poll until `poll` yields a result, bracketed by the wait events.
+    /// `poll` returns the value it observed and `Some(result)` once the
+    /// condition is met.
+    fn wait_for<T: Serialize>(
+        &mut self,
+        signal: SignalIndex,
+        condition: &str,
+        timeout: Duration,
+        mut poll: impl FnMut(&mut Self) -> Result<(f64, Option<T>), SpmError>,
+    ) -> Result<T, SpmError> {
+        self.signals()?;
+        self.emit(Event::custom(
+            "wait_started",
+            serde_json::json!({
+                "signal": signal.0,
+                "condition": condition,
+                "timeout_ms": timeout.as_millis() as u64,
+            }),
+        ));
+
+        let started = self.controller().now();
+        let mut polls = 0usize;
+        let mut last = None;
+        let result = loop {
+            polls += 1;
+            match poll(self) {
+                Ok((_, Some(done))) => break Ok(done),
+                Ok((value, None)) => last = Some(value),
+                Err(e) => break Err(e),
+            }
+            let left = timeout.saturating_sub(self.controller().now() - started);
+            if left.is_zero() {
+                break Err(SpmError::Timeout(format!(
+                    "signal {} did not reach {} within {:.1} s",
+                    signal,
+                    condition,
+                    timeout.as_secs_f64()
+                )));
+            }
+            let pause = (left.as_millis() as u64).min(POLL_INTERVAL_MS);
+            if let Err(e) = self.settle(pause) {
+                break Err(e);
+            }
+        };
+
+        let waited = self.controller().now() - started;
+        let (outcome, value) = match &result {
+            Ok(done) => ("met", serde_json::to_value(done).unwrap_or_default()),
+            Err(SpmError::Timeout(_)) => ("timed_out", serde_json::json!(last)),
+            Err(SpmError::ShutdownRequested) => ("stopped", serde_json::json!(last)),
+            Err(_) => ("failed", serde_json::json!(last)),
+        };
+        log::debug!(
+            "Wait on signal {} for {}: {} after {} ms ({} polls)",
+            signal,
+            condition,
+            outcome,
+            waited.as_millis(),
+            polls
+        );
+        self.emit(Event::custom(
+            "wait_finished",
+            serde_json::json!({
+                "signal": signal.0,
+                "condition": condition,
+                "result": outcome,
+                "waited_ms": waited.as_millis() as u64,
+                "polls": polls,
+                "value": value,
+            }),
+        ));
+        result
+    }
+}
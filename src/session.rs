@@ -0,0 +1,199 @@
+//! The setup around a run, shared by the bundled binaries.
+//!
+//! `tip-prep`, `rusty-tip` and `tip-prep-gui` all connect the same way:
+//! build the signal registry from the config (TCP map, units, virtual
+//! signals), check it against the saved signal profile, start the data
+//! stream and its recording, and wrap the controller so virtual signals
+//! read like real ones. [`Backend`] does that for Nanonis or the mock, and
+//! [`event_bus`] sets up the events of a run. Terminal prompts, Ctrl+C and
+//! logging setup are left to the binaries.
+
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use log::{info, warn};
+
+use crate::config::AppConfig;
+use crate::event::{ConsoleLogger, EventAccumulator, EventBus, FileLogger};
+use crate::mock_controller::{FREQ_SHIFT_INDEX, MockController, models};
+use crate::nanonis_controller::{NanonisController, NanonisSetupConfig, StreamSetup};
+use crate::signal_profile::{ProfileChange, verify_signal_profile};
+use crate::signal_registry::SignalRegistry;
+use crate::spm_controller::SpmController;
+use crate::spm_error::SpmError;
+use crate::stream_recorder::StreamMarkers;
+use crate::virtual_signals::VirtualSignalController;
+
+/// A connected controller, ready to hand to a routine.
+pub struct Backend {
+    /// The controller, wrapped so virtual signals can be read.
+    pub controller: Box<dyn SpmController>,
+    pub signals: SignalRegistry,
+    /// Marks events in the stream recording, if one is running.
+    pub stream_markers: Option<StreamMarkers>,
+}
+
+impl Backend {
+    /// Connect to Nanonis, build and check its signal registry, and start
+    /// the TCP stream, recording it if configured.
+    ///
+    /// `confirm_layout` is asked whether to accept a signal layout that
+    /// differs from the saved profile (`on_mismatch = "prompt"`).
+    pub fn nanonis(
+        config: &AppConfig,
+        confirm_layout: impl FnOnce(&[ProfileChange]) -> bool,
+    ) -> Result<Self, SpmError> {
+        info!(
+            "Nanonis: {}:{}",
+            config.nanonis.host_ip, config.nanonis.control_ports[0]
+        );
+        let client = crate::NanonisClient::builder()
+            .address(&config.nanonis.host_ip)
+            .port(config.nanonis.control_ports[0])
+            .build()?;
+        let setup = NanonisSetupConfig {
+            layout_file: config.nanonis.layout_file.clone(),
+            settings_file: config.nanonis.settings_file.clone(),
+            safe_tip_threshold_a: config.tip_prep.safe_tip_threshold,
+            ..Default::default()
+        };
+        let mut controller = NanonisController::new(client, setup);
+        info!("Connected to Nanonis system");
+
+        let signals = build_signal_registry(&mut controller, config)?;
+        verify_signal_profile(&config.signal_profile, &signals, confirm_layout)?;
+
+        let stream = StreamSetup::new(
+            &config.nanonis.host_ip,
+            config.data_acquisition.data_port,
+            config.data_acquisition.sample_rate as i32,
+        );
+        let streaming = controller.start_streaming(&signals, &stream)?;
+
+        let recording = &config.data_acquisition.recording;
+        let stream_markers = if !recording.enabled {
+            None
+        } else if !streaming {
+            warn!("Stream recording is enabled but no signal is streamed; nothing to record");
+            None
+        } else {
+            info!("Recording the data stream to {}", recording.output_path);
+            Some(controller.start_recording(recording)?)
+        };
+
+        Ok(Self {
+            controller: VirtualSignalController::wrap(Box::new(controller), &signals),
+            signals,
+            stream_markers,
+        })
+    }
+
+    /// The in-memory mock with the realistic, seeded tip model: the
+    /// frequency shift responds to the voltages the routine chooses and
+    /// carries noise and drift. No hardware, no TCP stream.
+    pub fn mock(config: &AppConfig) -> Result<Self, SpmError> {
+        info!("Mock mode: running against the mock controller (no hardware)");
+
+        let mut mock = MockController::builder()
+            .freq_shift_index(FREQ_SHIFT_INDEX)
+            .freq_shift(models::realistic(models::RealisticParams::default()))
+            // Within-batch scatter, well under the default 1.0 Hz `max_std_dev`
+            // so stable reads do real work without stalling in retries.
+            .sample_noise_hz(0.25)
+            .build();
+
+        // Resolve through the same registry path the real backend uses, so
+        // the mock exercises the lookup instead of bypassing it.
+        let signals = build_signal_registry(&mut mock, config)?;
+        let resolved = signals.resolve("freq shift")?;
+        if resolved != FREQ_SHIFT_INDEX {
+            return Err(SpmError::Workflow(format!(
+                "mock freq-shift index drifted: registry resolved {resolved}, \
+                 mock model answers for {FREQ_SHIFT_INDEX}"
+            )));
+        }
+
+        Ok(Self {
+            controller: VirtualSignalController::wrap(Box::new(mock), &signals),
+            signals,
+            stream_markers: None,
+        })
+    }
+}
+
+/// The signal registry for `controller` as `config` describes it: the
+/// standard map, the TCP channel mapping, unit overrides and virtual
+/// signals.
+pub fn build_signal_registry(
+    controller: &mut dyn SpmController,
+    config: &AppConfig,
+) -> Result<SignalRegistry, SpmError> {
+    let mut builder = SignalRegistry::builder().with_standard_map();
+
+    if let Some(ref mappings) = config.tcp_channel_mapping {
+        let tcp_map: Vec<(u8, u8)> = mappings
+            .iter()
+            .map(|m| (m.nanonis_index, m.tcp_channel))
+            .collect();
+        builder = builder.add_tcp_map(&tcp_map);
+    }
+    for entry in &config.signal_units {
+        builder = builder.set_unit(&entry.signal, entry.unit.as_deref(), entry.precision);
+    }
+    for signal in &config.virtual_signals {
+        builder = builder.add_virtual(&signal.name, &signal.expression);
+        if signal.unit.is_some() || signal.precision.is_some() {
+            builder = builder.set_unit(&signal.name, signal.unit.as_deref(), signal.precision);
+        }
+    }
+
+    builder
+        .from_controller(controller)?
+        .create_aliases()
+        .try_build()
+}
+
+/// An event bus labelling values with the units of `signals`, logging to
+/// the console and, if `[experiment_logging]` is enabled, to
+/// `<output_path>/<log_name>_<timestamp>.jsonl`. `stream_markers`, if any,
+/// marks its events in the stream recording.
+pub fn event_bus(
+    config: &AppConfig,
+    log_name: &str,
+    signals: &SignalRegistry,
+    stream_markers: Option<StreamMarkers>,
+) -> Result<EventBus, SpmError> {
+    let mut events = EventBus::new();
+    events.set_signal_units(signals.units());
+    events.add_observer(Box::new(ConsoleLogger));
+
+    if config.experiment_logging.enabled {
+        let dir = PathBuf::from(&config.experiment_logging.output_path);
+        fs::create_dir_all(&dir).map_err(|source| SpmError::Io {
+            source,
+            context: format!("creating event log directory {}", dir.display()),
+        })?;
+        let log_path = dir.join(format!(
+            "{log_name}_{}.jsonl",
+            Utc::now().format("%Y%m%d_%H%M%S")
+        ));
+        info!(
+            "Event log: {} (run {})",
+            log_path.display(),
+            events.run_id()
+        );
+        let file = fs::File::create(&log_path).map_err(|source| SpmError::Io {
+            source,
+            context: format!("creating event log {}", log_path.display()),
+        })?;
+        events.add_observer(Box::new(FileLogger::new(file)));
+    }
+
+    events.add_observer(Box::new(EventAccumulator::new(500)));
+    if let Some(markers) = stream_markers {
+        events.add_observer(Box::new(markers));
+    }
+
+    Ok(events)
+}
@@ -0,0 +1,423 @@
+//! Saved signal layouts, checked on every connect.
+//!
+//! The [`SignalRegistry`] is rebuilt from the controller's signal names on
+//! every start, so when someone reassigns the Nanonis signal slots, "freq
+//! shift" quietly resolves to whatever now sits at its index. A
+//! [`SignalProfile`] is the registry written to a JSON file; comparing it
+//! with the freshly built registry turns such a change into a
+//! [`ProfileChange`] list instead of a wrong reading.
+//!
+//! [`verify_signal_profile`] is the connect-time check the binaries run:
+//! the first connect saves the profile, later ones diff against it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{OnProfileMismatch, SignalProfileConfig};
+use crate::signal_registry::SignalRegistry;
+use crate::spm_error::SpmError;
+
+/// Version written to new profile files.
+pub const PROFILE_VERSION: u32 = 1;
+
+/// One signal slot of a saved layout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileSignal {
+    /// Name as reported by the controller, e.g. `"Freq. Shift (Hz)"`.
+    pub name: String,
+    /// Signal index (slot) on the controller.
+    pub index: u8,
+    /// TCP logger channel, if the signal is streamed.
+    #[serde(default)]
+    pub tcp_channel: Option<u8>,
+    /// Other names the registry resolves to this signal, lowercase.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// A [`SignalRegistry`] as saved to disk, one entry per signal slot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalProfile {
+    pub version: u32,
+    /// Ordered by index.
+    pub signals: Vec<ProfileSignal>,
+}
+
+/// One difference between a saved profile and the current layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileChange {
+    /// The signal still exists, at another index.
+    Moved { name: String, from: u8, to: u8 },
+    /// The slot holds a signal the profile does not know.
+    Renamed { index: u8, from: String, to: String },
+    /// The signal and its slot are both gone.
+    Removed { name: String, index: u8 },
+    /// Same signal and index, different TCP channel.
+    TcpChannelChanged {
+        name: String,
+        from: Option<u8>,
+        to: Option<u8>,
+    },
+    /// An alias now resolves to a signal at another index, though the
+    /// signal it named did not move there.
+    AliasMoved { alias: String, from: u8, to: u8 },
+}
+
+impl fmt::Display for ProfileChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn channel(ch: &Option<u8>) -> String {
+            ch.map_or_else(|| "none".to_string(), |c| c.to_string())
+        }
+        match self {
+            Self::Moved { name, from, to } => {
+                write!(f, "\"{name}\" moved from index {from} to {to}")
+            }
+            Self::Renamed { index, from, to } => {
+                write!(f, "index {index} was \"{from}\" and is now \"{to}\"")
+            }
+            Self::Removed { name, index } => {
+                write!(f, "\"{name}\" (index {index}) no longer exists")
+            }
+            Self::TcpChannelChanged { name, from, to } => write!(
+                f,
+                "\"{name}\" TCP channel changed from {} to {}",
+                channel(from),
+                channel(to)
+            ),
+            Self::AliasMoved { alias, from, to } => {
+                write!(
+                    f,
+                    "alias \"{alias}\" resolved to index {from} and now to {to}"
+                )
+            }
+        }
+    }
+}
+
+impl SignalProfile {
+    /// Snapshot a registry: every signal once, with the aliases pointing at it.
+    pub fn from_registry(registry: &SignalRegistry) -> Self {
+        let mut by_index: BTreeMap<u8, ProfileSignal> = BTreeMap::new();
+        // Virtual signals come from the config, not the controller.
+        for (key, signal) in registry.iter().filter(|(_, s)| !s.is_virtual()) {
+            let entry = by_index
+                .entry(signal.index)
+                .or_insert_with(|| ProfileSignal {
+                    name: signal.name.clone(),
+                    index: signal.index,
+                    tcp_channel: signal.tcp_channel,
+                    aliases: Vec::new(),
+                });
+            if *key != signal.name.to_lowercase() {
+                entry.aliases.push(key.clone());
+            }
+        }
+        let signals = by_index
+            .into_values()
+            .map(|mut signal| {
+                signal.aliases.sort();
+                signal
+            })
+            .collect();
+        Self {
+            version: PROFILE_VERSION,
+            signals,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SpmError> {
+        let text = fs::read_to_string(path).map_err(|source| SpmError::Io {
+            source,
+            context: format!("reading signal profile {}", path.display()),
+        })?;
+        let profile: Self = serde_json::from_str(&text).map_err(|e| SpmError::Io {
+            source: io::Error::new(io::ErrorKind::InvalidData, e),
+            context: format!("parsing signal profile {}", path.display()),
+        })?;
+        if profile.version != PROFILE_VERSION {
+            return Err(SpmError::Workflow(format!(
+                "Signal profile {} has version {}, expected {}",
+                path.display(),
+                profile.version,
+                PROFILE_VERSION
+            )));
+        }
+        Ok(profile)
+    }
+
+    /// Write the profile as pretty JSON, creating the parent directory.
+    pub fn save(&self, path: &Path) -> Result<(), SpmError> {
+        let io_error = |source| SpmError::Io {
+            source,
+            context: format!("writing signal profile {}", path.display()),
+        };
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io_error(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        fs::write(path, json + "\n").map_err(io_error)
+    }
+
+    /// How `current` departs from this saved profile, ordered by saved
+    /// index. Signals added since the profile was saved are not changes, nor
+    /// are aliases that went with their signal or no longer resolve.
+    pub fn diff(&self, current: &SignalProfile) -> Vec<ProfileChange> {
+        let by_name: BTreeMap<&str, &ProfileSignal> = current
+            .signals
+            .iter()
+            .map(|s| (s.name.as_str(), s))
+            .collect();
+        let by_index: BTreeMap<u8, &ProfileSignal> =
+            current.signals.iter().map(|s| (s.index, s)).collect();
+        let by_alias: BTreeMap<&str, &ProfileSignal> = current
+            .signals
+            .iter()
+            .flat_map(|s| s.aliases.iter().map(move |a| (a.as_str(), s)))
+            .collect();
+
+        let mut changes = Vec::new();
+        for saved in &self.signals {
+            match by_name.get(saved.name.as_str()) {
+                Some(now) if now.index != saved.index => changes.push(ProfileChange::Moved {
+                    name: saved.name.clone(),
+                    from: saved.index,
+                    to: now.index,
+                }),
+                Some(now) if now.tcp_channel != saved.tcp_channel => {
+                    changes.push(ProfileChange::TcpChannelChanged {
+                        name: saved.name.clone(),
+                        from: saved.tcp_channel,
+                        to: now.tcp_channel,
+                    })
+                }
+                Some(_) => {}
+                None => match by_index.get(&saved.index) {
+                    // A slot taken over by a signal that moved there is
+                    // reported once, as that signal's move.
+                    Some(now) if self.signals.iter().any(|s| s.name == now.name) => {
+                        changes.push(ProfileChange::Removed {
+                            name: saved.name.clone(),
+                            index: saved.index,
+                        })
+                    }
+                    Some(now) => changes.push(ProfileChange::Renamed {
+                        index: saved.index,
+                        from: saved.name.clone(),
+                        to: now.name.clone(),
+                    }),
+                    None => changes.push(ProfileChange::Removed {
+                        name: saved.name.clone(),
+                        index: saved.index,
+                    }),
+                },
+            }
+            for alias in &saved.aliases {
+                match by_alias.get(alias.as_str()) {
+                    Some(now) if now.index != saved.index && now.name != saved.name => changes
+                        .push(ProfileChange::AliasMoved {
+                            alias: alias.clone(),
+                            from: saved.index,
+                            to: now.index,
+                        }),
+                    _ => {}
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Check `registry` against the profile configured in `config`.
+///
+/// Without a configured path this does nothing. If the file does not exist
+/// yet, the registry is saved as the profile. Otherwise any differences are
+/// handled per [`SignalProfileConfig::on_mismatch`]; for
+/// [`OnProfileMismatch::Prompt`], `confirm` is asked whether to accept the
+/// new layout, which then replaces the saved profile.
+pub fn verify_signal_profile(
+    config: &SignalProfileConfig,
+    registry: &SignalRegistry,
+    confirm: impl FnOnce(&[ProfileChange]) -> bool,
+) -> Result<(), SpmError> {
+    let Some(path) = config.path.as_deref().map(Path::new) else {
+        return Ok(());
+    };
+    let current = SignalProfile::from_registry(registry);
+    if !path.exists() {
+        current.save(path)?;
+        log::info!(
+            "Saved signal profile ({} signals) to {}",
+            current.signals.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let changes = SignalProfile::load(path)?.diff(&current);
+    if changes.is_empty() {
+        log::info!("Signal layout matches profile {}", path.display());
+        return Ok(());
+    }
+    for change in &changes {
+        log::warn!("Signal profile {}: {}", path.display(), change);
+    }
+
+    match config.on_mismatch {
+        OnProfileMismatch::Warn => Ok(()),
+        OnProfileMismatch::Prompt if confirm(&changes) => {
+            current.save(path)?;
+            log::info!("Accepted the new signal layout; updated {}", path.display());
+            Ok(())
+        }
+        OnProfileMismatch::Prompt | OnProfileMismatch::Error => {
+            let list: Vec<String> = changes.iter().map(|c| format!("  - {c}")).collect();
+            Err(SpmError::Workflow(format!(
+                "The controller's signal layout differs from profile {}:\n{}\n\
+                 Check the signal slots in Nanonis; if the new layout is intended, \
+                 delete the profile to save it again.",
+                path.display(),
+                list.join("\n")
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn registry(names: &[&str]) -> SignalRegistry {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        SignalRegistry::with_hardcoded_tcp_mapping(&names)
+    }
+
+    fn profile(names: &[&str]) -> SignalProfile {
+        SignalProfile::from_registry(&registry(names))
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rusty_tip_profile_{name}_{}.json",
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn a_registry_is_saved_once_per_slot_with_its_aliases() {
+        let profile = profile(&["Current (A)", "Z (m)", "Freq. Shift (Hz)"]);
+
+        assert_eq!(profile.signals.len(), 3);
+        let freq = &profile.signals[2];
+        assert_eq!((freq.name.as_str(), freq.index), ("Freq. Shift (Hz)", 2));
+        assert_eq!(freq.tcp_channel, Some(2));
+        assert!(freq.aliases.contains(&"freq. shift".to_string()));
+
+        let path = temp_path("round_trip");
+        profile.save(&path).unwrap();
+        assert_eq!(SignalProfile::load(&path).unwrap(), profile);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn moved_renamed_and_removed_signals_are_told_apart() {
+        let saved = profile(&["Current (A)", "Z (m)", "Freq. Shift (Hz)", "Bias (V)"]);
+        // Nothing new here claims an alias a saved signal had (an "Amplitude"
+        // would compete with the current for "amp").
+        let moved = profile(&["Current (A)", "Freq. Shift (Hz)", "Input 3 (V)"]);
+        let renamed = profile(&["Current (A)", "Z rel (m)", "Freq. Shift (Hz)", "Bias (V)"]);
+
+        assert_eq!(
+            saved.diff(&moved),
+            [
+                ProfileChange::Removed {
+                    name: "Z (m)".into(),
+                    index: 1
+                },
+                ProfileChange::Moved {
+                    name: "Freq. Shift (Hz)".into(),
+                    from: 2,
+                    to: 1
+                },
+                ProfileChange::Removed {
+                    name: "Bias (V)".into(),
+                    index: 3
+                },
+            ]
+        );
+        assert_eq!(
+            saved.diff(&renamed),
+            [ProfileChange::Renamed {
+                index: 1,
+                from: "Z (m)".into(),
+                to: "Z rel (m)".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn a_changed_tcp_channel_is_a_change_and_new_signals_are_not() {
+        let saved = profile(&["Current (A)", "Z (m)"]);
+        let mut current = profile(&["Current (A)", "Z (m)", "Bias (V)"]);
+        assert!(saved.diff(&current).is_empty());
+
+        current.signals[1].tcp_channel = Some(9);
+        assert_eq!(
+            saved.diff(&current),
+            [ProfileChange::TcpChannelChanged {
+                name: "Z (m)".into(),
+                from: Some(1),
+                to: Some(9)
+            }]
+        );
+    }
+
+    #[test]
+    fn an_alias_resolving_to_another_signal_is_a_change() {
+        let saved = profile(&["Current (A)", "Z (m)", "Freq. Shift (Hz)"]);
+        let mut current = saved.clone();
+        let alias = "freq. shift".to_string();
+        current.signals[2].aliases.retain(|a| *a != alias);
+        current.signals[1].aliases.push(alias.clone());
+
+        assert_eq!(
+            saved.diff(&current),
+            [ProfileChange::AliasMoved {
+                alias,
+                from: 2,
+                to: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn verify_saves_first_then_refuses_a_changed_layout() {
+        let path = temp_path("verify");
+        let _ = fs::remove_file(&path);
+        let mut config = SignalProfileConfig {
+            path: Some(path.display().to_string()),
+            on_mismatch: OnProfileMismatch::Error,
+        };
+        let original = registry(&["Current (A)", "Freq. Shift (Hz)"]);
+        let swapped = registry(&["Freq. Shift (Hz)", "Current (A)"]);
+
+        verify_signal_profile(&config, &original, |_| unreachable!()).unwrap();
+        assert!(path.exists());
+        verify_signal_profile(&config, &original, |_| unreachable!()).unwrap();
+
+        let err = verify_signal_profile(&config, &swapped, |_| unreachable!()).unwrap_err();
+        assert!(err.to_string().contains("moved from index 1 to 0"), "{err}");
+
+        config.on_mismatch = OnProfileMismatch::Prompt;
+        assert!(verify_signal_profile(&config, &swapped, |_| false).is_err());
+        verify_signal_profile(&config, &swapped, |changes| changes.len() == 2).unwrap();
+        verify_signal_profile(&config, &swapped, |_| unreachable!()).unwrap();
+        let _ = fs::remove_file(&path);
+    }
+}
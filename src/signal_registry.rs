@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use crate::NanonisError;
 use crate::spm_controller::SpmController;
 use crate::spm_error::SpmError;
+use crate::units::{SignalUnit, SignalUnits};
+use crate::virtual_signals::{Expression, VIRTUAL_INDEX_BASE, VirtualSignal};
 use std::{collections::HashMap, fmt, ops::Deref};
 
 /// Index of a signal slot on the controller, as used by every signal-read
@@ -110,12 +112,15 @@ fn generate_signal_aliases(name: &str) -> Vec<String> {
 
 /// Signal registry with case-insensitive lookup and TCP/Nanonis index mapping
 #[derive(Debug, Clone, Default)]
-pub struct SignalRegistry(HashMap<String, Signal>);
+pub struct SignalRegistry {
+    signals: HashMap<String, Signal>,
+    virtuals: Vec<VirtualSignal>,
+}
 
 impl Deref for SignalRegistry {
     type Target = HashMap<String, Signal>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.signals
     }
 }
 /// Signal representation with both Nanonis index and optional TCP channel
@@ -131,6 +136,9 @@ pub struct Signal {
     pub index: u8,
     /// TCP channel index (0-23) if this signal has TCP logger mapping
     pub tcp_channel: Option<u8>,
+    /// Physical unit, from the name's parentheses or declared in config
+    #[serde(default)]
+    pub unit: Option<SignalUnit>,
 }
 
 impl Signal {
@@ -139,6 +147,20 @@ impl Signal {
         SignalIndex(self.index as u32)
     }
 
+    /// Whether this is a virtual signal, computed from real ones (see
+    /// [`crate::virtual_signals`]).
+    pub fn is_virtual(&self) -> bool {
+        self.index >= VIRTUAL_INDEX_BASE
+    }
+
+    /// `value` formatted with this signal's unit, or plainly without one.
+    pub fn format(&self, value: f64) -> String {
+        match &self.unit {
+            Some(unit) => unit.format(value),
+            None => value.to_string(),
+        }
+    }
+
     /// Create a new Signal with validation
     pub fn new(name: String, index: u8, tcp_channel: Option<u8>) -> Result<Self, NanonisError> {
         if index > 127 {
@@ -156,6 +178,7 @@ impl Signal {
         }
 
         Ok(Self {
+            unit: SignalUnit::from_signal_name(&name),
             name,
             index,
             tcp_channel,
@@ -166,6 +189,7 @@ impl Signal {
         let name = String::from(name);
 
         Signal {
+            unit: SignalUnit::from_signal_name(&name),
             name,
             index,
             tcp_channel,
@@ -176,6 +200,7 @@ impl Signal {
     pub fn index_only(name: &str, index: u8) -> Self {
         let name = String::from(name);
         Signal {
+            unit: SignalUnit::from_signal_name(&name),
             name,
             index,
             tcp_channel: None,
@@ -193,6 +218,10 @@ impl Signal {
 pub struct SignalRegistryBuilder {
     signals: HashMap<String, Signal>,
     nanonis_to_tcp: HashMap<u8, u8>,
+    /// Unit overrides by signal name, applied in `build`.
+    unit_overrides: Vec<(String, Option<String>, Option<u8>)>,
+    /// Virtual signals as (name, expression), compiled in `build`.
+    virtuals: Vec<(String, String)>,
 }
 
 impl SignalRegistryBuilder {
@@ -255,6 +284,7 @@ impl SignalRegistryBuilder {
                 name: name.clone(),
                 index: index as u8,
                 tcp_channel,
+                unit: SignalUnit::from_signal_name(name),
             };
             self.signals.insert(name.to_lowercase(), signal.clone());
 
@@ -271,6 +301,7 @@ impl SignalRegistryBuilder {
         let clean_name = name.split('(').next().unwrap().trim();
 
         let signal = Signal {
+            unit: SignalUnit::from_signal_name(&name),
             name: name.clone(),
             index,
             tcp_channel,
@@ -326,8 +357,125 @@ impl SignalRegistryBuilder {
         Ok(self.from_signal_names(&names))
     }
 
+    /// Declare the unit symbol and/or display precision of the signal known
+    /// as `name` (name or alias), overriding what its name implied. Applied
+    /// in `build`, after the names and aliases are in.
+    pub fn set_unit(mut self, name: &str, symbol: Option<&str>, precision: Option<u8>) -> Self {
+        self.unit_overrides
+            .push((name.to_string(), symbol.map(str::to_string), precision));
+        self
+    }
+
+    /// Declare a virtual signal `name` computed by `expression` over the
+    /// real signals (see [`crate::virtual_signals`]). Compiled when the
+    /// registry is built: the expression must parse and read only known real
+    /// signals, and `name` must be free. `build` skips a failing one with a
+    /// warning; `try_build` refuses it.
+    pub fn add_virtual(mut self, name: &str, expression: &str) -> Self {
+        self.virtuals
+            .push((name.to_string(), expression.to_string()));
+        self
+    }
+
+    /// Build the registry, skipping with a warning any virtual signal that
+    /// fails to compile.
     pub fn build(self) -> SignalRegistry {
-        SignalRegistry(self.signals)
+        let (registry, failures) = self.finish();
+        for failure in failures {
+            log::warn!("{failure}; ignored");
+        }
+        registry
+    }
+
+    /// Build the registry, failing if any virtual signal does not compile.
+    /// For virtual signals declared in a config, where a silently missing
+    /// signal would only surface later as "not found".
+    pub fn try_build(self) -> Result<SignalRegistry, SpmError> {
+        let (registry, failures) = self.finish();
+        if failures.is_empty() {
+            Ok(registry)
+        } else {
+            Err(SpmError::Workflow(failures.join("; ")))
+        }
+    }
+
+    /// The registry, and a description of each virtual signal left out.
+    fn finish(mut self) -> (SignalRegistry, Vec<String>) {
+        let mut failures = Vec::new();
+        let mut virtuals = Vec::new();
+        for (name, expression) in std::mem::take(&mut self.virtuals) {
+            match self.compile_virtual(&name, &expression, virtuals.len()) {
+                Ok(signal) => {
+                    self.signals.insert(
+                        name.to_lowercase(),
+                        Signal {
+                            name: name.clone(),
+                            index: signal.index().0 as u8,
+                            tcp_channel: None,
+                            unit: None,
+                        },
+                    );
+                    virtuals.push(signal);
+                }
+                Err(e) => failures.push(format!("virtual signal \"{name}\": {e}")),
+            }
+        }
+        for (name, symbol, precision) in std::mem::take(&mut self.unit_overrides) {
+            let Some(index) = self
+                .signals
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(&name))
+                .map(|(_, signal)| signal.index)
+            else {
+                log::warn!("Unit declared for unknown signal \"{name}\"; ignored");
+                continue;
+            };
+            for signal in self.signals.values_mut().filter(|s| s.index == index) {
+                let unit = signal.unit.get_or_insert_with(SignalUnit::default);
+                if let Some(symbol) = &symbol {
+                    unit.symbol = symbol.clone();
+                }
+                if let Some(precision) = precision {
+                    unit.precision = precision;
+                }
+            }
+        }
+        let registry = SignalRegistry {
+            signals: self.signals,
+            virtuals,
+        };
+        (registry, failures)
+    }
+
+    /// Compile the `count`-th virtual signal against the real signals.
+    fn compile_virtual(
+        &self,
+        name: &str,
+        expression: &str,
+        count: usize,
+    ) -> Result<VirtualSignal, String> {
+        if self.signals.keys().any(|k| k.eq_ignore_ascii_case(name)) {
+            return Err("a signal of that name already exists".to_string());
+        }
+        let index = VIRTUAL_INDEX_BASE as usize + count;
+        if index > u8::MAX as usize {
+            return Err("too many virtual signals".to_string());
+        }
+        let expression = Expression::parse(expression)?;
+        VirtualSignal::compile(name, SignalIndex(index as u32), expression, |input| {
+            let signal = self
+                .signals
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(input))
+                .map(|(_, signal)| signal)
+                .ok_or_else(|| format!("unknown signal \"{input}\""))?;
+            if signal.is_virtual() {
+                return Err(format!(
+                    "\"{input}\" is virtual; expressions read real signals only"
+                ));
+            }
+            Ok(signal.signal_index())
+        })
     }
 }
 
@@ -356,7 +504,7 @@ impl SignalRegistry {
     }
 
     pub fn all_names(&self) -> Vec<String> {
-        self.0
+        self.signals
             .values()
             .map(|s| s.name.clone())
             .collect::<std::collections::HashSet<_>>()
@@ -365,7 +513,7 @@ impl SignalRegistry {
     }
 
     pub fn tcp_signals(&self) -> Vec<&Signal> {
-        self.0
+        self.signals
             .values()
             .filter(|s| s.tcp_channel.is_some())
             .collect()
@@ -373,7 +521,7 @@ impl SignalRegistry {
 
     pub fn find_signals_like(&self, query: &str) -> Vec<&Signal> {
         let query_lower = query.to_lowercase();
-        self.0
+        self.signals
             .values()
             .filter(|s| s.name.to_lowercase().contains(&query_lower))
             .collect()
@@ -387,14 +535,78 @@ impl SignalRegistry {
         if let Some(signal) = self.get(name) {
             return Some(signal);
         }
-        self.0
+        self.signals
             .iter()
             .find(|(k, _)| k.eq_ignore_ascii_case(name))
             .map(|(_, v)| v)
     }
 
+    /// Index of the signal known as `name` (name or alias, any case),
+    /// failing with an error that names it.
+    pub fn resolve(&self, name: &str) -> Result<SignalIndex, SpmError> {
+        self.get_by_name(name)
+            .map(Signal::signal_index)
+            .ok_or_else(|| SpmError::Workflow(format!("signal '{name}' not found in registry")))
+    }
+
+    /// Unit of every signal that has one, by index.
+    pub fn units(&self) -> SignalUnits {
+        let mut units = SignalUnits::default();
+        for signal in self.signals.values() {
+            if let Some(unit) = &signal.unit {
+                units.insert(signal.signal_index(), unit.clone());
+            }
+        }
+        units
+    }
+
+    /// The virtual signals, compiled; hand them to a
+    /// [`VirtualSignalController`](crate::virtual_signals::VirtualSignalController)
+    /// to make them readable.
+    pub fn virtual_signals(&self) -> &[VirtualSignal] {
+        &self.virtuals
+    }
+
     /// Get signal by Nanonis index, returning the new Signal type
     pub fn get_by_index(&self, index: u8) -> Option<&Signal> {
-        self.0.values().find(|signal| signal.index == index)
+        self.signals.values().find(|signal| signal.index == index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_come_from_names_and_config_overrides_them_for_every_alias() {
+        let names: Vec<String> = ["Current (A)", "Freq. Shift (Hz)", "freq shift"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let registry = SignalRegistry::builder()
+            .from_signal_names(&names)
+            .create_aliases()
+            .set_unit("current", None, Some(1))
+            .set_unit("FREQ SHIFT", Some("Hz"), Some(2))
+            .set_unit("no such signal", Some("V"), None)
+            .build();
+
+        let current = registry.get_by_name("current (a)").unwrap();
+        assert_eq!(current.unit, Some(SignalUnit::new("A", 1)));
+        assert_eq!(current.format(2.5e-11), "25.0 pA");
+        assert_eq!(
+            registry.get_by_name("freq. shift").unwrap().unit,
+            Some(SignalUnit::new("Hz", 3))
+        );
+        assert_eq!(
+            registry.units().get(SignalIndex(2)),
+            Some(&SignalUnit::new("Hz", 2))
+        );
+        assert_eq!(registry.resolve("FREQ SHIFT").unwrap(), SignalIndex(2));
+        let missing = registry.resolve("no such signal").unwrap_err();
+        assert!(
+            missing.to_string().contains("'no such signal'"),
+            "{missing}"
+        );
     }
 }
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub use nanonis_rs::z_ctrl::{ZControllerStatus, ZHomeMode};
 
@@ -6,7 +6,7 @@ use nanonis_rs::{
     Position,
     motor::{MotorDirection, MotorDisplacement, MovementMode, Position3D},
     oscilloscope::{OsciData, TriggerConfig},
-    scan::{ScanAction, ScanConfig, ScanDirection, ScanProps, ScanPropsBuilder},
+    scan::{ScanAction, ScanConfig, ScanDirection, ScanFrame, ScanProps, ScanPropsBuilder},
     tcplog::TCPLogStatus,
     tip_recovery::TipShaperConfig,
 };
@@ -144,6 +144,8 @@ pub trait SpmController: Send {
     fn scan_props_set(&mut self, props: ScanPropsBuilder) -> Result<()>;
     fn scan_speed_get(&mut self) -> Result<ScanConfig>;
     fn scan_speed_set(&mut self, config: ScanConfig) -> Result<()>;
+    fn scan_frame_get(&mut self) -> Result<ScanFrame>;
+    fn scan_frame_set(&mut self, frame: ScanFrame) -> Result<()>;
 
     /// Grab pixel data from a completed (or in-progress) scan frame.
     ///
@@ -199,8 +201,36 @@ pub trait SpmController: Send {
     /// controllers without internal buffering.
     fn clear_data_buffer(&mut self) {}
 
+    /// Let `duration` pass, for fixed waits inside actions and routines.
+    /// Default sleeps; a simulated controller may advance its own clock
+    /// instead (see [`simulated_time`](Self::simulated_time)).
+    fn pause(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    /// `true` if [`pause`](Self::pause) does not take wall-clock time, so
+    /// callers should wait through it rather than sleeping themselves.
+    fn simulated_time(&self) -> bool {
+        false
+    }
+
+    /// The controller's clock, which deadlines and timed waits are measured
+    /// on. Default is the wall clock; under simulated time it advances with
+    /// [`pause`](Self::pause).
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
     // -- Signal Reading --
 
+    /// Frame rate (Hz) of the data stream
+    /// [`read_signal_samples`](Self::read_signal_samples) collects from, or
+    /// `None` when samples are polled instead and so not taken at any fixed
+    /// rate. Default `None`.
+    fn stream_sample_rate_hz(&self) -> Option<f64> {
+        None
+    }
+
     /// Collect raw signal samples for analysis or averaging.
     ///
     /// Returns up to `num_samples` data points for the given signal.
@@ -223,6 +253,33 @@ pub trait SpmController: Send {
         Ok(samples)
     }
 
+    /// Collect samples of several signals taken together: `result[i]` holds
+    /// the samples of `indices[i]`, and sample `k` of every signal comes from
+    /// the same moment, so their batches can be judged side by side.
+    ///
+    /// The default implementation takes one `read_signals` snapshot per
+    /// sample. Stream-backed implementations should override this to slice
+    /// every signal out of the same stream frames.
+    fn read_signals_samples(
+        &mut self,
+        indices: &[SignalIndex],
+        num_samples: usize,
+    ) -> Result<Vec<Vec<f64>>> {
+        if num_samples == 0 || indices.is_empty() {
+            return Err(SpmError::Protocol(
+                "read_signals_samples: need at least one signal and one sample".into(),
+            ));
+        }
+        let mut samples = vec![Vec::with_capacity(num_samples); indices.len()];
+        for _ in 0..num_samples {
+            let snapshot = self.read_signals(indices, true)?;
+            for (signal, value) in samples.iter_mut().zip(snapshot) {
+                signal.push(value);
+            }
+        }
+        Ok(samples)
+    }
+
     /// Read a noise-reduced signal value by averaging multiple samples.
     ///
     /// Convenience wrapper around `read_signal_samples` that returns the mean.
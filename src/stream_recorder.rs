@@ -0,0 +1,586 @@
+//! Continuous recording of the TCP data stream to disk.
+//!
+//! [`StreamRecorder`] writes every streamed frame, and markers for the
+//! events of the running routine, to rotating `.rtstream` files from a
+//! background thread. [`StreamReader`] reads them back.
+//!
+//! # File format (version 1)
+//!
+//! All integers and floats are little-endian.
+//!
+//! | Field | Size | Content |
+//! |-------|------|---------|
+//! | magic | 8 | `RTSTREAM` |
+//! | header length | 4 | `u32` |
+//! | header | header length | JSON [`StreamHeader`] |
+//! | records | rest of file | one after another, each starting with a tag byte |
+//!
+//! Record types:
+//!
+//! - `0x01` frame: `u64` stream counter, `u64` time (ns since the recording
+//!   started, on the sample clock inferred from the oversampling, so frames
+//!   that arrived in one burst stay evenly spaced), `u16` value count `n`,
+//!   then `n` × `f32`, one per channel in [`StreamHeader::channels`] order.
+//! - `0x02` marker: `u64` time (ns, same time base as frames), `u32` length,
+//!   then that many bytes of JSON `{"label": ..., "data": ...}`. Event
+//!   markers carry the [`Event`] as `data`, labelled with its `type`.
+//!
+//! Times are measured from [`StreamHeader::started_unix_ms`] and keep
+//! counting across rotated files, so a recording split over several files
+//! shares one time axis. A file cut short by a crash ends in a partial
+//! record; the reader stops cleanly before it.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::event::{Event, Observer};
+
+const MAGIC: &[u8; 8] = b"RTSTREAM";
+const FORMAT_VERSION: u32 = 1;
+const TAG_FRAME: u8 = 0x01;
+const TAG_MARKER: u8 = 0x02;
+
+/// File extension of recorded streams.
+pub const EXTENSION: &str = "rtstream";
+
+/// Metadata at the start of every recorded file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamHeader {
+    pub version: u32,
+    /// Channel names, in the order values appear in each frame.
+    pub channels: Vec<String>,
+    /// Wall-clock time (ms since the Unix epoch) of time zero.
+    pub started_unix_ms: u64,
+    /// Position of this file in the recording, from 0.
+    pub file_index: u32,
+}
+
+/// One record read back from a recorded file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamRecord {
+    Frame {
+        counter: u64,
+        time: Duration,
+        values: Vec<f32>,
+    },
+    Marker {
+        time: Duration,
+        label: String,
+        data: Value,
+    },
+}
+
+/// When to start a new file. Whichever limit is hit first wins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_duration: Duration,
+}
+
+enum Message {
+    Frame {
+        counter: u64,
+        at: Instant,
+        values: Vec<f32>,
+    },
+    Marker {
+        at: Instant,
+        label: String,
+        data: Value,
+    },
+    Stop,
+}
+
+/// Cheap, cloneable handle that feeds a running [`StreamRecorder`].
+///
+/// Sends never block; once the recorder has stopped they are dropped.
+#[derive(Clone)]
+pub struct RecorderTap {
+    sender: mpsc::Sender<Message>,
+}
+
+impl RecorderTap {
+    pub(crate) fn frame(&self, counter: u64, at: Instant, values: &[f32]) {
+        let _ = self.sender.send(Message::Frame {
+            counter,
+            at,
+            values: values.to_vec(),
+        });
+    }
+
+    /// Record a marker at the current instant.
+    pub fn mark(&self, label: impl Into<String>, data: Value) {
+        let _ = self.sender.send(Message::Marker {
+            at: Instant::now(),
+            label: label.into(),
+            data,
+        });
+    }
+}
+
+/// Records the data stream to rotating files in a directory.
+///
+/// Frames reach it through a [`RecorderTap`]; see
+/// `NanonisController::start_recording` for the one attached to the TCP
+/// reader. Dropping the recorder flushes and closes the current file.
+pub struct StreamRecorder {
+    tap: RecorderTap,
+    writer: Option<JoinHandle<io::Result<Vec<PathBuf>>>>,
+}
+
+impl StreamRecorder {
+    /// Create `dir` if needed, open the first file and start the writer
+    /// thread. `channels` name the values of each frame, in order.
+    pub fn start(dir: &Path, channels: Vec<String>, rotation: Rotation) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let (sender, receiver) = mpsc::channel();
+        let started = Instant::now();
+        let started_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let prefix = format!("stream_{}", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+
+        let mut files = RotatingFiles {
+            dir: dir.to_path_buf(),
+            prefix,
+            header: StreamHeader {
+                version: FORMAT_VERSION,
+                channels,
+                started_unix_ms,
+                file_index: 0,
+            },
+            rotation,
+            current: None,
+            written: Vec::new(),
+        };
+        files.open_next()?;
+
+        let writer = thread::Builder::new()
+            .name("stream-recorder".into())
+            .spawn(move || files.run(receiver, started))?;
+
+        Ok(Self {
+            tap: RecorderTap { sender },
+            writer: Some(writer),
+        })
+    }
+
+    pub fn tap(&self) -> RecorderTap {
+        self.tap.clone()
+    }
+
+    /// An [`Observer`] that records every event as a marker.
+    pub fn markers(&self) -> StreamMarkers {
+        StreamMarkers { tap: self.tap() }
+    }
+
+    /// Flush and close the recording; returns every file written.
+    pub fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<Vec<PathBuf>> {
+        let _ = self.tap.sender.send(Message::Stop);
+        match self.writer.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("stream recorder thread panicked"))),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Drop for StreamRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            log::warn!("Stream recorder: {e}");
+        }
+    }
+}
+
+/// Records every event as a stream marker, labelled with the event type,
+/// so actions can be lined up with the recorded signals.
+pub struct StreamMarkers {
+    tap: RecorderTap,
+}
+
+impl Observer for StreamMarkers {
+    fn on_event(&self, event: &Event) {
+        let data = match serde_json::to_value(event) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("StreamMarkers: failed to serialize event: {e}");
+                return;
+            }
+        };
+        let label = data["type"].as_str().unwrap_or("event").to_string();
+        self.tap.mark(label, data);
+    }
+}
+
+struct OpenFile {
+    writer: BufWriter<File>,
+    bytes: u64,
+    opened: Instant,
+}
+
+struct RotatingFiles {
+    dir: PathBuf,
+    prefix: String,
+    header: StreamHeader,
+    rotation: Rotation,
+    current: Option<OpenFile>,
+    written: Vec<PathBuf>,
+}
+
+impl RotatingFiles {
+    fn run(
+        mut self,
+        receiver: mpsc::Receiver<Message>,
+        started: Instant,
+    ) -> io::Result<Vec<PathBuf>> {
+        // Every tap dropping ends the loop just like an explicit stop.
+        while let Ok(message) = receiver.recv() {
+            let record = match message {
+                Message::Frame {
+                    counter,
+                    at,
+                    values,
+                } => encode_frame(counter, at.saturating_duration_since(started), &values),
+                Message::Marker { at, label, data } => encode_marker(
+                    at.saturating_duration_since(started),
+                    &json!({ "label": label, "data": data }),
+                ),
+                Message::Stop => break,
+            };
+            if let Err(e) = self.write(&record) {
+                log::error!("Stream recorder stopped: {e}");
+                self.close()?;
+                return Err(e);
+            }
+        }
+        self.close()?;
+        log::info!(
+            "Stream recording closed: {} file(s) in {}",
+            self.written.len(),
+            self.dir.display()
+        );
+        Ok(self.written)
+    }
+
+    fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        let full = self.current.as_ref().is_some_and(|file| {
+            file.bytes >= self.rotation.max_bytes
+                || file.opened.elapsed() >= self.rotation.max_duration
+        });
+        if full {
+            self.header.file_index += 1;
+            self.open_next()?;
+        }
+        let file = self
+            .current
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no open recording file"))?;
+        file.writer.write_all(record)?;
+        file.bytes += record.len() as u64;
+        Ok(())
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        self.close()?;
+        let path = self.dir.join(format!(
+            "{}_{:03}.{EXTENSION}",
+            self.prefix, self.header.file_index
+        ));
+        let header = serde_json::to_vec(&self.header).map_err(io::Error::other)?;
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(&header)?;
+        log::info!("Recording stream to {}", path.display());
+        self.current = Some(OpenFile {
+            writer,
+            bytes: (MAGIC.len() + 4 + header.len()) as u64,
+            opened: Instant::now(),
+        });
+        self.written.push(path);
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        match self.current.take() {
+            Some(mut file) => file.writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn nanos(time: Duration) -> u64 {
+    time.as_nanos().min(u64::MAX as u128) as u64
+}
+
+fn encode_frame(counter: u64, time: Duration, values: &[f32]) -> Vec<u8> {
+    let count = values.len().min(u16::MAX as usize);
+    let mut record = Vec::with_capacity(19 + 4 * count);
+    record.push(TAG_FRAME);
+    record.extend_from_slice(&counter.to_le_bytes());
+    record.extend_from_slice(&nanos(time).to_le_bytes());
+    record.extend_from_slice(&(count as u16).to_le_bytes());
+    for value in &values[..count] {
+        record.extend_from_slice(&value.to_le_bytes());
+    }
+    record
+}
+
+fn encode_marker(time: Duration, payload: &Value) -> Vec<u8> {
+    let body = payload.to_string().into_bytes();
+    let mut record = Vec::with_capacity(13 + body.len());
+    record.push(TAG_MARKER);
+    record.extend_from_slice(&nanos(time).to_le_bytes());
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(&body);
+    record
+}
+
+/// Reads one recorded file: the header up front, then the records as an
+/// iterator.
+pub struct StreamReader {
+    reader: BufReader<File>,
+    header: StreamHeader,
+}
+
+impl StreamReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a recorded stream (bad magic)",
+            ));
+        }
+        let length = u32::from_le_bytes(read_array(&mut reader)?) as usize;
+        let mut header = vec![0u8; length];
+        reader.read_exact(&mut header)?;
+        let header: StreamHeader = serde_json::from_slice(&header)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if header.version != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported stream format version {}", header.version),
+            ));
+        }
+        Ok(Self { reader, header })
+    }
+
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    fn read_record(&mut self, tag: u8) -> io::Result<StreamRecord> {
+        match tag {
+            TAG_FRAME => {
+                let counter = u64::from_le_bytes(read_array(&mut self.reader)?);
+                let time = Duration::from_nanos(u64::from_le_bytes(read_array(&mut self.reader)?));
+                let count = u16::from_le_bytes(read_array(&mut self.reader)?);
+                let values = (0..count)
+                    .map(|_| read_array(&mut self.reader).map(f32::from_le_bytes))
+                    .collect::<io::Result<_>>()?;
+                Ok(StreamRecord::Frame {
+                    counter,
+                    time,
+                    values,
+                })
+            }
+            TAG_MARKER => {
+                let time = Duration::from_nanos(u64::from_le_bytes(read_array(&mut self.reader)?));
+                let length = u32::from_le_bytes(read_array(&mut self.reader)?) as usize;
+                let mut body = vec![0u8; length];
+                self.reader.read_exact(&mut body)?;
+                let mut payload: Value = serde_json::from_slice(&body)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(StreamRecord::Marker {
+                    time,
+                    label: payload["label"].as_str().unwrap_or_default().to_string(),
+                    data: payload["data"].take(),
+                })
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown record tag {other:#04x}"),
+            )),
+        }
+    }
+}
+
+impl Iterator for StreamReader {
+    type Item = io::Result<StreamRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tag = [0u8; 1];
+        match self.reader.read(&mut tag) {
+            Ok(0) => None,
+            Ok(_) => match self.read_record(tag[0]) {
+                // A truncated last record: the file was cut off mid-write.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                result => Some(result),
+            },
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rusty_tip_stream_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read_all(files: &[PathBuf]) -> Vec<StreamRecord> {
+        files
+            .iter()
+            .flat_map(|path| StreamReader::open(path).unwrap())
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn frames_and_markers_round_trip() {
+        let dir = temp_dir("round_trip");
+        let recorder = StreamRecorder::start(
+            &dir,
+            vec!["Current (A)".into(), "Freq. Shift (Hz)".into()],
+            Rotation {
+                max_bytes: u64::MAX,
+                max_duration: Duration::from_secs(3600),
+            },
+        )
+        .unwrap();
+        let tap = recorder.tap();
+        tap.frame(1, Instant::now(), &[1e-10, -2.5]);
+        recorder.markers().on_event(&Event::data_collected(
+            "stable_read",
+            json!({ "value": -2.5 }),
+        ));
+        tap.frame(2, Instant::now(), &[2e-10, -2.75]);
+        let files = recorder.finish().unwrap();
+
+        assert_eq!(files.len(), 1);
+        let reader = StreamReader::open(&files[0]).unwrap();
+        assert_eq!(
+            reader.header().channels,
+            ["Current (A)", "Freq. Shift (Hz)"]
+        );
+        assert_eq!(reader.header().file_index, 0);
+
+        let records = read_all(&files);
+        assert_eq!(records.len(), 3);
+        let StreamRecord::Frame {
+            counter, values, ..
+        } = &records[0]
+        else {
+            panic!("expected a frame, got {:?}", records[0]);
+        };
+        assert_eq!((*counter, values.as_slice()), (1, &[1e-10, -2.5][..]));
+        let StreamRecord::Marker { label, data, .. } = &records[1] else {
+            panic!("expected a marker, got {:?}", records[1]);
+        };
+        assert_eq!(label, "data_collected");
+        assert_eq!(data["label"], "stable_read");
+
+        let times: Vec<Duration> = records
+            .iter()
+            .map(|r| match r {
+                StreamRecord::Frame { time, .. } | StreamRecord::Marker { time, .. } => *time,
+            })
+            .collect();
+        assert!(times.windows(2).all(|w| w[0] <= w[1]), "{times:?}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_rotate_at_the_size_limit_and_keep_one_time_axis() {
+        let dir = temp_dir("rotation");
+        let recorder = StreamRecorder::start(
+            &dir,
+            vec!["Z (m)".into()],
+            Rotation {
+                max_bytes: 256,
+                max_duration: Duration::from_secs(3600),
+            },
+        )
+        .unwrap();
+        let tap = recorder.tap();
+        for counter in 1..=40 {
+            tap.frame(counter, Instant::now(), &[counter as f32]);
+        }
+        let files = recorder.finish().unwrap();
+
+        assert!(files.len() > 1, "expected rotation, got {files:?}");
+        for (index, path) in files.iter().enumerate() {
+            assert!(fs::metadata(path).unwrap().len() < 256 + 64);
+            assert_eq!(
+                StreamReader::open(path).unwrap().header().file_index,
+                index as u32
+            );
+        }
+        let counters: Vec<u64> = read_all(&files)
+            .into_iter()
+            .map(|r| match r {
+                StreamRecord::Frame { counter, .. } => counter,
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(counters, (1..=40).collect::<Vec<_>>());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_truncated_last_record_is_skipped() {
+        let dir = temp_dir("truncated");
+        let recorder = StreamRecorder::start(
+            &dir,
+            vec!["Bias (V)".into()],
+            Rotation {
+                max_bytes: u64::MAX,
+                max_duration: Duration::from_secs(3600),
+            },
+        )
+        .unwrap();
+        recorder.tap().frame(1, Instant::now(), &[0.5]);
+        recorder.tap().frame(2, Instant::now(), &[0.6]);
+        let files = recorder.finish().unwrap();
+
+        let length = fs::metadata(&files[0]).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&files[0])
+            .unwrap()
+            .set_len(length - 3)
+            .unwrap();
+        assert_eq!(read_all(&files).len(), 1);
+
+        fs::write(&files[0], b"NOTASTREAM").unwrap();
+        assert!(StreamReader::open(&files[0]).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfirmationConfig, ConfirmationPolicy};
+use crate::event::EventPayload;
+
+/// What the confirmation test concluded after a reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmationDecision {
+    /// Not decided yet: read another site.
+    Continue,
+    /// The tip is confirmed sharp.
+    Accept,
+    /// The tip is not confirmed sharp.
+    Reject,
+}
+
+/// One confirmation reading and the statistics it was judged on, as
+/// published in `confirmation` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmationStep {
+    /// Site number (1-based).
+    pub site: usize,
+    pub reading: f64,
+    /// Mean of the readings so far.
+    pub mean: f64,
+    /// Half width of the confidence interval on the mean (sequential only).
+    pub half_width: Option<f64>,
+    pub decision: ConfirmationDecision,
+}
+
+impl EventPayload for ConfirmationStep {
+    const KIND: &'static str = "confirmation";
+}
+
+/// Decides, reading by reading, whether a tip that read sharp is sharp.
+///
+/// `Fixed` rejects on the first reading outside the window and accepts
+/// after `sites` readings inside it. `Sequential` rejects on a gross outlier
+/// and otherwise puts a Student-t confidence interval (n − 1 degrees of
+/// freedom) around the mean: inside the window accepts, outside rejects,
+/// straddling reads another site. A test still undecided after `sites`
+/// readings rejects.
+pub struct SharpnessTest {
+    config: ConfirmationConfig,
+    window: (f64, f64),
+    noise_floor: f64,
+    readings: Vec<f64>,
+}
+
+impl SharpnessTest {
+    /// `noise_floor` (Hz) is the smallest standard deviation assumed for a
+    /// reading, so a few identical readings cannot make the interval vanish.
+    pub fn new(config: &ConfirmationConfig, window: (f64, f64), noise_floor: f64) -> Self {
+        Self {
+            config: config.clone(),
+            window,
+            noise_floor,
+            readings: Vec::new(),
+        }
+    }
+
+    /// Judge the next reading.
+    pub fn push(&mut self, reading: f64) -> ConfirmationStep {
+        self.readings.push(reading);
+        let n = self.readings.len();
+        let mean = self.readings.iter().sum::<f64>() / n as f64;
+        let (lo, hi) = self.window;
+
+        let (half_width, decision) = match self.config.policy {
+            ConfirmationPolicy::Fixed => {
+                let decision = if reading < lo || reading > hi {
+                    ConfirmationDecision::Reject
+                } else if n >= self.config.sites {
+                    ConfirmationDecision::Accept
+                } else {
+                    ConfirmationDecision::Continue
+                };
+                (None, decision)
+            }
+            ConfirmationPolicy::Sequential => {
+                let p = 1.0 - (1.0 - self.config.confidence) / 2.0;
+                let t = student_t_quantile(p, (n - 1).max(1));
+                let half_width = t * self.std_dev().max(self.noise_floor) / (n as f64).sqrt();
+                let outlier =
+                    reading < lo - self.config.outlier_hz || reading > hi + self.config.outlier_hz;
+                let may_decide = n >= self.config.min_sites;
+                let inside = mean - half_width >= lo && mean + half_width <= hi;
+                let outside = mean + half_width < lo || mean - half_width > hi;
+                let decision = if outlier {
+                    ConfirmationDecision::Reject
+                } else if may_decide && inside {
+                    ConfirmationDecision::Accept
+                } else if (may_decide && outside) || n >= self.config.sites {
+                    ConfirmationDecision::Reject
+                } else {
+                    ConfirmationDecision::Continue
+                };
+                (Some(half_width), decision)
+            }
+        };
+
+        ConfirmationStep {
+            site: n,
+            reading,
+            mean,
+            half_width,
+            decision,
+        }
+    }
+
+    /// Sample standard deviation of the readings (0 for fewer than two).
+    fn std_dev(&self) -> f64 {
+        let n = self.readings.len();
+        if n < 2 {
+            return 0.0;
+        }
+        let mean = self.readings.iter().sum::<f64>() / n as f64;
+        let ss: f64 = self.readings.iter().map(|r| (r - mean).powi(2)).sum();
+        (ss / (n - 1) as f64).sqrt()
+    }
+}
+
+/// Standard normal quantile for `p` in (0, 1), Abramowitz & Stegun 26.2.23
+/// (absolute error below 4.5e-4).
+fn normal_quantile(p: f64) -> f64 {
+    let tail = |q: f64| {
+        let t = (-2.0 * q.ln()).sqrt();
+        t - (2.515517 + 0.802853 * t + 0.010328 * t * t)
+            / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t)
+    };
+    if p < 0.5 { -tail(p) } else { tail(1.0 - p) }
+}
+
+/// Student-t quantile for `p` in (0, 1) with `df` degrees of freedom: exact
+/// for one and two, otherwise the Cornish-Fisher expansion around the normal
+/// quantile (Abramowitz & Stegun 26.7.5), within 5e-3 of the table from
+/// three on.
+fn student_t_quantile(p: f64, df: usize) -> f64 {
+    match df {
+        1 => (std::f64::consts::PI * (p - 0.5)).tan(),
+        2 => (2.0 * p - 1.0) / (2.0 * p * (1.0 - p)).sqrt(),
+        _ => {
+            let x = normal_quantile(p);
+            let v = df as f64;
+            let x2 = x * x;
+            let g1 = x * (x2 + 1.0) / 4.0;
+            let g2 = x * ((5.0 * x2 + 16.0) * x2 + 3.0) / 96.0;
+            let g3 = x * (((3.0 * x2 + 19.0) * x2 + 17.0) * x2 - 15.0) / 384.0;
+            let g4 =
+                x * ((((79.0 * x2 + 776.0) * x2 + 1482.0) * x2 - 1920.0) * x2 - 945.0) / 92160.0;
+            x + g1 / v + g2 / v.powi(2) + g3 / v.powi(3) + g4 / v.powi(4)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequential() -> ConfirmationConfig {
+        ConfirmationConfig {
+            policy: ConfirmationPolicy::Sequential,
+            sites: 5,
+            min_sites: 2,
+            confidence: 0.95,
+            outlier_hz: 3.0,
+        }
+    }
+
+    fn decisions(test: &mut SharpnessTest, readings: &[f64]) -> Vec<ConfirmationDecision> {
+        readings.iter().map(|&r| test.push(r).decision).collect()
+    }
+
+    #[test]
+    fn normal_quantile_matches_the_table() {
+        assert!((normal_quantile(0.975) - 1.96).abs() < 1e-3);
+        assert!((normal_quantile(0.5)).abs() < 1e-3);
+        assert!((normal_quantile(0.05) + 1.645).abs() < 1e-3);
+    }
+
+    #[test]
+    fn student_t_quantile_matches_the_table() {
+        for (df, t) in [
+            (1, 12.706),
+            (2, 4.303),
+            (3, 3.182),
+            (4, 2.776),
+            (9, 2.262),
+            (30, 2.042),
+        ] {
+            let q = student_t_quantile(0.975, df);
+            assert!((q - t).abs() < 5e-3, "df {df}: {q} vs {t}");
+        }
+        assert!((student_t_quantile(0.025, 4) + 2.776).abs() < 2e-3);
+    }
+
+    #[test]
+    fn fixed_needs_every_site_in_the_window() {
+        let config = ConfirmationConfig::default();
+        let mut test = SharpnessTest::new(&config, (-2.0, 0.0), 0.1);
+        use ConfirmationDecision::*;
+        assert_eq!(
+            decisions(&mut test, &[-1.0, -1.0, -1.0]),
+            [Continue, Continue, Accept]
+        );
+
+        let mut test = SharpnessTest::new(&config, (-2.0, 0.0), 0.1);
+        assert_eq!(decisions(&mut test, &[-1.0, -2.1]), [Continue, Reject]);
+    }
+
+    #[test]
+    fn sequential_accepts_early_on_consistent_readings() {
+        let mut test = SharpnessTest::new(&sequential(), (-2.0, 0.0), 0.1);
+        use ConfirmationDecision::*;
+        assert_eq!(decisions(&mut test, &[-1.0, -1.05]), [Continue, Accept]);
+    }
+
+    #[test]
+    fn sequential_does_not_accept_two_readings_near_an_edge() {
+        // A normal interval (±0.14) would accept these; with one degree of
+        // freedom the interval (±0.9) still straddles the edge.
+        let mut test = SharpnessTest::new(&sequential(), (-2.0, 0.0), 0.1);
+        use ConfirmationDecision::*;
+        assert_eq!(decisions(&mut test, &[-1.5, -1.6]), [Continue, Continue]);
+    }
+
+    #[test]
+    fn sequential_rejects_a_gross_outlier_at_once() {
+        let mut test = SharpnessTest::new(&sequential(), (-2.0, 0.0), 0.1);
+        assert_eq!(test.push(-6.0).decision, ConfirmationDecision::Reject);
+    }
+
+    #[test]
+    fn sequential_reads_on_near_the_edge_and_gives_up_at_the_limit() {
+        let mut test = SharpnessTest::new(&sequential(), (-2.0, 0.0), 0.3);
+        use ConfirmationDecision::*;
+        // Centred on the edge: the interval keeps straddling it.
+        assert_eq!(
+            decisions(&mut test, &[-1.9, -2.1, -1.95, -2.05, -2.0]),
+            [Continue, Continue, Continue, Continue, Reject]
+        );
+    }
+
+    #[test]
+    fn sequential_rejects_readings_clearly_outside() {
+        let mut test = SharpnessTest::new(&sequential(), (-2.0, 0.0), 0.1);
+        use ConfirmationDecision::*;
+        assert_eq!(decisions(&mut test, &[-3.0, -3.1]), [Continue, Reject]);
+    }
+}
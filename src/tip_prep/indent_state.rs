@@ -0,0 +1,108 @@
+use crate::action::tip_shaper::TipShaperParams;
+use crate::config::{ConditioningConfig, ConditioningMode};
+
+/// Mutable state for indentation conditioning across cycles.
+pub struct IndentState {
+    /// Depth (m, magnitude) of the next indentation
+    pub current_depth_m: f64,
+    /// Indentations performed
+    pub indent_count: u32,
+    /// Depth steps taken below the minimum depth
+    depth_step: u16,
+    /// Consecutive indents at the current depth that left the tip blunt
+    indents_at_depth: usize,
+    /// Consecutive pulses that left the tip blunt (mixed mode)
+    blunt_pulses: u32,
+}
+
+impl IndentState {
+    pub fn new(config: &ConditioningConfig) -> Self {
+        Self {
+            current_depth_m: config.depth_bounds_m.0,
+            indent_count: 0,
+            depth_step: 0,
+            indents_at_depth: 0,
+            blunt_pulses: 0,
+        }
+    }
+
+    /// Whether the next cycle indents instead of pulsing.
+    pub fn should_indent(&self, config: &ConditioningConfig) -> bool {
+        match config.mode {
+            ConditioningMode::Pulse => false,
+            ConditioningMode::Indent => true,
+            ConditioningMode::Mixed => self.blunt_pulses >= config.pulses_before_indent,
+        }
+    }
+
+    /// Tip-shaper parameters for the next indentation: down by the current
+    /// depth at `bias_v`, then lift at `lift_bias_v` and restore feedback.
+    pub fn shaper_params(&self, config: &ConditioningConfig) -> TipShaperParams {
+        TipShaperParams {
+            change_bias: true,
+            bias_v: config.bias_v,
+            tip_lift_m: -self.current_depth_m,
+            lift_time_1_ms: config.indent_time_ms,
+            bias_lift_v: config.lift_bias_v,
+            lift_height_m: config.lift_height_m,
+            lift_time_2_ms: config.lift_time_ms,
+            restore_feedback: true,
+            ..Default::default()
+        }
+    }
+
+    /// Record the reading after a pulse; mixed mode counts blunt ones.
+    pub fn record_pulse(&mut self, sharp: bool) {
+        self.blunt_pulses = if sharp { 0 } else { self.blunt_pulses + 1 };
+    }
+
+    /// Record the reading after an indentation. Mixed mode goes back to
+    /// pulsing; blunt indents step the depth after `indents_before_step`.
+    pub fn record_indent(&mut self, config: &ConditioningConfig, sharp: bool) {
+        self.indent_count += 1;
+        self.blunt_pulses = 0;
+
+        if sharp {
+            self.indents_at_depth = 0;
+            return;
+        }
+
+        self.indents_at_depth += 1;
+        if self.indents_at_depth >= config.indents_before_step as usize {
+            self.indents_at_depth = 0;
+            if self.depth_step < config.depth_steps {
+                self.depth_step += 1;
+                let (min, max) = config.depth_bounds_m;
+                let step_size = (max - min) / config.depth_steps as f64;
+                let new_depth = (min + step_size * self.depth_step as f64).min(max);
+                log::info!(
+                    "Stepping indentation depth: {:.2} nm -> {:.2} nm",
+                    self.current_depth_m * 1e9,
+                    new_depth * 1e9
+                );
+                self.current_depth_m = new_depth;
+            } else {
+                log::debug!(
+                    "Indentation depth already at maximum: {:.2} nm",
+                    config.depth_bounds_m.1 * 1e9
+                );
+            }
+        }
+    }
+
+    /// Jump to the deepest indentation, for the recovery after a failed
+    /// stability check.
+    pub fn go_to_max_depth(&mut self, config: &ConditioningConfig) {
+        self.current_depth_m = config.depth_bounds_m.1;
+        self.depth_step = config.depth_steps;
+        self.indents_at_depth = 0;
+    }
+
+    /// Reset after stability failure -- back to the shallowest depth.
+    pub fn reset(&mut self, config: &ConditioningConfig) {
+        self.current_depth_m = config.depth_bounds_m.0;
+        self.depth_step = 0;
+        self.indents_at_depth = 0;
+        self.blunt_pulses = 0;
+    }
+}
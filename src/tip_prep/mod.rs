@@ -1,5 +1,24 @@
+pub mod confirmation;
+pub mod indent_state;
+pub mod optimise;
+pub mod pulse_learner;
 pub mod pulse_state;
 pub mod runner;
+pub mod simulate;
+pub mod site_map;
+pub mod tip_change;
 
+pub use confirmation::{ConfirmationDecision, ConfirmationStep, SharpnessTest};
+pub use indent_state::IndentState;
+pub use optimise::{Candidate, Optimisation, OptimiseSpec, ParameterRange, optimise};
+pub use pulse_learner::{PulseArm, PulseArmStats, PulseLearner};
 pub use pulse_state::PulseState;
-pub use runner::{Outcome, TipPrep, TipPrepParams, TipPrepSnapshot, run_tip_prep};
+pub use runner::{
+    Outcome, StabilityCheckRecord, TipPrep, TipPrepParams, TipPrepSnapshot, TipPrepState,
+    run_tip_prep,
+};
+pub use simulate::{
+    Distribution, SimulatedRun, SimulationSpec, SimulationSummary, simulate, simulation_config,
+};
+pub use site_map::{Site, SiteMap, SitePlanner, SiteRecord};
+pub use tip_change::{SweepSample, TipChangeDetector, TipChangeEvent, TipChangeKind};
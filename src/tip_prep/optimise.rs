@@ -0,0 +1,469 @@
+//! Search for the config that gets a tip sharp fastest in simulation.
+//!
+//! Numeric config fields given with a range are varied, and every candidate
+//! is scored by [`simulate`] on the same seeds: the objective is its
+//! [expected time to a sharp tip](SimulationSummary::expected_time_to_sharp_secs),
+//! which with the stability check on is a sharp *and stable* tip. The search
+//! samples the ranges at random first, then refines around the best
+//! candidate so far with shrinking steps.
+//!
+//! The sharp window the routine aims for (`tip_prep.sharp_tip_bounds`) can
+//! be varied, but success stays defined by the starting config's window: a
+//! candidate's run only counts if its final reading lands there (see
+//! [`SimulationSpec::acceptance_window`]). The other fields that define
+//! success (the stability and image acceptance thresholds, the confirmation
+//! rule) cannot be varied: loosening them would score best by redefining
+//! "sharp".
+
+use std::str::FromStr;
+
+use config::ConfigError;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::AppConfig;
+use crate::utils::Rng;
+
+use super::simulate::{SimulationSpec, SimulationSummary, simulate};
+
+/// Config paths that decide whether a run succeeded; a range on any of
+/// them, or on a field below them, is refused.
+const SUCCESS_CRITERIA: &[&str] = &[
+    "tip_prep.signal",
+    "tip_prep.stability.check_stability",
+    "tip_prep.stability.stable_tip_allowed_change",
+    "tip_prep.image_check.max_double_tip_score",
+    "tip_prep.image_check.max_streak_fraction",
+    "tip_prep.image_check.max_correlation_length_px",
+    "tip_prep.image_check.streak_sigma",
+    "tip_prep.confirmation",
+];
+
+/// A config field to vary and its range, parsed from `PATH=LOW:HIGH`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParameterRange {
+    /// Dotted path into the config, array elements by index:
+    /// `pulse_method.voltage`, `tip_prep.stability.bias_range.0`.
+    pub path: String,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl FromStr for ParameterRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let malformed = || format!("expected PATH=LOW:HIGH, got '{s}'");
+        let (path, bounds) = s.split_once('=').ok_or_else(malformed)?;
+        let (low, high) = bounds.split_once(':').ok_or_else(malformed)?;
+        let bound = |v: &str| {
+            v.trim()
+                .parse::<f64>()
+                .map_err(|e| format!("bad bound '{v}' in '{s}': {e}"))
+        };
+        let range = Self {
+            path: path.trim().to_string(),
+            low: bound(low)?,
+            high: bound(high)?,
+        };
+        if range.path.is_empty() {
+            return Err(malformed());
+        }
+        if !range.low.is_finite() || !range.high.is_finite() || range.low >= range.high {
+            return Err(format!("'{s}': LOW must be below HIGH"));
+        }
+        range.check_path()?;
+        Ok(range)
+    }
+}
+
+impl ParameterRange {
+    /// Refuse paths that are part of the success criterion.
+    fn check_path(&self) -> Result<(), String> {
+        let criterion = SUCCESS_CRITERIA.iter().find(|c| {
+            self.path == **c
+                || self
+                    .path
+                    .strip_prefix(**c)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        });
+        match criterion {
+            Some(c) => Err(format!(
+                "'{}' is part of the success criterion ({c}); varying it would \
+                 redefine what counts as sharp",
+                self.path
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How long to search and how each candidate is simulated.
+#[derive(Debug, Clone)]
+pub struct OptimiseSpec {
+    /// Runs per candidate; every candidate sees the same seeds.
+    pub simulation: SimulationSpec,
+    /// Candidates to score, the starting config included.
+    pub candidates: usize,
+    /// Share of the candidates drawn at random before refining.
+    pub explore_fraction: f64,
+    /// Seed of the search itself (the tips come from `simulation`).
+    pub seed: u64,
+}
+
+impl Default for OptimiseSpec {
+    fn default() -> Self {
+        Self {
+            simulation: SimulationSpec {
+                runs: 50,
+                ..Default::default()
+            },
+            candidates: 40,
+            explore_fraction: 0.5,
+            seed: 1,
+        }
+    }
+}
+
+/// One config the search tried.
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    /// Position in the search; 0 is the starting config.
+    pub index: usize,
+    /// Value of every varied field, in the order of the ranges.
+    pub values: Vec<f64>,
+    /// Why the candidate was not simulated (it failed validation).
+    pub rejected: Option<String>,
+    pub summary: Option<SimulationSummary>,
+    #[serde(skip)]
+    pub config: Option<AppConfig>,
+}
+
+impl Candidate {
+    /// Expected time to a sharp tip (s); infinite if no run completed or
+    /// the candidate was rejected.
+    pub fn score(&self) -> f64 {
+        self.summary
+            .as_ref()
+            .and_then(|s| s.expected_time_to_sharp_secs)
+            .unwrap_or(f64::INFINITY)
+    }
+}
+
+/// Every candidate tried and the best of them.
+#[derive(Debug, Clone, Serialize)]
+pub struct Optimisation {
+    pub ranges: Vec<ParameterRange>,
+    /// Index of the best candidate in `candidates`.
+    pub best: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Optimisation {
+    pub fn best(&self) -> &Candidate {
+        &self.candidates[self.best]
+    }
+
+    /// The starting config's candidate.
+    pub fn baseline(&self) -> &Candidate {
+        &self.candidates[0]
+    }
+}
+
+/// Search `ranges` around `base`. `on_candidate` sees every candidate as
+/// soon as it is scored. If a range varies the sharp window, every candidate
+/// is judged against `base`'s window unless `spec` sets another.
+///
+/// Fails if a range does not name a numeric field of `base`, or names a
+/// success criterion.
+pub fn optimise(
+    base: &AppConfig,
+    ranges: &[ParameterRange],
+    spec: &OptimiseSpec,
+    mut on_candidate: impl FnMut(&Candidate),
+) -> Result<Optimisation, ConfigError> {
+    if ranges.is_empty() {
+        return Err(ConfigError::Message(
+            "optimise: no parameter ranges given".to_string(),
+        ));
+    }
+    let document = serde_json::to_value(base).map_err(|e| ConfigError::Message(e.to_string()))?;
+    let mut start = Vec::with_capacity(ranges.len());
+    let mut integer = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        range
+            .check_path()
+            .map_err(|e| ConfigError::Message(format!("optimise: {e}")))?;
+        match field(&document, &range.path) {
+            Some(Value::Number(n)) => {
+                start.push(n.as_f64().unwrap_or(range.low));
+                integer.push(!n.is_f64());
+            }
+            _ => {
+                return Err(ConfigError::Message(format!(
+                    "optimise: '{}' is not a numeric field of this config",
+                    range.path
+                )));
+            }
+        }
+    }
+
+    let mut simulation = spec.simulation.clone();
+    if ranges
+        .iter()
+        .any(|r| r.path.starts_with("tip_prep.sharp_tip_bounds"))
+    {
+        simulation.acceptance_window = simulation
+            .acceptance_window
+            .or(Some(base.tip_prep.sharp_tip_bounds));
+    }
+
+    let mut rng = Rng::new(spec.seed);
+    let explore = (spec.candidates as f64 * spec.explore_fraction).round() as usize;
+    let mut candidates: Vec<Candidate> = Vec::with_capacity(spec.candidates);
+    let mut best = 0;
+
+    for index in 0..spec.candidates.max(1) {
+        let values: Vec<f64> = if index == 0 {
+            start.clone()
+        } else if index <= explore {
+            ranges
+                .iter()
+                .map(|r| r.low + rng.uniform() * (r.high - r.low))
+                .collect()
+        } else {
+            // Steps shrink from 20 % to 2 % of each range over the refinement.
+            let progress = (index - explore) as f64 / (spec.candidates - explore).max(1) as f64;
+            let scale = 0.2 - 0.18 * progress;
+            ranges
+                .iter()
+                .zip(&candidates[best].values)
+                .map(|(r, &v)| (v + rng.normal() * scale * (r.high - r.low)).clamp(r.low, r.high))
+                .collect()
+        };
+        let values: Vec<f64> = values
+            .iter()
+            .zip(&integer)
+            .map(|(&v, &int)| if int { v.round() } else { v })
+            .collect();
+
+        let candidate = match candidate_config(&document, ranges, &values) {
+            Ok(config) => Candidate {
+                index,
+                summary: Some(SimulationSummary::of(
+                    format!("candidate {index}"),
+                    &simulate(&config, &simulation),
+                )),
+                values,
+                rejected: None,
+                config: Some(config),
+            },
+            Err(reason) => Candidate {
+                index,
+                values,
+                rejected: Some(reason),
+                summary: None,
+                config: None,
+            },
+        };
+        on_candidate(&candidate);
+        if candidate.score() < candidates.get(best).map_or(f64::INFINITY, Candidate::score) {
+            best = index;
+        }
+        candidates.push(candidate);
+    }
+
+    Ok(Optimisation {
+        ranges: ranges.to_vec(),
+        best,
+        candidates,
+    })
+}
+
+/// `document` with the varied fields set to `values`, as a validated config.
+fn candidate_config(
+    document: &Value,
+    ranges: &[ParameterRange],
+    values: &[f64],
+) -> Result<AppConfig, String> {
+    let mut document = document.clone();
+    for (range, &value) in ranges.iter().zip(values) {
+        let slot = field_mut(&mut document, &range.path)
+            .ok_or_else(|| format!("'{}' vanished from the config", range.path))?;
+        *slot = match slot.as_number() {
+            Some(n) if !n.is_f64() => Value::from(value as i64),
+            _ => Value::from(value),
+        };
+    }
+    let config: AppConfig = serde_json::from_value(document).map_err(|e| e.to_string())?;
+    config.validate().map_err(|e| e.to_string())?;
+    Ok(config)
+}
+
+fn field<'a>(document: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(document, |value, key| match value {
+            Value::Object(map) => map.get(key),
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+fn field_mut<'a>(document: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    path.split('.')
+        .try_fold(document, |value, key| match value {
+            Value::Object(map) => map.get_mut(key),
+            Value::Array(items) => items.get_mut(key.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_parse_from_the_command_line_form() {
+        let range: ParameterRange = "tip_prep.stability.bias_range.0 = 0.1 : 1.5"
+            .parse()
+            .unwrap();
+        assert_eq!(range.path, "tip_prep.stability.bias_range.0");
+        assert_eq!((range.low, range.high), (0.1, 1.5));
+
+        assert!("pulse_method.voltage".parse::<ParameterRange>().is_err());
+        assert!(
+            "pulse_method.voltage=8:2"
+                .parse::<ParameterRange>()
+                .is_err()
+        );
+        assert!(
+            "pulse_method.voltage=a:2"
+                .parse::<ParameterRange>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn success_criteria_cannot_be_varied() {
+        for path in [
+            "tip_prep.signal=0:1",
+            "tip_prep.stability.stable_tip_allowed_change=0.1:1",
+            "tip_prep.confirmation.confidence=0.5:0.9",
+            "tip_prep.image_check.max_double_tip_score=0.1:0.9",
+        ] {
+            let err = path.parse::<ParameterRange>().unwrap_err();
+            assert!(err.contains("success criterion"), "{path}: {err}");
+        }
+        // A sibling with a matching prefix is not a criterion.
+        assert!(
+            "tip_prep.stability.bias_range.1=1:3"
+                .parse::<ParameterRange>()
+                .is_ok()
+        );
+
+        let constructed = ParameterRange {
+            path: "tip_prep.confirmation.sites".to_string(),
+            low: 1.0,
+            high: 5.0,
+        };
+        let err = optimise(
+            &AppConfig::default(),
+            &[constructed],
+            &OptimiseSpec::default(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("success criterion"), "{err}");
+    }
+
+    #[test]
+    fn fields_are_set_by_path_and_keep_their_type() {
+        let base = AppConfig::default();
+        let document = serde_json::to_value(&base).unwrap();
+        let ranges: Vec<ParameterRange> = [
+            "tip_prep.timing.post_pulse_settle_ms=0:1000",
+            "tip_prep.stability.bias_range.0=0.01:5",
+        ]
+        .iter()
+        .map(|r| r.parse().unwrap())
+        .collect();
+
+        let config = candidate_config(&document, &ranges, &[250.0, 0.5]).unwrap();
+        assert_eq!(config.tip_prep.timing.post_pulse_settle_ms, 250);
+        assert_eq!(config.tip_prep.stability.bias_range.0, 0.5);
+
+        // A lower bound above the upper one fails validation.
+        assert!(candidate_config(&document, &ranges, &[250.0, 5.0]).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_refused_up_front() {
+        let ranges = vec!["tip_prep.no_such_field=0:1".parse().unwrap()];
+        let err = optimise(
+            &AppConfig::default(),
+            &ranges,
+            &OptimiseSpec::default(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("no_such_field"), "{err}");
+    }
+
+    #[test]
+    fn the_best_candidate_is_no_worse_than_the_start() {
+        let mut base = AppConfig::default();
+        base.tip_prep.sharp_tip_bounds = [-14.0, -9.0];
+        base.tip_prep.stability.check_stability = false;
+        let ranges = vec!["pulse_method.voltage_bounds.1=3:9".parse().unwrap()];
+        let spec = OptimiseSpec {
+            simulation: SimulationSpec {
+                runs: 4,
+                max_cycles: 50,
+                ..Default::default()
+            },
+            candidates: 6,
+            ..Default::default()
+        };
+
+        let mut seen = 0;
+        let result = optimise(&base, &ranges, &spec, |_| seen += 1).unwrap();
+        assert_eq!(seen, 6);
+        assert_eq!(result.baseline().values, [6.0]);
+        assert!(result.best().score() <= result.baseline().score());
+        assert!(result.best().config.is_some());
+        assert!(
+            result
+                .candidates
+                .iter()
+                .all(|c| (3.0..=9.0).contains(&c.values[0]) || c.index == 0)
+        );
+    }
+
+    #[test]
+    fn a_varied_window_is_scored_against_the_starting_one() {
+        let mut base = AppConfig::default();
+        base.tip_prep.sharp_tip_bounds = [-14.0, -9.0];
+        base.tip_prep.stability.check_stability = false;
+        let ranges = vec!["tip_prep.sharp_tip_bounds.0=-30:-14".parse().unwrap()];
+        let spec = OptimiseSpec {
+            simulation: SimulationSpec {
+                runs: 4,
+                max_cycles: 50,
+                ..Default::default()
+            },
+            candidates: 4,
+            ..Default::default()
+        };
+
+        let result = optimise(&base, &ranges, &spec, |_| {}).unwrap();
+        for candidate in &result.candidates {
+            let config = candidate.config.as_ref().unwrap();
+            assert_eq!(config.tip_prep.sharp_tip_bounds[0], candidate.values[0]);
+            let summary = candidate.summary.as_ref().unwrap();
+            assert!(summary.sharp <= summary.completed, "{summary:?}");
+        }
+        // The baseline aims for the acceptance window itself.
+        let baseline = result.baseline().summary.as_ref().unwrap();
+        assert_eq!(baseline.sharp, baseline.completed);
+    }
+}
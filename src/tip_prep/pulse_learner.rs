@@ -0,0 +1,363 @@
+use serde::Serialize;
+
+use crate::controller_types::PolaritySign;
+
+/// Share of the reward a pulse earns for moving the tip toward the sharp
+/// window without landing in it. Landing earns 1.
+const PROGRESS_REWARD: f64 = 0.5;
+
+/// One pulse the learner can choose: a voltage magnitude, a polarity and,
+/// if the width is learned too, a width.
+#[derive(Debug, Clone, Serialize)]
+pub struct PulseArm {
+    pub voltage: f64,
+    pub polarity: PolaritySign,
+    /// Pulse width (ms); `None` if the learner does not choose the width.
+    pub width_ms: Option<u64>,
+    /// Pulses fired with this arm.
+    pub pulses: usize,
+    /// Pulses after which the tip read inside the sharp window.
+    pub sharp_hits: usize,
+    /// Discounted pulse count; recent pulses weigh more.
+    pub weight: f64,
+    /// Discounted reward sum.
+    pub reward: f64,
+}
+
+impl PulseArm {
+    /// Discounted mean reward, `None` before the first pulse.
+    pub fn mean_reward(&self) -> Option<f64> {
+        (self.weight > 0.0).then(|| self.reward / self.weight)
+    }
+}
+
+/// Arm statistics as published in `pulse_arms` events.
+#[derive(Debug, Clone, Serialize)]
+pub struct PulseArmStats {
+    pub voltage: f64,
+    pub polarity: PolaritySign,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width_ms: Option<u64>,
+    pub pulses: usize,
+    pub sharp_hits: usize,
+    pub mean_reward: Option<f64>,
+    /// Upper confidence bound the next choice was made on.
+    pub score: f64,
+    /// Whether this arm fires next.
+    pub selected: bool,
+}
+
+/// Learns, within a run, which pulse lands the tip in the sharp window.
+///
+/// A discounted UCB bandit: every arm keeps a discounted count and reward
+/// sum, the next arm is the one with the highest mean reward plus an
+/// exploration bonus, and untried arms go first (shortest width, then
+/// lowest voltage first).
+/// Discounting lets the learner follow a tip whose response changes over
+/// the run.
+///
+/// A pulse earns 1 if the next reading is inside the sharp window, and up
+/// to 0.5 for the fraction of the distance to the window it closed.
+/// Without a window, the distance is to zero shift.
+pub struct PulseLearner {
+    arms: Vec<PulseArm>,
+    exploration: f64,
+    discount: f64,
+    window: Option<(f64, f64)>,
+    /// Arm of the pulse about to fire / just fired.
+    current: usize,
+    /// Reading before the current pulse.
+    last: Option<f64>,
+}
+
+impl PulseLearner {
+    /// One arm per polarity, width and voltage level. With no `widths_ms`
+    /// the width is left to the caller and the arms carry none.
+    pub fn new(
+        voltage_bounds: (f64, f64),
+        voltage_levels: u16,
+        polarities: &[PolaritySign],
+        widths_ms: &[u64],
+        exploration: f64,
+        discount: f64,
+    ) -> Self {
+        let levels = voltage_levels.max(1) as usize;
+        let step = if levels > 1 {
+            (voltage_bounds.1 - voltage_bounds.0) / (levels - 1) as f64
+        } else {
+            0.0
+        };
+        let widths: Vec<Option<u64>> = if widths_ms.is_empty() {
+            vec![None]
+        } else {
+            widths_ms.iter().copied().map(Some).collect()
+        };
+        let arms = polarities
+            .iter()
+            .flat_map(|&polarity| widths.iter().map(move |&width_ms| (polarity, width_ms)))
+            .flat_map(|(polarity, width_ms)| {
+                (0..levels).map(move |i| PulseArm {
+                    voltage: voltage_bounds.0 + step * i as f64,
+                    polarity,
+                    width_ms,
+                    pulses: 0,
+                    sharp_hits: 0,
+                    weight: 0.0,
+                    reward: 0.0,
+                })
+            })
+            .collect();
+        Self {
+            arms,
+            exploration,
+            discount,
+            window: None,
+            current: 0,
+            last: None,
+        }
+    }
+
+    /// The freq-shift window (Hz) that counts as sharp.
+    pub fn set_window(&mut self, window: (f64, f64)) {
+        self.window = Some(window);
+    }
+
+    /// The arm the next pulse fires with.
+    pub fn current(&self) -> &PulseArm {
+        &self.arms[self.current]
+    }
+
+    pub fn arms(&self) -> &[PulseArm] {
+        &self.arms
+    }
+
+    /// Forget the last reading, e.g. after a pulse the learner did not
+    /// choose reshaped the tip. The arm statistics are kept.
+    pub fn forget_last(&mut self) {
+        self.last = None;
+    }
+
+    /// Credit the current arm with the reading taken after its pulse, then
+    /// choose the next arm.
+    pub fn observe(&mut self, freq_shift: f64) {
+        let hit = self.distance(freq_shift) == 0.0;
+        let reward = if hit {
+            1.0
+        } else {
+            match self.last.map(|before| self.distance(before)) {
+                Some(before) if before > 0.0 => {
+                    let closed = (before - self.distance(freq_shift)) / before;
+                    PROGRESS_REWARD * closed.clamp(0.0, 1.0)
+                }
+                _ => 0.0,
+            }
+        };
+
+        for arm in &mut self.arms {
+            arm.weight *= self.discount;
+            arm.reward *= self.discount;
+        }
+        let arm = &mut self.arms[self.current];
+        arm.pulses += 1;
+        arm.sharp_hits += hit as usize;
+        arm.weight += 1.0;
+        arm.reward += reward;
+        log::debug!(
+            "Pulse arm {:.2} V {:?}{}: reward {:.2} (freq_shift {:.3} Hz)",
+            arm.voltage,
+            arm.polarity,
+            arm.width_ms.map(|w| format!(" {w} ms")).unwrap_or_default(),
+            reward,
+            freq_shift
+        );
+
+        self.last = Some(freq_shift);
+        self.current = self.select();
+    }
+
+    /// Per-arm statistics, with the scores of the current choice.
+    pub fn stats(&self) -> Vec<PulseArmStats> {
+        let total = self.total_weight();
+        self.arms
+            .iter()
+            .enumerate()
+            .map(|(i, arm)| PulseArmStats {
+                voltage: arm.voltage,
+                polarity: arm.polarity,
+                width_ms: arm.width_ms,
+                pulses: arm.pulses,
+                sharp_hits: arm.sharp_hits,
+                mean_reward: arm.mean_reward(),
+                score: self.score(arm, total),
+                selected: i == self.current,
+            })
+            .collect()
+    }
+
+    fn distance(&self, freq_shift: f64) -> f64 {
+        match self.window {
+            Some((low, _)) if freq_shift < low => low - freq_shift,
+            Some((_, high)) if freq_shift > high => freq_shift - high,
+            Some(_) => 0.0,
+            None => freq_shift.abs(),
+        }
+    }
+
+    fn total_weight(&self) -> f64 {
+        self.arms.iter().map(|a| a.weight).sum()
+    }
+
+    fn score(&self, arm: &PulseArm, total: f64) -> f64 {
+        match arm.mean_reward() {
+            Some(mean) => {
+                mean + self.exploration * (total.max(1.0).ln().max(0.0) / arm.weight).sqrt()
+            }
+            None => f64::INFINITY,
+        }
+    }
+
+    /// Highest score wins; ties go to the earlier (gentler) arm.
+    fn select(&self) -> usize {
+        let total = self.total_weight();
+        let mut best = 0;
+        let mut best_score = f64::NEG_INFINITY;
+        for (i, arm) in self.arms.iter().enumerate() {
+            let score = self.score(arm, total);
+            if score > best_score {
+                best = i;
+                best_score = score;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn learner() -> PulseLearner {
+        let mut learner =
+            PulseLearner::new((2.0, 6.0), 3, &[PolaritySign::Positive], &[], 0.3, 1.0);
+        learner.set_window((-2.0, 0.0));
+        learner
+    }
+
+    #[test]
+    fn arms_span_the_voltage_bounds_for_each_polarity() {
+        let learner = PulseLearner::new(
+            (2.0, 6.0),
+            3,
+            &[PolaritySign::Positive, PolaritySign::Negative],
+            &[],
+            1.0,
+            1.0,
+        );
+        let arms: Vec<_> = learner
+            .arms()
+            .iter()
+            .map(|a| (a.voltage, a.polarity))
+            .collect();
+        assert_eq!(
+            arms,
+            vec![
+                (2.0, PolaritySign::Positive),
+                (4.0, PolaritySign::Positive),
+                (6.0, PolaritySign::Positive),
+                (2.0, PolaritySign::Negative),
+                (4.0, PolaritySign::Negative),
+                (6.0, PolaritySign::Negative),
+            ]
+        );
+    }
+
+    #[test]
+    fn widths_are_an_arm_dimension_gentlest_first() {
+        let mut learner = PulseLearner::new(
+            (2.0, 4.0),
+            2,
+            &[PolaritySign::Positive],
+            &[50, 200],
+            0.3,
+            1.0,
+        );
+        learner.set_window((-2.0, 0.0));
+        let arms: Vec<_> = learner
+            .arms()
+            .iter()
+            .map(|a| (a.voltage, a.width_ms))
+            .collect();
+        assert_eq!(
+            arms,
+            vec![
+                (2.0, Some(50)),
+                (4.0, Some(50)),
+                (2.0, Some(200)),
+                (4.0, Some(200)),
+            ]
+        );
+
+        // Only long 2 V pulses land the tip in the window.
+        for _ in 0..30 {
+            let arm = learner.current();
+            let sharp = arm.voltage == 2.0 && arm.width_ms == Some(200);
+            learner.observe(if sharp { -1.0 } else { -30.0 });
+        }
+        let stats = learner.stats();
+        assert!(stats[2].selected, "{stats:?}");
+        assert_eq!(stats[2].width_ms, Some(200));
+        assert!(stats[2].sharp_hits > stats.iter().map(|s| s.pulses).sum::<usize>() / 2);
+    }
+
+    #[test]
+    fn every_arm_is_tried_before_any_is_repeated() {
+        let mut learner = learner();
+        let mut tried = Vec::new();
+        for _ in 0..3 {
+            tried.push(learner.current().voltage);
+            learner.observe(-30.0);
+        }
+        assert_eq!(tried, vec![2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn the_arm_that_lands_in_the_window_is_preferred() {
+        let mut learner = learner();
+        // Only 4 V pulses land the tip in the window; the rest leave it blunt.
+        let mut fired = Vec::new();
+        for _ in 0..30 {
+            let voltage = learner.current().voltage;
+            fired.push(voltage);
+            learner.observe(if voltage == 4.0 { -1.0 } else { -30.0 });
+        }
+        let late_fours = fired[15..].iter().filter(|&&v| v == 4.0).count();
+        assert!(late_fours >= 12, "fired {fired:?}");
+
+        let stats = learner.stats();
+        assert_eq!(stats[1].sharp_hits, stats[1].pulses);
+        assert!(stats[1].selected);
+    }
+
+    #[test]
+    fn progress_toward_the_window_earns_partial_reward() {
+        let mut learner = learner();
+        learner.observe(-20.0); // 2 V, no previous reading
+        learner.observe(-11.0); // 4 V closes half the distance
+        let arms = learner.arms();
+        assert_eq!(arms[0].mean_reward(), Some(0.0));
+        assert_eq!(arms[1].mean_reward(), Some(0.25));
+        assert_eq!(arms[1].sharp_hits, 0);
+    }
+
+    #[test]
+    fn discounting_fades_old_results() {
+        let mut learner =
+            PulseLearner::new((2.0, 2.0), 1, &[PolaritySign::Positive], &[], 0.0, 0.5);
+        learner.set_window((-2.0, 0.0));
+        learner.observe(-1.0);
+        learner.observe(-30.0);
+        let arm = &learner.arms()[0];
+        // Hit weighted 0.5, miss weighted 1: mean reward 0.5 / 1.5.
+        assert!((arm.mean_reward().unwrap() - 1.0 / 3.0).abs() < 1e-12);
+    }
+}
@@ -1,6 +1,10 @@
 use std::collections::VecDeque;
 
-use crate::controller_types::{PolaritySign, PulseMethod, RandomPolaritySwitch};
+use crate::controller_types::{
+    PolaritySign, PulseMethod, PulseWidthStepping, RandomPolaritySwitch,
+};
+
+use super::pulse_learner::{PulseArmStats, PulseLearner};
 
 /// Mutable state for pulse voltage strategies that evolve across cycles.
 pub struct PulseState {
@@ -15,6 +19,19 @@ pub struct PulseState {
     pub pulse_count: u32,
     /// Random polarity switch config (cloned from PulseMethod)
     pub random_switch: Option<RandomPolaritySwitch>,
+    /// Arm statistics of the Adaptive method
+    pub learner: Option<PulseLearner>,
+    /// Width of the next pulse (ms)
+    pub current_width_ms: u64,
+    /// Width stepping config (cloned from PulseMethod)
+    pub width_stepping: Option<PulseWidthStepping>,
+    /// Width steps taken above the minimum width
+    width_step: u16,
+    /// Consecutive updates that left the voltage at its maximum
+    cycles_at_max_voltage: usize,
+    /// The last pulse was not the Adaptive method's choice (a random
+    /// polarity switch or a max pulse), so its reading is not credited
+    off_arm_pulse: bool,
 }
 
 impl PulseState {
@@ -24,6 +41,7 @@ impl PulseState {
                 voltage,
                 polarity,
                 random_polarity_switch,
+                ..
             } => ((*voltage), *polarity, random_polarity_switch.clone()),
             PulseMethod::Stepping {
                 voltage_bounds,
@@ -37,6 +55,44 @@ impl PulseState {
                 random_polarity_switch,
                 ..
             } => (voltage_bounds.0, *polarity, random_polarity_switch.clone()),
+            PulseMethod::Adaptive {
+                voltage_bounds,
+                polarity,
+                random_polarity_switch,
+                ..
+            } => (voltage_bounds.0, *polarity, random_polarity_switch.clone()),
+        };
+        let learner = match method {
+            PulseMethod::Adaptive {
+                voltage_bounds,
+                voltage_levels,
+                explore_polarity,
+                exploration,
+                discount,
+                polarity,
+                pulse_width,
+                ..
+            } => {
+                let polarities = if *explore_polarity {
+                    vec![*polarity, polarity.opposite()]
+                } else {
+                    vec![*polarity]
+                };
+                // The width is learned alongside the voltage instead of
+                // escalating at the maximum voltage.
+                let widths: Vec<u64> = pulse_width.as_ref().map_or_else(Vec::new, |w| {
+                    (0..=w.width_steps).map(|step| w.width_at(step)).collect()
+                });
+                Some(PulseLearner::new(
+                    *voltage_bounds,
+                    *voltage_levels,
+                    &polarities,
+                    &widths,
+                    *exploration,
+                    *discount,
+                ))
+            }
+            _ => None,
         };
         Self {
             current_voltage: initial_voltage,
@@ -46,31 +102,98 @@ impl PulseState {
             base_polarity,
             pulse_count: 0,
             random_switch,
+            learner,
+            current_width_ms: method.pulse_width().map_or(0, |w| w.width_bounds_ms.0),
+            width_stepping: method.pulse_width().cloned(),
+            width_step: 0,
+            cycles_at_max_voltage: 0,
+            off_arm_pulse: false,
         }
     }
 
-    /// Reset pulse state after stability failure -- back to minimum voltage.
+    /// Pulse width (ms) used when the method does not step the width.
+    pub fn with_default_width(mut self, width_ms: u64) -> Self {
+        if self.width_stepping.is_none() {
+            self.current_width_ms = width_ms;
+        }
+        self
+    }
+
+    /// Width of a max-voltage pulse: the max width when stepping, else the
+    /// current width.
+    pub fn max_width_ms(&self) -> u64 {
+        self.width_stepping
+            .as_ref()
+            .map_or(self.current_width_ms, |w| w.width_bounds_ms.1)
+    }
+
+    /// Tell the Adaptive method which freq-shift window (Hz) counts as sharp.
+    pub fn with_sharp_window(mut self, bounds: (f64, f64)) -> Self {
+        if let Some(learner) = &mut self.learner {
+            learner.set_window(bounds);
+        }
+        self
+    }
+
+    /// Polarity of the next pulse before random switching: the base
+    /// polarity, or the chosen arm's for the Adaptive method.
+    pub fn polarity(&self) -> PolaritySign {
+        self.learner
+            .as_ref()
+            .map_or(self.base_polarity, |l| l.current().polarity)
+    }
+
+    /// Arm statistics of the Adaptive method, `None` for the others.
+    pub fn arm_stats(&self) -> Option<Vec<PulseArmStats>> {
+        self.learner.as_ref().map(PulseLearner::stats)
+    }
+
+    /// Reset pulse state after stability failure -- back to minimum voltage
+    /// and width.
+    ///
+    /// The Adaptive method keeps what it learned and its next choice,
+    /// width included; it only forgets the reading from before the reset.
     pub fn reset(&mut self, method: &PulseMethod) {
         self.current_voltage = match method {
             PulseMethod::Fixed { voltage, .. } => *voltage,
             PulseMethod::Stepping { voltage_bounds, .. } => voltage_bounds.0,
             PulseMethod::Linear { voltage_bounds, .. } => voltage_bounds.0,
+            PulseMethod::Adaptive { .. } => self.current_voltage,
         };
+        if let Some(learner) = &mut self.learner {
+            learner.forget_last();
+        }
+        if let Some(width) = &self.width_stepping {
+            self.current_width_ms = self.learned_width_ms().unwrap_or(width.width_bounds_ms.0);
+        }
+        self.width_step = 0;
+        self.cycles_at_max_voltage = 0;
         self.cycles_without_change = 0;
         self.last_freq_shift = None;
         self.freq_shift_history.clear();
     }
 
+    /// The tip was reshaped by something other than a pulse (an
+    /// indentation): drop the last reading so it is not compared against,
+    /// or credited to, the next pulse.
+    pub fn forget_reading(&mut self) {
+        if let Some(learner) = &mut self.learner {
+            learner.forget_last();
+        }
+        self.last_freq_shift = None;
+    }
+
     /// Get the signed voltage for the next pulse.
     ///
     /// Applies polarity sign and random polarity switching to the magnitude.
     pub fn signed_voltage(&mut self) -> f64 {
         self.pulse_count += 1;
 
-        let effective_polarity = if self.should_use_opposite_polarity() {
-            self.base_polarity.opposite()
+        self.off_arm_pulse = self.should_use_opposite_polarity();
+        let effective_polarity = if self.off_arm_pulse {
+            self.polarity().opposite()
         } else {
-            self.base_polarity
+            self.polarity()
         };
 
         let sign = match effective_polarity {
@@ -97,9 +220,11 @@ impl PulseState {
     ///
     /// Counts as a pulse for random-polarity-switch purposes: increments
     /// `pulse_count` and, if this cycle hits a switch boundary, fires at
-    /// the opposite polarity. Matches V1 `execute_max_pulse` semantics.
+    /// the opposite polarity. Matches V1 `execute_max_pulse` semantics. Not
+    /// an Adaptive arm's pulse, so the reading after it is not credited.
     pub fn fire_max_pulse_voltage(&mut self, method: &PulseMethod) -> f64 {
         self.pulse_count += 1;
+        self.off_arm_pulse = true;
 
         let effective_polarity = if self.should_use_opposite_polarity() {
             self.base_polarity.opposite()
@@ -115,8 +240,63 @@ impl PulseState {
         sign * method.max_voltage()
     }
 
-    /// Update pulse voltage magnitude based on the latest freq_shift reading.
+    /// Update pulse voltage magnitude based on the latest freq_shift reading,
+    /// then the width if the method steps it. The Adaptive method chooses
+    /// the width with the voltage when it has width levels.
     pub fn update_voltage(&mut self, method: &PulseMethod, freq_shift: Option<f64>) {
+        self.update_magnitude(method, freq_shift);
+        match self.learned_width_ms() {
+            Some(width_ms) => self.current_width_ms = width_ms,
+            None => self.update_width(method.max_voltage()),
+        }
+    }
+
+    /// Width of the Adaptive method's next arm, if it learns the width.
+    fn learned_width_ms(&self) -> Option<u64> {
+        self.learner.as_ref().and_then(|l| l.current().width_ms)
+    }
+
+    /// Step the width up after `cycles_at_max_voltage` updates that left the
+    /// voltage at its maximum; back to the minimum once the voltage drops.
+    /// The Fixed method's voltage never drops, so for it the escalation is
+    /// permanent until [`reset`](Self::reset), whatever the readings do.
+    fn update_width(&mut self, max_voltage: f64) {
+        let Some(width) = &self.width_stepping else {
+            return;
+        };
+
+        if self.current_voltage < max_voltage - 1e-9 {
+            if self.width_step > 0 {
+                log::debug!("Pulse voltage below maximum, resetting pulse width");
+            }
+            self.width_step = 0;
+            self.cycles_at_max_voltage = 0;
+            self.current_width_ms = width.width_bounds_ms.0;
+            return;
+        }
+
+        self.cycles_at_max_voltage += 1;
+        if self.cycles_at_max_voltage >= width.cycles_at_max_voltage as usize {
+            self.cycles_at_max_voltage = 0;
+            if self.width_step < width.width_steps {
+                self.width_step += 1;
+                let new_width = width.width_at(self.width_step);
+                log::info!(
+                    "Voltage at maximum, stepping pulse width: {} ms -> {} ms",
+                    self.current_width_ms,
+                    new_width
+                );
+                self.current_width_ms = new_width;
+            } else {
+                log::debug!(
+                    "Pulse width already at maximum: {} ms",
+                    width.width_bounds_ms.1
+                );
+            }
+        }
+    }
+
+    fn update_magnitude(&mut self, method: &PulseMethod, freq_shift: Option<f64>) {
         match method {
             PulseMethod::Fixed { .. } => {
                 // Fixed: voltage never changes
@@ -240,6 +420,33 @@ impl PulseState {
 
                 self.last_freq_shift = freq_shift;
             }
+
+            PulseMethod::Adaptive { .. } => {
+                let off_arm = std::mem::take(&mut self.off_arm_pulse);
+                if let (Some(learner), true) = (&mut self.learner, off_arm) {
+                    // Not the arm's pulse: neither credit it nor measure the
+                    // arm's next pulse from the tip it left.
+                    learner.forget_last();
+                } else if let (Some(learner), Some(fs)) = (&mut self.learner, freq_shift) {
+                    learner.observe(fs);
+                    let next = learner.current();
+                    if next.voltage != self.current_voltage
+                        || next.width_ms.is_some_and(|w| w != self.current_width_ms)
+                    {
+                        log::info!(
+                            "Adaptive pulse: next {:.2}V ({:?}){}",
+                            next.voltage,
+                            next.polarity,
+                            next.width_ms
+                                .map(|w| format!(", {w} ms"))
+                                .unwrap_or_default()
+                        );
+                    }
+                    self.current_voltage = next.voltage;
+                }
+
+                self.last_freq_shift = freq_shift;
+            }
         }
     }
 }
@@ -1,20 +1,25 @@
+use std::borrow::Cow;
 use std::time::Duration;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::action::scan::ScanDirectionParam;
-use crate::config::{AppConfig, TipPrepConfig};
+use crate::analyzer::{AnalyzerInput, TipQuality, TipQualityAnalyzer};
+use crate::config::{AppConfig, ConditioningMode, TipPrepConfig};
 use crate::controller_types::{BiasSweepPolarity, PolaritySign};
-use crate::event::{Event, EventBus};
+use crate::event::{Event, EventBus, EventPayload};
 use crate::routine::{RepositionSpec, Routine, Rt, StableReadSpec, run_routine};
 use crate::shutdown::ShutdownFlag;
 use crate::signal_registry::SignalIndex;
 use crate::spm_controller::SpmController;
 use crate::spm_error::SpmError;
 
-use nanonis_rs::scan::ScanPropsBuilder;
+use nanonis_rs::scan::{ScanFrame, ScanPropsBuilder};
 
-use super::PulseState;
+use super::{
+    ConfirmationDecision, IndentState, PulseState, SharpnessTest, Site, SitePlanner, SweepSample,
+    TipChangeDetector, TipChangeEvent,
+};
 
 pub use crate::routine::Outcome;
 
@@ -22,15 +27,99 @@ pub use crate::routine::Outcome;
 // Public types
 // ============================================================================
 
-/// Snapshot of tip-prep state for GUI/observer consumption.
-#[derive(Serialize, Clone, Debug)]
+/// Snapshot of tip-prep state for GUI/observer consumption, published each
+/// cycle as [`TipPrepState::Pulsing`] or [`TipPrepState::Indenting`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TipPrepSnapshot {
     pub cycle: usize,
     pub elapsed_secs: f64,
     pub freq_shift: Option<f64>,
     pub pulse_voltage: f64,
+    pub pulse_width_ms: u64,
+    /// Depth (m) of this cycle's indentation; `None` for a pulse cycle.
+    pub indent_depth_m: Option<f64>,
     pub is_sharp: bool,
-    pub phase: &'static str,
+}
+
+/// The routine's state as published in `tip_prep_state` events: where it
+/// is, with what it just measured. The JSON carries the variant as `phase`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum TipPrepState {
+    /// A conditioning cycle with a bias pulse.
+    Pulsing(TipPrepSnapshot),
+    /// A conditioning cycle with an indentation.
+    Indenting(TipPrepSnapshot),
+    /// Confirming a sharp reading at other sites.
+    Confirming,
+    /// Sweeping the bias, judged against the baseline read.
+    StabilityCheck {
+        baseline_freq_shift: f64,
+    },
+    /// The sweep caught a tip change.
+    TipChanged {
+        tip_change: TipChangeEvent,
+    },
+    /// The tip held through the sweeps.
+    Stable {
+        final_freq_shift: f64,
+    },
+    /// Scanning a frame to judge the tip by its image.
+    ImageCheck,
+    ImageAccepted {
+        tip_quality: TipQuality,
+    },
+    ImageRejected {
+        tip_quality: TipQuality,
+    },
+}
+
+impl TipPrepState {
+    /// The `phase` name, e.g. `"stability_check"`.
+    pub fn phase(&self) -> &'static str {
+        match self {
+            TipPrepState::Pulsing(_) => "pulsing",
+            TipPrepState::Indenting(_) => "indenting",
+            TipPrepState::Confirming => "confirming",
+            TipPrepState::StabilityCheck { .. } => "stability_check",
+            TipPrepState::TipChanged { .. } => "tip_changed",
+            TipPrepState::Stable { .. } => "stable",
+            TipPrepState::ImageCheck => "image_check",
+            TipPrepState::ImageAccepted { .. } => "image_accepted",
+            TipPrepState::ImageRejected { .. } => "image_rejected",
+        }
+    }
+
+    /// The cycle snapshot, for the conditioning phases.
+    pub fn snapshot(&self) -> Option<&TipPrepSnapshot> {
+        match self {
+            TipPrepState::Pulsing(snapshot) | TipPrepState::Indenting(snapshot) => Some(snapshot),
+            _ => None,
+        }
+    }
+}
+
+impl EventPayload for TipPrepState {
+    const KIND: &'static str = "tip_prep_state";
+}
+
+/// One stability check in a tip-prep run, for the run report.
+#[derive(Serialize, Clone, Debug)]
+pub struct StabilityCheckRecord {
+    /// Whether the confirmation reads all landed in the sharp window.
+    pub confirmed_sharp: bool,
+    /// Last confirmation read, the reference for the sweep.
+    pub baseline_freq_shift: Option<f64>,
+    /// Read after the bias sweeps; `None` if no sweep ran.
+    pub final_freq_shift: Option<f64>,
+    pub stable: bool,
+    /// Result of the image check, if one ran (`[tip_prep.image_check]`).
+    pub image_check: Option<TipQuality>,
+    /// Tip change that ended the sweep early (`[tip_prep.stability.tip_change]`).
+    pub tip_change: Option<TipChangeEvent>,
+    /// Signals read at every sweep step, when tip-change detection is on.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sweep_trace: Vec<SweepSample>,
 }
 
 /// Everything a tip-preparation run needs besides the controller.
@@ -75,17 +164,23 @@ pub fn run_tip_prep(
 /// The reference implementation of [`Routine`]; see
 /// `docs/tip-prep/algorithm.md` for the cycle-by-cycle description.
 pub struct TipPrep<'a> {
-    config: &'a AppConfig,
+    config: Cow<'a, AppConfig>,
     freq_shift: SignalIndex,
+    current: Option<SignalIndex>,
     pulse: PulseState,
+    indent: IndentState,
+    sites: SitePlanner,
     bounds: (f64, f64),
     read_spec: StableReadSpec,
+    last_freq_shift: Option<f64>,
+    stability_checks: Vec<StabilityCheckRecord>,
 }
 
 enum StabilityOutcome {
     Stable,
     NotSharp,
     Unstable,
+    ImageRejected,
 }
 
 struct SweepPlan {
@@ -97,15 +192,30 @@ struct SweepPlan {
 
 impl<'a> TipPrep<'a> {
     pub fn new(config: &'a AppConfig, freq_shift: SignalIndex) -> Self {
+        Self::with_config(Cow::Borrowed(config), freq_shift)
+    }
+
+    /// Like [`new`](Self::new), but owning its config, e.g. one assembled
+    /// from a parsed `[tip_prep]` section.
+    pub fn owned(config: AppConfig, freq_shift: SignalIndex) -> TipPrep<'static> {
+        TipPrep::with_config(Cow::Owned(config), freq_shift)
+    }
+
+    fn with_config(config: Cow<'a, AppConfig>, freq_shift: SignalIndex) -> Self {
         let gates = &config.tip_prep.signal_stability;
+        let bounds = (
+            config.tip_prep.sharp_tip_bounds[0],
+            config.tip_prep.sharp_tip_bounds[1],
+        );
         Self {
-            config,
             freq_shift,
-            pulse: PulseState::new(&config.pulse_method),
-            bounds: (
-                config.tip_prep.sharp_tip_bounds[0],
-                config.tip_prep.sharp_tip_bounds[1],
-            ),
+            current: None,
+            pulse: PulseState::new(&config.pulse_method)
+                .with_sharp_window(bounds)
+                .with_default_width(config.tip_prep.timing.pulse_width_ms),
+            indent: IndentState::new(&config.tip_prep.conditioning),
+            sites: SitePlanner::new(&config.tip_prep.reposition),
+            bounds,
             read_spec: StableReadSpec {
                 num_samples: config.data_acquisition.stable_signal_samples,
                 max_std_dev: gates.max_std_dev_hz,
@@ -113,30 +223,119 @@ impl<'a> TipPrep<'a> {
                 max_retries: gates.read_retry_count as usize,
                 sample_rate_hz: config.data_acquisition.sample_rate as f64,
             },
+            last_freq_shift: None,
+            stability_checks: Vec::new(),
+            config,
         }
     }
 
+    /// Also read the tunnelling current during the stability sweep, for
+    /// tip-change detection (`[tip_prep.stability.tip_change]`).
+    pub fn with_current(mut self, current: SignalIndex) -> Self {
+        self.current = Some(current);
+        self
+    }
+
     fn is_sharp(&self, freq_shift: f64) -> bool {
         freq_shift >= self.bounds.0 && freq_shift <= self.bounds.1
     }
 
-    fn read_stable(&self, rt: &mut Rt) -> Result<f64, SpmError> {
-        rt.signals()?.read_stable(self.freq_shift, &self.read_spec)
+    fn read_stable(&mut self, rt: &mut Rt) -> Result<f64, SpmError> {
+        let value = rt
+            .signals()?
+            .read_stable(self.freq_shift, &self.read_spec)?;
+        self.last_freq_shift = Some(value);
+        self.sites.record_reading(value);
+        Ok(value)
     }
 
     /// Move to a fresh surface spot: withdraw, step the motors, re-approach.
+    /// The site comes from the reposition pattern, off visited and pulsed
+    /// sites (`[tip_prep.reposition]`).
     ///
     /// V1 parity: `post_move_settle_ms` sits between the motor move and the
     /// approach; `post_reposition_settle_ms` ends the whole reposition.
-    fn reposition(&self, rt: &mut Rt) -> Result<(), SpmError> {
+    fn reposition(&mut self, rt: &mut Rt) -> Result<(), SpmError> {
         let t = &self.config.tip_prep.timing;
-        rt.motor()?.reposition(&RepositionSpec {
-            x_steps: t.reposition_steps[0],
-            y_steps: t.reposition_steps[1],
+        let from = self.sites.position();
+        let to = self.sites.next_site()?;
+        let (x_steps, y_steps) = self.motor_steps_to(to)?;
+        let spec = RepositionSpec {
+            x_steps,
+            y_steps,
             post_move_settle_ms: t.post_move_settle_ms,
             post_approach_settle_ms: t.post_reposition_settle_ms,
             ..Default::default()
-        })
+        };
+        log::debug!(
+            "Reposition ({}, {}) -> ({}, {}): {} x {} steps",
+            from.x,
+            from.y,
+            to.x,
+            to.y,
+            spec.x_steps,
+            spec.y_steps
+        );
+        rt.motor()?.reposition(&spec)?;
+        self.sites.arrive(to);
+        Ok(())
+    }
+
+    /// Coarse-motor steps (x, y) from the current site to `to`.
+    fn motor_steps_to(&self, to: Site) -> Result<(i16, i16), SpmError> {
+        let t = &self.config.tip_prep.timing;
+        let from = self.sites.position();
+        let steps = |sites: i32, pitch: i16| {
+            i16::try_from(sites as i64 * pitch as i64).map_err(|_| {
+                SpmError::Workflow(format!(
+                    "reposition to site ({}, {}) needs more than {} motor steps",
+                    to.x,
+                    to.y,
+                    i16::MAX
+                ))
+            })
+        };
+        Ok((
+            steps(to.x - from.x, t.reposition_steps[0])?,
+            steps(to.y - from.y, t.reposition_steps[1])?,
+        ))
+    }
+
+    /// Fire a bias pulse with the current voltage and width.
+    fn fire_pulse(&mut self, rt: &mut Rt) -> Result<(), SpmError> {
+        let pulse_voltage = self.pulse.signed_voltage();
+        log::info!(
+            "Executing pulse #{}: {:.3}V, {} ms ({} method, {:?}{})",
+            self.pulse.pulse_count,
+            pulse_voltage,
+            self.pulse.current_width_ms,
+            self.config.pulse_method.method_name(),
+            self.pulse.polarity(),
+            if self.pulse.should_use_opposite_polarity() {
+                " - SWITCHED"
+            } else {
+                ""
+            }
+        );
+        rt.bias()?
+            .pulse(pulse_voltage, self.pulse.current_width_ms)?;
+        self.sites.mark_pulsed();
+        Ok(())
+    }
+
+    /// Indent the tip into the substrate with the tip shaper.
+    fn indent_tip(&mut self, rt: &mut Rt) -> Result<(), SpmError> {
+        let conditioning = &self.config.tip_prep.conditioning;
+        log::info!(
+            "Executing indent #{}: {:.2} nm at {:.2}V",
+            self.indent.indent_count + 1,
+            self.indent.current_depth_m * 1e9,
+            conditioning.bias_v
+        );
+        rt.tip_shaper()?
+            .shape(&self.indent.shaper_params(conditioning))?;
+        self.sites.mark_pulsed();
+        Ok(())
     }
 
     fn handle_stability(&mut self, rt: &mut Rt) -> Result<bool, SpmError> {
@@ -152,6 +351,11 @@ impl<'a> TipPrep<'a> {
             StabilityOutcome::Unstable => {
                 log::info!("Stability check failed - reset to blunt, continuing");
                 self.pulse.reset(&self.config.pulse_method);
+                self.indent.reset(&self.config.tip_prep.conditioning);
+                Ok(false)
+            }
+            StabilityOutcome::ImageRejected => {
+                log::info!("Image check rejected tip - continuing");
                 Ok(false)
             }
         }
@@ -161,31 +365,42 @@ impl<'a> TipPrep<'a> {
     // Confirm sharpness
     // ------------------------------------------------------------------
 
-    fn confirm_sharp(&self, rt: &mut Rt) -> Result<(bool, Option<f64>), SpmError> {
-        const CONFIRMATION_READS: usize = 3;
-        let mut last_freq_shift = None;
-
-        for i in 0..CONFIRMATION_READS {
+    /// Read fresh sites until the confirmation policy decides
+    /// (`[tip_prep.confirmation]`). Returns the verdict and, if confirmed,
+    /// the last reading as the stability baseline.
+    fn confirm_sharp(&mut self, rt: &mut Rt) -> Result<(bool, Option<f64>), SpmError> {
+        let confirmation = self.config.tip_prep.confirmation.clone();
+        // A stable read passed the noise gate, so its mean is uncertain by at
+        // most the gate's standard deviation over the batch size.
+        let noise_floor = self.read_spec.max_std_dev / (self.read_spec.num_samples as f64).sqrt();
+        let mut test = SharpnessTest::new(&confirmation, self.bounds, noise_floor);
+
+        loop {
             rt.check_shutdown()?;
             self.reposition(rt)?;
             rt.check_shutdown()?;
 
             let fs = self.read_stable(rt)?;
-            let in_bounds = self.is_sharp(fs);
+            let step = test.push(fs);
             log::info!(
-                "Confirmation {}/{}: freq_shift={:.3} Hz, in_bounds={}",
-                i + 1,
-                CONFIRMATION_READS,
+                "Confirmation {}/{}: freq_shift={:.3} Hz, mean={:.3} Hz{}, {:?}",
+                step.site,
+                confirmation.sites,
                 fs,
-                in_bounds
+                step.mean,
+                step.half_width
+                    .map(|h| format!(" +/- {:.3}", h))
+                    .unwrap_or_default(),
+                step.decision
             );
-            if !in_bounds {
-                return Ok((false, None));
+            rt.emit(Event::payload(&step));
+
+            match step.decision {
+                ConfirmationDecision::Continue => {}
+                ConfirmationDecision::Accept => return Ok((true, Some(fs))),
+                ConfirmationDecision::Reject => return Ok((false, None)),
             }
-            last_freq_shift = Some(fs);
         }
-
-        Ok((true, last_freq_shift))
     }
 
     // ------------------------------------------------------------------
@@ -193,23 +408,38 @@ impl<'a> TipPrep<'a> {
     // ------------------------------------------------------------------
 
     fn check_stability(&mut self, rt: &mut Rt) -> Result<StabilityOutcome, SpmError> {
-        rt.emit(Event::custom(
-            "tip_prep_state",
-            serde_json::json!({ "phase": "confirming" }),
-        ));
+        rt.emit(Event::payload(&TipPrepState::Confirming));
 
         // Step 1: Confirm sharpness with repositioning (3 reads)
         let (confirmed, baseline) = self.confirm_sharp(rt)?;
 
         if !confirmed {
             log::info!("Tip not confirmed sharp during pre-check");
+            self.stability_checks.push(StabilityCheckRecord {
+                confirmed_sharp: false,
+                baseline_freq_shift: None,
+                final_freq_shift: None,
+                stable: false,
+                image_check: None,
+                tip_change: None,
+                sweep_trace: Vec::new(),
+            });
             return Ok(StabilityOutcome::NotSharp);
         }
 
-        let stability = &self.config.tip_prep.stability;
+        let stability = self.config.tip_prep.stability.clone();
         if !stability.check_stability {
             log::info!("Stability checking disabled - accepting sharp tip");
-            return Ok(StabilityOutcome::Stable);
+            self.stability_checks.push(StabilityCheckRecord {
+                confirmed_sharp: true,
+                baseline_freq_shift: baseline,
+                final_freq_shift: None,
+                stable: true,
+                image_check: None,
+                tip_change: None,
+                sweep_trace: Vec::new(),
+            });
+            return self.check_image(rt);
         }
 
         let baseline = match baseline {
@@ -256,10 +486,9 @@ impl<'a> TipPrep<'a> {
         // Step 3: Run sweep plans, restoring the scan speed however they end
         let sweep_plans = build_sweep_plans(&self.config.tip_prep);
 
-        rt.emit(Event::custom(
-            "tip_prep_state",
-            serde_json::json!({ "phase": "stability_check", "baseline_freq_shift": baseline }),
-        ));
+        rt.emit(Event::payload(&TipPrepState::StabilityCheck {
+            baseline_freq_shift: baseline,
+        }));
 
         log::info!(
             "Starting stability check: {:?} polarity, {} sweep(s)",
@@ -267,14 +496,26 @@ impl<'a> TipPrep<'a> {
             sweep_plans.len()
         );
 
-        rt.guarded(
+        let mut detector = if !stability.tip_change.enabled {
+            None
+        } else if rt.controller().stream_sample_rate_hz().is_none() {
+            log::warn!("Tip-change detection needs the data stream; sweeping without it");
+            None
+        } else {
+            Some(TipChangeDetector::new(&stability.tip_change))
+        };
+
+        let tip_change = rt.guarded(
             |rt| {
                 for plan in &sweep_plans {
                     rt.check_shutdown()?;
                     self.prepare_for_sweep(rt, plan)?;
-                    self.execute_stability_sweep(rt, plan)?;
+                    let change = self.execute_stability_sweep(rt, plan, detector.as_mut())?;
+                    if change.is_some() {
+                        return Ok(change);
+                    }
                 }
-                Ok(())
+                Ok(None)
             },
             |rt| {
                 if let Some(config) = original_speed
@@ -286,6 +527,33 @@ impl<'a> TipPrep<'a> {
             },
         )?;
 
+        let sweep_trace = detector
+            .map(TipChangeDetector::into_trace)
+            .unwrap_or_default();
+
+        if let Some(change) = tip_change {
+            log::info!(
+                "Stability: sweep stopped early by a tip change ({:?}) at sweep {} step {}, bias {:.3}V",
+                change.kind,
+                change.sweep,
+                change.step,
+                change.bias_v
+            );
+            rt.emit(Event::payload(&TipPrepState::TipChanged {
+                tip_change: change.clone(),
+            }));
+            self.stability_checks.push(StabilityCheckRecord {
+                confirmed_sharp: true,
+                baseline_freq_shift: Some(baseline),
+                final_freq_shift: None,
+                stable: false,
+                image_check: None,
+                tip_change: Some(change),
+                sweep_trace,
+            });
+            return self.recover_from_instability(rt);
+        }
+
         // Step 4: Measure final freq_shift
         let final_fs = self.measure_final_freq_shift(rt)?;
 
@@ -302,59 +570,103 @@ impl<'a> TipPrep<'a> {
             threshold,
             is_stable
         );
+        self.stability_checks.push(StabilityCheckRecord {
+            confirmed_sharp: true,
+            baseline_freq_shift: Some(baseline),
+            final_freq_shift: Some(final_fs),
+            stable: is_stable,
+            image_check: None,
+            tip_change: None,
+            sweep_trace,
+        });
 
         if is_stable {
-            rt.emit(Event::custom(
-                "tip_prep_state",
-                serde_json::json!({ "phase": "stable", "final_freq_shift": final_fs }),
-            ));
-            Ok(StabilityOutcome::Stable)
+            rt.emit(Event::payload(&TipPrepState::Stable {
+                final_freq_shift: final_fs,
+            }));
+            self.check_image(rt)
         } else {
-            // The post-sweep withdraw in execute_stability_sweep only logs
-            // errors; re-withdraw here with error propagation so a max-voltage
-            // pulse never fires on an engaged tip if the earlier withdraw
-            // silently failed.
-            rt.z()?.withdraw()?;
-
-            // Fire max pulse and reset to blunt. `fire_max_pulse_voltage` bumps
-            // pulse_count and may flip polarity, so capture the effective sign
-            // from the returned voltage rather than re-reading base_polarity.
-            let signed_max = self.pulse.fire_max_pulse_voltage(&self.config.pulse_method);
-            let effective_polarity = if signed_max >= 0.0 {
-                PolaritySign::Positive
-            } else {
-                PolaritySign::Negative
-            };
-            log::info!(
-                "Executing MAX pulse #{} due to stability failure: {:.3}V ({:?}{})",
-                self.pulse.pulse_count,
-                signed_max,
-                effective_polarity,
-                if effective_polarity != self.pulse.base_polarity {
-                    " - SWITCHED"
-                } else {
-                    ""
-                },
-            );
-            rt.bias()?
-                .pulse(signed_max, self.config.tip_prep.timing.pulse_width_ms)?;
-
-            self.reposition(rt)?;
+            self.recover_from_instability(rt)
+        }
+    }
 
-            Ok(StabilityOutcome::Unstable)
+    /// Reshape the tip as hard as the conditioning mode allows after a
+    /// failed stability check, and move on: the deepest indentation in
+    /// indent mode, which fires no pulses, a maximum pulse otherwise.
+    fn recover_from_instability(&mut self, rt: &mut Rt) -> Result<StabilityOutcome, SpmError> {
+        if self.config.tip_prep.conditioning.mode == ConditioningMode::Indent {
+            self.indent_max_depth(rt)?;
+        } else {
+            self.pulse_max(rt)?;
         }
+        self.reposition(rt)?;
+
+        Ok(StabilityOutcome::Unstable)
+    }
+
+    fn pulse_max(&mut self, rt: &mut Rt) -> Result<(), SpmError> {
+        // The post-sweep withdraw in execute_stability_sweep only logs
+        // errors; re-withdraw here with error propagation so a max-voltage
+        // pulse never fires on an engaged tip if the earlier withdraw
+        // silently failed.
+        rt.z()?.withdraw()?;
+
+        // Fire max pulse and reset to blunt. `fire_max_pulse_voltage` bumps
+        // pulse_count and may flip polarity, so capture the effective sign
+        // from the returned voltage rather than re-reading base_polarity.
+        let signed_max = self.pulse.fire_max_pulse_voltage(&self.config.pulse_method);
+        let effective_polarity = if signed_max >= 0.0 {
+            PolaritySign::Positive
+        } else {
+            PolaritySign::Negative
+        };
+        log::info!(
+            "Executing MAX pulse #{} due to stability failure: {:.3}V, {} ms ({:?}{})",
+            self.pulse.pulse_count,
+            signed_max,
+            self.pulse.max_width_ms(),
+            effective_polarity,
+            if effective_polarity != self.pulse.base_polarity {
+                " - SWITCHED"
+            } else {
+                ""
+            },
+        );
+        rt.bias()?.pulse(signed_max, self.pulse.max_width_ms())?;
+        self.sites.mark_pulsed();
+        Ok(())
+    }
+
+    /// Indent at the maximum depth. The tip shaper works from feedback, and
+    /// the sweep may have ended withdrawn, so re-approach first.
+    fn indent_max_depth(&mut self, rt: &mut Rt) -> Result<(), SpmError> {
+        let conditioning = &self.config.tip_prep.conditioning;
+        self.indent.go_to_max_depth(conditioning);
+        log::info!(
+            "Executing MAX indent due to stability failure: {:.2} nm",
+            self.indent.current_depth_m * 1e9
+        );
+        rt.z()?.withdraw()?;
+        rt.z()?.calibrated_approach()?;
+        self.indent_tip(rt)?;
+        self.indent.indent_count += 1;
+        Ok(())
     }
 
     // ------------------------------------------------------------------
     // Stability sweep
     // ------------------------------------------------------------------
 
-    fn prepare_for_sweep(&self, rt: &mut Rt, plan: &SweepPlan) -> Result<(), SpmError> {
+    /// Withdraw, move to the planner's next site with a small Z retreat, and
+    /// re-approach at the sweep's starting bias.
+    fn prepare_for_sweep(&mut self, rt: &mut Rt, plan: &SweepPlan) -> Result<(), SpmError> {
         let t = &self.config.tip_prep.timing;
 
         rt.z()?.withdraw()?;
-        rt.motor()?
-            .move_3d(t.reposition_steps[0], t.reposition_steps[1], -3)?;
+        let to = self.sites.next_site()?;
+        let (x_steps, y_steps) = self.motor_steps_to(to)?;
+        rt.motor()?.move_3d(x_steps, y_steps, -3)?;
+        self.sites.arrive(to);
         rt.settle(200)?;
         rt.bias()?.set(plan.starting_bias)?;
         rt.z()?.calibrated_approach()?;
@@ -363,7 +675,14 @@ impl<'a> TipPrep<'a> {
         Ok(())
     }
 
-    fn execute_stability_sweep(&self, rt: &mut Rt, plan: &SweepPlan) -> Result<(), SpmError> {
+    /// Run one bias sweep while scanning. Returns the tip change that ended
+    /// it early, if `detector` caught one.
+    fn execute_stability_sweep(
+        &self,
+        rt: &mut Rt,
+        plan: &SweepPlan,
+        detector: Option<&mut TipChangeDetector>,
+    ) -> Result<Option<TipChangeEvent>, SpmError> {
         log::info!(
             "Sweep {}/{}: bias {:.2}V -> {:.2}V",
             plan.index,
@@ -380,8 +699,8 @@ impl<'a> TipPrep<'a> {
                 .bouncy_scan(true),
         )?;
 
-        rt.guarded(
-            |rt| self.sweep_inner(rt, plan),
+        let change = rt.guarded(
+            |rt| self.sweep_inner(rt, plan, detector),
             // Always stop the scan, restore properties and withdraw — the tip
             // is on the surface after the sweep whether it completed or was
             // interrupted, and none of this may shadow the sweep's own error.
@@ -407,28 +726,20 @@ impl<'a> TipPrep<'a> {
         // Restore bias to sweep starting value (not the last stepped value near 0V)
         rt.bias()?.set(plan.starting_bias)?;
 
-        Ok(())
+        Ok(change)
     }
 
-    fn sweep_inner(&self, rt: &mut Rt, plan: &SweepPlan) -> Result<(), SpmError> {
+    fn sweep_inner(
+        &self,
+        rt: &mut Rt,
+        plan: &SweepPlan,
+        mut detector: Option<&mut TipChangeDetector>,
+    ) -> Result<Option<TipChangeEvent>, SpmError> {
         let sc = &self.config.tip_prep.stability;
 
-        rt.scan()?.start(ScanDirectionParam::Down)?;
-
-        // Wait for the scan to actually start (max 5 seconds)
-        let mut scan_started = false;
-        for _ in 0..50 {
-            rt.settle(100)?;
-            if rt.scan()?.status()? {
-                scan_started = true;
-                break;
-            }
-        }
-
-        if !scan_started {
-            return Err(SpmError::Timeout(
-                "scan failed to start within 5 seconds".into(),
-            ));
+        start_scan(rt)?;
+        if let Some(detector) = detector.as_deref_mut() {
+            detector.start_sweep();
         }
 
         // Step bias through range
@@ -446,15 +757,188 @@ impl<'a> TipPrep<'a> {
                 current_bias
             );
 
-            rt.settle(sc.step_period_ms)?;
+            match detector.as_deref_mut() {
+                None => rt.settle(sc.step_period_ms)?,
+                Some(detector) => {
+                    let change =
+                        self.watch_step(rt, detector, plan, step as usize + 1, current_bias)?;
+                    if let Some(change) = change {
+                        log::warn!(
+                            "Tip change at step {}/{} (bias {:.3}V): {:?}, {:.3e} vs {:.3e}",
+                            step + 1,
+                            sc.bias_steps,
+                            current_bias,
+                            change.kind,
+                            change.value,
+                            change.reference
+                        );
+                        return Ok(Some(change));
+                    }
+                }
+            }
+
             current_bias += bias_step_size;
         }
 
         log::info!("Bias sweep completed");
-        Ok(())
+        Ok(None)
+    }
+
+    /// Spend one step period collecting the stream samples of the frequency
+    /// shift (and current) and judge them in stretches, so a change anywhere
+    /// inside the step is caught, not only one at its end.
+    fn watch_step(
+        &self,
+        rt: &mut Rt,
+        detector: &mut TipChangeDetector,
+        plan: &SweepPlan,
+        step: usize,
+        bias_v: f64,
+    ) -> Result<Option<TipChangeEvent>, SpmError> {
+        let sample_rate_hz = rt.controller().stream_sample_rate_hz().ok_or_else(|| {
+            SpmError::Unsupported("tip-change detection needs the data stream".into())
+        })?;
+        let period_s = self.config.tip_prep.stability.step_period_ms as f64 / 1000.0;
+        let num_samples = ((period_s * sample_rate_hz).round() as usize).max(1);
+
+        let mut indices = vec![self.freq_shift];
+        indices.extend(self.current);
+        rt.signals()?.clear_buffer();
+        let samples = rt.signals()?.read_samples(&indices, num_samples)?;
+
+        Ok(detector.push_step(
+            plan.index,
+            step,
+            bias_v,
+            &samples[0],
+            samples.get(1).map(Vec::as_slice),
+        ))
+    }
+
+    // ------------------------------------------------------------------
+    // Image check
+    // ------------------------------------------------------------------
+
+    /// Scan one frame and judge the tip from the image, if
+    /// `[tip_prep.image_check]` is enabled. Records the result on the
+    /// latest stability check.
+    fn check_image(&mut self, rt: &mut Rt) -> Result<StabilityOutcome, SpmError> {
+        let ic = &self.config.tip_prep.image_check;
+        if !ic.enabled {
+            return Ok(StabilityOutcome::Stable);
+        }
+
+        rt.emit(Event::payload(&TipPrepState::ImageCheck));
+        log::info!("Acquiring image for tip quality check");
+
+        let input = self.acquire_image(rt)?;
+        let analyzer = TipQualityAnalyzer {
+            max_double_tip_score: ic.max_double_tip_score,
+            max_streak_fraction: ic.max_streak_fraction,
+            max_correlation_length_px: ic.max_correlation_length_px,
+            streak_sigma: ic.streak_sigma,
+        };
+        let quality = analyzer.assess(&input)?;
+
+        log::info!(
+            "Image check: double_tip={:.2}, streaks={:.2}, correlation_length={:.1} px, accepted={}",
+            quality.double_tip_score,
+            quality.streak_fraction,
+            quality.correlation_length_px,
+            quality.accepted
+        );
+        let tip_quality = quality.clone();
+        rt.emit(Event::payload(&if quality.accepted {
+            TipPrepState::ImageAccepted { tip_quality }
+        } else {
+            TipPrepState::ImageRejected { tip_quality }
+        }));
+
+        let accepted = quality.accepted;
+        if let Some(check) = self.stability_checks.last_mut() {
+            check.image_check = Some(quality);
+        }
+        Ok(if accepted {
+            StabilityOutcome::Stable
+        } else {
+            StabilityOutcome::ImageRejected
+        })
+    }
+
+    /// Scan a single frame with the image-check settings and grab it,
+    /// restoring the scan properties, frame and speed however it ends.
+    fn acquire_image(&self, rt: &mut Rt) -> Result<AnalyzerInput, SpmError> {
+        let ic = &self.config.tip_prep.image_check;
+
+        let original_props = rt.scan()?.props_get()?;
+        let original_frame = rt.scan()?.frame_get()?;
+        let original_speed = match ic.scan_speed_m_s {
+            Some(_) => Some(rt.scan()?.speed_get()?),
+            None => None,
+        };
+
+        rt.guarded(
+            |rt| {
+                rt.scan()?.props_set(
+                    ScanPropsBuilder::new()
+                        .continuous_scan(false)
+                        .bouncy_scan(false),
+                )?;
+                if let Some(size) = ic.frame_size_m {
+                    rt.scan()?.frame_set(ScanFrame::new(
+                        original_frame.center,
+                        size as f32,
+                        size as f32,
+                        original_frame.angle_deg,
+                    ))?;
+                }
+                if let (Some(speed), Some(orig)) = (ic.scan_speed_m_s, original_speed) {
+                    let mut config = orig;
+                    // ScanConfig is the nanonis-rs wire format, which carries f32 speeds.
+                    config.forward_linear_speed_m_s = speed as f32;
+                    config.backward_linear_speed_m_s = speed as f32;
+                    config.keep_parameter_constant = 1;
+                    rt.scan()?.speed_set(config)?;
+                }
+
+                start_scan(rt)?;
+                rt.with_deadline(Duration::from_secs(ic.timeout_secs), |rt| {
+                    while rt.scan()?.status()? {
+                        rt.settle(500)?;
+                    }
+                    Ok(())
+                })?;
+
+                rt.scan()?.grab_frame(ic.channel_index, ic.forward)
+            },
+            |rt| {
+                match rt.scan() {
+                    Ok(mut scan) => {
+                        if scan.status().unwrap_or(true) {
+                            let _ = scan.stop();
+                        }
+                        if let Err(e) = scan.props_set(original_props.to_builder()) {
+                            log::error!("Failed to restore scan properties: {}", e);
+                        }
+                        if ic.frame_size_m.is_some()
+                            && let Err(e) = scan.frame_set(original_frame)
+                        {
+                            log::error!("Failed to restore scan frame: {}", e);
+                        }
+                        if let Some(config) = original_speed
+                            && let Err(e) = scan.speed_set(config)
+                        {
+                            log::error!("Failed to restore scan speed: {}", e);
+                        }
+                    }
+                    Err(e) => log::error!("Post-image scan cleanup skipped: {}", e),
+                }
+                Ok(())
+            },
+        )
     }
 
-    fn measure_final_freq_shift(&self, rt: &mut Rt) -> Result<f64, SpmError> {
+    fn measure_final_freq_shift(&mut self, rt: &mut Rt) -> Result<f64, SpmError> {
         log::info!("Measuring final freq_shift after sweeps");
 
         rt.z()?.withdraw()?;
@@ -472,11 +956,26 @@ impl Routine for TipPrep<'_> {
         "tip_prep"
     }
 
+    fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "pulses_fired": self.pulse.pulse_count,
+            "indents": self.indent.indent_count,
+            "sites_visited": self.sites.visited_this_run(),
+            "site": self.sites.position(),
+            "final_freq_shift": self.last_freq_shift,
+            "stability_checks": self.stability_checks,
+            "pulse_arms": self.pulse.arm_stats(),
+        })
+    }
+
     fn run(&mut self, rt: &mut Rt) -> Result<Outcome, SpmError> {
-        let cfg = self.config;
+        // Cheap for a borrowed config; an owned one is copied once per run.
+        let cfg = self.config.clone();
         let timing = &cfg.tip_prep.timing;
+        let conditioning = &cfg.tip_prep.conditioning;
 
         log::info!("Initializing...");
+        self.sites.start()?;
         rt.bias()?.set(cfg.tip_prep.initial_bias_v)?;
         rt.z()?.set_setpoint(cfg.tip_prep.initial_z_setpoint_a)?;
         rt.z()?.calibrated_approach()?;
@@ -509,7 +1008,7 @@ impl Routine for TipPrep<'_> {
             }
         }
 
-        // Main loop: pulse -> settle -> reposition -> measure -> check sharp
+        // Main loop: pulse (or indent) -> settle -> reposition -> measure -> check sharp
         // Matches V1 ordering: minimize time at pulsed position to avoid
         // unintended tip changes from continued surface interaction.
         while let Some(cycle) = cycles.next() {
@@ -522,21 +1021,14 @@ impl Routine for TipPrep<'_> {
                 );
             }
 
-            // Pulse with current voltage (determined by previous cycle's update)
-            let pulse_voltage = self.pulse.signed_voltage();
-            log::info!(
-                "Executing pulse #{}: {:.3}V ({} method, {:?}{})",
-                self.pulse.pulse_count,
-                pulse_voltage,
-                cfg.pulse_method.method_name(),
-                self.pulse.base_polarity,
-                if self.pulse.should_use_opposite_polarity() {
-                    " - SWITCHED"
-                } else {
-                    ""
-                }
-            );
-            rt.bias()?.pulse(pulse_voltage, timing.pulse_width_ms)?;
+            // Indent or pulse with the current depth / voltage (determined by
+            // the previous cycle's update)
+            let indenting = self.indent.should_indent(conditioning);
+            if indenting {
+                self.indent_tip(rt)?;
+            } else {
+                self.fire_pulse(rt)?;
+            }
             rt.settle(timing.post_pulse_settle_ms)?;
 
             // Reposition immediately: get away from pulse site
@@ -547,18 +1039,39 @@ impl Routine for TipPrep<'_> {
             let is_sharp = self.is_sharp(freq_shift);
 
             // Emit state snapshot for GUI observers
-            rt.emit(Event::custom(
-                "tip_prep_state",
-                serde_json::to_value(&TipPrepSnapshot {
-                    cycle,
-                    elapsed_secs: cycles.elapsed().as_secs_f64(),
-                    freq_shift: Some(freq_shift),
-                    pulse_voltage: self.pulse.current_voltage,
-                    is_sharp,
-                    phase: "pulsing",
-                })
-                .unwrap_or_default(),
-            ));
+            let snapshot = TipPrepSnapshot {
+                cycle,
+                elapsed_secs: cycles.elapsed().as_secs_f64(),
+                freq_shift: Some(freq_shift),
+                pulse_voltage: self.pulse.current_voltage,
+                pulse_width_ms: self.pulse.current_width_ms,
+                indent_depth_m: indenting.then_some(self.indent.current_depth_m),
+                is_sharp,
+            };
+            rt.emit(Event::payload(&if indenting {
+                TipPrepState::Indenting(snapshot)
+            } else {
+                TipPrepState::Pulsing(snapshot)
+            }));
+
+            // Update the strategy that just ran for the next cycle (uses the
+            // post-reposition measurement). Done before the sharp check so the
+            // pulse that sharpened the tip is credited, and a failed stability
+            // check resets the updated state.
+            if indenting {
+                self.indent.record_indent(conditioning, is_sharp);
+                self.pulse.forget_reading();
+            } else {
+                self.indent.record_pulse(is_sharp);
+                self.pulse
+                    .update_voltage(&cfg.pulse_method, Some(freq_shift));
+                if let Some(arms) = self.pulse.arm_stats() {
+                    rt.emit(Event::custom(
+                        "pulse_arms",
+                        serde_json::json!({ "cycle": cycle, "arms": arms }),
+                    ));
+                }
+            }
 
             if is_sharp {
                 log::info!(
@@ -570,16 +1083,28 @@ impl Routine for TipPrep<'_> {
                     return Ok(Outcome::Completed);
                 }
             }
-
-            // Update voltage strategy for next cycle (uses post-reposition measurement)
-            self.pulse
-                .update_voltage(&cfg.pulse_method, Some(freq_shift));
         }
 
         Ok(cycles.outcome())
     }
 }
 
+/// Start a downward scan and wait (max 5 seconds) until it is running.
+fn start_scan(rt: &mut Rt) -> Result<(), SpmError> {
+    rt.scan()?.start(ScanDirectionParam::Down)?;
+
+    for _ in 0..50 {
+        rt.settle(100)?;
+        if rt.scan()?.status()? {
+            return Ok(());
+        }
+    }
+
+    Err(SpmError::Timeout(
+        "scan failed to start within 5 seconds".into(),
+    ))
+}
+
 // ============================================================================
 // Sweep planning
 // ============================================================================
@@ -0,0 +1,399 @@
+//! Monte Carlo runs of [`TipPrep`] against the realistic mock tip, for tuning
+//! a config before spending instrument time on it.
+//!
+//! Each run drives the real routine against a [`MockController`] with a
+//! [`models::realistic`] tip drawn from its own seed, behind the same signal
+//! registry a session builds, so `tip_prep.signal` may name any signal the
+//! config defines. The mock runs on
+//! [simulated time](crate::mock_controller::MockControllerBuilder::simulated_time),
+//! which deadlines and timed waits also run on, so a run takes milliseconds; the instrument time it would have taken is
+//! the waits it recorded plus estimates for the operations themselves. Runs
+//! are spread over threads with rayon.
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::config::AppConfig;
+use crate::event::EventBus;
+use crate::mock_controller::models::{self, RealisticParams};
+use crate::mock_controller::{FREQ_SHIFT_INDEX, MockController, MockObservations};
+use crate::routine::operator::AutoOperator;
+use crate::routine::{Outcome, run_routine_reported};
+use crate::session::build_signal_registry;
+use crate::shutdown::ShutdownFlag;
+use crate::signal_registry::SignalIndex;
+use crate::spm_controller::SpmController;
+use crate::spm_error::SpmError;
+use crate::virtual_signals::VirtualSignalController;
+
+use super::TipPrep;
+
+/// What to simulate and what the instrument's own operations cost.
+#[derive(Debug, Clone)]
+pub struct SimulationSpec {
+    /// Runs per config.
+    pub runs: usize,
+    /// Seed of the first run; run `i` uses `first_seed + i`. Configs compared
+    /// side by side see the same seeds, so the same tips.
+    pub first_seed: u64,
+    /// The tip model; its `seed` is replaced per run.
+    pub tip: RealisticParams,
+    /// Within-batch scatter of the stream samples (Hz). The tip model already
+    /// jitters read to read; scatter here exercises the stable-read gates.
+    pub sample_noise_hz: f64,
+    /// Cycle budget for configs without `max_cycles`.
+    pub max_cycles: usize,
+    /// Estimated duration of one auto-approach (s).
+    pub approach_secs: f64,
+    /// Estimated duration of one coarse-motor move (s).
+    pub motor_move_secs: f64,
+    /// Window a completed run's final reading must lie in to count as
+    /// sharp. `None` takes the routine's verdict, which judges against the
+    /// config's own `sharp_tip_bounds`; set it to compare configs whose
+    /// windows differ against one fixed definition of sharp.
+    pub acceptance_window: Option<[f64; 2]>,
+}
+
+impl Default for SimulationSpec {
+    fn default() -> Self {
+        Self {
+            runs: 100,
+            first_seed: 1,
+            tip: RealisticParams::default(),
+            sample_noise_hz: 0.0,
+            max_cycles: 2000,
+            approach_secs: 5.0,
+            motor_move_secs: 1.0,
+            acceptance_window: None,
+        }
+    }
+}
+
+/// One simulated run.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulatedRun {
+    pub seed: u64,
+    /// How the run ended; `None` if it ended with an error.
+    pub outcome: Option<Outcome>,
+    pub error: Option<String>,
+    pub cycles: usize,
+    /// Last stable reading of the tip-prep signal.
+    pub final_freq_shift: Option<f64>,
+    /// Completed with the final reading inside the acceptance window.
+    pub sharp: bool,
+    pub pulses: usize,
+    pub indents: usize,
+    /// Stability checks on a confirmed-sharp tip, and how many failed.
+    pub stability_checks: usize,
+    pub stability_failures: usize,
+    /// Estimated instrument time of the whole run (s).
+    pub instrument_secs: f64,
+}
+
+impl SimulatedRun {
+    pub fn completed(&self) -> bool {
+        self.outcome == Some(Outcome::Completed)
+    }
+
+    /// A run that could not be set up.
+    fn failed(seed: u64, error: &SpmError) -> Self {
+        Self {
+            seed,
+            outcome: None,
+            error: Some(error.to_string()),
+            cycles: 0,
+            final_freq_shift: None,
+            sharp: false,
+            pulses: 0,
+            indents: 0,
+            stability_checks: 0,
+            stability_failures: 0,
+            instrument_secs: 0.0,
+        }
+    }
+}
+
+/// Spread of one quantity over runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Distribution {
+    pub n: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub p10: f64,
+    pub median: f64,
+    pub p90: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    /// `None` for no values.
+    pub fn of(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        // Nearest-rank percentile.
+        let at =
+            |q: f64| sorted[((q * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Some(Self {
+            n: sorted.len(),
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            min: sorted[0],
+            p10: at(0.1),
+            median: at(0.5),
+            p90: at(0.9),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Statistics of one config over all its runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationSummary {
+    /// Names the config in comparisons (usually its file name).
+    pub label: String,
+    pub runs: usize,
+    pub completed: usize,
+    /// Completed runs that ended sharp; all of them without an
+    /// [acceptance window](SimulationSpec::acceptance_window).
+    pub sharp: usize,
+    pub errors: usize,
+    /// Share of runs that ended sharp.
+    pub success_rate: f64,
+    /// Cycles of the runs that ended sharp.
+    pub cycles_to_sharp: Option<Distribution>,
+    /// Estimated instrument time of the runs that ended sharp (s).
+    pub time_to_sharp_secs: Option<Distribution>,
+    /// Instrument time of all runs per sharp run (s): what a sharp tip
+    /// costs on average when a failed run is followed by another attempt.
+    /// `None` if no run ended sharp.
+    pub expected_time_to_sharp_secs: Option<f64>,
+    /// Pulses fired, over all runs.
+    pub pulses: Option<Distribution>,
+    pub stability_checks: usize,
+    pub stability_failures: usize,
+    /// Share of stability checks that failed; `None` without any check.
+    pub stability_failure_rate: Option<f64>,
+}
+
+impl SimulationSummary {
+    pub fn of(label: impl Into<String>, runs: &[SimulatedRun]) -> Self {
+        let done: Vec<&SimulatedRun> = runs.iter().filter(|r| r.sharp).collect();
+        let stability_checks = runs.iter().map(|r| r.stability_checks).sum();
+        let stability_failures = runs.iter().map(|r| r.stability_failures).sum();
+        Self {
+            label: label.into(),
+            runs: runs.len(),
+            completed: runs.iter().filter(|r| r.completed()).count(),
+            sharp: done.len(),
+            errors: runs.iter().filter(|r| r.error.is_some()).count(),
+            success_rate: done.len() as f64 / runs.len().max(1) as f64,
+            cycles_to_sharp: Distribution::of(
+                &done.iter().map(|r| r.cycles as f64).collect::<Vec<_>>(),
+            ),
+            time_to_sharp_secs: Distribution::of(
+                &done.iter().map(|r| r.instrument_secs).collect::<Vec<_>>(),
+            ),
+            expected_time_to_sharp_secs: (!done.is_empty())
+                .then(|| runs.iter().map(|r| r.instrument_secs).sum::<f64>() / done.len() as f64),
+            pulses: Distribution::of(&runs.iter().map(|r| r.pulses as f64).collect::<Vec<_>>()),
+            stability_checks,
+            stability_failures,
+            stability_failure_rate: (stability_checks > 0)
+                .then(|| stability_failures as f64 / stability_checks as f64),
+        }
+    }
+}
+
+/// Simulate `spec.runs` runs of `config`, in parallel.
+pub fn simulate(config: &AppConfig, spec: &SimulationSpec) -> Vec<SimulatedRun> {
+    let config = simulation_config(config, spec);
+    (0..spec.runs as u64)
+        .into_par_iter()
+        .map(|i| simulate_run(&config, spec, spec.first_seed + i))
+        .collect()
+}
+
+/// The config a simulated run uses: a cycle budget instead of a wall-clock
+/// one (time is simulated), no image check (the mock has no surface to
+/// image) and no site-map file.
+pub fn simulation_config(config: &AppConfig, spec: &SimulationSpec) -> AppConfig {
+    let mut sim = config.clone();
+    let tp = &mut sim.tip_prep;
+    tp.max_cycles = Some(tp.max_cycles.unwrap_or(spec.max_cycles));
+    tp.max_duration_secs = None;
+    tp.image_check.enabled = false;
+    tp.reposition.map_file = None;
+    sim
+}
+
+/// One run of `config` (already a [`simulation_config`]) with the tip drawn
+/// from `seed`.
+fn simulate_run(config: &AppConfig, spec: &SimulationSpec, seed: u64) -> SimulatedRun {
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::realistic(RealisticParams { seed, ..spec.tip }))
+        .sample_noise_hz(spec.sample_noise_hz)
+        .noise_seed(seed)
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let (controller, criterion) = match with_signals(mock, config) {
+        Ok(wired) => wired,
+        Err(e) => return SimulatedRun::failed(seed, &e),
+    };
+    let (result, report) = run_routine_reported(
+        controller,
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(config, criterion),
+    );
+
+    let checks = report.summary["stability_checks"]
+        .as_array()
+        .map(|checks| {
+            checks
+                .iter()
+                .filter(|c| c["confirmed_sharp"] == true)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let count = |key: &str| report.summary[key].as_u64().unwrap_or(0) as usize;
+
+    let final_freq_shift = report.summary["final_freq_shift"].as_f64();
+    let completed = matches!(result, Ok(Outcome::Completed));
+    let sharp = completed
+        && spec.acceptance_window.is_none_or(|[low, high]| {
+            final_freq_shift.is_some_and(|value| (low..=high).contains(&value))
+        });
+
+    let obs = obs.lock();
+    SimulatedRun {
+        seed,
+        outcome: result.as_ref().ok().copied(),
+        error: result.err().map(|e| e.to_string()),
+        cycles: report.cycles,
+        final_freq_shift,
+        sharp,
+        pulses: count("pulses_fired"),
+        indents: count("indents"),
+        stability_checks: checks.len(),
+        stability_failures: checks.iter().filter(|c| c["stable"] == false).count(),
+        instrument_secs: instrument_secs(config, spec, &obs),
+    }
+}
+
+/// The mock behind the signal registry and virtual signals a session would
+/// give it, and `tip_prep.signal` resolved through that registry.
+fn with_signals(
+    mut mock: MockController,
+    config: &AppConfig,
+) -> Result<(Box<dyn SpmController>, SignalIndex), SpmError> {
+    let signals = build_signal_registry(&mut mock, config)?;
+    let criterion = signals.resolve(&config.tip_prep.signal)?;
+    Ok((
+        VirtualSignalController::wrap(Box::new(mock), &signals),
+        criterion,
+    ))
+}
+
+/// Instrument time of the run: every recorded wait, plus what the mock
+/// does instantly — pulses, indents, motor moves, approaches and the sample
+/// windows of stable reads.
+fn instrument_secs(config: &AppConfig, spec: &SimulationSpec, obs: &MockObservations) -> f64 {
+    let pulses: f64 = obs.pulse_widths.iter().map(|w| w.as_secs_f64()).sum();
+    let indents: f64 = obs
+        .tip_shapes
+        .iter()
+        .map(|shape| {
+            (shape.switch_off_delay
+                + shape.lift_time_1
+                + shape.bias_settling_time
+                + shape.lift_time_2
+                + shape.end_wait_time)
+                .as_secs_f64()
+        })
+        .sum();
+    let moves = obs.motor_moves as f64 * spec.motor_move_secs;
+    let approaches = obs.approach_count as f64 * spec.approach_secs;
+    let acquisition = &config.data_acquisition;
+    let reads = obs.count("read_signal_samples") as f64 * acquisition.stable_signal_samples as f64
+        / acquisition.sample_rate.max(1) as f64;
+
+    obs.waited.as_secs_f64() + pulses + indents + moves + approaches + reads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_uses_nearest_rank_percentiles() {
+        let values: Vec<f64> = (1..=10).map(f64::from).collect();
+        let d = Distribution::of(&values).unwrap();
+        assert_eq!(
+            (d.min, d.p10, d.median, d.p90, d.max),
+            (1.0, 1.0, 5.0, 9.0, 10.0)
+        );
+        assert_eq!(d.mean, 5.5);
+        assert!(Distribution::of(&[]).is_none());
+    }
+
+    #[test]
+    fn runs_are_reproducible_per_seed_and_summarised() {
+        let mut config = AppConfig::default();
+        // A wide window so runs finish within a few cycles.
+        config.tip_prep.sharp_tip_bounds = [-14.0, -9.0];
+        config.tip_prep.stability.bias_steps = 2;
+        config.tip_prep.stability.check_stability = false;
+        let spec = SimulationSpec {
+            runs: 4,
+            max_cycles: 50,
+            ..Default::default()
+        };
+
+        let runs = simulate(&config, &spec);
+        let again = simulate(&config, &spec);
+        assert_eq!(runs.len(), 4);
+        for (a, b) in runs.iter().zip(&again) {
+            assert_eq!((a.seed, a.cycles, a.pulses), (b.seed, b.cycles, b.pulses));
+        }
+
+        let summary = SimulationSummary::of("wide", &runs);
+        assert_eq!(summary.runs, 4);
+        assert_eq!(summary.errors, 0);
+        assert!(summary.completed > 0, "{summary:?}");
+        assert_eq!(summary.sharp, summary.completed);
+        let time = summary.time_to_sharp_secs.unwrap();
+        // At least the initial approach and one stable read.
+        assert!(time.min >= spec.approach_secs, "{time:?}");
+
+        // Judged against a window no final reading lands in, none is sharp.
+        let strict = SimulationSpec {
+            acceptance_window: Some([10.0, 20.0]),
+            ..spec
+        };
+        let summary = SimulationSummary::of("wide", &simulate(&config, &strict));
+        assert!(summary.completed > 0, "{summary:?}");
+        assert_eq!(summary.sharp, 0);
+        assert!(summary.expected_time_to_sharp_secs.is_none());
+    }
+
+    #[test]
+    fn runs_read_the_configured_signal() {
+        let mut config = AppConfig::default();
+        config.tip_prep.signal = "no such signal".into();
+        let spec = SimulationSpec {
+            runs: 1,
+            max_cycles: 5,
+            ..Default::default()
+        };
+
+        let runs = simulate(&config, &spec);
+        let error = runs[0].error.as_deref().unwrap_or_default();
+        assert!(error.contains("no such signal"), "{:?}", runs[0]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{RepositionConfig, RepositionPattern};
+use crate::spm_error::SpmError;
+use crate::utils::Rng;
+
+/// Random draws per reposition before the random pattern gives up.
+const RANDOM_ATTEMPTS: usize = 1000;
+
+/// A surface site, in units of `timing.reposition_steps` from the start site.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Site {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Site {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    /// Chebyshev distance: the number of site moves in a square grid.
+    fn distance(&self, other: Site) -> u32 {
+        self.x.abs_diff(other.x).max(self.y.abs_diff(other.y))
+    }
+}
+
+/// What happened at one site.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteRecord {
+    pub site: Site,
+    pub visits: u32,
+    /// Pulses and indentations fired here; the site counts as damaged.
+    pub pulses: u32,
+    /// Last stable freq-shift read here (Hz).
+    pub last_freq_shift: Option<f64>,
+}
+
+/// Sites visited and pulsed, and where the tip is now. Persisted as JSON
+/// (`reposition.map_file`) so later runs keep off damaged spots.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteMap {
+    /// Where the tip is, in sites from the first run's start site.
+    pub position: Site,
+    pub sites: Vec<SiteRecord>,
+}
+
+impl SiteMap {
+    /// Load a map, or start an empty one if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, SpmError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path).map_err(|source| SpmError::Io {
+            source,
+            context: format!("reading site map {}", path.display()),
+        })?;
+        serde_json::from_str(&text)
+            .map_err(|e| SpmError::Workflow(format!("site map {} is invalid: {e}", path.display())))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SpmError> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| SpmError::Workflow(format!("serializing site map: {e}")))?;
+        std::fs::write(path, text).map_err(|source| SpmError::Io {
+            source,
+            context: format!("writing site map {}", path.display()),
+        })
+    }
+
+    pub fn get(&self, site: Site) -> Option<&SiteRecord> {
+        self.sites.iter().find(|r| r.site == site)
+    }
+
+    fn entry(&mut self, site: Site) -> &mut SiteRecord {
+        match self.sites.iter().position(|r| r.site == site) {
+            Some(i) => &mut self.sites[i],
+            None => {
+                self.sites.push(SiteRecord {
+                    site,
+                    ..Default::default()
+                });
+                self.sites.last_mut().expect("just pushed")
+            }
+        }
+    }
+}
+
+/// Chooses where each reposition goes: the next site of the configured
+/// pattern that is inside the sample bounds, not visited before, and not
+/// within `avoid_radius_sites` of a pulsed site.
+pub struct SitePlanner {
+    config: RepositionConfig,
+    map: SiteMap,
+    /// Sites visited this run
+    visited_this_run: usize,
+    /// Next candidate index of the line / spiral / raster pattern
+    cursor: usize,
+    /// Spiral / raster candidates, in visiting order
+    candidates: Vec<Site>,
+    rng: Rng,
+}
+
+impl SitePlanner {
+    pub fn new(config: &RepositionConfig) -> Self {
+        let candidates = match config.pattern {
+            RepositionPattern::Line | RepositionPattern::Random => Vec::new(),
+            RepositionPattern::Spiral => spiral(config.radius_sites as i32),
+            RepositionPattern::Raster => raster(config.raster_size),
+        };
+        Self {
+            config: config.clone(),
+            map: SiteMap::default(),
+            visited_this_run: 0,
+            cursor: 0,
+            candidates,
+            rng: Rng::new(config.seed),
+        }
+    }
+
+    /// Load the persisted map, if one is configured, and record the start
+    /// site as visited.
+    pub fn start(&mut self) -> Result<(), SpmError> {
+        if let Some(path) = &self.config.map_file {
+            self.map = SiteMap::load(Path::new(path))?;
+            log::info!(
+                "Site map {}: {} known sites, tip at ({}, {})",
+                path,
+                self.map.sites.len(),
+                self.map.position.x,
+                self.map.position.y
+            );
+        }
+        let here = self.map.position;
+        self.arrive(here);
+        Ok(())
+    }
+
+    pub fn position(&self) -> Site {
+        self.map.position
+    }
+
+    pub fn map(&self) -> &SiteMap {
+        &self.map
+    }
+
+    pub fn visited_this_run(&self) -> usize {
+        self.visited_this_run
+    }
+
+    /// The next site to move to.
+    pub fn next_site(&mut self) -> Result<Site, SpmError> {
+        let next = match self.config.pattern {
+            RepositionPattern::Line => self.next_on_line(),
+            RepositionPattern::Spiral | RepositionPattern::Raster => self.next_candidate(),
+            RepositionPattern::Random => self.next_random(),
+        };
+        next.ok_or_else(|| {
+            SpmError::Workflow(format!(
+                "reposition pattern {:?} exhausted: no fresh site left within bounds \
+                 ({} sites in the map)",
+                self.config.pattern,
+                self.map.sites.len()
+            ))
+        })
+    }
+
+    /// The tip is now at `site`.
+    pub fn arrive(&mut self, site: Site) {
+        self.map.position = site;
+        self.map.entry(site).visits += 1;
+        self.visited_this_run += 1;
+        self.save();
+    }
+
+    /// A pulse or indentation was fired at the current site.
+    pub fn mark_pulsed(&mut self) {
+        let here = self.map.position;
+        self.map.entry(here).pulses += 1;
+        self.save();
+    }
+
+    /// A stable read was taken at the current site.
+    pub fn record_reading(&mut self, freq_shift: f64) {
+        let here = self.map.position;
+        self.map.entry(here).last_freq_shift = Some(freq_shift);
+    }
+
+    /// Whether the tip may be moved to `site`.
+    pub fn is_fresh(&self, site: Site) -> bool {
+        if let Some([x_min, x_max, y_min, y_max]) = self.config.bounds_sites
+            && (!(x_min..=x_max).contains(&site.x) || !(y_min..=y_max).contains(&site.y))
+        {
+            return false;
+        }
+        if self.map.get(site).is_some_and(|r| r.visits > 0) {
+            return false;
+        }
+        !self
+            .map
+            .sites
+            .iter()
+            .any(|r| r.pulses > 0 && r.site.distance(site) <= self.config.avoid_radius_sites)
+    }
+
+    /// Sites (k, k) from the start: the fixed `reposition_steps` move.
+    fn next_on_line(&mut self) -> Option<Site> {
+        loop {
+            self.cursor += 1;
+            let k = i32::try_from(self.cursor).ok()?;
+            let site = Site::new(k, k);
+            if self.is_fresh(site) {
+                return Some(site);
+            }
+            // The line only moves away from the start; once outside the
+            // bounds it never comes back.
+            if let Some([_, x_max, _, y_max]) = self.config.bounds_sites
+                && (k > x_max || k > y_max)
+            {
+                return None;
+            }
+        }
+    }
+
+    fn next_candidate(&mut self) -> Option<Site> {
+        while let Some(&site) = self.candidates.get(self.cursor) {
+            self.cursor += 1;
+            if self.is_fresh(site) {
+                return Some(site);
+            }
+        }
+        None
+    }
+
+    fn next_random(&mut self) -> Option<Site> {
+        let radius = self.config.radius_sites as i32;
+        let span = (2 * radius + 1) as f64;
+        for _ in 0..RANDOM_ATTEMPTS {
+            let x = (self.rng.uniform() * span) as i32 - radius;
+            let y = (self.rng.uniform() * span) as i32 - radius;
+            if x * x + y * y > radius * radius {
+                continue;
+            }
+            let site = Site::new(x, y);
+            if self.is_fresh(site) {
+                return Some(site);
+            }
+        }
+        None
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.config.map_file
+            && let Err(e) = self.map.save(Path::new(path))
+        {
+            // A lost map update must not abort a run mid-conditioning.
+            log::warn!("Failed to save site map: {e}");
+        }
+    }
+}
+
+/// Square spiral outward from the origin, ring by ring, up to `radius`.
+fn spiral(radius: i32) -> Vec<Site> {
+    let mut sites = Vec::new();
+    for r in 1..=radius {
+        // Start right of the origin and walk the ring counter-clockwise.
+        let mut ring: Vec<Site> = Vec::with_capacity(8 * r as usize);
+        ring.extend((-r + 1..=r).map(|y| Site::new(r, y)));
+        ring.extend((-r..r).rev().map(|x| Site::new(x, r)));
+        ring.extend((-r..r).rev().map(|y| Site::new(-r, y)));
+        ring.extend((-r + 1..=r).map(|x| Site::new(x, -r)));
+        sites.extend(ring);
+    }
+    sites
+}
+
+/// Back-and-forth rows over `[columns, rows]` from the origin.
+fn raster(size: [u32; 2]) -> Vec<Site> {
+    let (columns, rows) = (size[0] as i32, size[1] as i32);
+    (0..rows)
+        .flat_map(|y| {
+            let xs: Vec<i32> = if y % 2 == 0 {
+                (0..columns).collect()
+            } else {
+                (0..columns).rev().collect()
+            };
+            xs.into_iter().map(move |x| Site::new(x, y))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planner(pattern: RepositionPattern) -> SitePlanner {
+        let mut planner = SitePlanner::new(&RepositionConfig {
+            pattern,
+            raster_size: [3, 2],
+            radius_sites: 2,
+            ..Default::default()
+        });
+        planner.start().unwrap();
+        planner
+    }
+
+    fn walk(planner: &mut SitePlanner, n: usize) -> Vec<(i32, i32)> {
+        (0..n)
+            .map(|_| {
+                let site = planner.next_site().unwrap();
+                planner.arrive(site);
+                (site.x, site.y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn line_repeats_the_same_move() {
+        let mut planner = planner(RepositionPattern::Line);
+        assert_eq!(walk(&mut planner, 3), vec![(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn spiral_covers_each_ring_before_the_next() {
+        let mut planner = planner(RepositionPattern::Spiral);
+        let sites = walk(&mut planner, 8);
+        assert!(sites.iter().all(|&(x, y)| x.abs().max(y.abs()) == 1));
+        let next = planner.next_site().unwrap();
+        assert_eq!(next.x.abs().max(next.y.abs()), 2);
+        planner.arrive(next);
+        // Ring 2 has 16 sites; one is visited.
+        walk(&mut planner, 15);
+        assert!(planner.next_site().is_err());
+    }
+
+    #[test]
+    fn raster_snakes_through_the_box() {
+        let mut planner = planner(RepositionPattern::Raster);
+        // (0, 0) is the start site, already visited.
+        assert_eq!(
+            walk(&mut planner, 5),
+            vec![(1, 0), (2, 0), (2, 1), (1, 1), (0, 1)]
+        );
+        assert!(planner.next_site().is_err());
+    }
+
+    #[test]
+    fn random_stays_within_the_radius_and_never_revisits() {
+        let mut planner = planner(RepositionPattern::Random);
+        let sites = walk(&mut planner, 10);
+        assert!(sites.iter().all(|&(x, y)| x * x + y * y <= 4));
+        let mut unique = sites.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), sites.len());
+        assert!(!sites.contains(&(0, 0)));
+    }
+
+    #[test]
+    fn pulsed_sites_and_bounds_are_avoided() {
+        let mut planner = SitePlanner::new(&RepositionConfig {
+            pattern: RepositionPattern::Raster,
+            raster_size: [4, 1],
+            avoid_radius_sites: 1,
+            bounds_sites: Some([0, 2, 0, 0]),
+            ..Default::default()
+        });
+        planner.start().unwrap();
+        planner.mark_pulsed();
+        // (1, 0) is next to the pulsed start site, (3, 0) is out of bounds.
+        assert_eq!(planner.next_site().unwrap(), Site::new(2, 0));
+    }
+
+    #[test]
+    fn the_map_persists_between_runs() {
+        let path = std::env::temp_dir().join("rusty_tip_site_map_test.json");
+        let _ = std::fs::remove_file(&path);
+        let config = RepositionConfig {
+            pattern: RepositionPattern::Line,
+            map_file: Some(path.display().to_string()),
+            ..Default::default()
+        };
+
+        let mut first = SitePlanner::new(&config);
+        first.start().unwrap();
+        walk(&mut first, 2);
+        first.mark_pulsed();
+
+        let mut second = SitePlanner::new(&config);
+        second.start().unwrap();
+        assert_eq!(second.position(), Site::new(2, 2));
+        assert_eq!(second.map().get(Site::new(2, 2)).unwrap().pulses, 1);
+        assert_eq!(second.next_site().unwrap(), Site::new(3, 3));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller_types::TipChangeDetection;
+
+/// Readings a step's stream samples are averaged into by
+/// [`TipChangeDetector::push_step`]: enough to place a change within the
+/// step, few enough that scan and feedback transients average out.
+pub const READINGS_PER_STEP: usize = 4;
+
+/// One sweep step, or a stretch of it: the bias it ran at and the signals
+/// read there.
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepSample {
+    /// Sweep this step belongs to (1-based, as in the sweep plan).
+    pub sweep: usize,
+    /// Step within the sweep (1-based).
+    pub step: usize,
+    pub bias_v: f64,
+    pub freq_shift_hz: f64,
+    /// `None` when the current signal is unknown.
+    pub current_a: Option<f64>,
+}
+
+/// What gave the tip change away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TipChangeKind {
+    /// The frequency shift left the recent median by more than allowed.
+    FreqShiftJump,
+    /// The current left the recent median by more than allowed.
+    CurrentJump,
+    /// The frequency shift scattered too much over the window.
+    FreqShiftNoise,
+}
+
+/// A tip change detected during the stability sweep.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TipChangeEvent {
+    pub kind: TipChangeKind,
+    pub sweep: usize,
+    pub step: usize,
+    pub bias_v: f64,
+    /// The reading that tripped the detector (std dev for noise).
+    pub value: f64,
+    /// The reference it was judged against (median, or the std-dev limit).
+    pub reference: f64,
+}
+
+/// Judges sweep readings as they arrive and keeps the trace.
+///
+/// Each reading is compared with the median of the `window` readings before
+/// it, so the slow bias dependence of the signals passes while a discrete
+/// jump does not. Nothing is judged until a full window has been seen.
+///
+/// A reading is either one value per step ([`push`](Self::push)) or the
+/// mean of each of [`READINGS_PER_STEP`] consecutive stretches of a step's
+/// stream samples ([`push_step`](Self::push_step)); single samples are never
+/// judged. The trace keeps one entry per step either way.
+pub struct TipChangeDetector {
+    config: TipChangeDetection,
+    trace: Vec<SweepSample>,
+    /// The last `window` readings of the current sweep.
+    recent: VecDeque<SweepSample>,
+}
+
+impl TipChangeDetector {
+    pub fn new(config: &TipChangeDetection) -> Self {
+        Self {
+            config: config.clone(),
+            trace: Vec::new(),
+            recent: VecDeque::with_capacity(config.window),
+        }
+    }
+
+    /// Start judging a new sweep: the bias jumps between sweeps, so
+    /// readings of an earlier sweep are not a reference.
+    pub fn start_sweep(&mut self) {
+        self.recent.clear();
+    }
+
+    /// Record a reading; returns the change it reveals, if any.
+    pub fn push(&mut self, sample: SweepSample) -> Option<TipChangeEvent> {
+        let event = self.observe(&sample);
+        self.trace.push(sample);
+        event
+    }
+
+    /// Average the stream samples of one step into [`READINGS_PER_STEP`]
+    /// consecutive readings, `current_a[k]` taken with `freq_shift_hz[k]`,
+    /// judge them in order and record the step's means in the trace.
+    /// Returns the first change; the readings after it are not judged.
+    pub fn push_step(
+        &mut self,
+        sweep: usize,
+        step: usize,
+        bias_v: f64,
+        freq_shift_hz: &[f64],
+        current_a: Option<&[f64]>,
+    ) -> Option<TipChangeEvent> {
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+        let current_a = current_a.filter(|c| c.len() >= freq_shift_hz.len());
+        let stretch = freq_shift_hz.len().div_ceil(READINGS_PER_STEP).max(1);
+
+        let mut event = None;
+        let mut judged = 0;
+        for (k, freq) in freq_shift_hz.chunks(stretch).enumerate() {
+            let range = k * stretch..k * stretch + freq.len();
+            judged = range.end;
+            event = self.observe(&SweepSample {
+                sweep,
+                step,
+                bias_v,
+                freq_shift_hz: mean(freq),
+                current_a: current_a.map(|c| mean(&c[range])),
+            });
+            if event.is_some() {
+                break;
+            }
+        }
+
+        if judged > 0 {
+            self.trace.push(SweepSample {
+                sweep,
+                step,
+                bias_v,
+                freq_shift_hz: mean(&freq_shift_hz[..judged]),
+                current_a: current_a.map(|c| mean(&c[..judged])),
+            });
+        }
+        event
+    }
+
+    /// Every step so far, in order.
+    pub fn trace(&self) -> &[SweepSample] {
+        &self.trace
+    }
+
+    pub fn into_trace(self) -> Vec<SweepSample> {
+        self.trace
+    }
+
+    /// Judge `sample` against the window before it, then add it to the window.
+    fn observe(&mut self, sample: &SweepSample) -> Option<TipChangeEvent> {
+        let window = self.config.window;
+        let event = if self.recent.len() >= window {
+            self.judge(sample)
+        } else {
+            None
+        };
+        if self.recent.len() >= window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(sample.clone());
+        event
+    }
+
+    fn judge(&self, sample: &SweepSample) -> Option<TipChangeEvent> {
+        let recent = &self.recent;
+        let event = |kind, value, reference| TipChangeEvent {
+            kind,
+            sweep: sample.sweep,
+            step: sample.step,
+            bias_v: sample.bias_v,
+            value,
+            reference,
+        };
+
+        let freq: Vec<f64> = recent.iter().map(|s| s.freq_shift_hz).collect();
+        let freq_median = median(&freq);
+        if (sample.freq_shift_hz - freq_median).abs() > self.config.max_freq_shift_jump_hz {
+            return Some(event(
+                TipChangeKind::FreqShiftJump,
+                sample.freq_shift_hz,
+                freq_median,
+            ));
+        }
+
+        if let Some(current) = sample.current_a {
+            let currents: Vec<f64> = recent.iter().filter_map(|s| s.current_a).collect();
+            if currents.len() == recent.len() {
+                let current_median = median(&currents);
+                if current_median != 0.0
+                    && (current - current_median).abs()
+                        > self.config.max_current_jump_fraction * current_median.abs()
+                {
+                    return Some(event(TipChangeKind::CurrentJump, current, current_median));
+                }
+            }
+        }
+
+        // The window ending at this reading.
+        let mut latest = freq[1..].to_vec();
+        latest.push(sample.freq_shift_hz);
+        let (_, std_dev, _) = crate::action::signals::compute_stability_metrics(&latest);
+        if std_dev > self.config.max_freq_shift_std_dev_hz {
+            return Some(event(
+                TipChangeKind::FreqShiftNoise,
+                std_dev,
+                self.config.max_freq_shift_std_dev_hz,
+            ));
+        }
+
+        None
+    }
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector() -> TipChangeDetector {
+        TipChangeDetector::new(&TipChangeDetection {
+            enabled: true,
+            window: 4,
+            max_freq_shift_jump_hz: 1.0,
+            max_current_jump_fraction: 0.5,
+            max_freq_shift_std_dev_hz: 0.5,
+        })
+    }
+
+    fn sample(step: usize, freq_shift_hz: f64, current_a: Option<f64>) -> SweepSample {
+        SweepSample {
+            sweep: 1,
+            step,
+            bias_v: 1.0,
+            freq_shift_hz,
+            current_a,
+        }
+    }
+
+    #[test]
+    fn a_steady_trace_raises_nothing() {
+        let mut d = detector();
+        for step in 0..20 {
+            let drift = step as f64 * 0.01;
+            assert!(d.push(sample(step, -1.0 + drift, Some(1e-10))).is_none());
+        }
+        assert_eq!(d.trace().len(), 20);
+    }
+
+    #[test]
+    fn a_freq_shift_jump_is_caught_at_its_step() {
+        let mut d = detector();
+        for step in 0..6 {
+            assert!(d.push(sample(step, -1.0, None)).is_none());
+        }
+        let event = d.push(sample(6, -3.0, None)).expect("jump");
+        assert_eq!(event.kind, TipChangeKind::FreqShiftJump);
+        assert_eq!(event.step, 6);
+        assert_eq!(event.reference, -1.0);
+    }
+
+    #[test]
+    fn a_current_jump_is_caught_relative_to_the_median() {
+        let mut d = detector();
+        for step in 0..4 {
+            assert!(d.push(sample(step, -1.0, Some(1e-10))).is_none());
+        }
+        let event = d.push(sample(4, -1.0, Some(3e-10))).expect("jump");
+        assert_eq!(event.kind, TipChangeKind::CurrentJump);
+    }
+
+    #[test]
+    fn scatter_below_the_jump_limit_is_caught_as_noise() {
+        let mut d = detector();
+        let values = [-1.0, -2.4, -1.0, -2.4, -1.0, -2.4];
+        let events: Vec<_> = values
+            .iter()
+            .enumerate()
+            .filter_map(|(step, &v)| d.push(sample(step, v, None)))
+            .collect();
+        assert_eq!(events[0].kind, TipChangeKind::FreqShiftNoise);
+    }
+
+    #[test]
+    fn a_new_sweep_waits_for_a_fresh_window() {
+        let mut d = detector();
+        for step in 0..6 {
+            d.push(sample(step, -1.0, None));
+        }
+        d.start_sweep();
+        // The second sweep sits elsewhere; without a fresh window it is not judged.
+        for step in 0..4 {
+            assert!(d.push(sample(step, -2.5, None)).is_none());
+        }
+    }
+
+    #[test]
+    fn a_jump_inside_a_step_is_caught_and_the_step_is_traced_once() {
+        let mut d = detector();
+        assert!(d.push_step(1, 1, 1.0, &[-1.0; 8], None).is_none());
+        let mut freq = [-1.0; 8];
+        freq[4..].fill(-3.0);
+        let event = d.push_step(1, 2, 1.1, &freq, None).expect("jump");
+        assert_eq!(event.kind, TipChangeKind::FreqShiftJump);
+        assert_eq!(event.step, 2);
+
+        // One entry per step, the second averaged up to the jump.
+        let trace = d.trace();
+        assert_eq!(trace.len(), 2);
+        assert!((trace[1].freq_shift_hz - (-10.0 / 6.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn a_single_sample_transient_averages_out() {
+        let mut d = detector();
+        for step in 1..=3 {
+            let mut freq = [-1.0; 40];
+            // A feedback spike and a current glitch, one sample each.
+            freq[17] = -4.0;
+            let mut current = [1e-10; 40];
+            current[23] = 4e-10;
+            assert!(
+                d.push_step(1, step, 1.0, &freq, Some(&current)).is_none(),
+                "step {step}"
+            );
+        }
+    }
+}
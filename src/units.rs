@@ -0,0 +1,170 @@
+//! Physical units of signals and how their values are displayed.
+//!
+//! Values travel through the library as bare `f64` in SI base units (A, V,
+//! m, Hz). A [`SignalUnit`] says which unit a signal's values are in and how
+//! many decimals to show; [`SignalUnit::format`] picks the SI prefix, so
+//! `1.234e-10` with unit `A` reads `123.400 pA`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::signal_registry::SignalIndex;
+
+fn default_precision() -> u8 {
+    3
+}
+
+/// Unit and display precision of one signal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SignalUnit {
+    /// Unit symbol without prefix, e.g. `"A"`, `"V"`, `"m"`, `"Hz"`; empty
+    /// for a dimensionless signal.
+    pub symbol: String,
+    /// Decimals shown once the value is scaled to its prefix.
+    #[serde(default = "default_precision")]
+    pub precision: u8,
+}
+
+/// Dimensionless, default precision.
+impl Default for SignalUnit {
+    fn default() -> Self {
+        Self::new("", default_precision())
+    }
+}
+
+impl SignalUnit {
+    pub fn new(symbol: impl Into<String>, precision: u8) -> Self {
+        Self {
+            symbol: symbol.into(),
+            precision,
+        }
+    }
+
+    /// The unit Nanonis puts in a signal name's trailing parentheses:
+    /// `"Current (A)"` is in `A`. `None` for a name without one.
+    pub fn from_signal_name(name: &str) -> Option<Self> {
+        let inner = name.trim().strip_suffix(')')?;
+        let (_, symbol) = inner.rsplit_once('(')?;
+        let symbol = symbol.trim();
+        (!symbol.is_empty()).then(|| Self::new(symbol, default_precision()))
+    }
+
+    /// Whether SI prefixes apply; not for `%`, degrees or decibels.
+    fn takes_prefix(&self) -> bool {
+        !self.symbol.is_empty() && !matches!(self.symbol.as_str(), "%" | "deg" | "°" | "dB")
+    }
+
+    /// `value` scaled to a readable SI prefix, with the unit: `"-12.346 Hz"`,
+    /// `"123.400 pA"`.
+    pub fn format(&self, value: f64) -> String {
+        let precision = self.precision as usize;
+        if !value.is_finite() {
+            return format!("{value} {}", self.symbol).trim_end().to_string();
+        }
+        if !self.takes_prefix() {
+            let magnitude = value.abs();
+            let number = if magnitude != 0.0 && !(1e-3..1e6).contains(&magnitude) {
+                format!("{value:.precision$e}")
+            } else {
+                format!("{value:.precision$}")
+            };
+            return format!("{number} {}", self.symbol).trim_end().to_string();
+        }
+        let (scale, prefix) = si_prefix(value.abs());
+        format!("{:.precision$} {prefix}{}", value * scale, self.symbol)
+    }
+}
+
+/// Scale factor and SI prefix that bring a magnitude into `[1, 1000)`,
+/// from pico to giga. Zero stays unscaled.
+pub fn si_prefix(magnitude: f64) -> (f64, &'static str) {
+    if magnitude == 0.0 || !magnitude.is_finite() {
+        (1.0, "")
+    } else if magnitude >= 1e9 {
+        (1e-9, "G")
+    } else if magnitude >= 1e6 {
+        (1e-6, "M")
+    } else if magnitude >= 1e3 {
+        (1e-3, "k")
+    } else if magnitude >= 1.0 {
+        (1.0, "")
+    } else if magnitude >= 1e-3 {
+        (1e3, "m")
+    } else if magnitude >= 1e-6 {
+        (1e6, "μ")
+    } else if magnitude >= 1e-9 {
+        (1e9, "n")
+    } else {
+        (1e12, "p")
+    }
+}
+
+/// Units by signal index, for formatting values that only carry an index
+/// (see [`EventBus::set_signal_units`](crate::event::EventBus::set_signal_units)).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SignalUnits(HashMap<SignalIndex, SignalUnit>);
+
+impl SignalUnits {
+    pub fn insert(&mut self, index: SignalIndex, unit: SignalUnit) {
+        self.0.insert(index, unit);
+    }
+
+    pub fn get(&self, index: SignalIndex) -> Option<&SignalUnit> {
+        self.0.get(&index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `value` formatted with the unit of `index`, or plainly if it has none.
+    pub fn format(&self, index: SignalIndex, value: f64) -> String {
+        match self.get(index) {
+            Some(unit) => unit.format(value),
+            None => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_are_read_from_nanonis_signal_names() {
+        assert_eq!(
+            SignalUnit::from_signal_name("Current (A)"),
+            Some(SignalUnit::new("A", 3))
+        );
+        assert_eq!(
+            SignalUnit::from_signal_name("Freq. Shift (Hz)")
+                .unwrap()
+                .symbol,
+            "Hz"
+        );
+        assert_eq!(SignalUnit::from_signal_name("Z (m)").unwrap().symbol, "m");
+        assert_eq!(SignalUnit::from_signal_name("Counter"), None);
+        assert_eq!(SignalUnit::from_signal_name("Input 1 ()"), None);
+    }
+
+    #[test]
+    fn values_get_an_si_prefix_and_the_configured_precision() {
+        assert_eq!(SignalUnit::new("A", 3).format(1.234e-10), "123.400 pA");
+        assert_eq!(SignalUnit::new("Hz", 2).format(-12.3456), "-12.35 Hz");
+        assert_eq!(SignalUnit::new("m", 1).format(2.5e-9), "2.5 nm");
+        assert_eq!(SignalUnit::new("Hz", 0).format(32_768.0), "33 kHz");
+        assert_eq!(SignalUnit::new("V", 2).format(0.0), "0.00 V");
+        assert_eq!(SignalUnit::new("%", 1).format(0.5), "0.5 %");
+        assert_eq!(SignalUnit::new("", 2).format(1.5e-6), "1.50e-6");
+    }
+
+    #[test]
+    fn a_table_formats_by_index_and_falls_back_to_the_bare_value() {
+        let mut units = SignalUnits::default();
+        units.insert(SignalIndex(0), SignalUnit::new("A", 1));
+
+        assert_eq!(units.format(SignalIndex(0), 5e-12), "5.0 pA");
+        assert_eq!(units.format(SignalIndex(9), 0.25), "0.25");
+    }
+}
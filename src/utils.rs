@@ -87,6 +87,48 @@ where
     }
 }
 
+/// Small deterministic PRNG (xorshift64\*) so mock runs and seeded
+/// reposition patterns are reproducible — a figure generated from a mock run
+/// can be regenerated exactly.
+///
+/// Deliberately not `rand`: the crate isn't a dependency, and a fixed-seed
+/// generator is the property we actually want here.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Guard the all-zero state, which xorshift can never escape.
+        Rng(if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub(crate) fn uniform(&mut self) -> f64 {
+        // Top 53 bits land exactly in an f64 mantissa, so no rounding bias.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, via Box-Muller.
+    pub(crate) fn normal(&mut self) -> f64 {
+        let u1 = self.uniform().max(f64::MIN_POSITIVE);
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
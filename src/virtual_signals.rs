@@ -0,0 +1,1074 @@
+//! Virtual signals: quantities computed from real signals by an expression.
+//!
+//! A virtual signal is declared by name and expression (in config as
+//! `[[virtual_signals]]`), registered in the [`SignalRegistry`] at an index
+//! from [`VIRTUAL_INDEX_BASE`] up, and read like any other signal once the
+//! controller is wrapped in a [`VirtualSignalController`]:
+//!
+//! ```text
+//! conductance = current / bias
+//! df_smooth   = mean('freq shift', 50)
+//! z_rel       = z - approach(z)
+//! ```
+//!
+//! Expressions combine numbers and signals (by name or alias, quoted with
+//! `'...'` when the name has spaces) with `+ - * /`, parentheses and:
+//!
+//! - `abs(x)`, `sqrt(x)`, `min(a, b)`, `max(a, b)`
+//! - `mean(x, n)`: trailing moving average over the last `n` samples
+//! - `approach(x)`: the value `x` had when the last auto-approach finished
+//!   (or at the first read, if there was no approach since connecting)
+//!
+//! Only real signals can appear in an expression, so every virtual read is
+//! one read of its inputs from the same moment.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use nanonis_rs::{
+    Position,
+    motor::{MotorDirection, MotorDisplacement, MovementMode, Position3D},
+    oscilloscope::OsciData,
+    scan::{ScanAction, ScanConfig, ScanDirection, ScanFrame, ScanProps, ScanPropsBuilder},
+    tip_recovery::TipShaperConfig,
+};
+
+use crate::signal_registry::{SignalIndex, SignalRegistry};
+use crate::spm_controller::{
+    AcquisitionMode, Capability, DataStreamStatus, Result, SpmController, TriggerSetup,
+    ZControllerStatus, ZHomeMode,
+};
+use crate::spm_error::SpmError;
+
+/// First signal index given to virtual signals, just above the Nanonis
+/// range (0-127).
+pub const VIRTUAL_INDEX_BASE: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+}
+
+impl BinaryOp {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Min => a.min(b),
+            BinaryOp::Max => a.max(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnaryOp {
+    Neg,
+    Abs,
+    Sqrt,
+}
+
+impl UnaryOp {
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            UnaryOp::Neg => -x,
+            UnaryOp::Abs => x.abs(),
+            UnaryOp::Sqrt => x.sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    /// A signal by name, before resolution.
+    Name(String),
+    Signal(SignalIndex),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Mean(Box<Expr>, usize),
+    /// `approach(x)`; `slot` numbers the approach terms of one expression.
+    Approach {
+        slot: usize,
+        arg: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// Samples needed before the first output sample is complete.
+    fn history(&self) -> usize {
+        match self {
+            Expr::Number(_) | Expr::Name(_) | Expr::Signal(_) | Expr::Approach { .. } => 0,
+            Expr::Unary(_, x) => x.history(),
+            Expr::Binary(_, a, b) => a.history().max(b.history()),
+            Expr::Mean(x, n) => x.history() + n - 1,
+        }
+    }
+
+    /// Signals read on every evaluation; `approach` arguments are read only
+    /// when captured, so they are left out.
+    fn inputs(&self, into: &mut Vec<SignalIndex>) {
+        match self {
+            Expr::Signal(index) => {
+                if !into.contains(index) {
+                    into.push(*index);
+                }
+            }
+            Expr::Unary(_, x) | Expr::Mean(x, _) => x.inputs(into),
+            Expr::Binary(_, a, b) => {
+                a.inputs(into);
+                b.inputs(into);
+            }
+            Expr::Number(_) | Expr::Name(_) | Expr::Approach { .. } => {}
+        }
+    }
+
+    fn approaches<'a>(&'a self, into: &mut Vec<&'a Expr>) {
+        match self {
+            Expr::Approach { arg, .. } => into.push(arg),
+            Expr::Unary(_, x) | Expr::Mean(x, _) => x.approaches(into),
+            Expr::Binary(_, a, b) => {
+                a.approaches(into);
+                b.approaches(into);
+            }
+            Expr::Number(_) | Expr::Name(_) | Expr::Signal(_) => {}
+        }
+    }
+
+    fn resolve(
+        &mut self,
+        lookup: &impl Fn(&str) -> std::result::Result<SignalIndex, String>,
+    ) -> std::result::Result<(), String> {
+        match self {
+            Expr::Name(name) => *self = Expr::Signal(lookup(name)?),
+            Expr::Unary(_, x) | Expr::Mean(x, _) => x.resolve(lookup)?,
+            Expr::Approach { arg, .. } => arg.resolve(lookup)?,
+            Expr::Binary(_, a, b) => {
+                a.resolve(lookup)?;
+                b.resolve(lookup)?;
+            }
+            Expr::Number(_) | Expr::Signal(_) => {}
+        }
+        Ok(())
+    }
+
+    /// `len` output samples from `columns` holding `len` samples of every
+    /// input; `baselines` holds the captured value of each approach slot.
+    fn eval(
+        &self,
+        columns: &HashMap<SignalIndex, Vec<f64>>,
+        len: usize,
+        baselines: &[f64],
+    ) -> Vec<f64> {
+        match self {
+            Expr::Number(value) => vec![*value; len],
+            Expr::Signal(index) => columns.get(index).cloned().unwrap_or_default(),
+            Expr::Name(_) => vec![f64::NAN; len],
+            Expr::Unary(op, x) => x
+                .eval(columns, len, baselines)
+                .into_iter()
+                .map(|v| op.apply(v))
+                .collect(),
+            Expr::Binary(op, a, b) => a
+                .eval(columns, len, baselines)
+                .into_iter()
+                .zip(b.eval(columns, len, baselines))
+                .map(|(a, b)| op.apply(a, b))
+                .collect(),
+            Expr::Mean(x, n) => {
+                let values = x.eval(columns, len, baselines);
+                (0..values.len())
+                    .map(|k| {
+                        let window = &values[(k + 1).saturating_sub(*n)..=k];
+                        window.iter().sum::<f64>() / window.len() as f64
+                    })
+                    .collect()
+            }
+            Expr::Approach { slot, .. } => vec![baselines[*slot]; len],
+        }
+    }
+}
+
+/// A parsed, not yet resolved virtual-signal expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    source: String,
+    expr: Expr,
+    approach_slots: usize,
+}
+
+impl Expression {
+    /// Parse `source`; the signal names in it are checked only when the
+    /// expression is compiled against a registry.
+    pub fn parse(source: &str) -> std::result::Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            approach_slots: 0,
+            in_approach: false,
+        };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("unexpected {token} in \"{source}\""));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+            approach_slots: parser.approach_slots,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+/// A virtual signal compiled against the real signals of a registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualSignal {
+    name: String,
+    index: SignalIndex,
+    expression: Expression,
+    inputs: Vec<SignalIndex>,
+}
+
+impl VirtualSignal {
+    /// Resolve the signal names of `expression` with `lookup`, which must
+    /// answer with real signals only.
+    pub fn compile(
+        name: &str,
+        index: SignalIndex,
+        mut expression: Expression,
+        lookup: impl Fn(&str) -> std::result::Result<SignalIndex, String>,
+    ) -> std::result::Result<Self, String> {
+        expression.expr.resolve(&lookup)?;
+        let mut inputs = Vec::new();
+        expression.expr.inputs(&mut inputs);
+        Ok(Self {
+            name: name.to_string(),
+            index,
+            expression,
+            inputs,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn index(&self) -> SignalIndex {
+        self.index
+    }
+
+    pub fn expression(&self) -> &str {
+        self.expression.source()
+    }
+
+    fn history(&self) -> usize {
+        self.expression.expr.history()
+    }
+}
+
+/// A controller whose signal reads also answer for virtual signals.
+///
+/// Reads of real signals and every other command go straight to the wrapped
+/// controller; a read that includes virtual signals reads their inputs
+/// together with the requested real signals and evaluates the expressions.
+pub struct VirtualSignalController {
+    inner: Box<dyn SpmController>,
+    signals: Vec<VirtualSignal>,
+    /// Captured `approach(...)` values, per signal and slot.
+    baselines: Vec<Vec<Option<f64>>>,
+}
+
+impl VirtualSignalController {
+    pub fn new(inner: Box<dyn SpmController>, signals: Vec<VirtualSignal>) -> Self {
+        let baselines = signals
+            .iter()
+            .map(|s| vec![None; s.expression.approach_slots])
+            .collect();
+        Self {
+            inner,
+            signals,
+            baselines,
+        }
+    }
+
+    /// `inner` wrapped for the virtual signals of `registry`, or unchanged
+    /// if it defines none.
+    pub fn wrap(
+        inner: Box<dyn SpmController>,
+        registry: &SignalRegistry,
+    ) -> Box<dyn SpmController> {
+        if registry.virtual_signals().is_empty() {
+            inner
+        } else {
+            Box::new(Self::new(inner, registry.virtual_signals().to_vec()))
+        }
+    }
+
+    fn position(&self, index: SignalIndex) -> Option<usize> {
+        self.signals.iter().position(|s| s.index == index)
+    }
+
+    fn any_virtual(&self, indices: &[SignalIndex]) -> bool {
+        indices.iter().any(|&i| self.position(i).is_some())
+    }
+
+    /// Read `n` samples of `expr` straight from the wrapped controller.
+    fn sample_expr(&mut self, expr: &Expr, n: usize, wait_for_newest: bool) -> Result<Vec<f64>> {
+        let mut inputs = Vec::new();
+        expr.inputs(&mut inputs);
+        let len = n + expr.history();
+        let columns = self.read_inputs(&inputs, len, wait_for_newest)?;
+        let values = expr.eval(&columns, len, &[]);
+        Ok(values[len - n..].to_vec())
+    }
+
+    /// `len` samples of each of `inputs`, taken together.
+    fn read_inputs(
+        &mut self,
+        inputs: &[SignalIndex],
+        len: usize,
+        wait_for_newest: bool,
+    ) -> Result<HashMap<SignalIndex, Vec<f64>>> {
+        let values = if inputs.is_empty() {
+            Vec::new()
+        } else if len == 1 {
+            self.inner
+                .read_signals(inputs, wait_for_newest)?
+                .into_iter()
+                .map(|v| vec![v])
+                .collect()
+        } else {
+            self.inner.read_signals_samples(inputs, len)?
+        };
+        Ok(inputs.iter().copied().zip(values).collect())
+    }
+
+    /// Capture the approach values of signal `position` that are not yet
+    /// known.
+    fn ensure_baselines(&mut self, position: usize) -> Result<()> {
+        let signal = self.signals[position].clone();
+        let mut args = Vec::new();
+        signal.expression.expr.approaches(&mut args);
+        for (slot, arg) in args.into_iter().enumerate() {
+            if self.baselines[position][slot].is_none() {
+                let value = self.sample_expr(arg, 1, true)?[0];
+                self.baselines[position][slot] = Some(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// `n` samples of each of `indices`, real or virtual, from one read.
+    fn read_mixed(
+        &mut self,
+        indices: &[SignalIndex],
+        n: usize,
+        wait_for_newest: bool,
+    ) -> Result<Vec<Vec<f64>>> {
+        let mut inputs = Vec::new();
+        let mut history = 0;
+        for &index in indices {
+            match self.position(index) {
+                Some(position) => {
+                    self.ensure_baselines(position)?;
+                    let signal = &self.signals[position];
+                    history = history.max(signal.history());
+                    for input in &signal.inputs {
+                        if !inputs.contains(input) {
+                            inputs.push(*input);
+                        }
+                    }
+                }
+                None => {
+                    if !inputs.contains(&index) {
+                        inputs.push(index);
+                    }
+                }
+            }
+        }
+        let len = n + history;
+        let columns = self.read_inputs(&inputs, len, wait_for_newest)?;
+        Ok(indices
+            .iter()
+            .map(|&index| {
+                let values = match self.position(index) {
+                    Some(position) => {
+                        let baselines: Vec<f64> = self.baselines[position]
+                            .iter()
+                            .map(|b| b.unwrap_or(f64::NAN))
+                            .collect();
+                        self.signals[position]
+                            .expression
+                            .expr
+                            .eval(&columns, len, &baselines)
+                    }
+                    None => columns[&index].clone(),
+                };
+                values[len - n..].to_vec()
+            })
+            .collect())
+    }
+}
+
+impl SpmController for VirtualSignalController {
+    fn capabilities(&self) -> HashSet<Capability> {
+        self.inner.capabilities()
+    }
+
+    fn prepare(&mut self) -> Result<()> {
+        self.inner.prepare()
+    }
+
+    fn teardown(&mut self) {
+        self.inner.teardown()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        self.inner.reconnect()
+    }
+
+    fn read_signal(&mut self, index: SignalIndex, wait_for_newest: bool) -> Result<f64> {
+        if self.position(index).is_none() {
+            return self.inner.read_signal(index, wait_for_newest);
+        }
+        Ok(self.read_mixed(&[index], 1, wait_for_newest)?[0][0])
+    }
+
+    fn read_signals(&mut self, indices: &[SignalIndex], wait_for_newest: bool) -> Result<Vec<f64>> {
+        if !self.any_virtual(indices) {
+            return self.inner.read_signals(indices, wait_for_newest);
+        }
+        Ok(self
+            .read_mixed(indices, 1, wait_for_newest)?
+            .into_iter()
+            .map(|values| values[0])
+            .collect())
+    }
+
+    fn signal_names(&mut self) -> Result<Vec<String>> {
+        self.inner.signal_names()
+    }
+
+    fn get_bias(&mut self) -> Result<f64> {
+        self.inner.get_bias()
+    }
+
+    fn set_bias(&mut self, voltage: f64) -> Result<()> {
+        self.inner.set_bias(voltage)
+    }
+
+    fn bias_pulse(
+        &mut self,
+        voltage: f64,
+        width: Duration,
+        z_hold: bool,
+        absolute: bool,
+    ) -> Result<()> {
+        self.inner.bias_pulse(voltage, width, z_hold, absolute)
+    }
+
+    fn withdraw(&mut self, wait: bool, timeout: Duration) -> Result<()> {
+        self.inner.withdraw(wait, timeout)
+    }
+
+    /// Approach, then capture the `approach(...)` terms anew. Without `wait`
+    /// the approach is still running, so the values are only cleared and
+    /// taken at the next read; a failed capture is likewise only logged.
+    fn auto_approach(&mut self, wait: bool, timeout: Duration) -> Result<()> {
+        self.inner.auto_approach(wait, timeout)?;
+        for position in 0..self.signals.len() {
+            self.baselines[position].fill(None);
+            if !wait {
+                continue;
+            }
+            if let Err(e) = self.ensure_baselines(position) {
+                log::warn!(
+                    "Could not capture the approach values of virtual signal \"{}\": {e}",
+                    self.signals[position].name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn set_z_setpoint(&mut self, setpoint: f64) -> Result<()> {
+        self.inner.set_z_setpoint(setpoint)
+    }
+
+    fn set_z_home(&mut self, mode: ZHomeMode, position: f64) -> Result<()> {
+        self.inner.set_z_home(mode, position)
+    }
+
+    fn go_z_home(&mut self) -> Result<()> {
+        self.inner.go_z_home()
+    }
+
+    fn z_controller_status(&mut self) -> Result<ZControllerStatus> {
+        self.inner.z_controller_status()
+    }
+
+    fn get_position(&mut self, wait_for_newest: bool) -> Result<Position> {
+        self.inner.get_position(wait_for_newest)
+    }
+
+    fn set_position(&mut self, pos: Position, wait: bool) -> Result<()> {
+        self.inner.set_position(pos, wait)
+    }
+
+    fn move_motor(&mut self, direction: MotorDirection, steps: u16, wait: bool) -> Result<()> {
+        self.inner.move_motor(direction, steps, wait)
+    }
+
+    fn move_motor_3d(&mut self, displacement: MotorDisplacement, wait: bool) -> Result<()> {
+        self.inner.move_motor_3d(displacement, wait)
+    }
+
+    fn move_motor_closed_loop(&mut self, target: Position3D, mode: MovementMode) -> Result<()> {
+        self.inner.move_motor_closed_loop(target, mode)
+    }
+
+    fn stop_motor(&mut self) -> Result<()> {
+        self.inner.stop_motor()
+    }
+
+    fn scan_action(&mut self, action: ScanAction, direction: ScanDirection) -> Result<()> {
+        self.inner.scan_action(action, direction)
+    }
+
+    fn scan_status(&mut self) -> Result<bool> {
+        self.inner.scan_status()
+    }
+
+    fn scan_props_get(&mut self) -> Result<ScanProps> {
+        self.inner.scan_props_get()
+    }
+
+    fn scan_props_set(&mut self, props: ScanPropsBuilder) -> Result<()> {
+        self.inner.scan_props_set(props)
+    }
+
+    fn scan_speed_get(&mut self) -> Result<ScanConfig> {
+        self.inner.scan_speed_get()
+    }
+
+    fn scan_speed_set(&mut self, config: ScanConfig) -> Result<()> {
+        self.inner.scan_speed_set(config)
+    }
+
+    fn scan_frame_get(&mut self) -> Result<ScanFrame> {
+        self.inner.scan_frame_get()
+    }
+
+    fn scan_frame_set(&mut self, frame: ScanFrame) -> Result<()> {
+        self.inner.scan_frame_set(frame)
+    }
+
+    fn scan_frame_data_grab(
+        &mut self,
+        channel_index: u32,
+        forward: bool,
+    ) -> Result<(String, Vec<Vec<f32>>, bool)> {
+        self.inner.scan_frame_data_grab(channel_index, forward)
+    }
+
+    fn osci_read(
+        &mut self,
+        channel: i32,
+        trigger: Option<&TriggerSetup>,
+        mode: AcquisitionMode,
+    ) -> Result<OsciData> {
+        self.inner.osci_read(channel, trigger, mode)
+    }
+
+    fn tip_shaper(
+        &mut self,
+        config: &TipShaperConfig,
+        wait: bool,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.inner.tip_shaper(config, wait, timeout)
+    }
+
+    fn pll_center_freq_shift(&mut self) -> Result<()> {
+        self.inner.pll_center_freq_shift()
+    }
+
+    fn safe_tip_configure(
+        &mut self,
+        auto_recovery: bool,
+        auto_pause_scan: bool,
+        threshold: f64,
+    ) -> Result<()> {
+        self.inner
+            .safe_tip_configure(auto_recovery, auto_pause_scan, threshold)
+    }
+
+    fn safe_tip_status(&mut self) -> Result<(bool, bool, f64)> {
+        self.inner.safe_tip_status()
+    }
+
+    fn safe_tip_set_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.inner.safe_tip_set_enabled(enabled)
+    }
+
+    fn safe_tip_enabled(&mut self) -> Result<bool> {
+        self.inner.safe_tip_enabled()
+    }
+
+    fn data_stream_configure(&mut self, channels: &[i32], oversampling: i32) -> Result<()> {
+        self.inner.data_stream_configure(channels, oversampling)
+    }
+
+    fn data_stream_start(&mut self) -> Result<()> {
+        self.inner.data_stream_start()
+    }
+
+    fn data_stream_stop(&mut self) -> Result<()> {
+        self.inner.data_stream_stop()
+    }
+
+    fn data_stream_status(&mut self) -> Result<DataStreamStatus> {
+        self.inner.data_stream_status()
+    }
+
+    fn clear_data_buffer(&mut self) {
+        self.inner.clear_data_buffer()
+    }
+
+    fn pause(&self, duration: Duration) {
+        self.inner.pause(duration)
+    }
+
+    fn simulated_time(&self) -> bool {
+        self.inner.simulated_time()
+    }
+
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn stream_sample_rate_hz(&self) -> Option<f64> {
+        self.inner.stream_sample_rate_hz()
+    }
+
+    fn read_signal_samples(&mut self, index: SignalIndex, num_samples: usize) -> Result<Vec<f64>> {
+        if self.position(index).is_none() {
+            return self.inner.read_signal_samples(index, num_samples);
+        }
+        Ok(self
+            .read_signals_samples(&[index], num_samples)?
+            .swap_remove(0))
+    }
+
+    fn read_signals_samples(
+        &mut self,
+        indices: &[SignalIndex],
+        num_samples: usize,
+    ) -> Result<Vec<Vec<f64>>> {
+        if !self.any_virtual(indices) {
+            return self.inner.read_signals_samples(indices, num_samples);
+        }
+        if num_samples == 0 {
+            return Err(SpmError::Protocol(
+                "read_signals_samples: need at least one signal and one sample".into(),
+            ));
+        }
+        // More than one sample always comes from the stream, never from a
+        // snapshot, so `wait_for_newest` does not apply.
+        self.read_mixed(indices, num_samples, true)
+    }
+
+    fn read_stable_signal(&mut self, index: SignalIndex, num_samples: usize) -> Result<f64> {
+        if self.position(index).is_none() {
+            return self.inner.read_stable_signal(index, num_samples);
+        }
+        let samples = self.read_signal_samples(index, num_samples)?;
+        Ok(samples.iter().sum::<f64>() / samples.len() as f64)
+    }
+}
+
+// ============================================================================
+// Parsing
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {n}"),
+            Token::Name(name) => write!(f, "'{name}'"),
+            Token::Symbol(c) => write!(f, "'{c}'"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-*/(),".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, q)) if q == c => break,
+                    Some((_, ch)) => name.push(ch),
+                    None => return Err(format!("unterminated quote in \"{source}\"")),
+                }
+            }
+            tokens.push(Token::Name(name));
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            let mut prev = ' ';
+            while let Some(&(i, ch)) = chars.peek() {
+                let exponent_sign = (ch == '-' || ch == '+') && (prev == 'e' || prev == 'E');
+                if !(ch.is_ascii_digit() || ch == '.' || ch == 'e' || ch == 'E' || exponent_sign) {
+                    break;
+                }
+                prev = ch;
+                end = i + ch.len_utf8();
+                chars.next();
+            }
+            let text = &source[start..end];
+            let value = text
+                .parse()
+                .map_err(|_| format!("invalid number {text} in \"{source}\""))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&(_, ch)) = chars.peek() {
+                if !(ch.is_alphanumeric() || ch == '_' || ch == '.') {
+                    break;
+                }
+                name.push(ch);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+        } else {
+            return Err(format!("unexpected '{c}' in \"{source}\""));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    approach_slots: usize,
+    in_approach: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> std::result::Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(token) => Err(format!("expected '{symbol}', found {token}")),
+                None => Err(format!("expected '{symbol}' at the end")),
+            }
+        }
+    }
+
+    fn expr(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Sub
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Mul
+            } else if self.eat('/') {
+                BinaryOp::Div
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> std::result::Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> std::result::Result<Expr, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| "expression ends too early".to_string())?;
+        self.pos += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Symbol('(') => {
+                let inner = self.expr()?;
+                self.expect(')')?;
+                Ok(inner)
+            }
+            Token::Name(name) if self.eat('(') => self.call(&name),
+            Token::Name(name) => Ok(Expr::Name(name)),
+            Token::Symbol(c) => Err(format!("unexpected '{c}'")),
+        }
+    }
+
+    /// A function call, after its opening parenthesis.
+    fn call(&mut self, function: &str) -> std::result::Result<Expr, String> {
+        let expr = match function {
+            "abs" | "sqrt" => {
+                let op = if function == "abs" {
+                    UnaryOp::Abs
+                } else {
+                    UnaryOp::Sqrt
+                };
+                Expr::Unary(op, Box::new(self.expr()?))
+            }
+            "min" | "max" => {
+                let a = self.expr()?;
+                self.expect(',')?;
+                let b = self.expr()?;
+                let op = if function == "min" {
+                    BinaryOp::Min
+                } else {
+                    BinaryOp::Max
+                };
+                Expr::Binary(op, Box::new(a), Box::new(b))
+            }
+            "mean" => {
+                let x = self.expr()?;
+                self.expect(',')?;
+                let n = match self.peek() {
+                    Some(Token::Number(n)) if *n >= 1.0 && n.fract() == 0.0 => *n as usize,
+                    _ => return Err("mean() takes a whole number of samples, at least 1".into()),
+                };
+                self.pos += 1;
+                Expr::Mean(Box::new(x), n)
+            }
+            "approach" => {
+                if self.in_approach {
+                    return Err("approach() cannot be nested".into());
+                }
+                self.in_approach = true;
+                let arg = self.expr()?;
+                self.in_approach = false;
+                let slot = self.approach_slots;
+                self.approach_slots += 1;
+                Expr::Approach {
+                    slot,
+                    arg: Box::new(arg),
+                }
+            }
+            _ => return Err(format!("unknown function '{function}'")),
+        };
+        self.expect(')')?;
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_controller::{MockController, models};
+
+    fn registry(virtuals: &[(&str, &str)]) -> SignalRegistry {
+        let names: Vec<String> = ["Current (A)", "Bias (V)", "freq shift", "Z (m)"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let mut builder = SignalRegistry::builder().from_signal_names(&names);
+        for (name, expression) in virtuals {
+            builder = builder.add_virtual(name, expression);
+        }
+        builder.create_aliases().build()
+    }
+
+    #[test]
+    fn expressions_parse_with_precedence_and_report_errors() {
+        let expr = Expression::parse("-current / bias + 2 * 1e-3")
+            .unwrap()
+            .expr;
+        let columns = HashMap::from([(SignalIndex(0), vec![4.0]), (SignalIndex(1), vec![2.0])]);
+        let mut resolved = expr.clone();
+        resolved
+            .resolve(&|name| match name {
+                "current" => Ok(SignalIndex(0)),
+                _ => Ok(SignalIndex(1)),
+            })
+            .unwrap();
+        assert_eq!(resolved.eval(&columns, 1, &[]), vec![-2.0 + 0.002]);
+
+        assert!(Expression::parse("current /").is_err());
+        assert!(Expression::parse("(current").is_err());
+        assert!(Expression::parse("median(current)").is_err());
+        assert!(Expression::parse("mean(current, 0.5)").is_err());
+        assert!(Expression::parse("approach(z - approach(z))").is_err());
+        assert!(Expression::parse("'freq shift").is_err());
+    }
+
+    #[test]
+    fn moving_averages_need_history_and_smooth_trailing_windows() {
+        let mut expr = Expression::parse("mean(x, 3)").unwrap().expr;
+        expr.resolve(&|_| Ok(SignalIndex(0))).unwrap();
+        assert_eq!(expr.history(), 2);
+
+        let columns = HashMap::from([(SignalIndex(0), vec![3.0, 6.0, 9.0, 12.0])]);
+        assert_eq!(expr.eval(&columns, 4, &[]), vec![3.0, 4.5, 6.0, 9.0]);
+    }
+
+    #[test]
+    fn virtual_signals_register_above_the_nanonis_range() {
+        let registry = registry(&[
+            ("conductance", "current / bias"),
+            ("df2", "2 * 'freq shift'"),
+        ]);
+
+        let conductance = registry.get_by_name("conductance").unwrap();
+        assert_eq!(conductance.index, VIRTUAL_INDEX_BASE);
+        assert!(conductance.is_virtual());
+        assert_eq!(
+            registry.get_by_name("df2").unwrap().signal_index(),
+            SignalIndex(VIRTUAL_INDEX_BASE as u32 + 1)
+        );
+        assert_eq!(registry.virtual_signals().len(), 2);
+        assert!(!registry.get_by_name("current").unwrap().is_virtual());
+
+        let names = vec!["Current (A)".to_string()];
+        let strict = SignalRegistry::builder()
+            .from_signal_names(&names)
+            .add_virtual("unknown", "nope * 2")
+            .add_virtual("current", "current")
+            .create_aliases()
+            .try_build();
+        let Err(SpmError::Workflow(message)) = strict else {
+            panic!("expected a refusal");
+        };
+        assert!(message.contains("\"unknown\"") && message.contains("\"current\""));
+    }
+
+    #[test]
+    fn expressions_over_unknown_or_virtual_signals_are_skipped() {
+        let registry = registry(&[
+            ("ok", "current"),
+            ("unknown", "nope * 2"),
+            ("nested", "ok * 2"),
+            ("current", "bias"),
+        ]);
+        assert_eq!(registry.virtual_signals().len(), 1);
+        assert!(registry.get_by_name("unknown").is_none());
+        assert!(registry.get_by_name("nested").is_none());
+        assert!(!registry.get_by_name("current").unwrap().is_virtual());
+    }
+
+    #[test]
+    fn virtual_reads_evaluate_over_the_wrapped_controller() {
+        let registry = registry(&[
+            ("df_offset", "'freq shift' + 1"),
+            ("df_smooth", "mean('freq shift', 4)"),
+        ]);
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .freq_shift(models::always(-3.0))
+            .build();
+        let mut controller = VirtualSignalController::wrap(Box::new(mock), &registry);
+        let offset = registry.get_by_name("df_offset").unwrap().signal_index();
+        let smooth = registry.get_by_name("df_smooth").unwrap().signal_index();
+        let df = registry.get_by_name("freq shift").unwrap().signal_index();
+
+        assert_eq!(controller.read_signal(offset, true).unwrap(), -2.0);
+        assert_eq!(
+            controller.read_signals(&[df, offset], true).unwrap(),
+            vec![-3.0, -2.0]
+        );
+        assert_eq!(
+            controller.read_signal_samples(smooth, 5).unwrap(),
+            vec![-3.0; 5]
+        );
+        assert_eq!(controller.read_stable_signal(offset, 8).unwrap(), -2.0);
+    }
+
+    #[test]
+    fn approach_terms_are_captured_at_the_first_read_and_after_each_approach() {
+        let registry = registry(&[("df_rel", "'freq shift' - approach('freq shift')")]);
+        // Reads: capture, read, capture after the approach, read.
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .freq_shift(models::scripted(vec![-3.0, -5.0, -5.0, -6.0]))
+            .build();
+        let mut controller = VirtualSignalController::wrap(Box::new(mock), &registry);
+        let rel = registry.get_by_name("df_rel").unwrap().signal_index();
+
+        assert_eq!(controller.read_signal(rel, true).unwrap(), -2.0);
+        controller
+            .auto_approach(true, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(controller.read_signal(rel, true).unwrap(), -1.0);
+    }
+
+    #[test]
+    fn an_approach_without_wait_leaves_the_capture_to_the_next_read() {
+        let registry = registry(&[("df_rel", "'freq shift' - approach('freq shift')")]);
+        let mock = MockController::builder()
+            .freq_shift_index(SignalIndex(2))
+            .freq_shift(models::scripted(vec![-3.0, -5.0, -7.0, -9.0]))
+            .build();
+        let obs = mock.observations();
+        let mut controller = VirtualSignalController::wrap(Box::new(mock), &registry);
+        let rel = registry.get_by_name("df_rel").unwrap().signal_index();
+
+        assert_eq!(controller.read_signal(rel, true).unwrap(), -2.0);
+        controller
+            .auto_approach(false, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(obs.lock().freq_reads, 2, "nothing is read mid-approach");
+        assert_eq!(controller.read_signal(rel, true).unwrap(), -2.0);
+    }
+}
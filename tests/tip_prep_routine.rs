@@ -8,12 +8,17 @@
 use std::sync::{Arc, Mutex as StdMutex};
 
 use rusty_tip::SignalIndex;
-use rusty_tip::config::AppConfig;
-use rusty_tip::controller_types::{BiasSweepPolarity, PolaritySign, PulseMethod};
+use rusty_tip::config::{AppConfig, ConditioningMode, ConfirmationPolicy, RepositionPattern};
+use rusty_tip::controller_types::{
+    BiasSweepPolarity, PolaritySign, PulseMethod, PulseWidthStepping, RandomPolaritySwitch,
+};
 use rusty_tip::event::{Event, EventBus, Observer};
-use rusty_tip::mock_controller::{FaultKind, MockController, models};
+use rusty_tip::mock_controller::{FaultKind, FreqShiftModel, MockController, SampleOffset, models};
+use rusty_tip::routine::operator::AutoOperator;
+use rusty_tip::routine::run_routine_reported;
 use rusty_tip::shutdown::ShutdownFlag;
-use rusty_tip::tip_prep::{Outcome, TipPrepParams, run_tip_prep};
+use rusty_tip::spm_error::SpmError;
+use rusty_tip::tip_prep::{Outcome, TipPrep, TipPrepParams, TipPrepState, run_tip_prep};
 
 const FREQ_SHIFT_INDEX: SignalIndex = SignalIndex(2);
 
@@ -68,14 +73,12 @@ impl Observer for RecordingObserver {
     }
 }
 
-/// `true` if any recorded event is a `Custom { kind }` carrying the given phase.
+/// `true` if any recorded event is a `tip_prep_state` in the given phase.
 fn saw_phase(events: &[Event], phase: &str) -> bool {
-    events.iter().any(|e| match e {
-        Event::Custom { kind, data } if kind == "tip_prep_state" => {
-            data.get("phase").and_then(|p| p.as_str()) == Some(phase)
-        }
-        _ => false,
-    })
+    events
+        .iter()
+        .filter_map(Event::decode::<TipPrepState>)
+        .any(|state| state.phase() == phase)
 }
 
 // ============================================================================
@@ -194,6 +197,431 @@ fn tip_sharpens_after_pulses_then_completes() {
     assert!(obs.torn_down);
 }
 
+#[test]
+fn run_report_summarises_pulses_and_the_stability_check() {
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::sharpens_after(3, -40.0, -1.0))
+        .build();
+    let mut cfg = fast_config();
+    cfg.tip_prep.stability.check_stability = true;
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)));
+    assert_eq!(report.routine, "tip_prep");
+    assert_eq!(report.outcome, Some(Outcome::Completed));
+    assert_eq!(report.cycles, 3);
+    assert_eq!(report.actions["bias_pulse"], 3);
+    assert!(report.failures.is_empty());
+
+    let summary = &report.summary;
+    assert_eq!(summary["pulses_fired"], 3);
+    assert_eq!(summary["final_freq_shift"], -1.0);
+    let checks = summary["stability_checks"].as_array().unwrap();
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0]["stable"], true);
+    assert_eq!(checks[0]["baseline_freq_shift"], -1.0);
+}
+
+#[test]
+fn adaptive_pulses_try_each_voltage_and_publish_arm_stats() {
+    // Only a 4 V pulse sharpens the tip.
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(Box::new(|obs| match obs.pulses.last() {
+            Some(v) if (v - 4.0).abs() < 1e-9 => -1.0,
+            _ => -30.0,
+        }))
+        .build();
+    let obs = mock.observations();
+    let mut cfg = fast_config();
+    cfg.pulse_method = PulseMethod::Adaptive {
+        voltage_bounds: (2.0, 6.0),
+        voltage_levels: 3,
+        explore_polarity: false,
+        exploration: 0.5,
+        discount: 0.98,
+        polarity: PolaritySign::Positive,
+        random_polarity_switch: None,
+        pulse_width: None,
+    };
+    let recorder = RecordingObserver::default();
+    let mut events = EventBus::new();
+    events.add_observer(Box::new(recorder.clone()));
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &events,
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)));
+    assert_eq!(obs.lock().pulses, vec![2.0, 4.0]);
+
+    let events = recorder.events.lock().unwrap();
+    assert!(
+        events
+            .iter()
+            .any(|e| matches!(e, Event::Custom { kind, .. } if kind == "pulse_arms"))
+    );
+    let arms = report.summary["pulse_arms"].as_array().unwrap();
+    assert_eq!(arms.len(), 3);
+    assert_eq!(arms[1]["voltage"], 4.0);
+    assert_eq!(arms[1]["sharp_hits"], 1);
+}
+
+#[test]
+fn adaptive_arms_are_not_credited_with_switched_pulses() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(4);
+    cfg.pulse_method = PulseMethod::Adaptive {
+        voltage_bounds: (2.0, 4.0),
+        voltage_levels: 2,
+        explore_polarity: false,
+        exploration: 0.5,
+        discount: 0.98,
+        polarity: PolaritySign::Positive,
+        random_polarity_switch: Some(RandomPolaritySwitch {
+            enabled: true,
+            switch_every_n_pulses: 2,
+        }),
+        pulse_width: None,
+    };
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-30.0))
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::CycleLimit(4))));
+    let pulses = obs.lock().pulses.clone();
+    assert_eq!(pulses.iter().filter(|v| **v < 0.0).count(), 2, "{pulses:?}");
+    let credited: u64 = report.summary["pulse_arms"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|arm| arm["pulses"].as_u64().unwrap())
+        .sum();
+    assert_eq!(credited, 2, "only the arms' own pulses count");
+}
+
+#[test]
+fn adaptive_pulse_learns_the_width_with_the_voltage() {
+    // Only a long, gentle pulse sharpens this tip.
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(Box::new(|obs| {
+            match (obs.pulses.last(), obs.pulse_widths.last()) {
+                (Some(v), Some(w)) if (v - 2.0).abs() < 1e-9 && w.as_millis() == 150 => -1.0,
+                _ => -30.0,
+            }
+        }))
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+    let mut cfg = fast_config();
+    cfg.pulse_method = PulseMethod::Adaptive {
+        voltage_bounds: (2.0, 4.0),
+        voltage_levels: 2,
+        explore_polarity: false,
+        exploration: 0.5,
+        discount: 0.98,
+        polarity: PolaritySign::Positive,
+        random_polarity_switch: None,
+        pulse_width: Some(PulseWidthStepping {
+            width_bounds_ms: (50, 150),
+            width_steps: 1,
+            cycles_at_max_voltage: 1,
+        }),
+    };
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)));
+    let obs = obs.lock();
+    assert_eq!(obs.pulses, vec![2.0, 4.0, 2.0]);
+    let widths: Vec<u128> = obs.pulse_widths.iter().map(|w| w.as_millis()).collect();
+    assert_eq!(widths, vec![50, 50, 150]);
+
+    let arms = report.summary["pulse_arms"].as_array().unwrap();
+    assert_eq!(arms.len(), 4);
+    assert_eq!(arms[2]["width_ms"], 150);
+    assert_eq!(arms[2]["sharp_hits"], 1);
+}
+
+#[test]
+fn pulse_width_steps_up_while_voltage_is_at_max() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(4);
+    // Fixed sits at its max voltage, so every update counts toward a step.
+    cfg.pulse_method = PulseMethod::Fixed {
+        voltage: 4.0,
+        polarity: PolaritySign::Positive,
+        random_polarity_switch: None,
+        pulse_width: Some(PulseWidthStepping {
+            width_bounds_ms: (50, 150),
+            width_steps: 2,
+            cycles_at_max_voltage: 1,
+        }),
+    };
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-40.0))
+        .build();
+    let obs = mock.observations();
+    let recorder = RecordingObserver::default();
+    let mut events = EventBus::new();
+    events.add_observer(Box::new(recorder.clone()));
+
+    let outcome = run_tip_prep(
+        Box::new(mock),
+        TipPrepParams {
+            events: &events,
+            shutdown: &ShutdownFlag::new(),
+            config: &cfg,
+            freq_shift: FREQ_SHIFT_INDEX,
+        },
+    )
+    .expect("routine should not error");
+
+    assert!(matches!(outcome, Outcome::CycleLimit(4)));
+    let widths: Vec<u128> = obs
+        .lock()
+        .pulse_widths
+        .iter()
+        .map(|w| w.as_millis())
+        .collect();
+    assert_eq!(widths, vec![50, 100, 150, 150]);
+
+    let snapshot_widths: Vec<u64> = recorder
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Event::decode::<TipPrepState>)
+        .filter_map(|state| state.snapshot().map(|s| s.pulse_width_ms))
+        .collect();
+    assert_eq!(snapshot_widths, vec![50, 100, 150, 150]);
+}
+
+#[test]
+fn mixed_conditioning_indents_after_blunt_pulses() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.conditioning.mode = ConditioningMode::Mixed;
+    cfg.tip_prep.conditioning.pulses_before_indent = 2;
+    cfg.tip_prep.conditioning.depth_bounds_m = (1e-9, 3e-9);
+    cfg.tip_prep.conditioning.bias_v = 0.1;
+
+    // Pulses never help; the first indentation sharpens the tip.
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(Box::new(|obs| {
+            if obs.tip_shapes.is_empty() {
+                -40.0
+            } else {
+                -1.0
+            }
+        }))
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)));
+    let obs = obs.lock();
+    assert_eq!(obs.pulses.len(), 2);
+    assert_eq!(obs.tip_shapes.len(), 1);
+    let shape = &obs.tip_shapes[0];
+    assert!((shape.tip_lift_m + 1e-9).abs() < 1e-15, "indent goes down");
+    assert!((shape.bias_v - 0.1).abs() < 1e-6);
+    assert!(shape.restore_feedback);
+    assert_eq!(report.summary["indents"], 1);
+}
+
+#[test]
+fn indent_conditioning_steps_the_depth_while_the_tip_stays_blunt() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(4);
+    cfg.tip_prep.conditioning.mode = ConditioningMode::Indent;
+    cfg.tip_prep.conditioning.depth_bounds_m = (1e-9, 3e-9);
+    cfg.tip_prep.conditioning.depth_steps = 2;
+    cfg.tip_prep.conditioning.indents_before_step = 1;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-40.0))
+        .build();
+    let obs = mock.observations();
+    let recorder = RecordingObserver::default();
+    let mut events = EventBus::new();
+    events.add_observer(Box::new(recorder.clone()));
+
+    let outcome = run_tip_prep(
+        Box::new(mock),
+        TipPrepParams {
+            events: &events,
+            shutdown: &ShutdownFlag::new(),
+            config: &cfg,
+            freq_shift: FREQ_SHIFT_INDEX,
+        },
+    )
+    .expect("routine should not error");
+
+    assert!(matches!(outcome, Outcome::CycleLimit(4)));
+    let obs = obs.lock();
+    assert!(obs.pulses.is_empty(), "indent mode never pulses");
+    let depths_nm: Vec<f64> = obs
+        .tip_shapes
+        .iter()
+        .map(|s| (-s.tip_lift_m as f64 * 1e9 * 10.0).round() / 10.0)
+        .collect();
+    assert_eq!(depths_nm, vec![1.0, 2.0, 3.0, 3.0]);
+    assert!(saw_phase(&recorder.events.lock().unwrap(), "indenting"));
+}
+
+#[test]
+fn indent_mode_recovers_from_an_unstable_sweep_without_pulsing() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(3);
+    cfg.tip_prep.stability.check_stability = true;
+    cfg.tip_prep.conditioning.mode = ConditioningMode::Indent;
+    cfg.tip_prep.conditioning.depth_bounds_m = (1e-9, 3e-9);
+    cfg.tip_prep.conditioning.depth_steps = 2;
+
+    // Sharp from the start, but the first sweep shifts it by 0.5 Hz.
+    let model: FreqShiftModel = Box::new(|obs| {
+        if obs.called("scan_action") {
+            -1.5
+        } else {
+            -1.0
+        }
+    });
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(model)
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(result.is_ok(), "{result:?}");
+    let check = &report.summary["stability_checks"][0];
+    assert_eq!(check["stable"], false, "{check}");
+
+    let obs = obs.lock();
+    assert!(obs.pulses.is_empty(), "indent mode never pulses");
+    let deepest = obs.tip_shapes.first().expect("a recovery indentation");
+    assert!((-deepest.tip_lift_m as f64 - 3e-9).abs() < 1e-12);
+}
+
+#[test]
+fn spiral_reposition_walks_fresh_sites_around_the_start() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(3);
+    cfg.tip_prep.timing.reposition_steps = [2, 3];
+    cfg.tip_prep.reposition.pattern = RepositionPattern::Spiral;
+    cfg.tip_prep.reposition.radius_sites = 2;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-40.0))
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::CycleLimit(3))));
+    // Start (0, 0) -> (1, 0) -> (1, 1) -> (0, 1), in 2 x 3 step sites.
+    let moves: Vec<(i16, i16)> = obs
+        .lock()
+        .motor_displacements
+        .iter()
+        .map(|&(x, y, _)| (x, y))
+        .collect();
+    assert_eq!(moves, vec![(2, 0), (0, 3), (-2, 0)]);
+    assert_eq!(report.summary["site"]["x"], 0);
+    assert_eq!(report.summary["site"]["y"], 1);
+}
+
+#[test]
+fn the_stability_sweep_moves_through_the_site_planner() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.stability.check_stability = true;
+    cfg.tip_prep.timing.reposition_steps = [2, 3];
+    cfg.tip_prep.reposition.pattern = RepositionPattern::Spiral;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)), "{result:?}");
+    // Every lateral move, the sweep's included, lands on the reported site.
+    let (x, y) = obs
+        .lock()
+        .motor_displacements
+        .iter()
+        .fold((0i64, 0i64), |(x, y), &(dx, dy, _)| {
+            (x + dx as i64, y + dy as i64)
+        });
+    assert_eq!(report.summary["site"]["x"], x / 2);
+    assert_eq!(report.summary["site"]["y"], y / 3);
+    assert!(report.summary["sites_visited"].as_u64().unwrap() > 1);
+}
+
 // ============================================================================
 // Non-success outcomes
 // ============================================================================
@@ -365,6 +793,7 @@ fn sharp_but_unstable_fires_max_pulse_then_cycle_limit() {
         threshold_value: 0.1,
         polarity: PolaritySign::Positive,
         random_polarity_switch: None,
+        pulse_width: None,
     };
 
     // Freq-shift read sequence (see runner trace):
@@ -406,6 +835,391 @@ fn sharp_but_unstable_fires_max_pulse_then_cycle_limit() {
     );
 }
 
+/// The `decision` of every `confirmation` event, in order.
+fn confirmation_decisions(events: &[Event]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            Event::Custom { kind, data } if kind == "confirmation" => data
+                .get("decision")
+                .and_then(|d| d.as_str())
+                .map(str::to_owned),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn sequential_confirmation_accepts_consistent_sites_early() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.confirmation.policy = ConfirmationPolicy::Sequential;
+    cfg.tip_prep.confirmation.sites = 5;
+    cfg.tip_prep.confirmation.min_sites = 2;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .build();
+    let obs = mock.observations();
+
+    let recorder = RecordingObserver::default();
+    let events_handle = recorder.events.clone();
+    let mut bus = EventBus::new();
+    bus.add_observer(Box::new(recorder));
+
+    let outcome = run_tip_prep(
+        Box::new(mock),
+        TipPrepParams {
+            events: &bus,
+            shutdown: &ShutdownFlag::new(),
+            config: &cfg,
+            freq_shift: FREQ_SHIFT_INDEX,
+        },
+    )
+    .expect("routine should not error");
+
+    assert!(matches!(outcome, Outcome::Completed));
+    let events = events_handle.lock().unwrap();
+    // Two sites leave the t interval wider than the window; three do not.
+    assert_eq!(
+        confirmation_decisions(&events),
+        ["continue", "continue", "accept"]
+    );
+    // The initial read plus three confirmation sites, not five.
+    assert_eq!(obs.lock().freq_reads, 4);
+}
+
+#[test]
+fn sequential_confirmation_rejects_a_gross_outlier_at_once() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(1);
+    cfg.tip_prep.confirmation.policy = ConfirmationPolicy::Sequential;
+    cfg.tip_prep.confirmation.sites = 5;
+
+    // Sharp at first, then a confirmation site 7 Hz past the window, then blunt.
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::scripted(vec![-1.0, -9.0, -40.0]))
+        .build();
+    let obs = mock.observations();
+
+    let recorder = RecordingObserver::default();
+    let events_handle = recorder.events.clone();
+    let mut bus = EventBus::new();
+    bus.add_observer(Box::new(recorder));
+
+    let outcome = run_tip_prep(
+        Box::new(mock),
+        TipPrepParams {
+            events: &bus,
+            shutdown: &ShutdownFlag::new(),
+            config: &cfg,
+            freq_shift: FREQ_SHIFT_INDEX,
+        },
+    )
+    .expect("routine should not error");
+
+    assert!(matches!(outcome, Outcome::CycleLimit(1)));
+    let events = events_handle.lock().unwrap();
+    assert_eq!(confirmation_decisions(&events), ["reject"]);
+    assert_eq!(
+        obs.lock().pulses.len(),
+        1,
+        "back to pulsing after the reject"
+    );
+}
+
+#[test]
+fn tip_change_during_the_sweep_stops_it_early_and_fires_max_pulse() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(1);
+    cfg.tip_prep.stability.check_stability = true;
+    cfg.tip_prep.stability.polarity_mode = BiasSweepPolarity::Both;
+    cfg.tip_prep.stability.bias_steps = 200;
+    cfg.tip_prep.stability.tip_change.enabled = true;
+
+    // Sharp and quiet, except that the 16th reading of every sweep jumps
+    // by 3 Hz: the tip changes under the sweep.
+    let mut scanning_reads = 0;
+    let model: FreqShiftModel = Box::new(move |obs| {
+        if !obs.scan_running {
+            scanning_reads = 0;
+            return -1.0;
+        }
+        scanning_reads += 1;
+        if scanning_reads >= 16 { -4.0 } else { -1.0 }
+    });
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(model)
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::CycleLimit(1))));
+    let check = &report.summary["stability_checks"][0];
+    assert_eq!(check["stable"], false, "{check}");
+    assert!(
+        check["final_freq_shift"].is_null(),
+        "no final read after a change"
+    );
+    let change = &check["tip_change"];
+    assert_eq!(change["kind"], "freq_shift_jump", "{change}");
+    assert_eq!(change["sweep"], 1);
+    assert_eq!(change["step"], 16);
+    assert_eq!(check["sweep_trace"].as_array().map(Vec::len), Some(16));
+
+    let obs = obs.lock();
+    // One sweep start and 16 steps, not two sweeps of 200.
+    assert!(
+        obs.count("set_bias") < 50,
+        "the sweep should stop at the change, set_bias ran {} times",
+        obs.count("set_bias")
+    );
+    assert!(
+        obs.pulses.iter().any(|&v| (v - 6.0).abs() < 1e-9),
+        "a tip change should trigger the max-voltage pulse, got {:?}",
+        obs.pulses
+    );
+}
+
+#[test]
+fn a_tip_change_inside_a_step_period_is_caught_from_the_stream() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(1);
+    cfg.tip_prep.stability.check_stability = true;
+    cfg.tip_prep.stability.bias_steps = 20;
+    // 40 stream samples per step at the default 2 kHz.
+    cfg.tip_prep.stability.step_period_ms = 20;
+    cfg.tip_prep.stability.tip_change.enabled = true;
+
+    // The tip is steady at every step boundary; it changes 25 samples into
+    // the third step, which a read at the end of the step would miss.
+    let offset: SampleOffset = Box::new(|obs, sample| {
+        let step = obs.count("read_signals_samples");
+        if obs.scan_running && step == 3 && sample >= 25 {
+            -3.0
+        } else {
+            0.0
+        }
+    });
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .sample_offset(offset)
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX).with_current(SignalIndex(0)),
+    );
+
+    // The change fails the first check; the tip holds through the second.
+    assert!(matches!(result, Ok(Outcome::Completed)), "{result:?}");
+    let check = &report.summary["stability_checks"][0];
+    let change = &check["tip_change"];
+    assert_eq!(change["kind"], "freq_shift_jump", "{check}");
+    assert_eq!(change["step"], 3);
+    assert_eq!(check["sweep_trace"].as_array().map(Vec::len), Some(3));
+
+    let obs = obs.lock();
+    assert!(
+        obs.pulses.iter().any(|&v| (v - 6.0).abs() < 1e-9),
+        "the change should trigger the max-voltage pulse, got {:?}",
+        obs.pulses
+    );
+    // Synchronised stream batches, no per-step snapshot reads.
+    assert_eq!(obs.count("read_signal"), 0);
+}
+
+#[test]
+fn tip_change_detection_is_skipped_without_a_data_stream() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.stability.check_stability = true;
+    cfg.tip_prep.stability.tip_change.enabled = true;
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .without_data_stream()
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)), "{result:?}");
+    let check = &report.summary["stability_checks"][0];
+    assert!(check["tip_change"].is_null(), "{check}");
+    assert_eq!(obs.lock().count("read_signals_samples"), 0);
+}
+
+// ============================================================================
+// Image check
+// ============================================================================
+
+/// Gaussian bumps at pseudo-random spots: a stand-in for a clean image.
+/// `ghost` adds a copy of every bump that many pixels to the side, the way a
+/// second tip apex images the surface.
+fn scan_image(ghost: Option<usize>) -> Vec<Vec<f32>> {
+    const SIZE: usize = 48;
+    let mut state = 0x9E37_79B9u32;
+    let mut image = vec![vec![0.0f32; SIZE]; SIZE];
+    for _ in 0..60 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let (r0, c0) = ((state as usize) % SIZE, (state as usize / SIZE) % SIZE);
+        let copies = [Some(0), ghost];
+        for offset in copies.into_iter().flatten() {
+            for (r, row) in image.iter_mut().enumerate() {
+                for (c, v) in row.iter_mut().enumerate() {
+                    let dc = c as f32 - (c0 + offset) as f32;
+                    let d2 = (r as f32 - r0 as f32).powi(2) + dc * dc;
+                    *v += (-d2 / 2.0).exp();
+                }
+            }
+        }
+    }
+    image
+}
+
+#[test]
+fn image_check_accepts_a_clean_image_and_restores_the_frame() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.image_check.enabled = true;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .scan_image(scan_image(None))
+        .build();
+    let obs = mock.observations();
+
+    let (result, report) = run_routine_reported(
+        Box::new(mock),
+        &EventBus::new(),
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Ok(Outcome::Completed)));
+    let check = &report.summary["stability_checks"][0]["image_check"];
+    assert_eq!(check["accepted"], true, "{check}");
+    assert!(check["correlation_length_m"].is_number());
+
+    let obs = obs.lock();
+    assert_eq!(obs.count("scan_frame_data_grab"), 1);
+    // Once for the small frame, once to restore the original.
+    assert_eq!(obs.count("scan_frame_set"), 2);
+    assert!(!obs.scan_running);
+}
+
+#[test]
+fn an_image_check_frame_past_its_timeout_expires_as_a_deadline() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.image_check.enabled = true;
+    cfg.tip_prep.image_check.timeout_secs = 60;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .scan_image(scan_image(None))
+        .scan_frame_polls(usize::MAX)
+        .simulated_time()
+        .build();
+    let obs = mock.observations();
+
+    let recorder = RecordingObserver::default();
+    let events_handle = recorder.events.clone();
+    let mut bus = EventBus::new();
+    bus.add_observer(Box::new(recorder));
+
+    let (result, _report) = run_routine_reported(
+        Box::new(mock),
+        &bus,
+        &ShutdownFlag::new(),
+        &AutoOperator::defaults(),
+        &mut TipPrep::new(&cfg, FREQ_SHIFT_INDEX),
+    );
+
+    assert!(matches!(result, Err(SpmError::Timeout(_))), "{result:?}");
+    let events = events_handle.lock().unwrap();
+    assert!(
+        events
+            .iter()
+            .any(|e| matches!(e, Event::Custom { kind, .. } if kind == "deadline_expired"))
+    );
+    let obs = obs.lock();
+    assert!(!obs.called("scan_frame_data_grab"));
+    assert!(!obs.scan_running, "the cleanup stops the scan");
+}
+
+#[test]
+fn image_check_rejecting_a_double_tip_keeps_pulsing() {
+    let mut cfg = fast_config();
+    cfg.tip_prep.max_cycles = Some(2);
+    cfg.tip_prep.image_check.enabled = true;
+    cfg.tip_prep.image_check.frame_size_m = None;
+
+    let mock = MockController::builder()
+        .freq_shift_index(FREQ_SHIFT_INDEX)
+        .freq_shift(models::always(-1.0))
+        .scan_image(scan_image(Some(7)))
+        .build();
+    let obs = mock.observations();
+
+    let recorder = RecordingObserver::default();
+    let events_handle = recorder.events.clone();
+    let mut bus = EventBus::new();
+    bus.add_observer(Box::new(recorder));
+
+    let outcome = run_tip_prep(
+        Box::new(mock),
+        TipPrepParams {
+            events: &bus,
+            shutdown: &ShutdownFlag::new(),
+            config: &cfg,
+            freq_shift: FREQ_SHIFT_INDEX,
+        },
+    )
+    .expect("routine should not error");
+
+    assert!(
+        matches!(outcome, Outcome::CycleLimit(2)),
+        "a rejected image must not complete the run, got {}",
+        outcome_name(&outcome)
+    );
+    let obs = obs.lock();
+    assert_eq!(obs.pulses.len(), 2, "pulsing continues after a rejection");
+    assert!(
+        !obs.called("scan_frame_set"),
+        "frame_size_m = None keeps the frame"
+    );
+
+    let events = events_handle.lock().unwrap();
+    assert!(saw_phase(&events, "image_rejected"));
+    assert!(!saw_phase(&events, "image_accepted"));
+}
+
 /// The `realistic` tip model must drive the routine end to end, and each stable
 /// read must reach observers as one measurement — this is what the GUI's
 /// "Simulate" mode and the poster figure plot.
@@ -431,6 +1245,7 @@ fn realistic_model_completes_and_publishes_measurements() {
         linear_clamp: (-20.0, 0.0),
         polarity: PolaritySign::Positive,
         random_polarity_switch: None,
+        pulse_width: None,
     };
 
     let mock = MockController::builder()